@@ -0,0 +1,940 @@
+//! Language server for niri-mapper configuration files
+//!
+//! `niri-mapper lsp` speaks the Language Server Protocol over stdio: JSON-RPC
+//! messages framed by a `Content-Length` header. It reports the errors and
+//! lints of `validate` as diagnostics while a file is edited, completes node
+//! names, key names, device names and the app IDs of open windows, and
+//! describes keys and macro actions on hover.
+//!
+//! Documents are often invalid while they are edited, so completion and hover
+//! work on a lenient scan of the text rather than on the parsed document.
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+
+use miette::{Diagnostic, IntoDiagnostic};
+use niri_mapper_config::{Config, KeyCode, KeyCombo, LayoutSwitch, MacroAction};
+use serde_json::{json, Value};
+
+/// JSON-RPC error code for requests the server does not implement
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// LSP completion item kinds
+const KIND_FUNCTION: u32 = 3;
+const KIND_VALUE: u32 = 12;
+const KIND_KEYWORD: u32 = 14;
+const KIND_REFERENCE: u32 = 18;
+const KIND_CONSTANT: u32 = 21;
+
+/// Serve requests on stdin and stdout until the client exits
+pub fn run() -> miette::Result<()> {
+    let mut input = std::io::stdin().lock();
+    let mut output = std::io::stdout().lock();
+    let mut server = Server::default();
+
+    while let Some(message) = read_message(&mut input)? {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+        if method == "exit" {
+            return match server.shut_down {
+                true => Ok(()),
+                false => Err(miette::miette!("The client exited without shutting the language server down")),
+            };
+        }
+
+        match message.get("id") {
+            // Requests get a response
+            Some(id) => {
+                let response = match server.request(method, &params) {
+                    Some(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    None => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": METHOD_NOT_FOUND, "message": format!("Unknown method '{}'", method) },
+                    }),
+                };
+                write_message(&mut output, &response)?;
+            }
+            None => {
+                for notification in server.notify(method, &params) {
+                    write_message(&mut output, &notification)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read a message, or `None` at the end of the input
+fn read_message(input: &mut impl BufRead) -> miette::Result<Option<Value>> {
+    loop {
+        let mut length = None;
+        loop {
+            let mut line = String::new();
+            if input.read_line(&mut line).into_diagnostic()? == 0 {
+                return Ok(None);
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+
+        let length = length.ok_or_else(|| miette::miette!("LSP message without a valid Content-Length header"))?;
+        let mut body = vec![0; length];
+        input.read_exact(&mut body).into_diagnostic()?;
+        match serde_json::from_slice(&body) {
+            Ok(message) => return Ok(Some(message)),
+            Err(e) => tracing::warn!("Ignoring malformed LSP message: {}", e),
+        }
+    }
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> miette::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body).into_diagnostic()?;
+    output.flush().into_diagnostic()
+}
+
+#[derive(Default)]
+struct Server {
+    /// Text of the open documents, keyed by URI
+    documents: HashMap<String, String>,
+    /// The last configuration that loaded, for library macro and profile names
+    config: Option<Config>,
+    /// Whether the client sent `shutdown`
+    shut_down: bool,
+}
+
+impl Server {
+    /// The result of a request, or `None` if the method is not supported
+    fn request(&mut self, method: &str, params: &Value) -> Option<Value> {
+        match method {
+            "initialize" => Some(json!({
+                "capabilities": {
+                    // Documents are sent in full on every change
+                    "textDocumentSync": 1,
+                    "completionProvider": { "triggerCharacters": ["\"", "+", "="] },
+                    "hoverProvider": true,
+                },
+                "serverInfo": { "name": "niri-mapper", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => {
+                self.shut_down = true;
+                Some(Value::Null)
+            }
+            "textDocument/completion" => Some(match self.position(params) {
+                Some((text, offset)) => Value::Array(self.completions(text, offset)),
+                None => Value::Null,
+            }),
+            "textDocument/hover" => Some(
+                self.position(params)
+                    .and_then(|(text, offset)| self.hover(text, offset))
+                    .unwrap_or(Value::Null),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Handle a notification, returning the notifications to send back
+    fn notify(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_string());
+            }
+            "textDocument/didChange" => {
+                // With full sync, the last change is the whole document
+                let changes = params["contentChanges"].as_array();
+                let Some(text) = changes.and_then(|changes| changes.last()?["text"].as_str()) else {
+                    return Vec::new();
+                };
+                self.documents.insert(uri.clone(), text.to_string());
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish_diagnostics(&uri, Vec::new())];
+            }
+            _ => return Vec::new(),
+        }
+        vec![publish_diagnostics(&uri, self.diagnostics(&uri))]
+    }
+
+    /// The document and byte offset of a request's position
+    fn position(&self, params: &Value) -> Option<(&str, usize)> {
+        let text = self.documents.get(params["textDocument"]["uri"].as_str()?)?;
+        let offset = offset_at(text, &params["position"]);
+        Some((text, offset))
+    }
+
+    /// Load a document as a configuration file, reporting its errors and lints
+    fn diagnostics(&mut self, uri: &str) -> Vec<Value> {
+        let (Some(text), Some(path)) = (self.documents.get(uri), uri_to_path(uri)) else {
+            return Vec::new();
+        };
+        let file = path.display().to_string();
+        let host = niri_mapper_config::HostContext::current();
+
+        let mut diagnostics = Vec::new();
+        match niri_mapper_config::parse_config_source_with_warnings(text, &path, &host, false) {
+            Ok((config, warnings)) => {
+                for warning in &warnings {
+                    push_diagnostics(&mut diagnostics, text, &file, warning);
+                }
+                self.config = Some(config);
+            }
+            Err(e) => push_diagnostics(&mut diagnostics, text, &file, &e),
+        }
+        diagnostics
+    }
+
+    /// Completion items at a byte offset
+    fn completions(&self, text: &str, offset: usize) -> Vec<Value> {
+        let scan = scan(&text[..offset]);
+        // The token being typed, if the cursor is at its end
+        let typed = scan.tokens.last().filter(|token| token.value.end == offset);
+        let (role, parents, node) = match typed {
+            Some(token) => (token.role.clone(), token.parents.clone(), Some(token.node.clone())),
+            None if scan.node.is_some() => (Role::Argument, scan.parents, scan.node),
+            None => (Role::Node, scan.parents, None),
+        };
+        let parents: Vec<&str> = parents.iter().map(String::as_str).collect();
+        let node = node.as_deref().unwrap_or_default();
+
+        // Replace the whole value being typed, up to the end of the word
+        let start = typed.map_or(offset, |token| token.value.start);
+        let end = offset + text[offset..].find(is_delimiter).unwrap_or(text.len() - offset);
+        let quoted = typed.is_some_and(|token| token.quoted);
+        let new_text = |value: &str| match (quoted, &role) {
+            (true, _) => value.to_string(),
+            (false, Role::Node) if is_bare_identifier(value) => value.to_string(),
+            _ => format!("{:?}", value),
+        };
+        let item_at = |range: Range<usize>, new_text: String, label: &str, kind: u32, detail: Option<String>| {
+            let range = json!({ "start": position_at(text, range.start), "end": position_at(text, range.end) });
+            json!({ "label": label, "kind": kind, "detail": detail,
+                    "textEdit": { "range": range, "newText": new_text } })
+        };
+        let item = |label: &str, kind: u32, detail: Option<String>| {
+            item_at(start..end, new_text(label), label, kind, detail)
+        };
+
+        let block = parents.last().copied().unwrap_or_default();
+        match (&role, parents.as_slice(), node) {
+            (Role::Node, _, _) if matches!(block, "remap") => key_items(&item),
+            (Role::Node, _, _) if matches!(block, "combo" | "profile-switch" | "macro") => {
+                // Only the key after the last `+` of a combo is replaced
+                let typed_value = typed.map_or("", |token| &text[token.value.clone()]);
+                let key_start = start + typed_value.rfind('+').map_or(0, |plus| plus + 1);
+                let combo_item = |label: &str, kind: u32, detail: Option<String>| {
+                    item_at(key_start..end, label.to_string(), label, kind, detail)
+                };
+                let mut items = Vec::new();
+                if block != "macro" {
+                    items.extend(modifier_items(&combo_item));
+                }
+                items.extend(key_items(&combo_item));
+                items
+            }
+            (Role::Node, _, _) => niri_mapper_config::node_names(&parents)
+                .into_iter()
+                .map(|name| item(name, KIND_KEYWORD, None))
+                .collect(),
+            (Role::Argument, [], "device") => detected_devices()
+                .into_iter()
+                .map(|(name, tag)| item(&name, KIND_VALUE, tag))
+                .collect(),
+            (Role::Argument, _, _) if block == "remap" => key_items(&item),
+            (Role::Argument, ["global"], "macro-abort-key" | "macro-record-key") => key_items(&item),
+            (Role::Argument, _, _) if block == "combo" || node == "key" => {
+                modifier_items(&item).into_iter().chain(key_items(&item)).collect()
+            }
+            (Role::Argument, _, _) if block == "macro" || parents == ["macros"] => action_items(&item),
+            (Role::Argument, _, _) if block == "profile-switch" => self
+                .profile_names()
+                .into_iter()
+                .map(|name| item(&name, KIND_VALUE, None))
+                .collect(),
+            (Role::Argument, _, "app-id-hint") => niri_app_ids()
+                .into_iter()
+                .map(|app_id| item(&app_id, KIND_VALUE, Some("open window".to_string())))
+                .collect(),
+            (Role::Property(name), _, "match" | "exclude" | "when") if name == "app-id" => {
+                // `app-id` is a regex, so complete app IDs as anchored patterns
+                niri_app_ids()
+                    .into_iter()
+                    .map(|app_id| {
+                        let pattern = format!("^{}$", regex::escape(&app_id));
+                        let mut item = item(&pattern, KIND_VALUE, Some("open window".to_string()));
+                        item["filterText"] = json!(app_id);
+                        item
+                    })
+                    .collect()
+            }
+            (Role::Property(name), _, _) if name == "macro" => {
+                let mut names: Vec<&String> = self.config.iter().flat_map(|config| config.macros.keys()).collect();
+                names.sort();
+                names
+                    .into_iter()
+                    .map(|name| item(name, KIND_REFERENCE, Some("library macro".to_string())))
+                    .collect()
+            }
+            (Role::Property(name), _, "profile") if name == "template" => {
+                let mut names: Vec<&String> =
+                    self.config.iter().flat_map(|config| config.profile_templates.keys()).collect();
+                names.sort();
+                names
+                    .into_iter()
+                    .map(|name| item(name, KIND_REFERENCE, Some("profile template".to_string())))
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Profile names of every device of the last configuration that loaded
+    fn profile_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .config
+            .iter()
+            .flat_map(|config| config.devices.iter().flat_map(|device| device.profiles.keys().cloned()))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Hover documentation for the key, macro action or library macro at a
+    /// byte offset
+    fn hover(&self, text: &str, offset: usize) -> Option<Value> {
+        let scan = scan(text);
+        let token = scan
+            .tokens
+            .iter()
+            .find(|token| token.value.start <= offset && offset <= token.value.end)?;
+        let value = &text[token.value.clone()];
+        let block = token.parents.last().map(String::as_str).unwrap_or_default();
+        let parents: Vec<&str> = token.parents.iter().map(String::as_str).collect();
+
+        let docs = match (&token.role, parents.as_slice(), token.node.as_str()) {
+            (Role::Node, _, _) if matches!(block, "remap" | "macro") => describe_key(value),
+            (Role::Argument, _, _) if block == "remap" => describe_key(value),
+            (Role::Argument, ["global"], "macro-abort-key" | "macro-record-key") => describe_key(value),
+            (Role::Node, _, _) if matches!(block, "combo" | "profile-switch") => describe_combo(value),
+            (Role::Argument, _, _) if block == "combo" => describe_combo(value),
+            (Role::Argument, _, _) if block == "macro" || parents == ["macros"] => describe_action(value),
+            (Role::Argument, ["macros", _], "key") => describe_action(value),
+            (Role::Argument, ["macros", _], action @ ("delay" | "text" | "layout")) => {
+                describe_action(&format!("{}({})", action, value))
+            }
+            (Role::Property(name), _, _) if name == "macro" => {
+                let library_macro = self.config.as_ref()?.macros.get(value)?;
+                let params: Vec<String> = library_macro
+                    .params
+                    .iter()
+                    .map(|param| match &param.default {
+                        Some(default) => format!("{}={:?}", param.name, default),
+                        None => param.name.clone(),
+                    })
+                    .collect();
+                let actions: Vec<String> =
+                    library_macro.actions.iter().map(|action| format!("`{}`", action)).collect();
+                format!(
+                    "**Library macro** `{}`\n\nParameters: {}\n\nActions: {}",
+                    value,
+                    if params.is_empty() { "none".to_string() } else { params.join(", ") },
+                    actions.join(", ")
+                )
+            }
+            _ => return None,
+        };
+
+        Some(json!({
+            "contents": { "kind": "markdown", "value": docs },
+            "range": { "start": position_at(text, token.value.start), "end": position_at(text, token.value.end) },
+        }))
+    }
+}
+
+/// A `textDocument/publishDiagnostics` notification
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+/// Add an LSP diagnostic for each label of an error or lint
+///
+/// Errors that only collect others, like [`niri_mapper_config::ConfigError::Multiple`],
+/// add their related errors instead. Errors without labels, or with labels in
+/// another file, are shown at the start of the document.
+fn push_diagnostics(out: &mut Vec<Value>, text: &str, file: &str, diagnostic: &dyn Diagnostic) {
+    let labels: Vec<miette::LabeledSpan> = diagnostic.labels().map(Iterator::collect).unwrap_or_default();
+    if labels.is_empty() {
+        if let Some(related) = diagnostic.related() {
+            for related in related {
+                push_diagnostics(out, text, file, related);
+            }
+            return;
+        }
+    }
+
+    let mut message = diagnostic.to_string();
+    // Editors show where the error is, so drop the location from the message
+    if let Some(at) = message.find(" at line ").filter(|at| message[*at..].contains(", column ")) {
+        message.truncate(at);
+    }
+    if let Some(source) = std::error::Error::source(diagnostic) {
+        message = format!("{}: {}", message, source);
+    }
+    let help = diagnostic.help().map(|help| help.to_string().trim_end().to_string());
+    let severity = match diagnostic.severity() {
+        Some(miette::Severity::Warning) => 2,
+        Some(miette::Severity::Advice) => 3,
+        _ => 1,
+    };
+    let diagnostic_at = |range: Range<usize>, message: String| {
+        let message = match &help {
+            Some(help) if !help.is_empty() => format!("{}\n\nhelp: {}", message, help),
+            _ => message,
+        };
+        json!({
+            "range": { "start": position_at(text, range.start), "end": position_at(text, range.end) },
+            "severity": severity,
+            "code": diagnostic.code().map(|code| code.to_string()),
+            "source": "niri-mapper",
+            "message": message,
+        })
+    };
+
+    // The file the labels point into, `None` for the document itself
+    let source_file = labels.first().and_then(|label| {
+        let contents = diagnostic.source_code()?.read_span(label.inner(), 0, 0).ok()?;
+        contents.name().map(str::to_string)
+    });
+    match source_file {
+        Some(other) if other != file => out.push(diagnostic_at(0..0, format!("In {}: {}", other, message))),
+        _ if labels.is_empty() => out.push(diagnostic_at(0..0, message)),
+        _ => {
+            // An error about several places, like every unknown key, is
+            // reported at each place with what is wrong there
+            let several = labels.len() > 1;
+            for label in labels {
+                let start = label.offset().min(text.len());
+                let end = (label.offset() + label.len()).min(text.len());
+                let message = match label.label() {
+                    Some(label) if several => label.to_string(),
+                    Some(label) => format!("{}\n{}", message, label),
+                    None => message.clone(),
+                };
+                out.push(diagnostic_at(start..end, message));
+            }
+        }
+    }
+}
+
+/// What a token of a KDL document is
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Role {
+    /// A node name
+    Node,
+    /// An argument of a node
+    Argument,
+    /// The value of the named property of a node
+    Property(String),
+}
+
+/// A name or value in a KDL document
+#[derive(Debug)]
+struct Token {
+    role: Role,
+    /// Byte range of the value, without quotes
+    value: Range<usize>,
+    /// Whether the value is a quoted string
+    quoted: bool,
+    /// Names of the nodes the token is nested in, outermost first
+    parents: Vec<String>,
+    /// Name of the token's node
+    node: String,
+}
+
+/// The tokens of a KDL document, and where its end is nested
+#[derive(Debug, Default)]
+struct Scan {
+    tokens: Vec<Token>,
+    /// Names of the nodes the end of the text is nested in
+    parents: Vec<String>,
+    /// Name of the node the end of the text is in, if its name was written
+    node: Option<String>,
+}
+
+/// Whether a character ends a key name or bare value
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '"' | '{' | '}' | '(' | ')' | ';' | '=' | '+' | '\\')
+}
+
+/// Split KDL text into tokens, leniently
+///
+/// Unterminated strings and blocks, as in a document being typed, end at the
+/// end of the text. Comments, type annotations and line continuations are
+/// skipped.
+fn scan(text: &str) -> Scan {
+    let mut scan = Scan::default();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let next = chars.peek().map(|(_, next)| *next);
+        match c {
+            '\n' | ';' => scan.node = None,
+            '{' => {
+                scan.parents.push(scan.node.take().unwrap_or_default());
+            }
+            '}' => {
+                scan.parents.pop();
+                scan.node = None;
+            }
+            '/' if next == Some('/') => {
+                while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+            }
+            '/' if next == Some('*') => {
+                let mut previous = ' ';
+                for (_, c) in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            '(' => while chars.next().is_some_and(|(_, c)| c != ')') {},
+            '"' => {
+                let value = string_value(text, start, &mut chars);
+                push_token(&mut scan, text, None, value, true);
+            }
+            'r' if next == Some('"') || next == Some('#') => {
+                // Raw string: r"..." or r#"..."#
+                let hashes = text[start + 1..].chars().take_while(|c| *c == '#').count();
+                for _ in 0..=hashes {
+                    chars.next();
+                }
+                let terminator = format!("\"{}", "#".repeat(hashes));
+                let value_start = start + hashes + 2;
+                let value_end = text[value_start.min(text.len())..]
+                    .find(&terminator)
+                    .map_or(text.len(), |end| value_start + end);
+                while chars.next_if(|(i, _)| *i < value_end + terminator.len()).is_some() {}
+                push_token(&mut scan, text, None, value_start.min(text.len())..value_end, true);
+            }
+            c if c.is_whitespace() || c == '\\' => {}
+            _ => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) = chars.next_if(|(_, c)| !is_delimiter(*c) || *c == '+') {
+                    end = i + c.len_utf8();
+                }
+                if chars.next_if(|(_, c)| *c == '=').is_none() {
+                    push_token(&mut scan, text, None, start..end, false);
+                    continue;
+                }
+
+                // A property: its value follows the `=`
+                let property = text[start..end].to_string();
+                match chars.peek().copied() {
+                    Some((quote, '"')) => {
+                        chars.next();
+                        let value = string_value(text, quote, &mut chars);
+                        push_token(&mut scan, text, Some(property), value, true);
+                    }
+                    Some((value_start, c)) if !is_delimiter(c) => {
+                        let mut value_end = value_start;
+                        while let Some((i, c)) = chars.next_if(|(_, c)| !is_delimiter(*c)) {
+                            value_end = i + c.len_utf8();
+                        }
+                        push_token(&mut scan, text, Some(property), value_start..value_end, false);
+                    }
+                    _ => push_token(&mut scan, text, Some(property), end + 1..end + 1, false),
+                }
+            }
+        }
+    }
+    scan
+}
+
+/// Skip a quoted string whose opening quote is at `quote`, returning the range
+/// of its value
+fn string_value(
+    text: &str,
+    quote: usize,
+    chars: &mut std::iter::Peekable<std::str::CharIndices<'_>>,
+) -> Range<usize> {
+    let start = quote + 1;
+    let mut escaped = false;
+    for (i, c) in chars.by_ref() {
+        match c {
+            '"' if !escaped => return start..i,
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    start..text.len()
+}
+
+/// Add a token, which names the current node unless it has a name already
+fn push_token(scan: &mut Scan, text: &str, property: Option<String>, value: Range<usize>, quoted: bool) {
+    let role = match (property, &scan.node) {
+        (Some(property), _) => Role::Property(property),
+        (None, Some(_)) => Role::Argument,
+        (None, None) => {
+            scan.node = Some(text[value.clone()].to_string());
+            Role::Node
+        }
+    };
+    let node = scan.node.clone().unwrap_or_default();
+    scan.tokens.push(Token { role, value, quoted, parents: scan.parents.clone(), node });
+}
+
+/// Whether a name can be written as a KDL identifier without quotes
+fn is_bare_identifier(name: &str) -> bool {
+    let starts_like_number = name.trim_start_matches(['-', '+']).starts_with(|c: char| c.is_ascii_digit());
+    !name.is_empty()
+        && !starts_like_number
+        && !matches!(name, "true" | "false" | "null")
+        && name.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '+' | '.'))
+}
+
+/// Completion items for every key name, with the key it names
+fn key_items(item: &impl Fn(&str, u32, Option<String>) -> Value) -> Vec<Value> {
+    niri_mapper_config::key_names()
+        .map(|name| {
+            let detail = niri_mapper_config::key_code(name).map(|code| {
+                match niri_mapper_config::key_name(code).filter(|display| *display != name) {
+                    Some(display) => format!("{} (key code {})", display, code),
+                    None => format!("key code {}", code),
+                }
+            });
+            // List the names in the README's style before the kernel names
+            let kernel_name = name.starts_with("KEY_") || name.starts_with("BTN_");
+            let mut item = item(name, KIND_CONSTANT, detail);
+            item["sortText"] = json!(format!("{}{}", u8::from(kernel_name), name));
+            item
+        })
+        .collect()
+}
+
+/// Completion items for the modifiers of a combo
+fn modifier_items(item: &impl Fn(&str, u32, Option<String>) -> Value) -> Vec<Value> {
+    ["Ctrl+", "Shift+", "Alt+", "Super+"]
+        .into_iter()
+        .map(|modifier| item(modifier, KIND_KEYWORD, Some("modifier".to_string())))
+        .collect()
+}
+
+/// Completion items for a macro action: a key or one of the action functions
+fn action_items(item: &impl Fn(&str, u32, Option<String>) -> Value) -> Vec<Value> {
+    let functions = [
+        ("delay(50)", "wait before the next action"),
+        ("text()", "type text"),
+        ("layout(next)", "switch to the next keyboard layout"),
+        ("layout(prev)", "switch to the previous keyboard layout"),
+    ];
+    functions
+        .into_iter()
+        .map(|(action, detail)| item(action, KIND_FUNCTION, Some(detail.to_string())))
+        .chain(modifier_items(item))
+        .chain(key_items(item))
+        .collect()
+}
+
+/// Hover documentation for a single key
+fn describe_key(written: &str) -> String {
+    match written.parse::<KeyCode>() {
+        Ok(key) => format!("**Key** `{}`, key code {}", key, key.code()),
+        Err(e) => e,
+    }
+}
+
+/// Hover documentation for a key combo
+fn describe_combo(written: &str) -> String {
+    match written.parse::<KeyCombo>() {
+        Ok(combo) => format!("**Combo** `{}`, key code {} with modifiers held", combo, combo.key.code()),
+        Err(e) => e,
+    }
+}
+
+/// Hover documentation for a macro action
+fn describe_action(written: &str) -> String {
+    if written.contains('{') {
+        return "**Action** with parameters, which are filled in when the macro runs".to_string();
+    }
+    match written.parse::<MacroAction>() {
+        Ok(MacroAction::Key(combo)) => format!("**Key** action: press and release `{}`", combo),
+        Ok(MacroAction::Delay(ms)) => format!("**Delay** action: wait {} ms before the next action", ms),
+        Ok(MacroAction::Text(text)) => {
+            format!("**Text** action: type `{}` with the active keyboard layout", text)
+        }
+        Ok(MacroAction::SwitchLayout(layout)) => {
+            let target = match layout {
+                LayoutSwitch::Next => "the next keyboard layout".to_string(),
+                LayoutSwitch::Prev => "the previous keyboard layout".to_string(),
+                LayoutSwitch::Index(index) => format!("keyboard layout {} of niri's layout list", index),
+            };
+            format!("**Layout** action: ask niri to switch to {}", target)
+        }
+        Err(e) => e,
+    }
+}
+
+/// Input devices with their type tag, e.g. `[keyboard]`, or none if they
+/// cannot be listed
+fn detected_devices() -> Vec<(String, Option<String>)> {
+    match crate::detect_devices() {
+        Ok(devices) => devices
+            .into_iter()
+            .map(|device| (device.name, device.device_type.as_tag().map(str::to_string)))
+            .collect(),
+        Err(e) => {
+            tracing::warn!("Cannot list input devices for completion: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// App IDs of the windows open in niri, or none if niri is not reachable
+fn niri_app_ids() -> Vec<String> {
+    let windows = || -> Option<Vec<niri_ipc::Window>> {
+        let socket = std::env::var("NIRI_SOCKET").ok()?;
+        let mut stream = std::os::unix::net::UnixStream::connect(socket).ok()?;
+        writeln!(stream, "{}", serde_json::to_string(&niri_ipc::Request::Windows).ok()?).ok()?;
+        stream.flush().ok()?;
+        let mut reply = String::new();
+        std::io::BufReader::new(stream).read_line(&mut reply).ok()?;
+        match serde_json::from_str::<niri_ipc::Reply>(&reply).ok()? {
+            Ok(niri_ipc::Response::Windows(windows)) => Some(windows),
+            _ => None,
+        }
+    };
+
+    let mut app_ids: Vec<String> = windows()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|window| window.app_id)
+        .collect();
+    app_ids.sort();
+    app_ids.dedup();
+    app_ids
+}
+
+/// The local path of a `file://` URI
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    // Decode percent escapes, such as `%20` for spaces
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let hex = tail.get(..2).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (byte, hex) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// Byte offset of an LSP position, whose character counts UTF-16 code units
+fn offset_at(text: &str, position: &Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or(0) as usize;
+    let character = position["character"].as_u64().unwrap_or(0) as usize;
+
+    let line_start = match line {
+        0 => 0,
+        _ => match text.match_indices('\n').nth(line - 1) {
+            Some((newline, _)) => newline + 1,
+            None => return text.len(),
+        },
+    };
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+/// LSP position of a byte offset
+fn position_at(text: &str, offset: usize) -> Value {
+    let before = &text[..offset];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    json!({ "line": line, "character": character })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text of each token, with its role and the node it belongs to
+    fn tokens(text: &str) -> Vec<(String, Role, String)> {
+        scan(text)
+            .tokens
+            .into_iter()
+            .map(|token| (text[token.value].to_string(), token.role, token.node))
+            .collect()
+    }
+
+    #[test]
+    fn test_scan_roles() {
+        let text = "device \"Kbd\" {\n\
+                profile \"default\" app-id=\"firefox\" {\n        remap { CapsLock \"Escape\"; }\n";
+        assert_eq!(
+            tokens(text),
+            vec![
+                ("device".to_string(), Role::Node, "device".to_string()),
+                ("Kbd".to_string(), Role::Argument, "device".to_string()),
+                ("profile".to_string(), Role::Node, "profile".to_string()),
+                ("default".to_string(), Role::Argument, "profile".to_string()),
+                ("firefox".to_string(), Role::Property("app-id".to_string()), "profile".to_string()),
+                ("remap".to_string(), Role::Node, "remap".to_string()),
+                ("CapsLock".to_string(), Role::Node, "CapsLock".to_string()),
+                ("Escape".to_string(), Role::Argument, "CapsLock".to_string()),
+            ]
+        );
+
+        let scan = scan(text);
+        assert_eq!(scan.tokens[6].parents, vec!["device", "profile", "remap"]);
+        // The `remap` block is closed, the others are still open
+        assert_eq!(scan.parents, vec!["device", "profile"]);
+        assert_eq!(scan.node, None);
+    }
+
+    #[test]
+    fn test_scan_skips_comments_and_annotations() {
+        let text = "// remap { A \"B\" }\n/* combo {\n} */ (type)macro r#\"F1 \"x\"\"# Ctrl+Shift+Q\n";
+        assert_eq!(
+            tokens(text),
+            vec![
+                ("macro".to_string(), Role::Node, "macro".to_string()),
+                ("F1 \"x\"".to_string(), Role::Argument, "macro".to_string()),
+                ("Ctrl+Shift+Q".to_string(), Role::Argument, "macro".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scan_unterminated() {
+        // A document being typed ends inside a string
+        let typed = scan("remap {\n    A \"Esc");
+        assert_eq!(typed.parents, vec!["remap"]);
+        assert_eq!(typed.node.as_deref(), Some("A"));
+        let last = typed.tokens.last().expect("should have tokens");
+        assert_eq!(last.role, Role::Argument);
+        assert_eq!(last.value, 15..18);
+        assert!(last.quoted);
+
+        // A property whose value is not written yet
+        let property = scan("profile \"p\" app-id=");
+        let last = property.tokens.last().expect("should have tokens");
+        assert_eq!(last.role, Role::Property("app-id".to_string()));
+        assert!(last.value.is_empty());
+    }
+
+    #[test]
+    fn test_positions_count_utf16_units() {
+        // 'é' is two bytes and one UTF-16 unit, '😀' four bytes and two units
+        let text = "a\né😀b\nc";
+        let b = text.find('b').unwrap();
+        assert_eq!(position_at(text, b), json!({ "line": 1, "character": 3 }));
+        assert_eq!(offset_at(text, &json!({ "line": 1, "character": 3 })), b);
+        assert_eq!(offset_at(text, &json!({ "line": 1, "character": 1 })), text.find('😀').unwrap());
+        assert_eq!(position_at(text, text.len()), json!({ "line": 2, "character": 1 }));
+
+        // Every character boundary maps back to itself
+        for (offset, _) in text.char_indices() {
+            assert_eq!(offset_at(text, &position_at(text, offset)), offset);
+        }
+    }
+
+    #[test]
+    fn test_offset_past_the_end() {
+        let text = "ab\ncd";
+        // Past the end of a line is the end of the line
+        assert_eq!(offset_at(text, &json!({ "line": 0, "character": 10 })), 2);
+        // Past the last line is the end of the text
+        assert_eq!(offset_at(text, &json!({ "line": 5, "character": 0 })), text.len());
+    }
+
+    #[test]
+    fn test_uri_to_path() {
+        assert_eq!(uri_to_path("file:///home/me/config.kdl"), Some(PathBuf::from("/home/me/config.kdl")));
+        assert_eq!(
+            uri_to_path("file:///home/me/My%20Config/caf%C3%A9.kdl"),
+            Some(PathBuf::from("/home/me/My Config/café.kdl"))
+        );
+        // A `%` without two hex digits is kept as is
+        assert_eq!(uri_to_path("file:///tmp/100%.kdl"), Some(PathBuf::from("/tmp/100%.kdl")));
+        assert_eq!(uri_to_path("file:///tmp/%zz.kdl"), Some(PathBuf::from("/tmp/%zz.kdl")));
+        // Invalid UTF-8 has no path
+        assert_eq!(uri_to_path("file:///tmp/%FF.kdl"), None);
+        assert_eq!(uri_to_path("untitled:Untitled-1"), None);
+    }
+
+    /// Diagnostics of a document loaded as a configuration file
+    fn diagnostics_of(text: &str) -> Vec<Value> {
+        let path = std::env::temp_dir().join("niri-mapper-lsp-test").join("config.kdl");
+        let file = path.display().to_string();
+        let host = niri_mapper_config::HostContext::current();
+        let mut out = Vec::new();
+        match niri_mapper_config::parse_config_source_with_warnings(text, &path, &host, false) {
+            Ok((_, warnings)) => {
+                for warning in &warnings {
+                    push_diagnostics(&mut out, text, &file, warning);
+                }
+            }
+            Err(e) => push_diagnostics(&mut out, text, &file, &e),
+        }
+        out
+    }
+
+    #[test]
+    fn test_diagnostic_ranges() {
+        let text = "device \"Kbd 😀\" {\n\
+                profile \"default\" {\n        remap { /* é */ CapsLock \"Escap\"; }\n    }\n}\n";
+        let diagnostics = diagnostics_of(text);
+        let error = diagnostics
+            .iter()
+            .find(|diagnostic| diagnostic["severity"] == 1)
+            .expect("should report the unknown key");
+        let start = text.find("\"Escap\"").unwrap();
+        assert_eq!(error["range"]["start"], position_at(text, start));
+        assert_eq!(error["range"]["start"], json!({ "line": 2, "character": 33 }));
+        assert_eq!(error["range"]["end"], json!({ "line": 2, "character": 40 }));
+        assert_eq!(error["source"], "niri-mapper");
+    }
+
+    #[test]
+    fn test_diagnostic_per_label() {
+        let text = "device \"Kbd\" {\n\
+                profile \"default\" {\n        remap { Foo \"Escape\"; A \"Bar\"; }\n    }\n}\n";
+        let errors: Vec<Value> =
+            diagnostics_of(text).into_iter().filter(|diagnostic| diagnostic["severity"] == 1).collect();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0]["range"]["start"], position_at(text, text.find("Foo").unwrap()));
+        assert_eq!(errors[1]["range"]["start"], position_at(text, text.find("\"Bar\"").unwrap()));
+    }
+
+}
@@ -2,6 +2,8 @@
 //!
 //! Control and configuration tool for niri-mapper.
 
+mod lsp;
+
 use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
@@ -24,14 +26,88 @@ struct Cli {
 enum Commands {
     /// Validate the configuration file
     Validate {
-        /// Also enumerate devices and check which configured devices exist (read-only)
+        /// Also enumerate devices, check which configured devices exist and print
+        /// the effective rules of the ones found (read-only)
         #[arg(long)]
         dry_run: bool,
+
+        /// Print the effective profile with this name, with inherited rules merged in
+        #[arg(long, value_name = "NAME")]
+        profile: Option<String>,
+
+        /// Evaluate `when hostname=...` guards as if running on this host
+        #[arg(long, value_name = "HOSTNAME")]
+        host: Option<String>,
+
+        /// Evaluate `when env=...` guards as if this variable were set (repeatable)
+        #[arg(long, value_name = "NAME=VALUE")]
+        env: Vec<String>,
+
+        /// Fail if any lint is reported, e.g. in CI
+        #[arg(long)]
+        deny_warnings: bool,
+
+        /// Report unknown nodes and properties as warnings instead of errors,
+        /// as the daemon does
+        #[arg(long)]
+        no_strict: bool,
+    },
+
+    /// Print the resolved configuration or its JSON Schema
+    ///
+    /// Examples:
+    ///   niri-mapper config dump --json
+    ///   niri-mapper config schema > niri-mapper.schema.json
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+
+    /// Canonicalize configuration files in place
+    ///
+    /// Key names and combos are written the way niri-mapper displays them,
+    /// and the entries of `remap`, `combo`, `macro` and `profile-switch` blocks
+    /// are sorted by key. Comments are kept.
+    ///
+    /// Examples:
+    ///   niri-mapper fmt
+    ///   niri-mapper fmt --check ~/.config/niri-mapper/config.d/*.kdl
+    Fmt {
+        /// Files to format (default: the configuration file)
+        files: Vec<PathBuf>,
+
+        /// Only report files that would change, and fail if any would
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// Rewrite configuration files written for an older configuration version
+    ///
+    /// For example, `app-id-hint "firefox"` becomes a `match` rule. Files are
+    /// marked with the version they were migrated to.
+    ///
+    /// Examples:
+    ///   niri-mapper migrate
+    ///   niri-mapper migrate --check
+    Migrate {
+        /// Files to migrate (default: the configuration file)
+        files: Vec<PathBuf>,
+
+        /// Only report files that would change, and fail if any would
+        #[arg(long)]
+        check: bool,
     },
 
     /// List available input devices
     Devices,
 
+    /// Run a language server for the configuration over stdio
+    ///
+    /// Editors get diagnostics while a file is edited, completion of node
+    /// names, keys, device names and the app IDs of open windows, and hover
+    /// documentation for keys and macro actions.
+    Lsp,
+
     /// Generate niri keybinds KDL file
     Generate {
         /// Output path (overrides config setting)
@@ -103,6 +179,67 @@ enum Commands {
         profile: String,
     },
 
+    /// Abort all running macros
+    ///
+    /// Stops every macro that is currently playing and drops any queued runs.
+    /// The daemon must be running for this command to work.
+    ///
+    /// Examples:
+    ///   niri-mapper abort-macros
+    #[command(name = "abort-macros")]
+    AbortMacros,
+
+    /// Record a macro from a device
+    ///
+    /// The daemon records the keys typed on the device (after remapping), with
+    /// their delays, until the stop key is pressed. Recordings can be played
+    /// with `run-macro` and optionally written to the config file with --bind.
+    ///
+    /// Examples:
+    ///   niri-mapper record-macro "Keychron K3 Pro" greeting
+    ///   niri-mapper record-macro "Keychron K3 Pro" greeting --quantize 10 --bind F5
+    #[command(name = "record-macro")]
+    RecordMacro {
+        /// Name of the device (as configured in config.kdl)
+        device: String,
+
+        /// Name to store the recording under
+        name: String,
+
+        /// Key that stops the recording (default: macro-record-key, or Escape)
+        #[arg(long)]
+        stop_key: Option<String>,
+
+        /// Round delays to this many milliseconds (default: macro-record-quantize)
+        #[arg(long)]
+        quantize: Option<u64>,
+
+        /// Write the recording to the config file as a macro on this trigger key
+        #[arg(long)]
+        bind: Option<String>,
+
+        /// Profile to write the macro to (default: the device's active profile)
+        #[arg(long, requires = "bind")]
+        profile: Option<String>,
+    },
+
+    /// Play a named macro from the config's `macros` library, or a recording
+    ///
+    /// Parameters of library macros are given as PARAM=VALUE pairs.
+    ///
+    /// Examples:
+    ///   niri-mapper run-macro greeting
+    ///   niri-mapper run-macro switch-tab n=3
+    #[command(name = "run-macro")]
+    RunMacro {
+        /// Name of the library macro or recording
+        name: String,
+
+        /// Parameter values as PARAM=VALUE
+        #[arg(value_parser = parse_macro_arg)]
+        args: Vec<(String, String)>,
+    },
+
     /// Query niri compositor state
     ///
     /// Connects to the niri IPC socket and queries the current focused window
@@ -119,6 +256,28 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the configuration with includes, templates and inheritance applied
+    Dump {
+        /// Print the whole configuration as JSON, as described by `config schema`,
+        /// instead of the effective profiles of each device
+        #[arg(long)]
+        json: bool,
+
+        /// Evaluate `when hostname=...` guards as if running on this host
+        #[arg(long, value_name = "HOSTNAME")]
+        host: Option<String>,
+
+        /// Evaluate `when env=...` guards as if this variable were set (repeatable)
+        #[arg(long, value_name = "NAME=VALUE")]
+        env: Vec<String>,
+    },
+
+    /// Print the JSON Schema of `config dump --json`
+    Schema,
+}
+
 fn main() -> miette::Result<()> {
     // Initialize tracing
     tracing_subscriber::fmt()
@@ -126,6 +285,8 @@ fn main() -> miette::Result<()> {
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn")),
         )
+        // Keep stdout for command output, which `lsp` uses for the protocol
+        .with_writer(std::io::stderr)
         .init();
 
     let cli = Cli::parse();
@@ -134,8 +295,28 @@ fn main() -> miette::Result<()> {
     let config_path: PathBuf = shellexpand::tilde(&cli.config).into_owned().into();
 
     match cli.command {
-        Commands::Validate { dry_run } => cmd_validate(&config_path, dry_run),
+        Commands::Validate {
+            dry_run,
+            profile,
+            host,
+            env,
+            deny_warnings,
+            no_strict,
+        } => {
+            let host = host_context(host, &env)?;
+            cmd_validate(&config_path, dry_run, profile.as_deref(), &host, deny_warnings, !no_strict)
+        }
+        Commands::Config { command } => match command {
+            ConfigCommand::Dump { json, host, env } => {
+                let host = host_context(host, &env)?;
+                cmd_config_dump(&config_path, json, &host)
+            }
+            ConfigCommand::Schema => cmd_config_schema(),
+        },
+        Commands::Fmt { files, check } => cmd_fmt(&files_or_config(files, config_path), check),
+        Commands::Migrate { files, check } => cmd_migrate(&files_or_config(files, config_path), check),
         Commands::Devices => cmd_devices(),
+        Commands::Lsp => lsp::run(),
         Commands::Generate { output } => cmd_generate(&config_path, output),
         Commands::Status => cmd_status(),
         Commands::Start => cmd_start(),
@@ -161,16 +342,70 @@ fn main() -> miette::Result<()> {
             }
         }
         Commands::SwitchProfile { device, profile } => cmd_switch_profile(&device, &profile),
+        Commands::AbortMacros => cmd_abort_macros(),
+        Commands::RecordMacro {
+            device,
+            name,
+            stop_key,
+            quantize,
+            bind,
+            profile,
+        } => cmd_record_macro(&device, &name, stop_key, quantize, bind, profile),
+        Commands::RunMacro { name, args } => cmd_run_macro(&name, &args),
         Commands::NiriStatus { json } => cmd_niri_status(json),
     }
 }
 
-fn cmd_validate(config_path: &PathBuf, dry_run: bool) -> miette::Result<()> {
+/// The current host, with the host name and environment variables overridden
+/// by `validate --host` and `--env`
+fn host_context(
+    hostname: Option<String>,
+    env: &[String],
+) -> miette::Result<niri_mapper_config::HostContext> {
+    let mut host = niri_mapper_config::HostContext::current();
+    if let Some(hostname) = hostname {
+        host.hostname = Some(hostname);
+    }
+    for var in env {
+        let (name, value) = var
+            .split_once('=')
+            .ok_or_else(|| miette::miette!("Invalid --env '{}': expected NAME=VALUE", var))?;
+        host.env.insert(name.to_string(), value.to_string());
+    }
+    Ok(host)
+}
+
+fn cmd_validate(
+    config_path: &PathBuf,
+    dry_run: bool,
+    profile: Option<&str>,
+    host: &niri_mapper_config::HostContext,
+    deny_warnings: bool,
+    strict: bool,
+) -> miette::Result<()> {
     println!("Validating configuration: {}", config_path.display());
+    if let Some(hostname) = &host.hostname {
+        println!("Host: {}", hostname);
+    }
+
+    match niri_mapper_config::parse_config_with_warnings(config_path, host, strict) {
+        Ok((config, warnings)) => {
+            // Shown like errors, in their source context
+            for warning in &warnings {
+                eprintln!("{:?}", miette::Report::new(warning.clone()));
+            }
+            if deny_warnings && !warnings.is_empty() {
+                return Err(miette::miette!(
+                    help = "Fix the warnings, or allow them with an (allow:<code>) annotation or `allow \"<code>\"` in the global block",
+                    "Found {} warning(s), which --deny-warnings turns into errors",
+                    warnings.len()
+                ));
+            }
 
-    match niri_mapper_config::parse_config(config_path) {
-        Ok(config) => {
             println!("Configuration is valid!");
+            if !warnings.is_empty() {
+                println!("  Warnings: {}", warnings.len());
+            }
             println!("  Devices: {}", config.devices.len());
             for device in &config.devices {
                 println!(
@@ -180,6 +415,10 @@ fn cmd_validate(config_path: &PathBuf, dry_run: bool) -> miette::Result<()> {
                 );
             }
 
+            if let Some(profile_name) = profile {
+                print_effective_profile(&config, profile_name)?;
+            }
+
             if dry_run {
                 println!("\nDry run: checking device availability...");
                 check_device_availability(&config)?;
@@ -187,8 +426,128 @@ fn cmd_validate(config_path: &PathBuf, dry_run: bool) -> miette::Result<()> {
 
             Ok(())
         }
-        Err(e) => Err(miette::miette!("{}", e)),
+        // Reported as a diagnostic, showing every error in its source context
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Print the resolved configuration, as JSON or as each device's effective profiles
+fn cmd_config_dump(
+    config_path: &std::path::Path,
+    json: bool,
+    host: &niri_mapper_config::HostContext,
+) -> miette::Result<()> {
+    let config = niri_mapper_config::parse_config_for_host(config_path, host)?;
+
+    if json {
+        let output = serde_json::to_string_pretty(&config)
+            .map_err(|e| miette::miette!("Failed to serialize configuration: {}", e))?;
+        println!("{}", output);
+        return Ok(());
+    }
+
+    for device in &config.devices {
+        println!("device \"{}\"", device.name.as_deref().unwrap_or("<unnamed>"));
+        print_device_profiles(device);
+    }
+    Ok(())
+}
+
+/// Print the JSON Schema of the configuration as `config dump --json` prints it
+fn cmd_config_schema() -> miette::Result<()> {
+    let schema = niri_mapper_config::Config::json_schema();
+    let output = serde_json::to_string_pretty(&schema)
+        .map_err(|e| miette::miette!("Failed to serialize schema: {}", e))?;
+    println!("{}", output);
+    Ok(())
+}
+
+/// The files given on the command line, or the configuration file
+fn files_or_config(files: Vec<PathBuf>, config_path: PathBuf) -> Vec<PathBuf> {
+    if files.is_empty() {
+        vec![config_path]
+    } else {
+        files
+    }
+}
+
+/// Format configuration files, or with `check` fail if any would change
+fn cmd_fmt(files: &[PathBuf], check: bool) -> miette::Result<()> {
+    let mut changed = 0;
+    for path in files {
+        if niri_mapper_config::format_file(path, check)? {
+            changed += 1;
+            let verb = if check { "Would reformat" } else { "Reformatted" };
+            println!("{} {}", verb, path.display());
+        }
+    }
+
+    if check && changed > 0 {
+        return Err(miette::miette!(
+            "{} file(s) would be reformatted; run `niri-mapper fmt` to format them",
+            changed
+        ));
+    }
+    Ok(())
+}
+
+/// Migrate configuration files, or with `check` fail if any would change
+fn cmd_migrate(files: &[PathBuf], check: bool) -> miette::Result<()> {
+    let mut changed = 0;
+    for path in files {
+        let migration = niri_mapper_config::migrate_file(path, check)?;
+        for kept in &migration.kept {
+            eprintln!("Warning: {}: {}", path.display(), kept);
+        }
+        if migration.changes.is_empty() {
+            continue;
+        }
+        changed += 1;
+        let verb = if check { "Would migrate" } else { "Migrated" };
+        println!("{} {}:", verb, path.display());
+        for change in &migration.changes {
+            println!("  {}", change);
+        }
+    }
+
+    if check && changed > 0 {
+        return Err(miette::miette!(
+            "{} file(s) would be migrated to configuration version {}; run `niri-mapper migrate`",
+            changed,
+            niri_mapper_config::CONFIG_VERSION
+        ));
+    }
+    if changed == 0 {
+        println!("Already at configuration version {}", niri_mapper_config::CONFIG_VERSION);
+    }
+    Ok(())
+}
+
+/// Print a profile of every device that has it, after `extends` is resolved
+fn print_effective_profile(
+    config: &niri_mapper_config::Config,
+    profile_name: &str,
+) -> miette::Result<()> {
+    let mut found = false;
+    for device in &config.devices {
+        if let Some(profile) = device.profiles.get(profile_name) {
+            found = true;
+            println!(
+                "\nEffective profile '{}' of device '{}':",
+                profile_name,
+                device.name.as_deref().unwrap_or("<unnamed>")
+            );
+            print!("{}", niri_mapper_config::format_profile(profile_name, profile));
+        }
     }
+
+    if !found {
+        return Err(miette::miette!(
+            "No device has a profile named '{}'",
+            profile_name
+        ));
+    }
+    Ok(())
 }
 
 /// Information about a system device for matching
@@ -266,6 +625,7 @@ fn check_device_availability(config: &niri_mapper_config::Config) -> miette::Res
                         sys_dev.name,
                         sys_dev.vendor_product()
                     );
+                    print_device_profiles(device_config);
                     found_count += 1;
                 }
                 None => {
@@ -294,22 +654,36 @@ fn check_device_availability(config: &niri_mapper_config::Config) -> miette::Res
     Ok(())
 }
 
+/// Print the effective profiles of a device, with global and inherited rules
+/// merged in, starting with the default profile
+fn print_device_profiles(device: &niri_mapper_config::DeviceConfig) {
+    let mut names: Vec<&String> = device.profiles.keys().collect();
+    names.sort_by_key(|name| (name.as_str() != "default", name.as_str()));
+
+    for name in names {
+        let formatted = niri_mapper_config::format_profile(name, &device.profiles[name]);
+        for line in formatted.lines() {
+            println!("      {}", line);
+        }
+    }
+}
+
 /// Represents a detected input device with its properties
-struct DetectedDevice {
-    name: String,
-    device_type: DeviceType,
+pub(crate) struct DetectedDevice {
+    pub(crate) name: String,
+    pub(crate) device_type: DeviceType,
 }
 
 /// Type of input device
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
-enum DeviceType {
+pub(crate) enum DeviceType {
     Keyboard,
     Mouse,
     Other,
 }
 
 impl DeviceType {
-    fn as_tag(&self) -> Option<&'static str> {
+    pub(crate) fn as_tag(&self) -> Option<&'static str> {
         match self {
             DeviceType::Keyboard => Some("[keyboard]"),
             DeviceType::Mouse => Some("[mouse]"),
@@ -319,6 +693,21 @@ impl DeviceType {
 }
 
 fn cmd_devices() -> miette::Result<()> {
+    let devices = detect_devices()?;
+
+    println!("Available input devices:");
+    for device in &devices {
+        match device.device_type.as_tag() {
+            Some(tag) => println!("  \"{}\" {}", device.name, tag),
+            None => println!("  \"{}\"", device.name),
+        }
+    }
+
+    Ok(())
+}
+
+/// Input devices under /dev/input that can be opened, sorted by name
+pub(crate) fn detect_devices() -> miette::Result<Vec<DetectedDevice>> {
     let mut devices: Vec<DetectedDevice> = Vec::new();
 
     for entry in std::fs::read_dir("/dev/input").into_diagnostic()? {
@@ -379,20 +768,12 @@ fn cmd_devices() -> miette::Result<()> {
     // Remove duplicates (same device name)
     devices.dedup_by(|a, b| a.name == b.name);
 
-    println!("Available input devices:");
-    for device in &devices {
-        match device.device_type.as_tag() {
-            Some(tag) => println!("  \"{}\" {}", device.name, tag),
-            None => println!("  \"{}\"", device.name),
-        }
-    }
-
-    Ok(())
+    Ok(devices)
 }
 
 fn cmd_generate(config_path: &PathBuf, output: Option<PathBuf>) -> miette::Result<()> {
     let mut config =
-        niri_mapper_config::parse_config(config_path).map_err(|e| miette::miette!("{}", e))?;
+        niri_mapper_config::parse_config(config_path)?;
 
     if let Some(output_path) = output {
         config.global.niri_keybinds_path = output_path;
@@ -978,6 +1359,156 @@ fn cmd_switch_profile(device: &str, profile: &str) -> miette::Result<()> {
     }
 }
 
+/// Abort all running macros.
+///
+/// Sends an `abort_macros` request to the daemon via IPC.
+fn cmd_abort_macros() -> miette::Result<()> {
+    let message = send_daemon_request(&serde_json::json!({ "type": "abort_macros" }))
+        .map_err(|e| miette::miette!("Abort failed: {}", e))?;
+    println!("{}", message.unwrap_or_else(|| "Aborted running macros.".to_string()));
+    Ok(())
+}
+
+/// Start recording a macro on a device.
+///
+/// The daemon records the device's processed key stream until the stop key
+/// is pressed. The recording can then be played with `run-macro`.
+///
+/// # Arguments
+/// * `device` - Name of the device as configured in config.kdl
+/// * `name` - Name to store the recording under
+/// * `stop_key` - Key that stops the recording (daemon default if None)
+/// * `quantize` - Round delays to this many milliseconds (daemon default if None)
+/// * `bind` - Trigger key to write the macro to the config file under
+/// * `profile` - Profile to write the macro to (active profile if None)
+fn cmd_record_macro(
+    device: &str,
+    name: &str,
+    stop_key: Option<String>,
+    quantize: Option<u64>,
+    bind: Option<String>,
+    profile: Option<String>,
+) -> miette::Result<()> {
+    let mut request = serde_json::json!({
+        "type": "record_macro",
+        "device": device,
+        "name": name,
+    });
+    if let Some(stop_key) = stop_key {
+        request["stop_key"] = stop_key.into();
+    }
+    if let Some(quantize) = quantize {
+        request["quantize_ms"] = quantize.into();
+    }
+    if let Some(bind) = bind {
+        request["bind"] = bind.into();
+    }
+    if let Some(profile) = profile {
+        request["profile"] = profile.into();
+    }
+
+    let message = send_daemon_request(&request)
+        .map_err(|e| miette::miette!("Recording failed: {}", e))?;
+    println!("{}", message.unwrap_or_else(|| format!("Recording macro '{}'.", name)));
+    Ok(())
+}
+
+/// Play a library macro or recording.
+///
+/// # Arguments
+/// * `name` - Name of the library macro or recording
+/// * `args` - Parameter values for a library macro
+fn cmd_run_macro(name: &str, args: &[(String, String)]) -> miette::Result<()> {
+    let args: serde_json::Map<String, serde_json::Value> = args
+        .iter()
+        .map(|(param, value)| (param.clone(), serde_json::Value::from(value.as_str())))
+        .collect();
+    let message = send_daemon_request(
+        &serde_json::json!({ "type": "run_macro", "name": name, "args": args }),
+    )
+    .map_err(|e| miette::miette!("Running macro failed: {}", e))?;
+    println!("{}", message.unwrap_or_else(|| format!("Started macro '{}'.", name)));
+    Ok(())
+}
+
+/// Parse a `PARAM=VALUE` macro argument.
+fn parse_macro_arg(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((param, value)) if !param.is_empty() => Ok((param.to_string(), value.to_string())),
+        _ => Err(format!("expected PARAM=VALUE, got '{}'", arg)),
+    }
+}
+
+/// Send a request to the daemon via IPC and wait for a success or error response.
+///
+/// Returns the optional message of a success response, or the error message
+/// of an error response.
+fn send_daemon_request(request: &serde_json::Value) -> miette::Result<Option<String>> {
+    use serde::Deserialize;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    // IPC response message format (matches daemon's IpcResponse)
+    #[derive(Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum IpcResponse {
+        Success {
+            #[serde(default)]
+            message: Option<String>,
+        },
+        Error {
+            message: String,
+        },
+        #[serde(other)]
+        Unknown,
+    }
+
+    // Determine socket path
+    let socket_path = if let Ok(runtime_dir) = std::env::var("XDG_RUNTIME_DIR") {
+        std::path::PathBuf::from(runtime_dir).join("niri-mapper.sock")
+    } else {
+        let uid = unsafe { nix::libc::getuid() };
+        std::path::PathBuf::from(format!("/tmp/niri-mapper-{}.sock", uid))
+    };
+
+    // Connect to the daemon
+    let mut stream = UnixStream::connect(&socket_path).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound
+            || e.kind() == std::io::ErrorKind::ConnectionRefused
+        {
+            miette::miette!(
+                "Cannot connect to niri-mapper daemon.\n\
+                 Is the daemon running? Check with: niri-mapper status"
+            )
+        } else {
+            miette::miette!("Failed to connect to daemon: {}", e)
+        }
+    })?;
+
+    writeln!(stream, "{}", request)
+        .map_err(|e| miette::miette!("Failed to send request to daemon: {}", e))?;
+
+    stream
+        .flush()
+        .map_err(|e| miette::miette!("Failed to flush request: {}", e))?;
+
+    // Read the response
+    let mut reader = BufReader::new(stream);
+    let mut response_line = String::new();
+    reader
+        .read_line(&mut response_line)
+        .map_err(|e| miette::miette!("Failed to read response from daemon: {}", e))?;
+
+    let response: IpcResponse = serde_json::from_str(response_line.trim())
+        .map_err(|e| miette::miette!("Failed to parse daemon response: {}", e))?;
+
+    match response {
+        IpcResponse::Success { message } => Ok(message),
+        IpcResponse::Error { message } => Err(miette::miette!("{}", message)),
+        IpcResponse::Unknown => Err(miette::miette!("Unexpected response from daemon")),
+    }
+}
+
 /// Query niri compositor state (focused window and workspaces).
 ///
 /// Connects to the niri IPC socket and queries current state.
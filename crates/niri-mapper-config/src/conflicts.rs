@@ -0,0 +1,752 @@
+//! Conflict and shadowing analysis of resolved rules
+//!
+//! Rules are compared by their resolved keys, so aliases (`Esc` and
+//! `Escape`) and modifier order (`Ctrl+Shift+A` and `Shift+Ctrl+A`) cannot
+//! hide a conflict. The analysis follows the daemon's processing order:
+//!
+//! 1. `profile-switch` keybinds, matched with exactly the modifiers held
+//! 2. Conditional rules whose `when` conditions match
+//! 3. Macros, triggered by their key with any modifiers held
+//! 4. Combos
+//! 5. Remaps
+//!
+//! Anything not consumed by these reaches niri, so `niri-passthrough`
+//! keybinds whose keys a rule intercepts never fire.
+//!
+//! A rule that an earlier step always takes over is reported as an error,
+//! with the locations of both rules. Remap chains (`A "B"` with `B "C"`)
+//! are not errors, as remaps are not applied to each other's output and
+//! swapping two keys is common, but are reported as the `remap-chain` lint.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::error::{ConfigError, ErrorSpan, Errors, SourceFile, SourceLocation};
+use crate::lints::{Lint, Lints};
+use crate::model::*;
+
+/// Where a rule is written
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RuleSite<'a> {
+    /// Source code of the file the rule is in
+    pub source: &'a str,
+    /// Name of the file, if not named by the caller
+    pub file: Option<&'a str>,
+    /// Index of the file among the loaded files, once known
+    pub file_index: Option<usize>,
+    /// Location of the rule's key
+    pub location: SourceLocation,
+}
+
+impl<'a> RuleSite<'a> {
+    pub fn new(source: &'a str, location: SourceLocation) -> Self {
+        Self { source, file: None, file_index: None, location }
+    }
+
+    /// The site labelled for an error
+    fn span(&self, label: impl Into<String>, help: impl Into<String>) -> ErrorSpan {
+        ErrorSpan {
+            src: SourceFile {
+                name: self.file.map(str::to_string),
+                content: self.source.to_string(),
+            },
+            location: self.location,
+            label: label.into(),
+            help: help.into(),
+        }
+    }
+
+    /// Identifies the site among the loaded files: the file's index and the
+    /// offset in it
+    fn id(&self) -> SiteId {
+        (self.file_index, self.location.offset)
+    }
+}
+
+/// The file index and offset of a [`RuleSite`]
+type SiteId = (Option<usize>, usize);
+
+/// An error about `rule`, which conflicts with the rule at `other`
+pub(crate) fn conflict(
+    rule: (&RuleSite, &str),
+    other: (&RuleSite, &str),
+    message: impl Into<String>,
+    help: &str,
+) -> ConfigError {
+    ConfigError::Conflict {
+        message: message.into(),
+        rule: Box::new(rule.0.span(rule.1, help)),
+        other: Box::new(other.0.span(other.1, "")),
+    }
+}
+
+/// An error about two entries of a block whose keys, written at `first` and
+/// `second`, resolve to the same key
+pub(crate) fn duplicate_entry(
+    source: &str,
+    first: SourceLocation,
+    second: SourceLocation,
+    block: &str,
+    resolved: &impl std::fmt::Display,
+) -> ConfigError {
+    let written = |location: SourceLocation| {
+        source[location.offset..location.offset + location.len].trim_matches('"')
+    };
+    let (first_text, second_text) = (written(first), written(second));
+    let (first, second) = (RuleSite::new(source, first), RuleSite::new(source, second));
+    let message = if first_text == second_text {
+        format!("Duplicate {} entry '{}'", block, resolved)
+    } else {
+        format!(
+            "Duplicate {} entry '{}': '{}' and '{}' are the same {}",
+            block,
+            resolved,
+            first_text,
+            second_text,
+            if block == "combo" || block == "profile-switch" { "combo" } else { "key" }
+        )
+    };
+    conflict(
+        (&second, "duplicate entry"),
+        (&first, "first defined here"),
+        message,
+        "Each key can only have one entry per block; remove one of them or add `when` conditions",
+    )
+}
+
+/// Where each rule of a profile is written
+///
+/// Merged the same way as the rules themselves when profiles extend each
+/// other, so the site of every effective rule can be looked up by its key.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RuleSites<'a> {
+    pub remap: HashMap<KeyCode, RuleSite<'a>>,
+    pub combo: HashMap<KeyCombo, RuleSite<'a>>,
+    pub macros: HashMap<KeyCode, RuleSite<'a>>,
+    /// Sites of the conditional rules, in the order of [`Profile::conditional_rules`]
+    pub conditional: Vec<RuleSite<'a>>,
+    /// Sites of the `niri-passthrough` keybinds, keyed as written
+    pub niri_passthrough: HashMap<String, RuleSite<'a>>,
+}
+
+impl<'a> RuleSites<'a> {
+    /// Sites of a profile whose rules are merged over `parent`'s
+    pub fn merged(parent: &Self, child: Self) -> Self {
+        let mut sites = parent.clone();
+        sites.remap.extend(child.remap);
+        sites.combo.extend(child.combo);
+        sites.macros.extend(child.macros);
+        sites.niri_passthrough.extend(child.niri_passthrough);
+        // Conditional rules of the child come first, as in `merge_profile`
+        sites.conditional = child.conditional;
+        sites.conditional.extend(parent.conditional.iter().copied());
+        sites
+    }
+
+    /// Set the file of the sites that are not in one yet: its index among
+    /// the loaded files and its name
+    pub fn in_file(mut self, index: usize, name: Option<&'a str>) -> Self {
+        let sites = self
+            .remap
+            .values_mut()
+            .chain(self.combo.values_mut())
+            .chain(self.macros.values_mut())
+            .chain(self.conditional.iter_mut())
+            .chain(self.niri_passthrough.values_mut());
+        for site in sites {
+            site.file_index = site.file_index.or(Some(index));
+            site.file = site.file.or(name);
+        }
+        self
+    }
+}
+
+/// A rule as seen by the analysis: what triggers it and where it is written
+struct Rule<'s, 'a> {
+    kind: RuleKind,
+    site: &'s RuleSite<'a>,
+    /// Whether the rule only applies under `when` conditions
+    conditional: bool,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+enum RuleKind {
+    Remap(KeyCode, KeyCode),
+    Combo(KeyCombo),
+    Macro(KeyCode),
+}
+
+impl RuleKind {
+    /// Whether pressing `combo` triggers the rule
+    fn triggered_by(&self, combo: &KeyCombo) -> bool {
+        match self {
+            Self::Remap(from, _) => *from == combo.key,
+            Self::Combo(trigger) => trigger == combo,
+            Self::Macro(trigger) => *trigger == combo.key,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Remap(from, to) => format!("remap '{}' -> '{}'", from, to),
+            Self::Combo(combo) => format!("combo '{}'", combo),
+            Self::Macro(trigger) => format!("macro on '{}'", trigger),
+        }
+    }
+}
+
+/// Finds rules that shadow each other, reporting each conflict once even if
+/// the rules are inherited by several profiles
+#[derive(Debug, Default)]
+pub(crate) struct ConflictChecker {
+    /// Site pairs already reported
+    reported: HashSet<(SiteId, SiteId)>,
+}
+
+impl ConflictChecker {
+    /// Add an error about `rule` conflicting with `other`, unless reported already
+    fn report(
+        &mut self,
+        errors: &mut Errors,
+        rule: (&RuleSite, &str),
+        other: (&RuleSite, &str),
+        message: String,
+        help: &str,
+    ) {
+        if self.reported.insert((rule.0.id(), other.0.id())) {
+            errors.push(conflict(rule, other, message, help));
+        }
+    }
+
+    /// The rules of a profile, with their sites
+    ///
+    /// Rules without a site (from a block that failed to parse) are left out.
+    fn rules<'s, 'a>(profile: &Profile, sites: &'s RuleSites<'a>) -> Vec<Rule<'s, 'a>> {
+        let mut rules = Vec::new();
+        for (from, to) in &profile.remap {
+            if let Some(site) = sites.remap.get(from) {
+                rules.push(Rule { kind: RuleKind::Remap(*from, *to), site, conditional: false });
+            }
+        }
+        for combo in profile.combo.keys() {
+            if let Some(site) = sites.combo.get(combo) {
+                rules.push(Rule { kind: RuleKind::Combo(combo.clone()), site, conditional: false });
+            }
+        }
+        for trigger in profile.macros.keys() {
+            if let Some(site) = sites.macros.get(trigger) {
+                rules.push(Rule { kind: RuleKind::Macro(*trigger), site, conditional: false });
+            }
+        }
+        for (rule, site) in profile.conditional_rules.iter().zip(&sites.conditional) {
+            let kind = match &rule.entry {
+                ConditionalEntry::Remap { from, to } => RuleKind::Remap(*from, *to),
+                ConditionalEntry::Combo { from, .. } => RuleKind::Combo(from.clone()),
+                ConditionalEntry::Macro { trigger, .. } => RuleKind::Macro(*trigger),
+            };
+            rules.push(Rule { kind, site, conditional: true });
+        }
+        // Sorted by location, so errors are reported in a stable order
+        rules.sort_by_key(|rule| (rule.site.file, rule.site.location.offset));
+        rules
+    }
+
+    /// Check the rules of a profile against each other and its
+    /// `niri-passthrough` keybinds
+    pub fn check_profile(&mut self, profile: &Profile, sites: &RuleSites, errors: &mut Errors, lints: &mut Lints) {
+        let rules = Self::rules(profile, sites);
+        let unconditional = || rules.iter().filter(|rule| !rule.conditional);
+
+        for rule in unconditional() {
+            let RuleKind::Macro(trigger) = rule.kind else {
+                continue;
+            };
+            for other in unconditional() {
+                match &other.kind {
+                    RuleKind::Remap(from, _) if *from == trigger => self.report(
+                        errors,
+                        (other.site, "never applies"),
+                        (rule.site, "macro trigger"),
+                        format!(
+                            "Macro trigger key '{}' conflicts with remap source key '{}'",
+                            trigger, from
+                        ),
+                        "A key either triggers a macro or is remapped; remove the macro or the remap entry",
+                    ),
+                    RuleKind::Combo(combo) if combo.key == trigger => self.report(
+                        errors,
+                        (other.site, "never applies"),
+                        (rule.site, "macro trigger"),
+                        format!(
+                            "Macro trigger key '{}' conflicts with combo trigger '{}' (base key '{}')",
+                            trigger, combo, combo.key
+                        ),
+                        "Macros trigger with any modifiers held; trigger the macro with another key, or remove the combo",
+                    ),
+                    _ => {}
+                }
+            }
+        }
+
+        // A combo without modifiers is matched before the remap of its key
+        for rule in unconditional() {
+            let RuleKind::Combo(combo) = &rule.kind else {
+                continue;
+            };
+            if !combo.modifiers.is_empty() {
+                continue;
+            }
+            for other in unconditional() {
+                if let RuleKind::Remap(from, _) = other.kind {
+                    if from == combo.key {
+                        self.report(
+                            errors,
+                            (other.site, "never applies"),
+                            (rule.site, "combo without modifiers"),
+                            format!("Remap of '{}' is shadowed by combo '{}'", from, combo),
+                            "A combo without modifiers replaces the key like a remap; remove one of them",
+                        );
+                    }
+                }
+            }
+        }
+
+        self.lint_remap_chains(&rules, lints);
+
+        for keybind in &profile.niri_passthrough {
+            let Some(site) = sites.niri_passthrough.get(&keybind.key) else {
+                continue;
+            };
+            // Keys niri understands but the registry does not are not checked
+            let Ok(combo) = keybind.key.parse::<KeyCombo>() else {
+                continue;
+            };
+            for rule in rules.iter().filter(|rule| rule.kind.triggered_by(&combo)) {
+                let when = if rule.conditional { " while its conditions match" } else { "" };
+                self.report(
+                    errors,
+                    (site, "never reaches niri"),
+                    (rule.site, "intercepted here"),
+                    format!(
+                        "niri-passthrough keybind '{}' is intercepted by {}{} before niri sees it",
+                        keybind.key,
+                        rule.kind.describe(),
+                        when
+                    ),
+                    "Bind the niri action to another key, or remove the rule on this key",
+                );
+            }
+        }
+    }
+
+    /// Lint remaps whose target is itself remapped or triggers a macro, which
+    /// remapped keys do not do
+    fn lint_remap_chains(&mut self, rules: &[Rule], lints: &mut Lints) {
+        for rule in rules {
+            let RuleKind::Remap(from, to) = rule.kind else {
+                continue;
+            };
+            // Swapping two keys is not a chain, even if one of them also has
+            // conditional remaps
+            let swapped = rules.iter().any(|other| other.kind == RuleKind::Remap(to, from));
+            for other in rules {
+                let chained = match other.kind {
+                    RuleKind::Remap(next, _) => next == to && !swapped,
+                    RuleKind::Macro(trigger) => trigger == to,
+                    RuleKind::Combo(_) => false,
+                };
+                if chained && self.reported.insert((rule.site.id(), other.site.id())) {
+                    let message = format!(
+                        "Remap '{}' -> '{}' emits '{}', which is not passed through {}",
+                        from,
+                        to,
+                        to,
+                        other.kind.describe(),
+                    );
+                    let span = rule.site.span(
+                        format!("emits '{}'", to),
+                        "Rules apply to physical keys only; remap to the final key directly",
+                    );
+                    let related = other.site.span(other.kind.describe(), "");
+                    lints.push_related(Lint::RemapChain, message, span, related);
+                }
+            }
+        }
+    }
+
+    /// Check a device's `profile-switch` keybinds against the rules of one
+    /// of its profiles
+    ///
+    /// Profile-switch keybinds are matched before any rule, with exactly
+    /// their modifiers held.
+    pub fn check_profile_switch(
+        &mut self,
+        switch_sites: &HashMap<KeyCombo, RuleSite>,
+        profile: &Profile,
+        sites: &RuleSites,
+        errors: &mut Errors,
+    ) {
+        let rules = Self::rules(profile, sites);
+        let mut keybinds: Vec<(&KeyCombo, &RuleSite)> = switch_sites.iter().collect();
+        keybinds.sort_by_key(|(_, site)| site.location.offset);
+
+        for (keybind, switch_site) in keybinds {
+            for rule in rules.iter().filter(|rule| rule.kind.triggered_by(keybind)) {
+                let (message, label) = match &rule.kind {
+                    RuleKind::Combo(_) => (
+                        format!("Combo '{}' is shadowed by the profile-switch keybind '{}'", keybind, keybind),
+                        "never applies",
+                    ),
+                    // Macros trigger with any modifiers, so only the keybind's are shadowed
+                    RuleKind::Macro(trigger) if !keybind.modifiers.is_empty() => (
+                        format!(
+                            "Macro on '{}' is shadowed by the profile-switch keybind '{}' while {} is held",
+                            trigger,
+                            keybind,
+                            keybind
+                                .modifiers
+                                .iter()
+                                .map(ToString::to_string)
+                                .collect::<Vec<_>>()
+                                .join("+")
+                        ),
+                        "shadowed with the keybind's modifiers",
+                    ),
+                    // A remap still applies while no modifiers are held
+                    RuleKind::Remap(..) if !keybind.modifiers.is_empty() => continue,
+                    kind => (
+                        format!(
+                            "{} is shadowed by the profile-switch keybind '{}'",
+                            capitalize(&kind.describe()),
+                            keybind
+                        ),
+                        "never applies",
+                    ),
+                };
+                self.report(
+                    errors,
+                    (rule.site, label),
+                    (switch_site, "profile-switch keybind"),
+                    message,
+                    "Profile-switch keybinds are matched before any rule; use another keybind or trigger key",
+                );
+            }
+
+            for keybind_pt in &profile.niri_passthrough {
+                let Some(site) = sites.niri_passthrough.get(&keybind_pt.key) else {
+                    continue;
+                };
+                if keybind_pt.key.parse::<KeyCombo>().ok().as_ref() == Some(keybind) {
+                    self.report(
+                        errors,
+                        (site, "never reaches niri"),
+                        (switch_site, "profile-switch keybind"),
+                        format!(
+                            "niri-passthrough keybind '{}' is intercepted by the profile-switch keybind '{}'",
+                            keybind_pt.key, keybind
+                        ),
+                        "Bind the niri action or the profile switch to another key",
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// `text` with its first letter in upper case
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use miette::Diagnostic;
+
+    use crate::error::ConfigError;
+    use crate::lints::Lint;
+    use crate::parser::{parse_config_str, parse_config_str_with_warnings, parse_config_with_warnings};
+    use crate::HostContext;
+
+    /// The conflicts in a configuration, as (message, text labelled at the
+    /// rule, text labelled at the other rule)
+    fn conflicts(config: &str) -> Vec<(String, String, String)> {
+        let errors = match parse_config_str(config) {
+            Ok(_) => return Vec::new(),
+            Err(ConfigError::Multiple { errors }) => errors,
+            Err(err) => vec![err],
+        };
+        errors
+            .iter()
+            .map(|error| {
+                assert!(matches!(error, ConfigError::Conflict { .. }), "{:?}", error);
+                let labels: Vec<_> = error.labels().expect("error has labels").collect();
+                let text = |i: usize| {
+                    let span = labels[i].inner();
+                    config[span.offset()..span.offset() + span.len()].to_string()
+                };
+                (error.to_string(), text(0), text(1))
+            })
+            .collect()
+    }
+
+    /// The messages of the `remap-chain` lints of a configuration
+    fn remap_chains(config: &str) -> Vec<String> {
+        let (_, warnings) = parse_config_str_with_warnings(config, &HostContext::current(), false).unwrap();
+        warnings
+            .into_iter()
+            .filter(|warning| warning.lint == Lint::RemapChain)
+            .map(|warning| warning.message)
+            .collect()
+    }
+
+    fn device(profile: &str) -> String {
+        format!("device \"Test Keyboard\" {{\n{}\n}}\n", profile)
+    }
+
+    #[test]
+    fn test_alias_collisions() {
+        let config = device(
+            r#"
+            profile "default" {
+                remap {
+                    Escape "CapsLock"
+                }
+                macro {
+                    Esc "A"
+                }
+            }
+            "#,
+        );
+        let found = conflicts(&config);
+        assert_eq!(found.len(), 1, "{:#?}", found);
+        assert!(found[0].0.starts_with("Macro trigger key 'Escape' conflicts with remap source key 'Escape'"));
+        assert_eq!((found[0].1.as_str(), found[0].2.as_str()), ("Escape", "Esc"));
+    }
+
+    #[test]
+    fn test_macro_shadows_combo_with_any_modifiers() {
+        let config = device(
+            r#"
+            profile "default" {
+                combo {
+                    Ctrl+Alt+F3 "F4"
+                }
+                macro {
+                    F3 "A"
+                }
+            }
+            "#,
+        );
+        let found = conflicts(&config);
+        assert_eq!(found.len(), 1, "{:#?}", found);
+        assert!(found[0].0.contains("conflicts with combo trigger 'Ctrl+Alt+F3'"), "{}", found[0].0);
+        assert_eq!((found[0].1.as_str(), found[0].2.as_str()), ("Ctrl+Alt+F3", "F3"));
+    }
+
+    #[test]
+    fn test_combo_without_modifiers_shadows_remap() {
+        let config = device(
+            r#"
+            profile "default" {
+                remap {
+                    CapsLock "Escape"
+                }
+                combo {
+                    Caps_Lock "LeftCtrl"
+                }
+            }
+            "#,
+        );
+        let found = conflicts(&config);
+        assert_eq!(found.len(), 1, "{:#?}", found);
+        assert!(found[0].0.starts_with("Remap of 'CapsLock' is shadowed by combo 'CapsLock'"), "{}", found[0].0);
+        assert_eq!((found[0].1.as_str(), found[0].2.as_str()), ("CapsLock", "Caps_Lock"));
+    }
+
+    #[test]
+    fn test_combos_with_modifiers_do_not_shadow_remaps() {
+        let config = device(
+            r#"
+            profile "default" {
+                remap {
+                    CapsLock "Escape"
+                }
+                combo {
+                    Shift+CapsLock "CapsLock"
+                }
+            }
+            "#,
+        );
+        assert_eq!(conflicts(&config), Vec::new());
+    }
+
+    #[test]
+    fn test_passthrough_intercepted() {
+        let config = device(
+            r#"
+            profile "default" {
+                remap {
+                    F1 "F2"
+                }
+                combo {
+                    Super+Shift+E "F3"
+                }
+                niri-passthrough {
+                    Mod+F1 {
+                        spawn "foot"
+                    }
+                    Shift+Mod+E {
+                        quit
+                    }
+                    Mod+T {
+                        spawn "foot"
+                    }
+                }
+            }
+            "#,
+        );
+        let found = conflicts(&config);
+        assert_eq!(found.len(), 2, "{:#?}", found);
+        assert!(found[0].0.starts_with("niri-passthrough keybind 'Mod+F1' is intercepted by remap 'F1' -> 'F2'"));
+        assert_eq!((found[0].1.as_str(), found[0].2.as_str()), ("Mod+F1", "F1"));
+        assert!(found[1].0.starts_with("niri-passthrough keybind 'Shift+Mod+E' is intercepted by combo"));
+        assert_eq!((found[1].1.as_str(), found[1].2.as_str()), ("Shift+Mod+E", "Super+Shift+E"));
+    }
+
+    #[test]
+    fn test_passthrough_intercepted_by_conditional_rule() {
+        let config = device(
+            r#"
+            profile "default" {
+                remap {
+                    F1 "F2" {
+                        when app-id="firefox"
+                    }
+                }
+                niri-passthrough {
+                    Mod+F1 {
+                        spawn "foot"
+                    }
+                }
+            }
+            "#,
+        );
+        let found = conflicts(&config);
+        assert_eq!(found.len(), 1, "{:#?}", found);
+        assert!(found[0].0.contains("while its conditions match"), "{}", found[0].0);
+    }
+
+    #[test]
+    fn test_profile_switch_shadows_rules() {
+        let config = device(
+            r#"
+            profile "default" {
+                remap {
+                    F9 "F10"
+                }
+                combo {
+                    Shift+Ctrl+F11 "F1"
+                }
+                macro {
+                    F12 "A"
+                }
+                niri-passthrough {
+                    Ctrl+F8 {
+                        spawn "foot"
+                    }
+                }
+            }
+            profile-switch {
+                F9 "default"
+                Ctrl+Shift+F11 "default"
+                F12 "default"
+                Control+F8 "default"
+            }
+            "#,
+        );
+        let found = conflicts(&config);
+        let found: Vec<(&str, &str, &str)> =
+            found.iter().map(|(message, rule, other)| (message.as_str(), rule.as_str(), other.as_str())).collect();
+        assert_eq!(found.len(), 4, "{:#?}", found);
+        assert!(found[0].0.starts_with("Remap 'F9' -> 'F10' is shadowed by the profile-switch keybind 'F9'"));
+        assert_eq!((found[0].1, found[0].2), ("F9", "F9"));
+        // Modifier order does not hide the conflict
+        assert!(found[1].0.starts_with("Combo 'Ctrl+Shift+F11' is shadowed"), "{}", found[1].0);
+        assert_eq!((found[1].1, found[1].2), ("Shift+Ctrl+F11", "Ctrl+Shift+F11"));
+        assert!(found[2].0.starts_with("Macro on 'F12' is shadowed by the profile-switch keybind 'F12'"));
+        assert!(found[3].0.starts_with("niri-passthrough keybind 'Ctrl+F8' is intercepted by the profile-switch"));
+        assert_eq!((found[3].1, found[3].2), ("Ctrl+F8", "Control+F8"));
+    }
+
+    #[test]
+    fn test_remap_chain_lint() {
+        let config = device(
+            r#"
+            profile "default" {
+                remap {
+                    A "B"
+                    B "C"
+                    CapsLock "F5"
+                }
+                macro {
+                    F5 "X"
+                }
+            }
+            "#,
+        );
+        let chains = remap_chains(&config);
+        assert_eq!(chains.len(), 2, "{:#?}", chains);
+        assert!(chains[0].starts_with("Remap 'A' -> 'B' emits 'B', which is not passed through remap 'B' -> 'C'"));
+        assert!(
+            chains[1].starts_with("Remap 'CapsLock' -> 'F5' emits 'F5', which is not passed through macro on 'F5'"),
+            "{}",
+            chains[1]
+        );
+    }
+
+    #[test]
+    fn test_swaps_are_not_remap_chains() {
+        let config = device(
+            r#"
+            profile "default" {
+                remap {
+                    CapsLock "Escape"
+                    Escape "CapsLock"
+                }
+            }
+            "#,
+        );
+        assert_eq!(remap_chains(&config), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_conflicts_in_included_files_told_apart() {
+        let dir = std::env::temp_dir().join("niri-mapper-test-conflicts-include");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // The conflicting rules are at the same offsets in both files, so
+        // only the file tells the sites apart
+        let keyboard = |name: &str| {
+            format!(
+                "device \"{}\" {{\n    profile \"default\" {{ remap {{ F1 \"F2\"; }}; macro {{ F1 \"A\"; }}; }}\n    \
+                 profile \"gaming\" {{ }}\n}}\n",
+                name
+            )
+        };
+        std::fs::write(dir.join("other.kdl"), keyboard("Keyboard B")).unwrap();
+        std::fs::write(dir.join("config.kdl"), keyboard("Keyboard A") + "include \"other.kdl\"\n").unwrap();
+
+        let errors = match parse_config_with_warnings(&dir.join("config.kdl"), &HostContext::current(), false) {
+            Err(ConfigError::Multiple { errors }) => errors,
+            other => panic!("Expected Multiple error, got: {:?}", other.map(|_| ())),
+        };
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        // One conflict in each file, not one per profile
+        assert_eq!(messages.len(), 2, "{:#?}", messages);
+        assert!(messages.iter().all(|m| m.starts_with("Macro trigger key 'F1'")), "{:#?}", messages);
+        assert!(messages[1].contains("other.kdl"), "{:#?}", messages);
+    }
+}
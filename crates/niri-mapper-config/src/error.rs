@@ -1,8 +1,62 @@
-use miette::{Diagnostic, LabeledSpan, SourceCode};
+use miette::{Diagnostic, LabeledSpan, MietteError, MietteSpanContents, SourceCode, SourceSpan, SpanContents};
 use thiserror::Error;
 
 // Note: SourceCode is used in the Diagnostic impl for source_code() method
 
+/// Source code of a configuration file, for showing errors in context
+///
+/// With `include` and `config.d`, a configuration spans several files, so the
+/// source carries the name of the file it was read from.
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    /// Path of the file (`None` for configuration parsed from a string)
+    pub name: Option<String>,
+    /// Contents of the file
+    pub content: String,
+}
+
+impl SourceFile {
+    /// Source code that was not read from a file
+    pub fn new(content: impl Into<String>) -> Self {
+        Self { name: None, content: content.into() }
+    }
+
+    /// Source code read from the named file
+    pub fn named(name: impl Into<String>, content: impl Into<String>) -> Self {
+        Self { name: Some(name.into()), content: content.into() }
+    }
+}
+
+impl SourceCode for SourceFile {
+    fn read_span<'a>(
+        &'a self,
+        span: &SourceSpan,
+        context_lines_before: usize,
+        context_lines_after: usize,
+    ) -> Result<Box<dyn SpanContents<'a> + 'a>, MietteError> {
+        let contents = self.content.read_span(span, context_lines_before, context_lines_after)?;
+        match &self.name {
+            Some(name) => Ok(Box::new(MietteSpanContents::new_named(
+                name.clone(),
+                contents.data(),
+                *contents.span(),
+                contents.line(),
+                contents.column(),
+                contents.line_count(),
+            ))),
+            None => Ok(contents),
+        }
+    }
+}
+
+/// ` in <file>` for messages about a named source, empty otherwise
+fn in_file(src: Option<&SourceFile>) -> String {
+    match src.and_then(|src| src.name.as_deref()) {
+        Some(name) => format!(" in {}", name),
+        None => String::new(),
+    }
+}
+
 /// Source location in a configuration file
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SourceLocation {
@@ -43,6 +97,47 @@ impl std::fmt::Display for SourceLocation {
     }
 }
 
+/// Where an error is in a configuration file, with a label and help for it
+#[derive(Debug, Clone, Error)]
+#[error("{label}")]
+pub struct ErrorSpan {
+    /// Source code of the file
+    pub src: SourceFile,
+    /// Location of the node or value in error
+    pub location: SourceLocation,
+    /// Label shown at the location
+    pub label: String,
+    /// How to fix the error
+    pub help: String,
+}
+
+/// A span shown on its own, for the other rule of a [`ConfigError::Conflict`]
+/// when it is in another file
+impl Diagnostic for ErrorSpan {
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        Some(&self.src as &dyn SourceCode)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        Some(Box::new(std::iter::once(LabeledSpan::new_with_span(
+            Some(self.label.clone()),
+            self.location.to_source_span(),
+        ))))
+    }
+
+    fn severity(&self) -> Option<miette::Severity> {
+        Some(miette::Severity::Advice)
+    }
+}
+
+/// ` at <location> in <file>` for errors with a span, empty otherwise
+pub(crate) fn at_span(span: Option<&ErrorSpan>) -> String {
+    match span {
+        Some(span) => format!(" at {}{}", span.location, in_file(Some(&span.src))),
+        None => String::new(),
+    }
+}
+
 /// Details about an invalid key in the configuration
 #[derive(Debug, Clone)]
 pub struct InvalidKeyInfo {
@@ -88,27 +183,45 @@ impl std::fmt::Display for KeyPosition {
 
 #[derive(Error, Debug)]
 pub enum ConfigError {
-    #[error("Failed to parse KDL configuration")]
+    #[error("Failed to parse KDL configuration{}", in_file(Some(.src)))]
     ParseError {
-        src: String,
+        /// Boxed, as it is much larger than the other variants' fields
+        src: Box<SourceFile>,
         span: miette::SourceSpan,
         #[source]
         source: kdl::KdlError,
     },
 
-    #[error("Invalid configuration: {message}")]
-    Invalid { message: String },
+    #[error("Invalid configuration: {message}{}", at_span(.span.as_deref()))]
+    Invalid {
+        message: String,
+        /// Where the error is, for errors about a value in a configuration file
+        span: Option<Box<ErrorSpan>>,
+    },
+
+    #[error("Missing required field: {field}{}", at_span(.span.as_deref()))]
+    MissingField {
+        field: String,
+        /// The node the field is missing from
+        span: Option<Box<ErrorSpan>>,
+    },
 
-    #[error("Missing required field: {field}")]
-    MissingField { field: String },
+    #[error("{message}{}", at_span(Some(.rule)))]
+    Conflict {
+        message: String,
+        /// The rule that is shadowed, unreachable or ambiguous
+        rule: Box<ErrorSpan>,
+        /// The rule it conflicts with
+        other: Box<ErrorSpan>,
+    },
 
     #[error("Unknown key: {key}")]
     UnknownKey { key: String },
 
-    #[error("{}", format_invalid_keys_message(.invalid_keys))]
+    #[error("{}", format_invalid_keys_message(.invalid_keys, .src.as_ref()))]
     InvalidKeys {
         /// Source code for displaying context
-        src: Option<String>,
+        src: Option<SourceFile>,
         /// All invalid keys found during parsing
         invalid_keys: Vec<InvalidKeyInfo>,
     },
@@ -121,6 +234,12 @@ pub enum ConfigError {
 
     #[error("Failed to read configuration file")]
     Io(#[from] std::io::Error),
+
+    #[error("Found {} errors in the configuration", .errors.len())]
+    Multiple {
+        /// Every error found, each reported as a related diagnostic
+        errors: Vec<ConfigError>,
+    },
 }
 
 impl Diagnostic for ConfigError {
@@ -129,10 +248,12 @@ impl Diagnostic for ConfigError {
             ConfigError::ParseError { .. } => "niri_mapper::config::parse_error",
             ConfigError::Invalid { .. } => "niri_mapper::config::invalid",
             ConfigError::MissingField { .. } => "niri_mapper::config::missing_field",
+            ConfigError::Conflict { .. } => "niri_mapper::config::conflict",
             ConfigError::UnknownKey { .. } => "niri_mapper::config::unknown_key",
             ConfigError::InvalidKeys { .. } => "niri_mapper::config::invalid_keys",
             ConfigError::DuplicateKeybinds { .. } => "niri_mapper::config::duplicate_keybinds",
             ConfigError::Io(_) => "niri_mapper::config::io_error",
+            ConfigError::Multiple { .. } => "niri_mapper::config::multiple",
         };
         Some(Box::new(code))
     }
@@ -142,10 +263,13 @@ impl Diagnostic for ConfigError {
             ConfigError::ParseError { .. } => {
                 Some("Check the KDL syntax. Common issues: missing quotes around strings, unclosed braces, or invalid node names.".to_string())
             }
-            ConfigError::Invalid { .. } => {
+            ConfigError::Invalid { span: Some(span), .. } => Some(span.help.clone()),
+            ConfigError::Invalid { span: None, .. } => {
                 Some("Review the configuration structure and ensure all values are valid.".to_string())
             }
-            ConfigError::MissingField { field } => {
+            ConfigError::MissingField { span: Some(span), .. } => Some(span.help.clone()),
+            ConfigError::Conflict { rule, .. } => Some(rule.help.clone()),
+            ConfigError::MissingField { field, span: None } => {
                 if field.contains("device name") {
                     Some("Add a name argument to the device block, e.g.: device \"My Keyboard\" { ... }".to_string())
                 } else {
@@ -162,7 +286,9 @@ impl Diagnostic for ConfigError {
                 help.push_str("  - Special: Escape, Enter, Tab, Space, Backspace, CapsLock\n");
                 help.push_str("  - Function: F1-F24\n");
                 help.push_str("  - Navigation: Up, Down, Left, Right, Home, End, PageUp, PageDown\n");
-                help.push_str("  - Raw evdev: KEY_* format (e.g., KEY_A, KEY_LEFTMETA)\n");
+                help.push_str("  - Media: VolumeUp, Mute, PlayPause, BrightnessUp, XF86AudioRaiseVolume, etc.\n");
+                help.push_str("  - Mouse: MouseLeft, MouseRight, MouseMiddle, MouseSide, MouseExtra\n");
+                help.push_str("  - Raw evdev: KEY_* and BTN_* names (e.g., KEY_MACRO1, BTN_SIDE), or KEY_* without the prefix\n");
 
                 // Add specific suggestion if there's only one invalid key
                 if invalid_keys.len() == 1 {
@@ -185,14 +311,20 @@ impl Diagnostic for ConfigError {
                     _ => None,
                 }
             }
+            ConfigError::Multiple { .. } => {
+                Some("Each error is listed below; fix them all and validate again.".to_string())
+            }
         };
         help.map(|s| Box::new(s) as Box<dyn std::fmt::Display>)
     }
 
     fn source_code(&self) -> Option<&dyn SourceCode> {
         match self {
-            ConfigError::ParseError { src, .. } => Some(src as &dyn SourceCode),
+            ConfigError::ParseError { src, .. } => Some(src.as_ref() as &dyn SourceCode),
             ConfigError::InvalidKeys { src: Some(src), .. } => Some(src as &dyn SourceCode),
+            ConfigError::Invalid { span: Some(span), .. }
+            | ConfigError::MissingField { span: Some(span), .. } => Some(&span.src as &dyn SourceCode),
+            ConfigError::Conflict { rule, .. } => Some(&rule.src as &dyn SourceCode),
             _ => None,
         }
     }
@@ -208,13 +340,49 @@ impl Diagnostic for ConfigError {
             ConfigError::InvalidKeys { invalid_keys, .. } => {
                 let labels = invalid_keys.iter().map(|key_info| {
                     let label = format!(
-                        "unknown {} key '{}'",
-                        key_info.position, key_info.key
+                        "unknown {} key '{}'{}",
+                        key_info.position,
+                        key_info.key,
+                        did_you_mean(&key_info.key)
                     );
                     LabeledSpan::new_with_span(Some(label), key_info.location.to_source_span())
                 });
                 Some(Box::new(labels))
             }
+            ConfigError::Invalid { span: Some(span), .. }
+            | ConfigError::MissingField { span: Some(span), .. } => {
+                Some(Box::new(std::iter::once(LabeledSpan::new_with_span(
+                    Some(span.label.clone()),
+                    span.location.to_source_span(),
+                ))))
+            }
+            ConfigError::Conflict { rule, other, .. } => {
+                let mut labels = vec![LabeledSpan::new_with_span(
+                    Some(rule.label.clone()),
+                    rule.location.to_source_span(),
+                )];
+                // The other rule is labelled here if it is in the same file,
+                // and reported as a related diagnostic otherwise
+                if other.src.name == rule.src.name {
+                    labels.push(LabeledSpan::new_with_span(
+                        Some(other.label.clone()),
+                        other.location.to_source_span(),
+                    ));
+                }
+                Some(Box::new(labels.into_iter()))
+            }
+            _ => None,
+        }
+    }
+
+    fn related<'a>(&'a self) -> Option<Box<dyn Iterator<Item = &'a dyn Diagnostic> + 'a>> {
+        match self {
+            ConfigError::Multiple { errors } => {
+                Some(Box::new(errors.iter().map(|error| error as &dyn Diagnostic)))
+            }
+            ConfigError::Conflict { rule, other, .. } if other.src.name != rule.src.name => {
+                Some(Box::new(std::iter::once(&**other as &dyn Diagnostic)))
+            }
             _ => None,
         }
     }
@@ -230,7 +398,7 @@ impl Diagnostic for ConfigError {
 }
 
 /// Format the list of invalid keys for the error message
-fn format_invalid_keys_message(keys: &[InvalidKeyInfo]) -> String {
+fn format_invalid_keys_message(keys: &[InvalidKeyInfo], src: Option<&SourceFile>) -> String {
     if keys.is_empty() {
         return "No invalid keys".to_string();
     }
@@ -238,12 +406,192 @@ fn format_invalid_keys_message(keys: &[InvalidKeyInfo]) -> String {
     if keys.len() == 1 {
         let key = &keys[0];
         return format!(
-            "Invalid key '{}' in {} block at {}",
-            key.key, key.context, key.location
+            "Invalid key '{}' in {} block at {}{}{}",
+            key.key,
+            key.context,
+            key.location,
+            in_file(src),
+            did_you_mean(&key.key)
         );
     }
 
-    format!("Found {} invalid key(s) in configuration", keys.len())
+    let file = src.and_then(|src| src.name.as_deref()).unwrap_or("configuration");
+    let list: Vec<String> = keys
+        .iter()
+        .map(|key| format!("'{}'{}", key.key, did_you_mean(&key.key)))
+        .collect();
+    format!("Found {} invalid key(s) in {}: {}", keys.len(), file, list.join(", "))
+}
+
+/// A " (did you mean 'X'?)" hint for an unknown key name, if a known one is close
+fn did_you_mean(key: &str) -> String {
+    crate::keys::suggest_key(key)
+        .map(|suggestion| format!(" (did you mean '{}'?)", suggestion))
+        .unwrap_or_default()
+}
+
+impl ConfigError {
+    /// An invalid value at `location` in `source`, shown in context with a
+    /// label and help
+    pub(crate) fn invalid_at(
+        source: &str,
+        location: SourceLocation,
+        message: impl Into<String>,
+        label: impl Into<String>,
+        help: impl Into<String>,
+    ) -> Self {
+        ConfigError::Invalid {
+            message: message.into(),
+            span: Some(Box::new(ErrorSpan {
+                src: SourceFile::new(source),
+                location,
+                label: label.into(),
+                help: help.into(),
+            })),
+        }
+    }
+
+    /// A required field missing from the node at `location` in `source`
+    pub(crate) fn missing_at(
+        source: &str,
+        location: SourceLocation,
+        field: impl Into<String>,
+        label: impl Into<String>,
+        help: impl Into<String>,
+    ) -> Self {
+        ConfigError::MissingField {
+            field: field.into(),
+            span: Some(Box::new(ErrorSpan {
+                src: SourceFile::new(source),
+                location,
+                label: label.into(),
+                help: help.into(),
+            })),
+        }
+    }
+
+    /// Name the file the error's source code was read from
+    ///
+    /// Errors that already name their file, such as errors from an included
+    /// file, keep it.
+    pub(crate) fn in_file(mut self, name: Option<&str>) -> Self {
+        let src = match &mut self {
+            ConfigError::ParseError { src, .. } => Some(src.as_mut()),
+            ConfigError::InvalidKeys { src, .. } => src.as_mut(),
+            ConfigError::Invalid { span, .. } | ConfigError::MissingField { span, .. } => {
+                span.as_deref_mut().map(|span| &mut span.src)
+            }
+            ConfigError::Conflict { rule, other, .. } => {
+                if other.src.name.is_none() {
+                    other.src.name = name.map(str::to_string);
+                }
+                Some(&mut rule.src)
+            }
+            ConfigError::Multiple { errors } => {
+                *errors = std::mem::take(errors).into_iter().map(|e| e.in_file(name)).collect();
+                None
+            }
+            _ => None,
+        };
+        if let Some(src) = src {
+            if src.name.is_none() {
+                src.name = name.map(str::to_string);
+            }
+        }
+        self
+    }
+
+    /// Call `f` with each source code the error shows, and the locations in it
+    ///
+    /// Used to move errors found in a translated file (see
+    /// [`crate::formats`]) back into the file as written.
+    pub(crate) fn for_each_source(&mut self, f: &mut impl FnMut(&mut SourceFile, Vec<&mut SourceLocation>)) {
+        match self {
+            ConfigError::ParseError { src, span, .. } => {
+                let mut location = SourceLocation::new(0, 0, span.offset(), span.len());
+                f(src, vec![&mut location]);
+                *span = location.to_source_span();
+            }
+            ConfigError::InvalidKeys { src: Some(src), invalid_keys } => {
+                f(src, invalid_keys.iter_mut().map(|key| &mut key.location).collect());
+            }
+            ConfigError::Invalid { span: Some(span), .. } | ConfigError::MissingField { span: Some(span), .. } => {
+                f(&mut span.src, vec![&mut span.location]);
+            }
+            ConfigError::Conflict { rule, other, .. } => {
+                f(&mut rule.src, vec![&mut rule.location]);
+                f(&mut other.src, vec![&mut other.location]);
+            }
+            ConfigError::Multiple { errors } => {
+                for error in errors {
+                    error.for_each_source(f);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Errors collected while parsing, so that all errors in a configuration
+/// are reported together instead of only the first one
+#[derive(Debug, Default)]
+pub(crate) struct Errors(Vec<ConfigError>);
+
+impl Errors {
+    /// Add an error
+    ///
+    /// Invalid keys of the same file are combined into one error, and the
+    /// errors of a [`ConfigError::Multiple`] are added one by one.
+    pub(crate) fn push(&mut self, error: ConfigError) {
+        match error {
+            ConfigError::Multiple { errors } => {
+                for error in errors {
+                    self.push(error);
+                }
+            }
+            ConfigError::InvalidKeys { src, invalid_keys } => {
+                let file = |src: &Option<SourceFile>| src.as_ref().and_then(|src| src.name.clone());
+                let same_file = self.0.iter_mut().find_map(|e| match e {
+                    ConfigError::InvalidKeys { src: existing, invalid_keys: keys }
+                        if file(existing) == file(&src) =>
+                    {
+                        Some((existing, keys))
+                    }
+                    _ => None,
+                });
+                match same_file {
+                    Some((existing, keys)) => {
+                        keys.extend(invalid_keys);
+                        if existing.is_none() {
+                            *existing = src;
+                        }
+                    }
+                    None => self.0.push(ConfigError::InvalidKeys { src, invalid_keys }),
+                }
+            }
+            error => self.0.push(error),
+        }
+    }
+
+    /// The value of `result`, or `None` once its error is added
+    pub(crate) fn ok<T>(&mut self, result: Result<T, ConfigError>) -> Option<T> {
+        result.map_err(|e| self.push(e)).ok()
+    }
+
+    /// Whether no errors were added
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// `Ok` if no errors were added, otherwise the error, or all of them as a
+    /// [`ConfigError::Multiple`]
+    pub(crate) fn finish(mut self) -> Result<(), ConfigError> {
+        match self.0.len() {
+            0 => Ok(()),
+            1 => Err(self.0.remove(0)),
+            _ => Err(ConfigError::Multiple { errors: self.0 }),
+        }
+    }
 }
 
 /// Format the list of duplicate keybinds for the error message
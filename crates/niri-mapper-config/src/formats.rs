@@ -0,0 +1,1277 @@
+//! JSON and TOML configuration files
+//!
+//! A `.json` or `.toml` file has the shape printed by `config dump --json`
+//! (see `config schema`), with every field optional: a field that is `null`
+//! or an empty list or object is the same as leaving it out. Generators such
+//! as Nix's `builtins.toJSON` can so write the configuration directly, and a
+//! dump loads back as the same configuration. Input may also use the fields
+//! the dump leaves out because it has them resolved: `extends`, `template`
+//! and the rules of the global block.
+//!
+//! The file is translated into KDL and parsed like a `.kdl` file, so it is
+//! validated the same way. While translating, each piece of KDL remembers
+//! the part of the file it came from, and [`Translation::map`] moves the
+//! spans of errors and lints back into the file as written.
+
+use std::ops::Range;
+use std::path::Path;
+
+use crate::error::{ConfigError, Errors, SourceLocation};
+use crate::parser::offset_to_line_col;
+
+/// Format of a configuration file, chosen by its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Kdl,
+    Json,
+    Toml,
+}
+
+impl Format {
+    /// The format of the file at `path`; KDL for unknown extensions and for
+    /// configuration that is not read from a file
+    pub(crate) fn of(path: Option<&Path>) -> Self {
+        match path.and_then(Path::extension).and_then(|ext| ext.to_str()) {
+            Some("json") => Format::Json,
+            Some("toml") => Format::Toml,
+            _ => Format::Kdl,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Kdl => "KDL",
+            Format::Json => "JSON",
+            Format::Toml => "TOML",
+        }
+    }
+}
+
+/// Extensions of the configuration files loaded from a drop-in directory
+pub(crate) const EXTENSIONS: [&str; 3] = ["kdl", "json", "toml"];
+
+/// A configuration file translated into KDL
+#[derive(Debug)]
+pub(crate) struct Translation {
+    /// The file as written
+    pub(crate) source: String,
+    /// Ranges of the KDL, each with the range of the file it came from
+    spans: Vec<(Range<usize>, Range<usize>)>,
+}
+
+impl Translation {
+    /// The location in the file as written of `location` in the KDL
+    ///
+    /// The smallest piece of KDL containing the location is used, so an
+    /// error about a key points at the key and one about a value at the value.
+    pub(crate) fn map(&self, location: SourceLocation) -> SourceLocation {
+        let offset = location.offset;
+        let span = self
+            .spans
+            .iter()
+            .filter(|(kdl, _)| kdl.start <= offset && offset < kdl.end.max(kdl.start + 1))
+            .min_by_key(|(kdl, _)| kdl.len())
+            .map(|(_, span)| span.clone())
+            .unwrap_or(0..0);
+        let (line, column) = offset_to_line_col(&self.source, span.start);
+        SourceLocation::new(line, column, span.start, span.len())
+    }
+}
+
+/// Translate a JSON or TOML configuration file into KDL
+///
+/// Errors in the structure of the file, such as a list where an object is
+/// expected, are added to `errors` with spans in `content`, leaving out the
+/// part they are in. Gives `None` if the file does not parse at all.
+pub(crate) fn translate(content: &str, format: Format, errors: &mut Errors) -> Option<(String, Translation)> {
+    let root = match format {
+        Format::Json => parse_json(content),
+        Format::Toml => parse_toml(content),
+        Format::Kdl => unreachable!("KDL is not translated"),
+    };
+    let root = match root {
+        Ok(root) => root,
+        Err((message, span)) => {
+            errors.push(ConfigError::invalid_at(
+                content,
+                location(content, &span),
+                format!("Failed to parse {} configuration: {}", format.name(), message),
+                "syntax error here",
+                format!("Check the {} syntax", format.name()),
+            ));
+            return None;
+        }
+    };
+
+    let mut kdl = Kdl { source: content, out: String::new(), spans: Vec::new(), errors };
+    kdl.document(&root);
+    let translation = Translation { source: content.to_string(), spans: kdl.spans };
+    Some((kdl.out, translation))
+}
+
+fn location(source: &str, span: &Range<usize>) -> SourceLocation {
+    let (line, column) = offset_to_line_col(source, span.start);
+    SourceLocation::new(line, column, span.start, span.len())
+}
+
+/// A value with the byte range of the file it was read from
+#[derive(Debug)]
+struct Spanned<T> {
+    value: T,
+    span: Range<usize>,
+}
+
+/// A JSON or TOML value
+#[derive(Debug)]
+enum Value {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Spanned<Value>>),
+    /// Fields in the order they are written
+    Object(Vec<(Spanned<String>, Spanned<Value>)>),
+}
+
+impl Value {
+    /// Whether the value is the same as leaving the field out
+    fn is_absent(&self) -> bool {
+        match self {
+            Value::Null => true,
+            Value::Array(items) => items.is_empty(),
+            Value::Object(fields) => fields.is_empty(),
+            _ => false,
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "a boolean",
+            Value::Integer(_) | Value::Float(_) => "a number",
+            Value::String(_) => "a string",
+            Value::Array(_) => "a list",
+            Value::Object(_) => "an object",
+        }
+    }
+}
+
+type Field = (Spanned<String>, Spanned<Value>);
+
+/// Writes the KDL translation of a file, with the spans it came from
+struct Kdl<'a> {
+    source: &'a str,
+    out: String,
+    spans: Vec<(Range<usize>, Range<usize>)>,
+    errors: &'a mut Errors,
+}
+
+impl Kdl<'_> {
+    /// Write `text` as the translation of `span`
+    fn mapped(&mut self, text: &str, span: &Range<usize>) {
+        let start = self.out.len();
+        self.out.push_str(text);
+        self.spans.push((start..self.out.len(), span.clone()));
+    }
+
+    /// Start a node whose name is the translation of `span`
+    fn node(&mut self, name: &str, span: &Range<usize>) {
+        self.mapped(&quote(name), span);
+    }
+
+    /// Add an argument to the current node
+    fn arg(&mut self, value: &Spanned<Value>) {
+        if let Some(text) = self.scalar(value) {
+            self.out.push(' ');
+            self.mapped(&text, &value.span);
+        }
+    }
+
+    /// Add a property to the current node
+    fn prop(&mut self, name: &Spanned<String>, value: &Spanned<Value>) {
+        if let Some(text) = self.scalar(value) {
+            self.out.push(' ');
+            self.mapped(&format!("{}={}", quote(&name.value), text), &(name.span.start..value.span.end));
+        }
+    }
+
+    fn open(&mut self) {
+        self.out.push_str(" {\n");
+    }
+
+    fn close(&mut self) {
+        self.out.push_str("}\n");
+    }
+
+    fn end(&mut self) {
+        self.out.push('\n');
+    }
+
+    /// A node for a field this translation does not know, so that it is
+    /// reported like an unknown KDL node
+    fn unknown(&mut self, name: &Spanned<String>) {
+        self.node(&name.value, &name.span);
+        self.end();
+    }
+
+    fn error(&mut self, span: &Range<usize>, message: String, label: &str, help: &str) {
+        self.errors.push(ConfigError::invalid_at(self.source, location(self.source, span), message, label, help));
+    }
+
+    /// The KDL for a string, number, boolean or null, or `None` after an error
+    fn scalar(&mut self, value: &Spanned<Value>) -> Option<String> {
+        match &value.value {
+            Value::Null => Some("null".to_string()),
+            Value::Bool(b) => Some(b.to_string()),
+            Value::Integer(i) => Some(i.to_string()),
+            Value::Float(f) => Some(format!("{:?}", f)),
+            Value::String(s) => Some(quote(s)),
+            other => {
+                let message = format!("Expected a single value, found {}", other.describe());
+                self.error(&value.span, message, "expected a value", "Use a string, number or boolean here");
+                None
+            }
+        }
+    }
+
+    /// The fields of an object, or `None` after an error
+    fn object<'v>(&mut self, value: &'v Spanned<Value>, what: &str) -> Option<&'v [Field]> {
+        match &value.value {
+            Value::Object(fields) => Some(fields),
+            other => {
+                let message = format!("Expected {} to be an object, found {}", what, other.describe());
+                self.error(&value.span, message, "expected an object", "Write the fields in braces");
+                None
+            }
+        }
+    }
+
+    /// The items of a list, or `None` after an error
+    fn array<'v>(&mut self, value: &'v Spanned<Value>, what: &str) -> Option<&'v [Spanned<Value>]> {
+        match &value.value {
+            Value::Array(items) => Some(items),
+            other => {
+                let message = format!("Expected {} to be a list, found {}", what, other.describe());
+                self.error(&value.span, message, "expected a list", "Write the items in brackets");
+                None
+            }
+        }
+    }
+
+    /// The items of a list, or a single value as a list of one
+    fn items<'v>(&self, value: &'v Spanned<Value>) -> &'v [Spanned<Value>] {
+        match &value.value {
+            Value::Array(items) => items,
+            _ => std::slice::from_ref(value),
+        }
+    }
+
+    /// Report a field of a fixed-shape object that is not one of `known`
+    fn unknown_field(&mut self, name: &Spanned<String>, what: &str, known: &[&str]) {
+        self.error(
+            &name.span,
+            format!("Unknown field '{}' in {}", name.value, what),
+            "unknown field",
+            &format!("Expected one of: {}", known.join(", ")),
+        );
+    }
+
+    fn document(&mut self, root: &Spanned<Value>) {
+        let Some(fields) = self.object(root, "the configuration") else {
+            return;
+        };
+        for (key, value) in fields {
+            if value.value.is_absent() {
+                continue;
+            }
+            match key.value.as_str() {
+                "include" => {
+                    for path in self.items(value) {
+                        self.node("include", &key.span);
+                        self.arg(path);
+                        self.end();
+                    }
+                }
+                "global" => self.global(key, value),
+                "devices" => {
+                    for device in self.array(value, "devices").unwrap_or_default() {
+                        self.device(device);
+                    }
+                }
+                "macros" => self.macro_library(key, value),
+                "profile-templates" => {
+                    for (name, template) in self.object(value, "profile-templates").unwrap_or_default() {
+                        self.node("profile-template", &name.span);
+                        self.out.push(' ');
+                        self.mapped(&quote(&name.value), &name.span);
+                        self.profile(template, true);
+                    }
+                }
+                _ => self.unknown(key),
+            }
+        }
+    }
+
+    /// `when` host guards: an object of conditions, or a list of them
+    fn host_guards(&mut self, key: &Spanned<String>, value: &Spanned<Value>) {
+        for guard in self.items(value) {
+            let Some(conditions) = self.object(guard, "a when guard") else {
+                continue;
+            };
+            self.node("when", &key.span);
+            for (name, condition) in conditions {
+                self.prop(name, condition);
+            }
+            self.end();
+        }
+    }
+
+    fn global(&mut self, key: &Spanned<String>, value: &Spanned<Value>) {
+        let Some(fields) = self.object(value, "global") else {
+            return;
+        };
+        self.node("global", &key.span);
+        self.open();
+        for (name, value) in fields {
+            if value.value.is_absent() {
+                continue;
+            }
+            match name.value.as_str() {
+                "when" => self.host_guards(name, value),
+                "xkb" => {
+                    let Some(options) = self.object(value, "xkb") else {
+                        continue;
+                    };
+                    self.node("xkb", &name.span);
+                    self.open();
+                    for (option, value) in options {
+                        if !value.value.is_absent() {
+                            self.node(&option.value, &option.span);
+                            self.arg(value);
+                            self.end();
+                        }
+                    }
+                    self.close();
+                }
+                "rules" => {
+                    if let Some(rules) = self.object(value, "rules") {
+                        self.profile_children(rules, false);
+                    }
+                }
+                _ => {
+                    self.node(&name.value, &name.span);
+                    for value in self.items(value) {
+                        self.arg(value);
+                    }
+                    self.end();
+                }
+            }
+        }
+        self.close();
+    }
+
+    fn device(&mut self, value: &Spanned<Value>) {
+        let Some(fields) = self.object(value, "a device") else {
+            return;
+        };
+        // Errors about the device as a whole point at its name
+        let name = fields.iter().find(|(key, _)| key.value == "name");
+        let name_span = name.map_or(value.span.start..value.span.start + 1, |(_, name)| name.span.clone());
+        self.node("device", &name_span);
+        if let Some((_, name)) = name {
+            self.arg(name);
+        }
+        self.open();
+        for (key, value) in fields {
+            if value.value.is_absent() {
+                continue;
+            }
+            match key.value.as_str() {
+                "name" => {}
+                "when" => self.host_guards(key, value),
+                "profiles" => {
+                    for (name, profile) in self.object(value, "profiles").unwrap_or_default() {
+                        self.node("profile", &name.span);
+                        self.out.push(' ');
+                        self.mapped(&quote(&name.value), &name.span);
+                        self.profile(profile, true);
+                    }
+                }
+                "profile-switch" => {
+                    let Some(keybinds) = self.object(value, "profile-switch") else {
+                        continue;
+                    };
+                    self.node("profile-switch", &key.span);
+                    self.open();
+                    for (keybind, profile) in keybinds {
+                        self.node(&keybind.value, &keybind.span);
+                        self.arg(profile);
+                        self.end();
+                    }
+                    self.close();
+                }
+                "vendor-product" => {
+                    self.node("vendor-product", &key.span);
+                    self.arg(value);
+                    self.end();
+                }
+                _ => self.unknown(key),
+            }
+        }
+        self.close();
+    }
+
+    /// A profile's properties and children, after its node name and name
+    fn profile(&mut self, value: &Spanned<Value>, properties: bool) {
+        let Some(fields) = self.object(value, "a profile") else {
+            self.end();
+            return;
+        };
+        for (key, value) in fields {
+            if properties && matches!(key.value.as_str(), "extends" | "template") && !value.value.is_absent() {
+                self.prop(key, value);
+            }
+        }
+        self.open();
+        self.profile_children(fields, properties);
+        self.close();
+    }
+
+    /// The children of a profile, or the rules of the global block if
+    /// `properties` is not set
+    fn profile_children(&mut self, fields: &[Field], properties: bool) {
+        let field = |name: &str| fields.iter().find(|(key, value)| key.value == name && !value.value.is_absent());
+        // Conditional rules are written in the block of their kind
+        let mut conditional: [Vec<(&Spanned<String>, &[Field])>; 3] = Default::default();
+        if let Some((key, rules)) = field("conditional-rules") {
+            for rule in self.array(rules, "conditional-rules").unwrap_or_default() {
+                if let Some((kind, entry)) = self.conditional_rule(rule) {
+                    conditional[kind].push((key, entry));
+                }
+            }
+        }
+        let [remap_rules, combo_rules, macro_rules] = conditional;
+
+        for (key, value) in fields {
+            if value.value.is_absent() {
+                continue;
+            }
+            match key.value.as_str() {
+                "extends" | "template" if properties => {}
+                "app-id-hint" | "layout-hint" | "workspace-hint" | "output-hint" if properties => {
+                    self.node(&key.value, &key.span);
+                    self.arg(value);
+                    self.end();
+                }
+                "match-rules" | "exclude-rules" if properties => {
+                    let kind = if key.value == "match-rules" { "match" } else { "exclude" };
+                    for rule in self.array(value, &key.value).unwrap_or_default() {
+                        self.window_match(kind, rule);
+                    }
+                }
+                "remap" | "combo" => {
+                    let rules = if key.value == "remap" { &remap_rules } else { &combo_rules };
+                    self.key_value_block(key, Some(value), rules);
+                }
+                "macros" => self.macro_block(key, Some(value), field("macro-policies"), &macro_rules),
+                "macro-policies" => {
+                    if field("macros").is_none() {
+                        self.macro_block(key, None, field("macro-policies"), &macro_rules);
+                    }
+                }
+                "conditional-rules" => {
+                    // Blocks that only hold conditional rules
+                    if !remap_rules.is_empty() && field("remap").is_none() {
+                        let name = Spanned { value: "remap".to_string(), span: key.span.clone() };
+                        self.key_value_block(&name, None, &remap_rules);
+                    }
+                    if !combo_rules.is_empty() && field("combo").is_none() {
+                        let name = Spanned { value: "combo".to_string(), span: key.span.clone() };
+                        self.key_value_block(&name, None, &combo_rules);
+                    }
+                    if !macro_rules.is_empty() && field("macros").is_none() && field("macro-policies").is_none() {
+                        self.macro_block(key, None, None, &macro_rules);
+                    }
+                }
+                "niri-passthrough" if properties => {
+                    self.node("niri-passthrough", &key.span);
+                    self.open();
+                    for keybind in self.array(value, "niri-passthrough").unwrap_or_default() {
+                        self.passthrough(keybind);
+                    }
+                    self.close();
+                }
+                _ => self.unknown(key),
+            }
+        }
+    }
+
+    /// A `match`, `exclude` or `when` rule
+    fn window_match(&mut self, kind: &str, rule: &Spanned<Value>) {
+        let Some(fields) = self.object(rule, "a window rule") else {
+            return;
+        };
+        self.node(kind, &rule.span);
+        for (name, value) in fields {
+            // 0 is the default priority, which only match rules have
+            let default_priority = name.value == "priority" && matches!(value.value, Value::Integer(0));
+            if !value.value.is_absent() && !default_priority {
+                self.prop(name, value);
+            }
+        }
+        self.end();
+    }
+
+    /// The kind (remap, combo or macro) and fields of a conditional rule,
+    /// `{ "when": [...], "entry": { "remap": { "from": ..., "to": ... } } }`
+    fn conditional_rule<'v>(&mut self, rule: &'v Spanned<Value>) -> Option<(usize, &'v [Field])> {
+        let fields = self.object(rule, "a conditional rule")?;
+        for (key, _) in fields {
+            if !matches!(key.value.as_str(), "when" | "entry") {
+                self.unknown_field(key, "a conditional rule", &["when", "entry"]);
+            }
+        }
+        let field = |name: &str| fields.iter().find(|(key, value)| key.value == name && !value.value.is_absent());
+        if field("when").is_none() {
+            let message = "Conditional rule without when conditions".to_string();
+            self.error(&rule.span, message, "no conditions", "Add \"when\": [{ \"app-id\": \"^kitty$\" }]");
+            return None;
+        }
+        let Some((_, entry)) = field("entry") else {
+            let message = "Conditional rule without an entry".to_string();
+            self.error(&rule.span, message, "no entry", "Add \"entry\": { \"remap\": { \"from\": ..., \"to\": ... } }");
+            return None;
+        };
+        let [(kind, _)] = self.object(entry, "the entry of a conditional rule")? else {
+            let message = "The entry of a conditional rule has one field: remap, combo or macro".to_string();
+            self.error(&entry.span, message, "expected one field", "Write one rule per conditional rule");
+            return None;
+        };
+        let index = match kind.value.as_str() {
+            "remap" => 0,
+            "combo" => 1,
+            "macro" => 2,
+            _ => {
+                self.unknown_field(kind, "the entry of a conditional rule", &["remap", "combo", "macro"]);
+                return None;
+            }
+        };
+        Some((index, fields))
+    }
+
+    /// The `when` conditions of a conditional rule, as a child block
+    fn when_block(&mut self, rule: &[Field]) {
+        self.open();
+        if let Some((_, when)) = rule.iter().find(|(key, _)| key.value == "when") {
+            for condition in self.items(when) {
+                self.window_match("when", condition);
+            }
+        }
+        self.close();
+    }
+
+    /// The fields of the entry of a conditional rule
+    fn rule_entry(rule: &[Field]) -> &[Field] {
+        let entry = rule.iter().find(|(key, _)| key.value == "entry").map(|(_, entry)| entry);
+        match entry.map(|entry| &entry.value) {
+            Some(Value::Object(fields)) => match fields.first().map(|(_, value)| &value.value) {
+                Some(Value::Object(fields)) => fields,
+                _ => &[],
+            },
+            _ => &[],
+        }
+    }
+
+    /// A `remap` or `combo` block: keys mapped to keys, or to `null` to unset
+    /// an inherited entry
+    fn key_value_block(
+        &mut self,
+        key: &Spanned<String>,
+        entries: Option<&Spanned<Value>>,
+        rules: &[(&Spanned<String>, &[Field])],
+    ) {
+        self.node(&key.value, &key.span);
+        self.open();
+        for (from, to) in entries.and_then(|entries| self.object(entries, &key.value)).unwrap_or_default() {
+            self.node(&from.value, &from.span);
+            self.arg(to);
+            self.end();
+        }
+        for (_, rule) in rules {
+            let entry = Self::rule_entry(rule);
+            let field = |name: &str| entry.iter().find(|(key, _)| key.value == name).map(|(_, value)| value);
+            let (Some(from), Some(to)) = (field("from"), field("to")) else {
+                self.missing_entry_fields(rule, &["from", "to"]);
+                continue;
+            };
+            let Value::String(name) = &from.value else {
+                self.scalar(from);
+                continue;
+            };
+            self.node(name, &from.span);
+            self.arg(to);
+            self.when_block(rule);
+        }
+        self.close();
+    }
+
+    fn missing_entry_fields(&mut self, rule: &[Field], fields: &[&str]) {
+        let span = rule
+            .iter()
+            .find(|(key, _)| key.value == "entry")
+            .map_or(0..0, |(_, entry)| entry.span.clone());
+        let message = format!("The entry of a conditional rule needs the fields {}", fields.join(", "));
+        self.error(&span, message, "missing fields", "Give each of the fields");
+    }
+
+    /// A profile's `macro` block
+    ///
+    /// A macro is a list of actions, or an object of properties such as
+    /// `{ "macro": "switch-tab", "n": "2" }`; `null` unsets an inherited
+    /// macro. The policies of `macro-policies` are added as `policy=`.
+    fn macro_block(
+        &mut self,
+        key: &Spanned<String>,
+        macros: Option<&Spanned<Value>>,
+        policies: Option<&Field>,
+        rules: &[(&Spanned<String>, &[Field])],
+    ) {
+        let macros = macros.and_then(|macros| self.object(macros, "macros")).unwrap_or_default();
+        let policies = policies
+            .and_then(|(_, policies)| self.object(policies, "macro-policies"))
+            .unwrap_or_default();
+        for (trigger, _) in policies {
+            if !macros.iter().any(|(key, _)| key.value == trigger.value) {
+                self.error(
+                    &trigger.span,
+                    format!("macro-policies sets a policy for '{}', which has no macro", trigger.value),
+                    "no macro",
+                    "Add the macro to macros, or remove its policy",
+                );
+            }
+        }
+
+        self.node("macro", &key.span);
+        self.open();
+        for (trigger, value) in macros {
+            self.node(&trigger.value, &trigger.span);
+            self.macro_actions(value);
+            if let Some((name, policy)) = policies.iter().find(|(key, _)| key.value == trigger.value) {
+                let property = Spanned { value: "policy".to_string(), span: name.span.clone() };
+                self.prop(&property, policy);
+            }
+            self.end();
+        }
+        for (_, rule) in rules {
+            let entry = Self::rule_entry(rule);
+            let field = |name: &str| entry.iter().find(|(key, _)| key.value == name);
+            let (Some((_, trigger)), Some((_, actions))) = (field("trigger"), field("actions")) else {
+                self.missing_entry_fields(rule, &["trigger", "actions"]);
+                continue;
+            };
+            let Value::String(name) = &trigger.value else {
+                self.scalar(trigger);
+                continue;
+            };
+            self.node(name, &trigger.span);
+            self.macro_actions(actions);
+            if let Some((name, policy)) = field("policy") {
+                let property = Spanned { value: "policy".to_string(), span: name.span.clone() };
+                self.prop(&property, policy);
+            }
+            self.when_block(rule);
+        }
+        self.close();
+    }
+
+    fn macro_actions(&mut self, value: &Spanned<Value>) {
+        match &value.value {
+            Value::Object(properties) => {
+                for (name, value) in properties {
+                    self.prop(name, value);
+                }
+            }
+            _ => {
+                for action in self.items(value) {
+                    self.arg(action);
+                }
+            }
+        }
+    }
+
+    /// A keybind of `niri-passthrough`, `{ "key": ..., "action": ... }`,
+    /// whose action is niri's KDL (e.g. `spawn "alacritty";`)
+    fn passthrough(&mut self, keybind: &Spanned<Value>) {
+        let Some(fields) = self.object(keybind, "a niri-passthrough keybind") else {
+            return;
+        };
+        let field = |name: &str| fields.iter().find(|(key, _)| key.value == name).map(|(_, value)| value);
+        for (key, _) in fields {
+            if !matches!(key.value.as_str(), "key" | "action") {
+                self.unknown_field(key, "a niri-passthrough keybind", &["key", "action"]);
+            }
+        }
+        let Some(Spanned { value: Value::String(key), span: key_span }) = field("key") else {
+            let message = "niri-passthrough keybind without a key".to_string();
+            self.error(&keybind.span, message, "no key", "Give the keybind as \"key\": \"Super+Return\"");
+            return;
+        };
+        match field("action") {
+            Some(Spanned { value: Value::String(action), span }) => {
+                // The action is checked on its own, so that it cannot change
+                // the structure of the configuration around it
+                if let Err(e) = action.parse::<kdl::KdlDocument>() {
+                    self.error(
+                        span,
+                        format!("Invalid niri action for '{}': {}", key, e),
+                        "invalid action",
+                        "Write the action as in niri's config, e.g. spawn \"alacritty\";",
+                    );
+                    return;
+                }
+                self.node(key, key_span);
+                self.out.push_str(" {\n");
+                self.mapped(action, span);
+                self.out.push('\n');
+                self.close();
+            }
+            // `null` unsets an inherited keybind
+            Some(action @ Spanned { value: Value::Null, .. }) => {
+                self.node(key, key_span);
+                self.arg(action);
+                self.end();
+            }
+            _ => {
+                let message = format!("niri-passthrough keybind '{}' needs an action", key);
+                let help = "Give niri's action as a string, e.g. \"spawn \\\"alacritty\\\";\"";
+                self.error(key_span, message, "no action", help);
+            }
+        }
+    }
+
+    /// The top-level macro library: names mapped to
+    /// `{ "params": [{ "name": ..., "default": ... }], "actions": [...] }`,
+    /// or to a list of actions
+    fn macro_library(&mut self, key: &Spanned<String>, value: &Spanned<Value>) {
+        let Some(macros) = self.object(value, "macros") else {
+            return;
+        };
+        self.node("macros", &key.span);
+        self.open();
+        for (name, value) in macros {
+            let fields = match &value.value {
+                Value::Object(fields) => fields.as_slice(),
+                _ => {
+                    self.node(&name.value, &name.span);
+                    for action in self.array(value, "a macro").unwrap_or_default() {
+                        self.arg(action);
+                    }
+                    self.end();
+                    continue;
+                }
+            };
+            self.node(&name.value, &name.span);
+            let mut actions = None;
+            for (field, value) in fields {
+                match field.value.as_str() {
+                    "params" => {
+                        for param in self.array(value, "params").unwrap_or_default() {
+                            self.macro_param(param);
+                        }
+                    }
+                    "actions" => actions = Some(value),
+                    _ => self.unknown_field(field, "a library macro", &["params", "actions"]),
+                }
+            }
+            // Parameters are properties, which KDL allows among the arguments
+            for action in actions.map(|actions| self.items(actions)).unwrap_or_default() {
+                self.arg(action);
+            }
+            self.end();
+        }
+        self.close();
+    }
+
+    fn macro_param(&mut self, param: &Spanned<Value>) {
+        let Some(fields) = self.object(param, "a macro parameter") else {
+            return;
+        };
+        let mut name = None;
+        let mut default = None;
+        for (field, value) in fields {
+            match (field.value.as_str(), &value.value) {
+                ("name", Value::String(value)) => name = Some(value),
+                ("default", _) => default = Some(value),
+                ("name", _) => {
+                    self.scalar(value);
+                }
+                _ => self.unknown_field(field, "a macro parameter", &["name", "default"]),
+            }
+        }
+        let Some(name) = name else {
+            let message = "Macro parameter without a name".to_string();
+            let help = "Give the parameter as { \"name\": \"n\", \"default\": \"1\" }";
+            self.error(&param.span, message, "no name", help);
+            return;
+        };
+        let null = Spanned { value: Value::Null, span: param.span.clone() };
+        let property = Spanned { value: name.clone(), span: param.span.clone() };
+        self.prop(&property, default.unwrap_or(&null));
+    }
+}
+
+/// A KDL string
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A syntax error: what went wrong and where
+type SyntaxError = (String, Range<usize>);
+
+/// How deeply JSON lists and objects may be nested, far more than a
+/// configuration needs; deeper input is an error rather than a stack overflow
+const MAX_JSON_DEPTH: usize = 128;
+
+/// Parse JSON, keeping the span of every key and value
+fn parse_json(source: &str) -> Result<Spanned<Value>, SyntaxError> {
+    let mut parser = JsonParser { source, pos: 0, depth: 0 };
+    parser.whitespace();
+    let value = parser.value()?;
+    parser.whitespace();
+    if parser.pos < source.len() {
+        return Err(parser.unexpected("the end of the file"));
+    }
+    Ok(value)
+}
+
+struct JsonParser<'a> {
+    source: &'a str,
+    pos: usize,
+    /// Number of lists and objects the current position is in
+    depth: usize,
+}
+
+impl JsonParser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.source.as_bytes().get(self.pos).copied()
+    }
+
+    fn whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    /// An error about the character at the current position
+    fn unexpected(&self, expected: &str) -> SyntaxError {
+        match self.source[self.pos..].chars().next() {
+            Some(c) => (format!("expected {}, found '{}'", expected, c), self.pos..self.pos + c.len_utf8()),
+            None => (format!("expected {}, found the end of the file", expected), self.pos..self.pos),
+        }
+    }
+
+    fn expect(&mut self, byte: u8, expected: &str) -> Result<(), SyntaxError> {
+        if self.peek() != Some(byte) {
+            return Err(self.unexpected(expected));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Spanned<Value>, SyntaxError> {
+        let start = self.pos;
+        let value = match self.peek() {
+            Some(b'{') => self.nested(Self::object)?,
+            Some(b'[') => self.nested(Self::array)?,
+            Some(b'"') => Value::String(self.string()?),
+            Some(b'-' | b'0'..=b'9') => self.number()?,
+            Some(b't') => self.literal("true", Value::Bool(true))?,
+            Some(b'f') => self.literal("false", Value::Bool(false))?,
+            Some(b'n') => self.literal("null", Value::Null)?,
+            _ => return Err(self.unexpected("a value")),
+        };
+        Ok(Spanned { value, span: start..self.pos })
+    }
+
+    /// Parse a list or object one level deeper
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Value, SyntaxError>) -> Result<Value, SyntaxError> {
+        if self.depth == MAX_JSON_DEPTH {
+            let message = format!("lists and objects nested more than {} levels deep", MAX_JSON_DEPTH);
+            return Err((message, self.pos..self.pos + 1));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn literal(&mut self, text: &str, value: Value) -> Result<Value, SyntaxError> {
+        if !self.source[self.pos..].starts_with(text) {
+            return Err(self.unexpected("a value"));
+        }
+        self.pos += text.len();
+        Ok(value)
+    }
+
+    fn object(&mut self) -> Result<Value, SyntaxError> {
+        self.pos += 1;
+        let mut fields = Vec::new();
+        self.whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.unexpected("a field name in quotes"));
+            }
+            let start = self.pos;
+            let name = self.string()?;
+            let name = Spanned { value: name, span: start..self.pos };
+            self.whitespace();
+            self.expect(b':', "':'")?;
+            self.whitespace();
+            fields.push((name, self.value()?));
+            self.whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                }
+                _ => return Err(self.unexpected("',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, SyntaxError> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        self.whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Array(items));
+        }
+        loop {
+            self.whitespace();
+            items.push(self.value()?);
+            self.whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                _ => return Err(self.unexpected("',' or ']'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, SyntaxError> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut value = String::new();
+        loop {
+            let Some(c) = self.source[self.pos..].chars().next() else {
+                return Err(("unterminated string".to_string(), start..self.pos));
+            };
+            match c {
+                '"' => {
+                    self.pos += 1;
+                    return Ok(value);
+                }
+                '\\' => {
+                    self.pos += 1;
+                    value.push(self.escape()?);
+                }
+                c if (c as u32) < 0x20 => {
+                    return Err(self.unexpected("an escape sequence for the control character"));
+                }
+                c => {
+                    self.pos += c.len_utf8();
+                    value.push(c);
+                }
+            }
+        }
+    }
+
+    /// The character of an escape sequence, after its backslash
+    fn escape(&mut self) -> Result<char, SyntaxError> {
+        let start = self.pos - 1;
+        let c = match self.peek() {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                self.pos += 1;
+                let high = self.hex4()?;
+                // Characters outside the Basic Multilingual Plane are written
+                // as a surrogate pair, `\uD83D\uDE00` for U+1F600
+                let code = if (0xD800..0xDC00).contains(&high) && self.source[self.pos..].starts_with("\\u") {
+                    self.pos += 2;
+                    let low = self.hex4()?;
+                    if !(0xDC00..0xE000).contains(&low) {
+                        return Err(("unpaired surrogate in unicode escape".to_string(), start..self.pos));
+                    }
+                    0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                } else {
+                    high
+                };
+                return char::from_u32(code)
+                    .ok_or_else(|| ("invalid unicode escape".to_string(), start..self.pos));
+            }
+            _ => return Err(self.unexpected("an escape sequence")),
+        };
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn hex4(&mut self) -> Result<u32, SyntaxError> {
+        let digits = self
+            .source
+            .get(self.pos..self.pos + 4)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()));
+        let Some(digits) = digits else {
+            return Err(self.unexpected("four hex digits"));
+        };
+        self.pos += 4;
+        Ok(u32::from_str_radix(digits, 16).expect("hex digits"))
+    }
+
+    fn digits(&mut self) -> Result<(), SyntaxError> {
+        if !matches!(self.peek(), Some(b'0'..=b'9')) {
+            return Err(self.unexpected("a digit"));
+        }
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<Value, SyntaxError> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        if self.peek() == Some(b'0') {
+            self.pos += 1;
+        } else {
+            self.digits()?;
+        }
+        let mut integer = true;
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            self.digits()?;
+            integer = false;
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.pos += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            self.digits()?;
+            integer = false;
+        }
+        let text = &self.source[start..self.pos];
+        match text.parse::<i64>() {
+            Ok(i) if integer => Ok(Value::Integer(i)),
+            _ => text
+                .parse()
+                .map(Value::Float)
+                .map_err(|_| ("invalid number".to_string(), start..self.pos)),
+        }
+    }
+}
+
+/// Parse TOML, keeping the span of every key and value
+fn parse_toml(source: &str) -> Result<Spanned<Value>, SyntaxError> {
+    let doc = toml_edit::Document::parse(source)
+        .map_err(|e| (e.message().to_string(), e.span().unwrap_or(0..0)))?;
+    toml_table(doc.as_table(), 0..0)
+}
+
+fn toml_table(table: &toml_edit::Table, span: Range<usize>) -> Result<Spanned<Value>, SyntaxError> {
+    let span = table.span().unwrap_or(span);
+    let mut fields = Vec::new();
+    for (name, item) in table.iter() {
+        let key_span = table.key(name).and_then(|key| key.span()).unwrap_or_else(|| span.clone());
+        let value = toml_item(item, key_span.clone())?;
+        fields.push((Spanned { value: name.to_string(), span: key_span }, value));
+    }
+    Ok(Spanned { value: Value::Object(fields), span })
+}
+
+fn toml_item(item: &toml_edit::Item, span: Range<usize>) -> Result<Spanned<Value>, SyntaxError> {
+    match item {
+        toml_edit::Item::None => Ok(Spanned { value: Value::Null, span }),
+        toml_edit::Item::Value(value) => toml_value(value, span),
+        toml_edit::Item::Table(table) => toml_table(table, span),
+        toml_edit::Item::ArrayOfTables(tables) => {
+            let span = tables.span().unwrap_or(span);
+            let items = tables
+                .iter()
+                .map(|table| toml_table(table, span.clone()))
+                .collect::<Result<_, _>>()?;
+            Ok(Spanned { value: Value::Array(items), span })
+        }
+    }
+}
+
+fn toml_value(value: &toml_edit::Value, span: Range<usize>) -> Result<Spanned<Value>, SyntaxError> {
+    let span = value.span().unwrap_or(span);
+    let value = match value {
+        toml_edit::Value::String(s) => Value::String(s.value().clone()),
+        toml_edit::Value::Integer(i) => Value::Integer(*i.value()),
+        toml_edit::Value::Float(f) => Value::Float(*f.value()),
+        toml_edit::Value::Boolean(b) => Value::Bool(*b.value()),
+        toml_edit::Value::Datetime(_) => {
+            return Err(("dates and times are not used by niri-mapper".to_string(), span));
+        }
+        toml_edit::Value::Array(array) => Value::Array(
+            array
+                .iter()
+                .map(|value| toml_value(value, span.clone()))
+                .collect::<Result<_, _>>()?,
+        ),
+        toml_edit::Value::InlineTable(table) => {
+            let mut fields = Vec::new();
+            for (name, value) in table.iter() {
+                let key_span = table.key(name).and_then(|key| key.span()).unwrap_or_else(|| span.clone());
+                let value = toml_value(value, key_span.clone())?;
+                fields.push((Spanned { value: name.to_string(), span: key_span }, value));
+            }
+            Value::Object(fields)
+        }
+    };
+    Ok(Spanned { value, span })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The string a JSON document consists of
+    fn json_string(source: &str) -> Result<String, SyntaxError> {
+        match parse_json(source)?.value {
+            Value::String(s) => Ok(s),
+            other => panic!("Expected a string, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_json_escapes() {
+        assert_eq!(json_string(r#""\" \\ \/ \b \f \n \r \t""#).unwrap(), "\" \\ / \u{8} \u{c} \n \r \t");
+        assert_eq!(json_string(r#""caf\u00e9 \u00E9""#).unwrap(), "café é");
+        assert_eq!(json_string("\"plain ü\"").unwrap(), "plain ü");
+
+        let (message, span) = json_string(r#""a\qb""#).unwrap_err();
+        assert_eq!(message, "expected an escape sequence, found 'q'");
+        assert_eq!(span, 3..4);
+        let (message, _) = json_string(r#""\u12G4""#).unwrap_err();
+        assert_eq!(message, "expected four hex digits, found '1'");
+        let (message, _) = json_string("\"a\nb\"").unwrap_err();
+        assert!(message.starts_with("expected an escape sequence for the control character"), "{}", message);
+        let (message, span) = json_string(r#""open"#).unwrap_err();
+        assert_eq!(message, "unterminated string");
+        assert_eq!(span, 0..5);
+    }
+
+    #[test]
+    fn test_json_surrogate_pairs() {
+        assert_eq!(json_string(r#""\uD83D\uDE00""#).unwrap(), "\u{1F600}");
+        assert_eq!(json_string(r#""\uD83D\uDE00 ok""#).unwrap(), "\u{1F600} ok");
+
+        // A high surrogate must be followed by a low one
+        let (message, span) = json_string(r#""\uD83D\u0041""#).unwrap_err();
+        assert_eq!(message, "unpaired surrogate in unicode escape");
+        assert_eq!(span, 1..13);
+        let (message, _) = json_string(r#""\uD83D\uD83D""#).unwrap_err();
+        assert_eq!(message, "unpaired surrogate in unicode escape");
+        let (message, span) = json_string(r#""\uD83D x""#).unwrap_err();
+        assert_eq!(message, "invalid unicode escape");
+        assert_eq!(span, 1..7);
+        let (message, _) = json_string(r#""\uDE00""#).unwrap_err();
+        assert_eq!(message, "invalid unicode escape");
+    }
+
+    #[test]
+    fn test_json_numbers() {
+        let number = |source: &str| parse_json(source).map(|value| value.value);
+        assert!(matches!(number("0"), Ok(Value::Integer(0))));
+        assert!(matches!(number("-12"), Ok(Value::Integer(-12))));
+        assert!(matches!(number("1.5"), Ok(Value::Float(f)) if f == 1.5));
+        assert!(matches!(number("2e3"), Ok(Value::Float(f)) if f == 2000.0));
+        assert!(matches!(number("-5E-1"), Ok(Value::Float(f)) if f == -0.5));
+        // Integers too large for i64 are still numbers
+        assert!(matches!(number("99999999999999999999"), Ok(Value::Float(_))));
+
+        assert_eq!(number("01").unwrap_err(), ("expected the end of the file, found '1'".to_string(), 1..2));
+        assert_eq!(number("-").unwrap_err(), ("expected a digit, found the end of the file".to_string(), 1..1));
+        assert_eq!(number("1.").unwrap_err(), ("expected a digit, found the end of the file".to_string(), 2..2));
+        assert_eq!(number("1e+x").unwrap_err(), ("expected a digit, found 'x'".to_string(), 3..4));
+        assert_eq!(number("+1").unwrap_err(), ("expected a value, found '+'".to_string(), 0..1));
+    }
+
+    #[test]
+    fn test_json_depth() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(parse_json(&nested(MAX_JSON_DEPTH)).is_ok());
+
+        let (message, span) = parse_json(&nested(MAX_JSON_DEPTH + 1)).unwrap_err();
+        assert_eq!(message, "lists and objects nested more than 128 levels deep");
+        assert_eq!(span, MAX_JSON_DEPTH..MAX_JSON_DEPTH + 1);
+
+        // Far deeper input is rejected the same way instead of overflowing the stack
+        assert!(parse_json(&"[".repeat(200_000)).is_err());
+        assert!(parse_json(&r#"{"a":"#.repeat(200_000)).is_err());
+    }
+
+    #[test]
+    fn test_json_error_spans() {
+        let error = |source: &str| parse_json(source).unwrap_err();
+        assert_eq!(error(r#"{"a": tru}"#), ("expected a value, found 't'".to_string(), 6..7));
+        assert_eq!(error(r#"{"a" 1}"#), ("expected ':', found '1'".to_string(), 5..6));
+        assert_eq!(error(r#"{a: 1}"#), ("expected a field name in quotes, found 'a'".to_string(), 1..2));
+        assert_eq!(error("[1, 2"), ("expected ',' or ']', found the end of the file".to_string(), 5..5));
+        assert_eq!(error(r#"{"a": 1,}"#), ("expected a field name in quotes, found '}'".to_string(), 8..9));
+        assert_eq!(error("[1] ü"), ("expected the end of the file, found 'ü'".to_string(), 4..6));
+
+        // Values keep the span they were written at
+        let root = parse_json("{ \"devices\": [ true ] }").unwrap();
+        let Value::Object(fields) = root.value else { panic!("Expected an object") };
+        let (name, value) = &fields[0];
+        assert_eq!(name.span, 2..11);
+        assert_eq!(value.span, 13..21);
+    }
+
+    #[test]
+    fn test_syntax_errors_point_into_the_file() {
+        let mut errors = Errors::default();
+        assert!(translate("{\n  \"global\": [\"\\uD83D\\u0041\"]\n}", Format::Json, &mut errors).is_none());
+        let error = errors.finish().unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("unpaired surrogate"), "{}", message);
+        assert!(message.contains("line 2, column 15"), "{}", message);
+    }
+}
@@ -5,7 +5,7 @@ use std::path::Path;
 use std::time::SystemTime;
 
 use crate::error::{ConfigError, DuplicateKeybindInfo};
-use crate::model::Config;
+use crate::model::{Config, DeviceConfig, NiriKeybind, Profile};
 
 /// Format a SystemTime as an ISO 8601 timestamp (UTC).
 fn format_timestamp(time: SystemTime) -> String {
@@ -84,6 +84,48 @@ fn translate_modifiers(key: &str) -> String {
         .join("+")
 }
 
+/// Passthrough keybinds a profile defines itself, with the profile template
+/// each one comes from.
+///
+/// Keybinds inherited unchanged through `extends` are left out, since niri
+/// keybinds are global and the parent profile already provides them. Keybinds
+/// inherited unchanged from a profile template are attributed to the template
+/// that defines them, so a template used by several devices only provides
+/// its keybinds once.
+fn own_passthrough<'a>(
+    config: &'a Config,
+    device: &'a DeviceConfig,
+    profile: &'a Profile,
+) -> impl Iterator<Item = (Option<&'a str>, &'a NiriKeybind)> {
+    let same = |a: &NiriKeybind, b: &NiriKeybind| a.key == b.key && a.action == b.action;
+    let parent = profile
+        .extends
+        .as_ref()
+        .and_then(|parent| device.profiles.get(parent));
+    profile
+        .niri_passthrough
+        .iter()
+        .filter(move |keybind| {
+            !parent.is_some_and(|parent| parent.niri_passthrough.iter().any(|p| same(p, keybind)))
+        })
+        .map(move |keybind| {
+            // Follow the template's `extends` chain up to the template the
+            // keybind originally comes from
+            let mut origin = None;
+            let mut template = profile.template.as_deref();
+            while let Some(name) = template {
+                match config.profile_templates.get(name) {
+                    Some(t) if t.niri_passthrough.iter().any(|p| same(p, keybind)) => {
+                        origin = Some(name);
+                        template = t.extends.as_deref();
+                    }
+                    _ => break,
+                }
+            }
+            (origin, keybind)
+        })
+}
+
 /// Detect duplicate keybinds across all devices and profiles.
 ///
 /// Scans all niri-passthrough keybinds from all devices/profiles and identifies
@@ -103,11 +145,15 @@ pub fn detect_duplicate_keybinds(config: &Config) -> Result<(), ConfigError> {
             .unwrap_or_else(|| "<unnamed>".to_string());
 
         for (profile_name, profile) in &device.profiles {
-            for keybind in &profile.niri_passthrough {
-                keybind_sources
-                    .entry(keybind.key.clone())
-                    .or_default()
-                    .push((device_name.clone(), profile_name.clone()));
+            for (template, keybind) in own_passthrough(config, device, profile) {
+                let source = match template {
+                    Some(template) => ("profile-template".to_string(), template.to_string()),
+                    None => (device_name.clone(), profile_name.clone()),
+                };
+                let sources = keybind_sources.entry(keybind.key.clone()).or_default();
+                if !sources.contains(&source) {
+                    sources.push(source);
+                }
             }
         }
     }
@@ -162,10 +208,18 @@ pub fn generate_niri_keybinds(config: &Config, source_path: &Path) -> String {
 
     output.push_str("binds {\n");
 
+    // Keybinds of profile templates already written, as (template, key)
+    let mut template_keybinds = std::collections::HashSet::new();
+
     // Collect all niri-passthrough keybinds from all devices/profiles
     for device in &config.devices {
-        for (_profile_name, profile) in &device.profiles {
-            for keybind in &profile.niri_passthrough {
+        for profile in device.profiles.values() {
+            for (template, keybind) in own_passthrough(config, device, profile) {
+                if let Some(template) = template {
+                    if !template_keybinds.insert((template, keybind.key.as_str())) {
+                        continue;
+                    }
+                }
                 // Translate modifiers (Super -> Mod, others pass through)
                 let niri_key = translate_modifiers(&keybind.key);
                 output.push_str(&format!("    {} {{ {} }}\n", niri_key, keybind.action));
@@ -189,6 +243,7 @@ fn validate_kdl(content: &str) -> Result<(), ConfigError> {
                 "Generated KDL is invalid (this is a bug in niri-mapper): {}",
                 e
             ),
+            span: None,
         }
     })?;
     Ok(())
@@ -297,7 +352,7 @@ mod tests {
         let invalid_kdl = "binds {\n    Mod+Return { spawn \"alacritty\"\n";
         let result = validate_kdl(invalid_kdl);
         assert!(result.is_err());
-        if let Err(ConfigError::Invalid { message }) = result {
+        if let Err(ConfigError::Invalid { message, .. }) = result {
             assert!(message.contains("Generated KDL is invalid"));
         } else {
             panic!("Expected ConfigError::Invalid");
@@ -308,6 +363,8 @@ mod tests {
     fn test_detect_duplicate_keybinds_no_duplicates() {
         // Config with unique keybinds across devices should pass
         let config = Config {
+            macros: HashMap::new(),
+            profile_templates: HashMap::new(),
             global: GlobalConfig::default(),
             devices: vec![
                 DeviceConfig {
@@ -350,10 +407,81 @@ mod tests {
         assert!(detect_duplicate_keybinds(&config).is_ok());
     }
 
+    #[test]
+    fn test_inherited_keybinds_are_not_duplicates() {
+        let config = crate::parser::parse_config_str(
+            r#"
+            device "Keyboard" {
+                profile "default" {
+                    niri-passthrough {
+                        Super+Return { spawn "alacritty"; }
+                    }
+                }
+                profile "firefox" extends="default" {
+                    niri-passthrough {
+                        Super+T { spawn "kitty"; }
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert!(detect_duplicate_keybinds(&config).is_ok());
+        let output = generate_niri_keybinds(&config, Path::new("/tmp/config.kdl"));
+        assert_eq!(output.matches("Mod+Return").count(), 1);
+        assert_eq!(output.matches("Mod+T").count(), 1);
+    }
+
+    #[test]
+    fn test_template_keybinds_are_not_duplicates() {
+        let config = crate::parser::parse_config_str(
+            r#"
+            profile-template "coding" {
+                niri-passthrough {
+                    Super+Return { spawn "alacritty"; }
+                }
+            }
+            device "Keyboard1" {
+                profile "default" template="coding"
+            }
+            device "Keyboard2" {
+                profile "default" template="coding"
+            }
+            device "Keyboard3" {
+                profile "default" template="coding" {
+                    niri-passthrough {
+                        Super+Return { spawn "kitty"; }
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        // Keyboard3 overrides the template's keybind, which conflicts with it
+        match detect_duplicate_keybinds(&config) {
+            Err(ConfigError::DuplicateKeybinds { duplicates }) => {
+                assert_eq!(duplicates.len(), 2);
+                assert!(duplicates.iter().any(|d| d.device == "profile-template" && d.profile == "coding"));
+                assert!(duplicates.iter().any(|d| d.device == "Keyboard3"));
+            }
+            other => panic!("Expected duplicate keybinds, got {:?}", other),
+        }
+
+        let mut config = config;
+        config.devices.pop();
+        assert!(detect_duplicate_keybinds(&config).is_ok());
+        let output = generate_niri_keybinds(&config, Path::new("/tmp/config.kdl"));
+        assert_eq!(output.matches("Mod+Return").count(), 1);
+    }
+
     #[test]
     fn test_detect_duplicate_keybinds_across_devices() {
         // DoD: Duplicate Super+Return in two devices fails with message naming both sources
         let config = Config {
+            macros: HashMap::new(),
+            profile_templates: HashMap::new(),
             global: GlobalConfig::default(),
             devices: vec![
                 DeviceConfig {
@@ -435,6 +563,8 @@ mod tests {
     fn test_detect_duplicate_keybinds_across_profiles() {
         // Duplicates within the same device but different profiles should also be detected
         let config = Config {
+            macros: HashMap::new(),
+            profile_templates: HashMap::new(),
             global: GlobalConfig::default(),
             devices: vec![DeviceConfig {
                 name: Some("Keyboard1".to_string()),
@@ -493,6 +623,8 @@ mod tests {
         let keybinds_path = temp_dir.join("keybinds.kdl");
 
         let config = Config {
+            macros: HashMap::new(),
+            profile_templates: HashMap::new(),
             global: GlobalConfig {
                 niri_keybinds_path: keybinds_path.clone(),
                 ..Default::default()
@@ -550,6 +682,8 @@ mod tests {
     #[test]
     fn test_generate_keybinds() {
         let config = Config {
+            macros: HashMap::new(),
+            profile_templates: HashMap::new(),
             global: GlobalConfig::default(),
             devices: vec![DeviceConfig {
                 name: Some("Test".to_string()),
@@ -582,6 +716,8 @@ mod tests {
     #[test]
     fn test_generate_keybinds_complex_modifiers() {
         let config = Config {
+            macros: HashMap::new(),
+            profile_templates: HashMap::new(),
             global: GlobalConfig::default(),
             devices: vec![DeviceConfig {
                 name: Some("Test".to_string()),
@@ -632,6 +768,8 @@ mod tests {
         let source_path = temp_dir.join("config.kdl");
 
         let config = Config {
+            macros: HashMap::new(),
+            profile_templates: HashMap::new(),
             global: GlobalConfig {
                 niri_keybinds_path: keybinds_path.clone(),
                 ..Default::default()
@@ -685,6 +823,8 @@ mod tests {
     fn test_smoke_round_trip_kdl_generation() {
         // Create a sample config with multiple devices and keybinds
         let config = Config {
+            macros: HashMap::new(),
+            profile_templates: HashMap::new(),
             global: GlobalConfig::default(),
             devices: vec![
                 DeviceConfig {
@@ -812,6 +952,8 @@ mod tests {
 
         // Create config with invalid path (non-existent nested directory that we'll make read-only)
         let config = Config {
+            macros: HashMap::new(),
+            profile_templates: HashMap::new(),
             global: GlobalConfig {
                 // Use a path where we cannot create the temp file
                 niri_keybinds_path: PathBuf::from("/nonexistent/path/keybinds.kdl"),
@@ -851,6 +993,8 @@ mod tests {
     #[test]
     fn test_generate_keybinds_header_format() {
         let config = Config {
+            macros: HashMap::new(),
+            profile_templates: HashMap::new(),
             global: GlobalConfig::default(),
             devices: vec![DeviceConfig {
                 name: Some("Test".to_string()),
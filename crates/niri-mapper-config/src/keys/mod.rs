@@ -0,0 +1,397 @@
+//! Key name registry shared by the configuration parser and the daemon
+//!
+//! Every evdev key and button is known by its kernel name (`KEY_VOLUMEUP`,
+//! `BTN_SIDE`), and keys also without the `KEY_` prefix (`VolumeUp`,
+//! `Macro1`). On top of that come friendly aliases (`Esc`, `Ctrl`,
+//! `PrintScreen`, `MouseLeft`) and the XF86 keysym names used by xkb and niri
+//! (`XF86AudioRaiseVolume`). Names are matched ignoring case.
+
+mod codes;
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use codes::EVDEV_CODES;
+
+/// Friendly key names and the kernel names of their keys. The first name of
+/// each key is the one it is displayed as.
+const ALIASES: &[(&str, &str)] = &[
+    // Special keys
+    ("Escape", "KEY_ESC"),
+    ("Esc", "KEY_ESC"),
+    ("CapsLock", "KEY_CAPSLOCK"),
+    ("Caps_Lock", "KEY_CAPSLOCK"),
+    ("Caps", "KEY_CAPSLOCK"),
+    ("Enter", "KEY_ENTER"),
+    ("Return", "KEY_ENTER"),
+    ("Tab", "KEY_TAB"),
+    ("Space", "KEY_SPACE"),
+    ("Backspace", "KEY_BACKSPACE"),
+    ("PrintScreen", "KEY_SYSRQ"),
+    ("Print", "KEY_SYSRQ"),
+    ("SysRq", "KEY_SYSRQ"),
+    ("ScrollLock", "KEY_SCROLLLOCK"),
+    ("Pause", "KEY_PAUSE"),
+    ("Compose", "KEY_COMPOSE"),
+    // Modifiers
+    ("LeftCtrl", "KEY_LEFTCTRL"),
+    ("LCtrl", "KEY_LEFTCTRL"),
+    ("Ctrl", "KEY_LEFTCTRL"),
+    ("RightCtrl", "KEY_RIGHTCTRL"),
+    ("RCtrl", "KEY_RIGHTCTRL"),
+    ("LeftShift", "KEY_LEFTSHIFT"),
+    ("LShift", "KEY_LEFTSHIFT"),
+    ("Shift", "KEY_LEFTSHIFT"),
+    ("RightShift", "KEY_RIGHTSHIFT"),
+    ("RShift", "KEY_RIGHTSHIFT"),
+    ("LeftAlt", "KEY_LEFTALT"),
+    ("LAlt", "KEY_LEFTALT"),
+    ("Alt", "KEY_LEFTALT"),
+    ("RightAlt", "KEY_RIGHTALT"),
+    ("RAlt", "KEY_RIGHTALT"),
+    ("LeftMeta", "KEY_LEFTMETA"),
+    ("LMeta", "KEY_LEFTMETA"),
+    ("Super", "KEY_LEFTMETA"),
+    ("Meta", "KEY_LEFTMETA"),
+    ("RightMeta", "KEY_RIGHTMETA"),
+    ("RMeta", "KEY_RIGHTMETA"),
+    // Symbol keys
+    ("Minus", "KEY_MINUS"),
+    ("-", "KEY_MINUS"),
+    ("Equal", "KEY_EQUAL"),
+    ("Equals", "KEY_EQUAL"),
+    ("=", "KEY_EQUAL"),
+    ("LeftBrace", "KEY_LEFTBRACE"),
+    ("LBrace", "KEY_LEFTBRACE"),
+    ("[", "KEY_LEFTBRACE"),
+    ("RightBrace", "KEY_RIGHTBRACE"),
+    ("RBrace", "KEY_RIGHTBRACE"),
+    ("]", "KEY_RIGHTBRACE"),
+    ("Semicolon", "KEY_SEMICOLON"),
+    (";", "KEY_SEMICOLON"),
+    ("Apostrophe", "KEY_APOSTROPHE"),
+    ("'", "KEY_APOSTROPHE"),
+    ("Grave", "KEY_GRAVE"),
+    ("`", "KEY_GRAVE"),
+    ("Backslash", "KEY_BACKSLASH"),
+    ("\\", "KEY_BACKSLASH"),
+    ("Comma", "KEY_COMMA"),
+    (",", "KEY_COMMA"),
+    ("Dot", "KEY_DOT"),
+    ("Period", "KEY_DOT"),
+    (".", "KEY_DOT"),
+    ("Slash", "KEY_SLASH"),
+    ("/", "KEY_SLASH"),
+    // Arrow keys
+    ("Up", "KEY_UP"),
+    ("UpArrow", "KEY_UP"),
+    ("Down", "KEY_DOWN"),
+    ("DownArrow", "KEY_DOWN"),
+    ("Left", "KEY_LEFT"),
+    ("LeftArrow", "KEY_LEFT"),
+    ("Right", "KEY_RIGHT"),
+    ("RightArrow", "KEY_RIGHT"),
+    // Navigation keys
+    ("Home", "KEY_HOME"),
+    ("End", "KEY_END"),
+    ("PageUp", "KEY_PAGEUP"),
+    ("PgUp", "KEY_PAGEUP"),
+    ("PageDown", "KEY_PAGEDOWN"),
+    ("PgDn", "KEY_PAGEDOWN"),
+    ("PgDown", "KEY_PAGEDOWN"),
+    ("Insert", "KEY_INSERT"),
+    ("Ins", "KEY_INSERT"),
+    ("Delete", "KEY_DELETE"),
+    ("Del", "KEY_DELETE"),
+    // Numpad keys
+    ("KP0", "KEY_KP0"),
+    ("Numpad0", "KEY_KP0"),
+    ("KP1", "KEY_KP1"),
+    ("Numpad1", "KEY_KP1"),
+    ("KP2", "KEY_KP2"),
+    ("Numpad2", "KEY_KP2"),
+    ("KP3", "KEY_KP3"),
+    ("Numpad3", "KEY_KP3"),
+    ("KP4", "KEY_KP4"),
+    ("Numpad4", "KEY_KP4"),
+    ("KP5", "KEY_KP5"),
+    ("Numpad5", "KEY_KP5"),
+    ("KP6", "KEY_KP6"),
+    ("Numpad6", "KEY_KP6"),
+    ("KP7", "KEY_KP7"),
+    ("Numpad7", "KEY_KP7"),
+    ("KP8", "KEY_KP8"),
+    ("Numpad8", "KEY_KP8"),
+    ("KP9", "KEY_KP9"),
+    ("Numpad9", "KEY_KP9"),
+    ("KPDot", "KEY_KPDOT"),
+    ("KPDecimal", "KEY_KPDOT"),
+    ("Numpad_Dot", "KEY_KPDOT"),
+    ("KPEnter", "KEY_KPENTER"),
+    ("Numpad_Enter", "KEY_KPENTER"),
+    ("KPPlus", "KEY_KPPLUS"),
+    ("KPAdd", "KEY_KPPLUS"),
+    ("Numpad_Plus", "KEY_KPPLUS"),
+    ("KPMinus", "KEY_KPMINUS"),
+    ("KPSubtract", "KEY_KPMINUS"),
+    ("Numpad_Minus", "KEY_KPMINUS"),
+    ("KPAsterisk", "KEY_KPASTERISK"),
+    ("KPMultiply", "KEY_KPASTERISK"),
+    ("Numpad_Multiply", "KEY_KPASTERISK"),
+    ("KPSlash", "KEY_KPSLASH"),
+    ("KPDivide", "KEY_KPSLASH"),
+    ("Numpad_Divide", "KEY_KPSLASH"),
+    ("NumLock", "KEY_NUMLOCK"),
+    ("Num_Lock", "KEY_NUMLOCK"),
+    // Media and system keys
+    ("Mute", "KEY_MUTE"),
+    ("VolumeDown", "KEY_VOLUMEDOWN"),
+    ("VolumeUp", "KEY_VOLUMEUP"),
+    ("MicMute", "KEY_MICMUTE"),
+    ("PlayPause", "KEY_PLAYPAUSE"),
+    ("NextSong", "KEY_NEXTSONG"),
+    ("PreviousSong", "KEY_PREVIOUSSONG"),
+    ("StopCD", "KEY_STOPCD"),
+    ("BrightnessDown", "KEY_BRIGHTNESSDOWN"),
+    ("BrightnessUp", "KEY_BRIGHTNESSUP"),
+    // Mouse buttons
+    ("MouseLeft", "BTN_LEFT"),
+    ("MouseRight", "BTN_RIGHT"),
+    ("MouseMiddle", "BTN_MIDDLE"),
+    ("MouseSide", "BTN_SIDE"),
+    ("MouseExtra", "BTN_EXTRA"),
+    ("MouseForward", "BTN_FORWARD"),
+    ("MouseBack", "BTN_BACK"),
+];
+
+/// XF86 keysym names, as xkb's evdev keymap assigns them to key codes
+const XF86_KEYSYMS: &[(&str, &str)] = &[
+    ("XF86Back", "KEY_BACK"),
+    ("XF86Forward", "KEY_FORWARD"),
+    ("XF86Reload", "KEY_REFRESH"),
+    ("XF86Refresh", "KEY_REFRESH"),
+    ("XF86Stop", "KEY_STOP"),
+    ("XF86Search", "KEY_SEARCH"),
+    ("XF86HomePage", "KEY_HOMEPAGE"),
+    ("XF86Favorites", "KEY_BOOKMARKS"),
+    ("XF86Mail", "KEY_MAIL"),
+    ("XF86Calculator", "KEY_CALC"),
+    ("XF86MyComputer", "KEY_COMPUTER"),
+    ("XF86Explorer", "KEY_FILE"),
+    ("XF86Tools", "KEY_CONFIG"),
+    ("XF86Phone", "KEY_PHONE"),
+    ("XF86Messenger", "KEY_CHAT"),
+    ("XF86AudioMute", "KEY_MUTE"),
+    ("XF86AudioLowerVolume", "KEY_VOLUMEDOWN"),
+    ("XF86AudioRaiseVolume", "KEY_VOLUMEUP"),
+    ("XF86AudioMicMute", "KEY_MICMUTE"),
+    ("XF86AudioPlay", "KEY_PLAYPAUSE"),
+    ("XF86AudioPause", "KEY_PAUSECD"),
+    ("XF86AudioStop", "KEY_STOPCD"),
+    ("XF86AudioNext", "KEY_NEXTSONG"),
+    ("XF86AudioPrev", "KEY_PREVIOUSSONG"),
+    ("XF86AudioRecord", "KEY_RECORD"),
+    ("XF86AudioRewind", "KEY_REWIND"),
+    ("XF86AudioForward", "KEY_FASTFORWARD"),
+    ("XF86AudioMedia", "KEY_MEDIA"),
+    ("XF86Eject", "KEY_EJECTCD"),
+    ("XF86MonBrightnessDown", "KEY_BRIGHTNESSDOWN"),
+    ("XF86MonBrightnessUp", "KEY_BRIGHTNESSUP"),
+    ("XF86KbdBrightnessDown", "KEY_KBDILLUMDOWN"),
+    ("XF86KbdBrightnessUp", "KEY_KBDILLUMUP"),
+    ("XF86KbdLightOnOff", "KEY_KBDILLUMTOGGLE"),
+    ("XF86Display", "KEY_SWITCHVIDEOMODE"),
+    ("XF86TouchpadToggle", "KEY_TOUCHPAD_TOGGLE"),
+    ("XF86ScreenSaver", "KEY_SCREENLOCK"),
+    ("XF86Battery", "KEY_BATTERY"),
+    ("XF86WLAN", "KEY_WLAN"),
+    ("XF86Bluetooth", "KEY_BLUETOOTH"),
+    ("XF86Sleep", "KEY_SLEEP"),
+    ("XF86WakeUp", "KEY_WAKEUP"),
+    ("XF86PowerOff", "KEY_POWER"),
+    ("XF86Copy", "KEY_COPY"),
+    ("XF86Cut", "KEY_CUT"),
+    ("XF86Paste", "KEY_PASTE"),
+    ("XF86Open", "KEY_OPEN"),
+    ("XF86Close", "KEY_CLOSE"),
+    ("XF86New", "KEY_NEW"),
+    ("XF86Save", "KEY_SAVE"),
+    ("XF86Undo", "KEY_UNDO"),
+    ("XF86Redo", "KEY_REDO"),
+];
+
+#[derive(Default)]
+struct Registry {
+    /// Key code of every name, keyed by the upper-cased name
+    codes: HashMap<String, u16>,
+    /// Display name of every key code
+    names: HashMap<u16, String>,
+    /// All names as written, for suggestions
+    written: Vec<String>,
+}
+
+impl Registry {
+    fn add(&mut self, name: &str, code: u16) {
+        if let std::collections::hash_map::Entry::Vacant(entry) = self.codes.entry(name.to_uppercase()) {
+            entry.insert(code);
+            self.written.push(name.to_string());
+        }
+    }
+}
+
+fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let short = |name: &str| name.strip_prefix("KEY_").map(str::to_string);
+        let aliases = ALIASES.iter().chain(XF86_KEYSYMS).map(|(name, kernel_name)| {
+            let code = evdev_code(kernel_name)
+                .unwrap_or_else(|| panic!("{} is an alias of unknown key {}", name, kernel_name));
+            (*name, code)
+        });
+
+        let mut registry = Registry::default();
+        // Kernel names take precedence, then friendly names, then XF86 names,
+        // then kernel names without their prefix
+        for (name, code) in EVDEV_CODES {
+            registry.add(name, *code);
+        }
+        for (name, code) in aliases.clone() {
+            registry.add(name, code);
+        }
+        for (name, code) in EVDEV_CODES {
+            if let Some(short) = short(name) {
+                registry.add(&short, *code);
+            }
+        }
+
+        // Kernel names are displayed without their prefix, unless that name
+        // belongs to another key (e.g., `Print` is an alias of `KEY_SYSRQ`)
+        let kernel_names: Vec<(String, u16)> = EVDEV_CODES
+            .iter()
+            .map(|(name, code)| match short(name) {
+                Some(short) if registry.codes[&short.to_uppercase()] == *code => (short, *code),
+                _ => (name.to_string(), *code),
+            })
+            .collect();
+        let display = aliases.map(|(name, code)| (name.to_string(), code)).chain(kernel_names);
+        for (name, code) in display {
+            registry.names.entry(code).or_insert(name);
+        }
+
+        registry
+    })
+}
+
+/// Code of a kernel key name (e.g., `"KEY_ESC"`)
+fn evdev_code(kernel_name: &str) -> Option<u16> {
+    EVDEV_CODES
+        .iter()
+        .find(|(name, _)| *name == kernel_name)
+        .map(|(_, code)| *code)
+}
+
+/// Resolve a key name (e.g., `"CapsLock"`, `"XF86AudioMute"`, `"KEY_F13"`)
+/// to its evdev key code, ignoring case
+pub fn key_code(name: &str) -> Option<u16> {
+    registry().codes.get(&name.to_uppercase()).copied()
+}
+
+/// Name a key code is displayed as (e.g., `"Escape"` for `KEY_ESC`)
+///
+/// Uses the first friendly or XF86 name of the key, or else its kernel name,
+/// without the `KEY_` prefix where that is unambiguous. The name resolves back
+/// to the same code.
+pub fn key_name(code: u16) -> Option<&'static str> {
+    registry().names.get(&code).map(String::as_str)
+}
+
+/// Every key name the registry knows, kernel names and aliases alike, e.g.
+/// for completion
+pub fn key_names() -> impl Iterator<Item = &'static str> {
+    registry().written.iter().map(String::as_str)
+}
+
+/// The known key name closest to an unknown one, for "did you mean" hints
+pub fn suggest_key(name: &str) -> Option<&'static str> {
+    closest_name(name, registry().written.iter().map(String::as_str))
+}
+
+/// The candidate closest to `name`, ignoring case, if it is close enough to
+/// be a likely typo
+pub(crate) fn closest_name<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let upper = name.to_uppercase();
+    let max_distance = (upper.chars().count() / 3).max(1);
+
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(&upper, &candidate.to_uppercase()), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(kernel_name: &str) -> u16 {
+        evdev_code(kernel_name).unwrap()
+    }
+
+    #[test]
+    fn test_key_code_resolves_all_name_kinds() {
+        assert_eq!(key_code("CapsLock"), Some(code("KEY_CAPSLOCK")));
+        assert_eq!(key_code("esc"), Some(code("KEY_ESC")));
+        assert_eq!(key_code("KEY_F13"), Some(code("KEY_F13")));
+        assert_eq!(key_code("f24"), Some(code("KEY_F24")));
+        assert_eq!(key_code("Macro1"), Some(code("KEY_MACRO1")));
+        assert_eq!(key_code("BTN_SIDE"), Some(code("BTN_SIDE")));
+        assert_eq!(key_code("BTN_MOUSE"), Some(code("BTN_LEFT")));
+        assert_eq!(key_code("MouseLeft"), Some(code("BTN_LEFT")));
+        assert_eq!(key_code("XF86AudioRaiseVolume"), Some(code("KEY_VOLUMEUP")));
+        assert_eq!(key_code("BrightnessUp"), Some(code("KEY_BRIGHTNESSUP")));
+        assert_eq!(key_code("Ctrl+C"), None);
+        assert_eq!(key_code("KEY_FOOBAR"), None);
+        assert_eq!(key_code("NotAKey"), None);
+    }
+
+    #[test]
+    fn test_key_name_round_trips() {
+        assert_eq!(key_name(code("KEY_ESC")), Some("Escape"));
+        assert_eq!(key_name(code("KEY_LEFTCTRL")), Some("LeftCtrl"));
+        assert_eq!(key_name(code("KEY_BACK")), Some("XF86Back"));
+        assert_eq!(key_name(code("KEY_F5")), Some("F5"));
+        assert_eq!(key_name(code("BTN_TRIGGER_HAPPY1")), Some("BTN_TRIGGER_HAPPY1"));
+        assert_eq!(key_name(0), None);
+
+        for (_, code) in EVDEV_CODES {
+            let name = key_name(*code).unwrap();
+            assert_eq!(key_code(name), Some(*code), "{} does not resolve back", name);
+        }
+    }
+
+    #[test]
+    fn test_suggest_key() {
+        assert_eq!(suggest_key("Escpae"), Some("Escape"));
+        assert_eq!(suggest_key("capslok"), Some("CapsLock"));
+        assert_eq!(suggest_key("XF86AudioRaiseVolum"), Some("XF86AudioRaiseVolume"));
+        assert_eq!(suggest_key("Completely Wrong"), None);
+    }
+}
@@ -1,14 +1,29 @@
 //! Configuration parsing for niri-mapper
 //!
-//! This crate handles parsing KDL configuration files and generating
-//! niri-compatible KDL keybind files.
+//! This crate handles parsing KDL (or JSON and TOML) configuration files,
+//! generating niri-compatible KDL keybind files, and writing changes such as
+//! recorded macros, formatting and migrations back into the configuration.
 
+mod conflicts;
 mod error;
+mod formats;
+mod keys;
+mod lints;
 mod model;
 mod parser;
 mod generator;
+mod rewrite;
+mod writer;
 
-pub use error::ConfigError;
+pub use error::{ConfigError, SourceFile};
+pub use keys::{key_code, key_name, key_names, suggest_key};
+pub use lints::{ConfigWarning, Lint};
 pub use model::*;
-pub use parser::parse_config;
+pub use parser::{
+    node_names, parse_config, parse_config_for_host, parse_config_source_with_warnings, parse_config_str,
+    parse_config_str_for_host, parse_config_str_with_warnings, parse_config_with_warnings, parse_niri_xkb,
+    CONFIG_VERSION,
+};
 pub use generator::{generate_niri_keybinds, write_niri_keybinds};
+pub use rewrite::{format_config, format_file, migrate_config, migrate_file, Migration};
+pub use writer::{format_profile, write_macro};
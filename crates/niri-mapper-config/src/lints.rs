@@ -0,0 +1,266 @@
+//! Lints: problems that do not stop a configuration from loading
+//!
+//! Every lint has a stable code and is reported as a warning. A lint can be
+//! allowed for a node and its children with a type annotation, e.g.
+//! `(allow:unknown-node)my-node`, or for the whole configuration with
+//! `allow "unknown-node"` in the `global` block.
+
+use miette::{Diagnostic, LabeledSpan, SourceCode};
+use schemars::JsonSchema;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::error::{at_span, ConfigError, ErrorSpan, Errors, SourceFile, SourceLocation};
+
+/// A kind of problem reported as a warning
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Lint {
+    /// A node the parser does not know, which is ignored
+    UnknownNode,
+    /// A macro property that is not an argument of a referenced macro
+    UnknownProperty,
+    /// An action node of a library macro other than key, delay, text or layout
+    UnknownAction,
+    /// A device with several profiles but no `profile-switch` keybinds
+    NoProfileSwitch,
+    /// A remap whose target key is remapped again or triggers a macro, which
+    /// remapped keys do not do
+    RemapChain,
+}
+
+impl Lint {
+    /// Every lint, in the order they are documented
+    pub const ALL: [Lint; 5] = [
+        Lint::UnknownNode,
+        Lint::UnknownProperty,
+        Lint::UnknownAction,
+        Lint::NoProfileSwitch,
+        Lint::RemapChain,
+    ];
+
+    /// Lints that are errors in strict mode: unknown names are usually typos,
+    /// whose settings would silently not apply
+    pub(crate) const STRICT: [Lint; 2] = [Lint::UnknownNode, Lint::UnknownProperty];
+
+    /// The lint's stable code, as written in allow lists
+    pub fn code(self) -> &'static str {
+        match self {
+            Lint::UnknownNode => "unknown-node",
+            Lint::UnknownProperty => "unknown-property",
+            Lint::UnknownAction => "unknown-action",
+            Lint::NoProfileSwitch => "no-profile-switch",
+            Lint::RemapChain => "remap-chain",
+        }
+    }
+}
+
+impl std::fmt::Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl std::str::FromStr for Lint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Lint::ALL
+            .into_iter()
+            .find(|lint| lint.code() == s)
+            .ok_or_else(|| format!("Unknown lint: {}", s))
+    }
+}
+
+/// The codes of every lint, for help messages
+pub(crate) fn known_lints() -> String {
+    let codes: Vec<&str> = Lint::ALL.iter().map(|lint| lint.code()).collect();
+    codes.join(", ")
+}
+
+/// A lint found in a configuration, shown in context like an error
+#[derive(Debug, Clone, Error)]
+#[error("{message}{}{}", at_span(.related.as_ref()), at_span(Some(.span)))]
+pub struct ConfigWarning {
+    pub lint: Lint,
+    pub message: String,
+    /// Where the problem is, with a label and how to fix it
+    pub span: ErrorSpan,
+    /// The other rule the message is about, such as the rule a remap chain
+    /// leads to; its location is shown right after the message
+    pub related: Option<ErrorSpan>,
+}
+
+impl ConfigWarning {
+    /// The lint as an error, for strict mode
+    pub(crate) fn into_error(self) -> ConfigError {
+        match self.related {
+            Some(related) => ConfigError::Conflict {
+                message: self.message,
+                rule: Box::new(self.span),
+                other: Box::new(related),
+            },
+            None => ConfigError::Invalid { message: self.message, span: Some(Box::new(self.span)) },
+        }
+    }
+}
+
+impl Diagnostic for ConfigWarning {
+    fn code<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        Some(Box::new(self.lint.code()))
+    }
+
+    fn severity(&self) -> Option<miette::Severity> {
+        Some(miette::Severity::Warning)
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn std::fmt::Display + 'a>> {
+        Some(Box::new(&self.span.help))
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        Some(&self.span.src as &dyn SourceCode)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        self.span.labels()
+    }
+}
+
+/// Lints collected while parsing, like [`Errors`]
+#[derive(Debug, Default)]
+pub(crate) struct Lints(Vec<ConfigWarning>);
+
+impl Lints {
+    /// Add a lint about `location` in `source`
+    pub(crate) fn push(
+        &mut self,
+        lint: Lint,
+        source: &str,
+        location: SourceLocation,
+        message: impl Into<String>,
+        label: impl Into<String>,
+        help: impl Into<String>,
+    ) {
+        self.push_span(
+            lint,
+            message,
+            ErrorSpan {
+                src: SourceFile::new(source),
+                location,
+                label: label.into(),
+                help: help.into(),
+            },
+        );
+    }
+
+    /// Add a lint about a span that may already name its file
+    pub(crate) fn push_span(&mut self, lint: Lint, message: impl Into<String>, span: ErrorSpan) {
+        self.0.push(ConfigWarning { lint, message: message.into(), span, related: None });
+    }
+
+    /// Add a lint about a span whose message is about another span too
+    pub(crate) fn push_related(&mut self, lint: Lint, message: impl Into<String>, span: ErrorSpan, related: ErrorSpan) {
+        self.0.push(ConfigWarning { lint, message: message.into(), span, related: Some(related) });
+    }
+
+    /// Add the lints of `other`, naming the file they were found in unless
+    /// they already name one
+    pub(crate) fn append(&mut self, other: Lints, file: Option<&str>) {
+        for mut warning in other.0 {
+            for span in std::iter::once(&mut warning.span).chain(warning.related.as_mut()) {
+                if span.src.name.is_none() {
+                    span.src.name = file.map(str::to_string);
+                }
+            }
+            self.0.push(warning);
+        }
+    }
+
+    /// The lints that are not allowed by an annotation or by `allowed`,
+    /// in the order they were found
+    pub(crate) fn finish(self, annotations: &[AllowAnnotation], allowed: &[Lint]) -> Vec<ConfigWarning> {
+        self.0
+            .into_iter()
+            .filter(|warning| {
+                !allowed.contains(&warning.lint)
+                    && !annotations.iter().any(|annotation| annotation.allows(warning))
+            })
+            .collect()
+    }
+}
+
+/// Lints allowed for a node and its children by a type annotation
+///
+/// `(allow)node` allows every lint, `(allow:remap-chain)node` one of them and
+/// `("allow:remap-chain,unknown-node")node` several.
+#[derive(Debug)]
+pub(crate) struct AllowAnnotation {
+    /// File the node is in
+    file: Option<String>,
+    /// Byte range of the node, including its children
+    range: std::ops::Range<usize>,
+    /// The allowed lints, or `None` for all of them
+    lints: Option<Vec<Lint>>,
+}
+
+impl AllowAnnotation {
+    fn allows(&self, warning: &ConfigWarning) -> bool {
+        self.file == warning.span.src.name
+            && self.range.contains(&warning.span.location.offset)
+            && self.lints.as_ref().is_none_or(|lints| lints.contains(&warning.lint))
+    }
+}
+
+/// Collect the `allow` annotations of `nodes` and their children
+///
+/// Other type annotations are left alone. Annotations naming unknown lints
+/// are added to `errors`.
+pub(crate) fn allow_annotations(
+    nodes: &[kdl::KdlNode],
+    source: &str,
+    file: Option<&str>,
+    annotations: &mut Vec<AllowAnnotation>,
+    errors: &mut Errors,
+) {
+    for node in nodes {
+        if let Some(ty) = node.ty() {
+            let lints = match ty.value().split_once(':') {
+                None if ty.value() == "allow" => Some(Ok(None)),
+                Some(("allow", codes)) => Some(
+                    codes
+                        .split(',')
+                        .map(|code| code.trim().parse::<Lint>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map(Some),
+                ),
+                _ => None,
+            };
+            match lints {
+                Some(Ok(lints)) => annotations.push(AllowAnnotation {
+                    file: file.map(str::to_string),
+                    range: node.span().offset()..node.span().offset() + node.span().len(),
+                    lints,
+                }),
+                Some(Err(e)) => {
+                    let span = ty.span();
+                    let (line, column) = crate::parser::offset_to_line_col(source, span.offset());
+                    errors.push(
+                        ConfigError::invalid_at(
+                            source,
+                            SourceLocation::new(line, column, span.offset(), span.len()),
+                            e,
+                            "unknown lint",
+                            format!("Known lints: {}", known_lints()),
+                        )
+                        .in_file(file),
+                    );
+                }
+                None => {}
+            }
+        }
+        if let Some(children) = node.children() {
+            allow_annotations(children.nodes(), source, file, annotations, errors);
+        }
+    }
+}
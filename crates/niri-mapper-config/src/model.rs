@@ -1,17 +1,14 @@
 //! Configuration data model
 //!
-//! # Per-Application Profile Foundation (v0.4.0)
+//! # Profile Selection
 //!
-//! This module defines the configuration data structures for niri-mapper, including
-//! support for per-application profile switching. v0.4.0 implements the **foundation**
-//! for this feature:
+//! This module defines the configuration data structures for niri-mapper. Each
+//! device has named profiles, one of which is active at a time.
 //!
-//! ## Current Capabilities (v0.4.0)
-//!
-//! - **`app_id_hint` field**: Profiles can optionally include an `app_id_hint` string
-//!   that associates the profile with a specific application's `app_id` (e.g.,
-//!   `"org.mozilla.firefox"`). This field is parsed and stored but **not used for
-//!   automatic switching** in v0.4.0.
+//! - **Automatic switching**: Profiles can carry an `app-id-hint`,
+//!   `workspace-hint`, `output-hint` or `layout-hint`. The daemon follows niri's
+//!   focus, workspace and keyboard layout events and activates the matching
+//!   profile, as decided by [`DeviceConfig::select_profile`].
 //!
 //! - **Manual profile switching**: Users can switch profiles manually via:
 //!   - CLI: `niri-mapper switch-profile <device> <profile>`
@@ -21,19 +18,6 @@
 //! - **Active profile tracking**: The daemon tracks which profile is currently active
 //!   for each device.
 //!
-//! ## Future Capabilities (Backlog)
-//!
-//! Automatic profile switching based on focused application is **explicitly out of
-//! scope** for v0.4.0 and is planned for a future release. The intended behavior:
-//!
-//! 1. Listen to niri's focus change events (via IPC event stream)
-//! 2. When focus changes to a window with `app_id` matching a profile's `app_id_hint`,
-//!    automatically switch to that profile
-//! 3. When focus changes to a window with no matching profile, switch to "default"
-//!
-//! The `app_id_hint` field is provided now so users can begin annotating their
-//! profiles in preparation for this feature.
-//!
 //! ## Example Configuration
 //!
 //! ```kdl
@@ -44,7 +28,7 @@
 //!         }
 //!     }
 //!     profile "firefox" {
-//!         app-id-hint "org.mozilla.firefox"  // For future auto-switching
+//!         app-id-hint "org.mozilla.firefox"  // Activated while Firefox is focused
 //!         remap {
 //!             CapsLock "LeftCtrl"
 //!         }
@@ -56,18 +40,43 @@
 //! }
 //! ```
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::path::PathBuf;
 
+use regex::Regex;
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use serde::{Serialize, Serializer};
+
+use crate::lints::Lint;
+
 /// Root configuration structure
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
 pub struct Config {
     pub global: GlobalConfig,
     pub devices: Vec<DeviceConfig>,
+    /// Named macros from the top-level `macros` library, keyed by name
+    #[serde(serialize_with = "sorted")]
+    pub macros: HashMap<String, NamedMacro>,
+    /// Top-level `profile-template` definitions, keyed by name
+    ///
+    /// Templates are stored with their `extends` resolved. Device profiles
+    /// instantiate them with `profile "coding" template="coding"`.
+    #[serde(serialize_with = "sorted")]
+    pub profile_templates: HashMap<String, Profile>,
+}
+
+impl Config {
+    /// JSON Schema of the configuration as it is serialized, e.g. by
+    /// `niri-mapper config dump --json`
+    pub fn json_schema() -> Schema {
+        schemars::schema_for!(Config)
+    }
 }
 
 /// Global settings
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
 pub struct GlobalConfig {
     pub log_level: LogLevel,
     pub niri_keybinds_path: PathBuf,
@@ -75,20 +84,67 @@ pub struct GlobalConfig {
     pub niri_ipc_enabled: bool,
     /// Number of retry attempts for niri IPC connections (default: 3)
     pub niri_ipc_retry_count: u32,
+    /// Key that aborts every running macro on any device (default: none)
+    ///
+    /// The key is consumed by the daemon and never reaches the focused application.
+    pub macro_abort_key: Option<KeyCode>,
+    /// How physical input is handled while a macro is playing (default: off)
+    pub macro_isolation: MacroIsolation,
+    /// Key that starts and stops macro recording on the device it is pressed on
+    /// (default: none)
+    pub macro_record_key: Option<KeyCode>,
+    /// Granularity in milliseconds that recorded delays are rounded to
+    /// (default: 0, delays are kept as recorded)
+    pub macro_record_quantize: u64,
+    /// How macro `text()` actions enter characters the keyboard layout cannot
+    /// type (default: Ctrl+Shift+U)
+    pub unicode_input: UnicodeInput,
+    /// xkb keymap used to type macro text and, in keysym mode, to interpret
+    /// rule keys (default: read from niri's config)
+    pub xkb: Option<XkbSettings>,
+    /// Whether rule keys are key codes or the characters of the keyboard
+    /// layout (default: key codes)
+    pub key_mode: KeyMode,
+    /// Lints that are not reported anywhere in the configuration
+    ///
+    /// The `allow` lists of several `global` blocks add up.
+    pub allow: Vec<Lint>,
+    /// `remap`, `combo` and `macro` rules of the `global` block, applied to
+    /// every device
+    ///
+    /// The parser merges them under the rules of every device profile: a
+    /// profile's own entries win over global entries for the same key, and
+    /// `KEY null` in a profile drops a global entry. Devices without a
+    /// `default` profile get one with just these rules. Left out of
+    /// `config dump --json`, since the profiles there already hold them and
+    /// loading the dump would merge them a second time.
+    #[serde(skip_serializing)]
+    pub rules: Profile,
 }
 
 impl Default for GlobalConfig {
     fn default() -> Self {
         Self {
             log_level: LogLevel::Info,
-            niri_keybinds_path: PathBuf::from("~/.config/niri/niri-mapper-keybinds.kdl"),
+            // Expanded like a configured path, which is written to as is
+            niri_keybinds_path: shellexpand::tilde("~/.config/niri/niri-mapper-keybinds.kdl").into_owned().into(),
             niri_ipc_enabled: true,
             niri_ipc_retry_count: 3,
+            macro_abort_key: None,
+            macro_isolation: MacroIsolation::Off,
+            macro_record_key: None,
+            macro_record_quantize: 0,
+            unicode_input: UnicodeInput::CtrlShiftU,
+            xkb: None,
+            key_mode: KeyMode::Keycode,
+            allow: Vec::new(),
+            rules: Profile::default(),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
 pub enum LogLevel {
     Trace,
     Debug,
@@ -113,17 +169,279 @@ impl std::str::FromStr for LogLevel {
     }
 }
 
+/// How physical input is handled while a macro is playing
+///
+/// With isolation enabled, held modifiers are released before playback and
+/// pressed again afterwards, so they cannot leak into the macro's keystrokes.
+///
+/// ```kdl
+/// global {
+///     macro-isolation "queue"
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum MacroIsolation {
+    /// Physical input is mixed with the macro's keystrokes
+    #[default]
+    Off,
+    /// Physical input is held back and replayed after playback
+    Queue,
+    /// Physical input is discarded during playback
+    Drop,
+}
+
+impl std::str::FromStr for MacroIsolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "queue" => Ok(Self::Queue),
+            "drop" => Ok(Self::Drop),
+            _ => Err(format!(
+                "Unknown macro isolation mode: {} (expected one of: off, queue, drop)",
+                s
+            )),
+        }
+    }
+}
+
+/// How the keys in remap, combo and macro rules are interpreted
+///
+/// ```kdl
+/// global {
+///     key-mode "keysym"
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyMode {
+    /// Keys are evdev key codes, named after their position on a US layout
+    #[default]
+    Keycode,
+    /// Keys are the characters they type on the active keyboard layout
+    Keysym,
+}
+
+impl std::str::FromStr for KeyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "keycode" => Ok(Self::Keycode),
+            "keysym" => Ok(Self::Keysym),
+            _ => Err(format!("Unknown key mode: {} (expected one of: keycode, keysym)", s)),
+        }
+    }
+}
+
+/// How macro text is typed when a character has no key in the keyboard layout
+///
+/// ```kdl
+/// global {
+///     unicode-input "ctrl-shift-u"
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnicodeInput {
+    /// Ctrl+Shift+U, the hex code point and Space (GTK and IBus)
+    #[default]
+    CtrlShiftU,
+    /// Characters that cannot be typed are skipped
+    None,
+}
+
+impl std::str::FromStr for UnicodeInput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ctrl-shift-u" => Ok(Self::CtrlShiftU),
+            "none" => Ok(Self::None),
+            _ => Err(format!(
+                "Unknown unicode input method: {} (expected one of: ctrl-shift-u, none)",
+                s
+            )),
+        }
+    }
+}
+
+/// xkb keymap settings, in the form of niri's `input { keyboard { xkb { ... } } }`
+///
+/// Empty fields use the xkb defaults. When `file` is set, the keymap is
+/// loaded from that file and the other fields are ignored.
+///
+/// ```kdl
+/// global {
+///     xkb {
+///         layout "us,de"
+///         variant ",nodeadkeys"
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct XkbSettings {
+    pub rules: String,
+    pub model: String,
+    pub layout: String,
+    pub variant: String,
+    pub options: Option<String>,
+    pub file: Option<PathBuf>,
+}
+
 /// Device-specific configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
 pub struct DeviceConfig {
     /// Device name to match (from evdev)
     pub name: Option<String>,
     /// Vendor:Product ID to match (e.g., "3434:0361")
     pub vendor_product: Option<String>,
     /// Profiles for this device
+    #[serde(serialize_with = "sorted")]
     pub profiles: HashMap<String, Profile>,
-    /// Profile switch keybindings: maps key combo string (e.g., "Ctrl+Shift+1") to profile name
-    pub profile_switch: HashMap<String, String>,
+    /// Profile switch keybindings: maps key combo (e.g., `Ctrl+Shift+1`) to profile name
+    #[serde(serialize_with = "sorted")]
+    pub profile_switch: HashMap<KeyCombo, String>,
+}
+
+impl DeviceConfig {
+    /// Profile to switch to for what the user is focused on
+    ///
+    /// Profiles whose `match` rules match the focus win first: the one with
+    /// the highest `priority`, then the first by name. Otherwise, profile
+    /// hints are checked in order of precedence, and the first profile (by
+    /// name) matching the highest-precedence hint wins:
+    ///
+    /// 1. `app-id-hint` against the focused window's app ID
+    /// 2. `workspace-hint` against the focused workspace
+    /// 3. `output-hint` against the focused output
+    /// 4. `layout-hint` against the active keyboard layout
+    ///
+    /// A profile with a matching `exclude` rule is skipped by both.
+    ///
+    /// If no profile matches and the active profile was selected by a hint,
+    /// the device returns to `default`. Profiles without hints are only
+    /// switched to manually and stay active until switched away from.
+    ///
+    /// Returns `None` if the active profile should stay.
+    pub fn select_profile(&self, active_profile: &str, focus: &FocusContext) -> Option<&str> {
+        let mut names: Vec<&String> = self.profiles.keys().collect();
+        names.sort();
+
+        let best_match = names
+            .iter()
+            .filter_map(|name| {
+                self.profiles[name.as_str()]
+                    .match_priority(focus)
+                    .map(|priority| (priority, name.as_str()))
+            })
+            // Highest priority first, then the first name
+            .max_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(a.1)))
+            .map(|(_, name)| name);
+
+        let rules: [fn(&Profile, &FocusContext) -> bool; 4] = [
+            |profile, focus| focus.app_id.as_deref().is_some_and(|id| profile.matches_app_id(id)),
+            |profile, focus| profile.matches_workspace(focus.workspace_name.as_deref(), focus.workspace_idx),
+            |profile, focus| focus.output.as_deref().is_some_and(|output| profile.matches_output(output)),
+            |profile, focus| focus.layout.as_deref().is_some_and(|layout| profile.matches_layout(layout)),
+        ];
+        let matched = best_match.or_else(|| rules.iter().find_map(|rule| {
+            names
+                .iter()
+                .find(|name| {
+                    let profile = &self.profiles[name.as_str()];
+                    !profile.excludes(focus) && rule(profile, focus)
+                })
+                .map(|name| name.as_str())
+        }));
+
+        let target = match matched {
+            Some(name) => name,
+            None => {
+                let active_has_hint = self
+                    .profiles
+                    .get(active_profile)
+                    .is_some_and(Profile::has_hints);
+                if !active_has_hint || !self.profiles.contains_key("default") {
+                    return None;
+                }
+                "default"
+            }
+        };
+
+        (target != active_profile).then_some(target)
+    }
+}
+
+/// What the user is focused on, as reported by niri
+///
+/// Used with [`DeviceConfig::select_profile`] to activate profiles by their
+/// hints. Fields are `None` until niri reports them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FocusContext {
+    /// App ID of the focused window
+    pub app_id: Option<String>,
+    /// Title of the focused window
+    pub title: Option<String>,
+    /// Whether the focused window is floating (`None` if no window is focused)
+    pub is_floating: Option<bool>,
+    /// Name of the focused workspace
+    pub workspace_name: Option<String>,
+    /// Index of the focused workspace on its output (1-based, as in niri)
+    pub workspace_idx: Option<u8>,
+    /// Name of the focused output (e.g., "HDMI-A-1")
+    pub output: Option<String>,
+    /// Name of the active keyboard layout (e.g., "English (US)")
+    pub layout: Option<String>,
+}
+
+/// The machine a configuration is loaded on
+///
+/// Top-level `device` and `global` nodes guarded with
+/// `when hostname="thinkpad"` or `when env="NAME=value"` only apply when
+/// the guard matches this context.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostContext {
+    /// Host name of the machine (`None` if it cannot be determined)
+    pub hostname: Option<String>,
+    /// Environment variables
+    pub env: HashMap<String, String>,
+}
+
+impl HostContext {
+    /// The context of the machine the process is running on
+    pub fn current() -> Self {
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+            .ok()
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty());
+
+        Self {
+            hostname,
+            env: std::env::vars().collect(),
+        }
+    }
+
+    /// Whether the host name is `name`, ignoring case
+    pub fn matches_hostname(&self, name: &str) -> bool {
+        self.hostname
+            .as_deref()
+            .is_some_and(|hostname| hostname.eq_ignore_ascii_case(name))
+    }
+
+    /// Whether an `env` condition matches: `"NAME"` matches if the variable is
+    /// set and not empty, `"NAME=value"` if it has exactly that value
+    pub fn matches_env(&self, condition: &str) -> bool {
+        match condition.split_once('=') {
+            Some((name, value)) => self.env.get(name).is_some_and(|v| v == value),
+            None => self.env.get(condition).is_some_and(|v| !v.is_empty()),
+        }
+    }
 }
 
 /// A named profile containing remapping rules.
@@ -131,64 +449,764 @@ pub struct DeviceConfig {
 /// Profiles group remapping rules that can be switched at runtime. Each device
 /// can have multiple profiles, with one active at any time.
 ///
-/// # Per-Application Profile Hints (v0.4.0 Foundation)
-///
-/// The `app_id_hint` field allows associating a profile with a specific
-/// application's `app_id` (e.g., `"org.mozilla.firefox"`). This is a **foundation
-/// feature** for future automatic profile switching:
+/// # Profile Hints
 ///
-/// - **v0.4.0**: The field is parsed and stored, but automatic switching is NOT
-///   implemented. Users must switch profiles manually (via CLI, control socket,
-///   or keybinds).
-///
-/// - **Future release**: When a window gains focus, niri-mapper will check if
-///   any profile has a matching `app_id_hint` and automatically switch to it.
+/// Hints activate a profile automatically while they match what the user is
+/// focused on; see [`DeviceConfig::select_profile`] for their precedence.
 ///
 /// # Example
 ///
 /// ```kdl
 /// profile "firefox" {
-///     app-id-hint "org.mozilla.firefox"  // Parsed but not used for auto-switching yet
+///     app-id-hint "org.mozilla.firefox"
 ///     remap {
 ///         CapsLock "LeftCtrl"
 ///     }
 /// }
 /// ```
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
 pub struct Profile {
-    /// Optional app ID hint for future auto-switching based on focused application.
+    /// Profile this one extends (`profile "firefox" extends="default"`)
+    ///
+    /// The parser merges the parent's remaps, combos, macros and passthrough
+    /// keybinds into this profile, so the rule fields below hold the
+    /// effective (merged) rules. It is left out of `config dump --json`, since
+    /// loading the dump would merge the parent's rules a second time.
+    #[serde(skip_serializing)]
+    pub extends: Option<String>,
+    /// Profile template this profile instantiates (`profile "coding" template="coding"`)
     ///
-    /// When set, this profile is intended to be activated when a window with a
-    /// matching `app_id` gains focus. This field is parsed in v0.4.0 but automatic
-    /// switching based on it is **not yet implemented** (backlog).
+    /// Like [`Profile::extends`], the template's rules are merged into this
+    /// profile. Its hints and `match`/`exclude` rules are inherited too, so
+    /// every device using the template switches to it under the same conditions.
+    /// Left out of `config dump --json` for the same reason as `extends`.
+    #[serde(skip_serializing)]
+    pub template: Option<String>,
+    /// App ID that activates this profile while its window is focused
     ///
-    /// To use this profile now, switch to it manually via CLI, control socket,
-    /// or configure a `profile-switch` keybind.
+    /// Matches the focused window's `app_id` exactly (e.g., `"org.mozilla.firefox"`).
     pub app_id_hint: Option<String>,
+    /// Keyboard layout that activates this profile
+    ///
+    /// When niri switches to a layout whose name contains the hint (ignoring
+    /// case, e.g. `"russian"` matches "Russian (phonetic)"), the daemon switches
+    /// every device that has this profile to it. Switching to a layout no
+    /// profile hints at returns those devices to the `default` profile.
+    pub layout_hint: Option<String>,
+    /// Workspace that activates this profile
+    ///
+    /// Matches the focused workspace's name, or its index on its output if
+    /// the hint is a number (e.g., `"games"` or `"3"`).
+    pub workspace_hint: Option<String>,
+    /// Output (monitor) that activates this profile, e.g. `"HDMI-A-1"`
+    ///
+    /// Matches while the focused workspace is on this output.
+    pub output_hint: Option<String>,
+    /// `match` rules: the profile applies to windows matching any of them
+    pub match_rules: Vec<WindowMatch>,
+    /// `exclude` rules: the profile never applies to windows matching any of them
+    pub exclude_rules: Vec<WindowMatch>,
     /// Simple 1:1 key remaps
-    pub remap: HashMap<String, String>,
+    #[serde(serialize_with = "sorted")]
+    pub remap: HashMap<KeyCode, KeyCode>,
     /// Key combination remaps
-    pub combo: HashMap<String, String>,
-    /// Macro definitions (key -> sequence of actions)
-    pub macros: HashMap<String, Vec<MacroAction>>,
+    #[serde(serialize_with = "sorted")]
+    pub combo: HashMap<KeyCombo, KeyCombo>,
+    /// Macro definitions (trigger key -> sequence of actions)
+    #[serde(serialize_with = "sorted")]
+    pub macros: HashMap<KeyCode, Vec<MacroAction>>,
+    /// Concurrency policy per macro trigger key
+    ///
+    /// Only macros with an explicit `policy=` property have an entry here;
+    /// all other macros use [`MacroPolicy::default()`].
+    #[serde(serialize_with = "sorted")]
+    pub macro_policies: HashMap<KeyCode, MacroPolicy>,
+    /// `remap`, `combo` and `macro` entries with `when` conditions, in config order
+    pub conditional_rules: Vec<ConditionalRule>,
     /// Keys to pass through to niri with their actions
     pub niri_passthrough: Vec<NiriKeybind>,
 }
 
+/// A key or button, identified by its evdev key code
+///
+/// Parsed from any name the key registry knows (see [`crate::key_code`]) and
+/// displayed by its preferred name, so `Esc` and `KEY_ESC` both display as
+/// `Escape`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct KeyCode(pub u16);
+
+impl KeyCode {
+    /// The evdev key code
+    pub fn code(self) -> u16 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for KeyCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match crate::keys::key_name(self.0) {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl std::str::FromStr for KeyCode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        crate::keys::key_code(s).map(KeyCode).ok_or_else(|| {
+            match crate::keys::suggest_key(s) {
+                Some(suggestion) => format!("Unknown key '{}' (did you mean '{}'?)", s, suggestion),
+                None => format!("Unknown key '{}'", s),
+            }
+        })
+    }
+}
+
+/// Serialized by its display name, e.g. `"Escape"`
+impl Serialize for KeyCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl JsonSchema for KeyCode {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "KeyCode".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "type": "string",
+            "description": "A key name, e.g. \"Escape\", \"XF86AudioPlay\" or \"BTN_EXTRA\"",
+        })
+    }
+}
+
+/// A modifier of a [`KeyCombo`]
+///
+/// Left and right variants are not distinguished: `Ctrl` is held when either
+/// Ctrl key is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Modifier {
+    Ctrl,
+    Shift,
+    Alt,
+    Super,
+}
+
+impl Modifier {
+    /// The left key of this modifier, which is pressed to emit it
+    pub fn key(self) -> KeyCode {
+        // Codes from linux/input-event-codes.h
+        KeyCode(match self {
+            Self::Ctrl => 29,
+            Self::Shift => 42,
+            Self::Alt => 56,
+            Self::Super => 125,
+        })
+    }
+}
+
+impl std::fmt::Display for Modifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Ctrl => "Ctrl",
+            Self::Shift => "Shift",
+            Self::Alt => "Alt",
+            Self::Super => "Super",
+        })
+    }
+}
+
+impl std::str::FromStr for Modifier {
+    type Err = String;
+
+    /// Parse a modifier name, ignoring case: `Ctrl`/`Control`, `Shift`, `Alt`
+    /// or `Super`/`Meta`/`Mod`/`Win`/`Windows`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "CTRL" | "CONTROL" => Ok(Self::Ctrl),
+            "SHIFT" => Ok(Self::Shift),
+            "ALT" => Ok(Self::Alt),
+            "SUPER" | "META" | "MOD" | "WIN" | "WINDOWS" => Ok(Self::Super),
+            _ => Err(format!("Unknown modifier '{}'", s)),
+        }
+    }
+}
+
+/// A key with the modifiers held while it is pressed, e.g. `Ctrl+Shift+Q`
+///
+/// Modifiers may be written in any order around the key. A lone modifier
+/// name such as `Super` is the modifier's key itself, with no modifiers held.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct KeyCombo {
+    /// Modifiers that are held
+    pub modifiers: BTreeSet<Modifier>,
+    /// The key that is pressed
+    pub key: KeyCode,
+}
+
+impl KeyCombo {
+    /// A combo of a single key, without modifiers
+    pub fn new(key: KeyCode) -> Self {
+        Self {
+            modifiers: BTreeSet::new(),
+            key,
+        }
+    }
+}
+
+impl std::fmt::Display for KeyCombo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for modifier in &self.modifiers {
+            write!(f, "{}+", modifier)?;
+        }
+        write!(f, "{}", self.key)
+    }
+}
+
+impl std::str::FromStr for KeyCombo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        if parts.iter().any(|part| part.is_empty()) {
+            return Err(format!("Invalid key combo '{}': empty component", s));
+        }
+        if let [single] = parts.as_slice() {
+            if let Ok(modifier) = single.parse::<Modifier>() {
+                return Ok(Self::new(
+                    single.parse().unwrap_or_else(|_| modifier.key()),
+                ));
+            }
+        }
+
+        let mut modifiers = BTreeSet::new();
+        let mut key = None;
+        for part in parts {
+            if let Ok(modifier) = part.parse::<Modifier>() {
+                if !modifiers.insert(modifier) {
+                    return Err(format!("Invalid key combo '{}': duplicate modifier {}", s, modifier));
+                }
+            } else if key.is_some() {
+                return Err(format!(
+                    "Invalid key combo '{}': multiple non-modifier keys ('{}')",
+                    s, part
+                ));
+            } else {
+                key = Some(part.parse::<KeyCode>()?);
+            }
+        }
+
+        match key {
+            Some(key) => Ok(Self { modifiers, key }),
+            None => Err(format!("Invalid key combo '{}': only modifiers, no trigger key", s)),
+        }
+    }
+}
+
+/// Serialized as written, e.g. `"Ctrl+Shift+Q"`
+impl Serialize for KeyCombo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl JsonSchema for KeyCombo {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "KeyCombo".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "type": "string",
+            "description": "A key with the modifiers held, e.g. \"Ctrl+Shift+Q\"",
+        })
+    }
+}
+
 /// A single action in a macro sequence
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MacroAction {
     /// Press and release a key/combo
-    Key(String),
+    Key(KeyCombo),
     /// Delay in milliseconds
     Delay(u64),
+    /// Type a string of (Unicode) text using the active keyboard layout
+    Text(String),
+    /// Ask niri to switch the keyboard layout
+    SwitchLayout(LayoutSwitch),
+}
+
+impl std::fmt::Display for MacroAction {
+    /// Formats the action as written in a `macro` block (e.g., `Ctrl+c`, `delay(50)`
+    /// or `text(café)`)
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Key(combo) => write!(f, "{}", combo),
+            Self::Delay(ms) => write!(f, "delay({})", ms),
+            Self::Text(text) => write!(f, "text({})", text),
+            Self::SwitchLayout(layout) => write!(f, "layout({})", layout),
+        }
+    }
+}
+
+impl std::str::FromStr for MacroAction {
+    type Err = String;
+
+    /// Parse an action as written in a `macro` block: a key or combo (e.g.,
+    /// `Ctrl+C`), `delay(ms)`, `text(...)` or `layout(...)`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let argument = |name: &str| {
+            s.strip_prefix(name)
+                .and_then(|rest| rest.strip_prefix('('))
+                .and_then(|rest| rest.strip_suffix(')'))
+        };
+
+        if let Some(ms) = argument("delay") {
+            return match ms.parse::<u64>() {
+                Ok(0) => Err(format!(
+                    "Invalid delay value '{}': delay must be a positive integer (got 0)",
+                    s
+                )),
+                Ok(ms) if ms > MAX_MACRO_DELAY_MS => Err(format!(
+                    "Invalid delay value '{}': maximum delay is {}ms (10 seconds), got {}ms",
+                    s, MAX_MACRO_DELAY_MS, ms
+                )),
+                Ok(ms) => Ok(Self::Delay(ms)),
+                Err(_) => Err(format!(
+                    "Invalid delay value '{}': delay must be a positive integer",
+                    s
+                )),
+            };
+        }
+        if let Some(text) = argument("text") {
+            if text.is_empty() {
+                return Err("Invalid macro action 'text()': text must not be empty".to_string());
+            }
+            return Ok(Self::Text(text.to_string()));
+        }
+        if let Some(target) = argument("layout") {
+            return target
+                .parse()
+                .map(Self::SwitchLayout)
+                .map_err(|e| format!("Invalid macro action '{}': {}", s, e));
+        }
+        s.parse().map(Self::Key)
+    }
+}
+
+/// Serialized as written in a `macro` block, e.g. `"delay(50)"`
+impl Serialize for MacroAction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl JsonSchema for MacroAction {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "MacroAction".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "type": "string",
+            "description": "A key or combo to press, \"delay(ms)\", \"text(...)\" or \"layout(next|prev|index)\"",
+        })
+    }
+}
+
+/// Longest delay a macro `delay(ms)` action may have
+pub const MAX_MACRO_DELAY_MS: u64 = 10000;
+
+/// Keyboard layout to switch to with a `layout(...)` macro action
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutSwitch {
+    /// The next configured layout
+    Next,
+    /// The previous configured layout
+    Prev,
+    /// The layout at an index in niri's layout list
+    Index(u8),
+}
+
+impl std::fmt::Display for LayoutSwitch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Next => f.write_str("next"),
+            Self::Prev => f.write_str("prev"),
+            Self::Index(idx) => write!(f, "{}", idx),
+        }
+    }
+}
+
+impl std::str::FromStr for LayoutSwitch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "next" => Ok(Self::Next),
+            "prev" => Ok(Self::Prev),
+            other => other.parse().map(Self::Index).map_err(|_| {
+                format!("Unknown layout: {} (expected next, prev or a layout index)", s)
+            }),
+        }
+    }
+}
+
+impl Profile {
+    /// Whether this profile has any hint for automatic activation
+    pub fn has_hints(&self) -> bool {
+        self.app_id_hint.is_some()
+            || self.layout_hint.is_some()
+            || self.workspace_hint.is_some()
+            || self.output_hint.is_some()
+            || !self.match_rules.is_empty()
+    }
+
+    /// Priority with which this profile's `match` rules select it
+    ///
+    /// Returns the highest priority of the matching `match` rules, or `None`
+    /// if none matches or an `exclude` rule matches.
+    pub fn match_priority(&self, focus: &FocusContext) -> Option<i32> {
+        if self.excludes(focus) {
+            return None;
+        }
+        self.match_rules
+            .iter()
+            .filter(|rule| rule.matches(focus))
+            .map(|rule| rule.priority)
+            .max()
+    }
+
+    /// Whether one of this profile's `exclude` rules matches the focus
+    pub fn excludes(&self, focus: &FocusContext) -> bool {
+        self.exclude_rules.iter().any(|rule| rule.matches(focus))
+    }
+
+    /// Whether this profile's `app-id-hint` matches a window's app ID
+    pub fn matches_app_id(&self, app_id: &str) -> bool {
+        self.app_id_hint.as_deref() == Some(app_id)
+    }
+
+    /// Whether this profile's `workspace-hint` matches a workspace
+    pub fn matches_workspace(&self, name: Option<&str>, idx: Option<u8>) -> bool {
+        self.workspace_hint
+            .as_deref()
+            .is_some_and(|hint| workspace_matches(hint, name, idx))
+    }
+
+    /// Whether this profile's `output-hint` matches an output name
+    pub fn matches_output(&self, output: &str) -> bool {
+        self.output_hint.as_deref() == Some(output)
+    }
+
+    /// Whether this profile's `layout-hint` matches a niri keyboard layout name
+    pub fn matches_layout(&self, layout_name: &str) -> bool {
+        self.layout_hint
+            .as_deref()
+            .is_some_and(|hint| layout_matches(hint, layout_name))
+    }
+}
+
+/// Whether a keyboard layout name contains a pattern, ignoring case
+fn layout_matches(pattern: &str, layout_name: &str) -> bool {
+    layout_name.to_lowercase().contains(&pattern.to_lowercase())
+}
+
+/// Whether a workspace name, or index if numeric, refers to a workspace
+fn workspace_matches(pattern: &str, name: Option<&str>, idx: Option<u8>) -> bool {
+    match pattern.parse::<u8>() {
+        Ok(pattern_idx) => idx == Some(pattern_idx),
+        Err(_) => name == Some(pattern),
+    }
+}
+
+/// A `match` or `exclude` rule on the focused window
+///
+/// All criteria that are set must match. `app-id` and `title` are regular
+/// expressions, searched anywhere in the value unless anchored:
+///
+/// ```kdl
+/// profile "youtube" {
+///     match app-id="^firefox$" title="YouTube" priority=10
+///     exclude is-floating=true
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct WindowMatch {
+    /// Regex on the window's app ID
+    #[serde(serialize_with = "regex_pattern")]
+    #[schemars(with = "Option<String>")]
+    pub app_id: Option<Regex>,
+    /// Regex on the window's title
+    #[serde(serialize_with = "regex_pattern")]
+    #[schemars(with = "Option<String>")]
+    pub title: Option<Regex>,
+    /// Whether the window must be floating (`true`) or tiled (`false`)
+    pub is_floating: Option<bool>,
+    /// Workspace name, or index on its output if numeric
+    pub workspace: Option<String>,
+    /// Output (monitor) name
+    pub output: Option<String>,
+    /// Keyboard layout, matched like `layout-hint`: the active layout's name
+    /// contains it, ignoring case
+    pub layout: Option<String>,
+    /// Priority among matching profiles (default 0, higher wins)
+    pub priority: i32,
+}
+
+impl WindowMatch {
+    /// Whether the rule matches what the user is focused on
+    pub fn matches(&self, focus: &FocusContext) -> bool {
+        let regex_matches = |regex: &Option<Regex>, value: &Option<String>| match regex {
+            Some(regex) => value.as_deref().is_some_and(|value| regex.is_match(value)),
+            None => true,
+        };
+
+        regex_matches(&self.app_id, &focus.app_id)
+            && regex_matches(&self.title, &focus.title)
+            && self
+                .is_floating
+                .is_none_or(|floating| focus.is_floating == Some(floating))
+            && self.workspace.as_deref().is_none_or(|workspace| {
+                workspace_matches(workspace, focus.workspace_name.as_deref(), focus.workspace_idx)
+            })
+            && self
+                .output
+                .as_deref()
+                .is_none_or(|output| focus.output.as_deref() == Some(output))
+            && self
+                .layout
+                .as_deref()
+                .is_none_or(|layout| focus.layout.as_deref().is_some_and(|name| layout_matches(layout, name)))
+    }
+}
+
+/// A `remap`, `combo` or `macro` entry that only applies under conditions
+///
+/// An entry with a child block of `when` conditions is checked on every key
+/// press against what the user is focused on. `when` takes the same
+/// properties as a `match` rule (except `priority`), and the entry applies
+/// while any of its conditions matches. Matching conditional entries take
+/// precedence over the profile's unconditional entries, and the first one in
+/// config order wins:
+///
+/// ```kdl
+/// profile "default" {
+///     remap {
+///         CapsLock "Escape"
+///         CapsLock "LeftCtrl" {
+///             when app-id="^Alacritty$"
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConditionalRule {
+    /// Conditions, any of which makes the entry apply
+    pub when: Vec<WindowMatch>,
+    /// The entry itself
+    pub entry: ConditionalEntry,
+}
+
+impl ConditionalRule {
+    /// Whether the entry applies to what the user is focused on
+    pub fn applies(&self, focus: &FocusContext) -> bool {
+        self.when.iter().any(|condition| condition.matches(focus))
+    }
+}
+
+/// The entry of a [`ConditionalRule`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConditionalEntry {
+    /// A `remap` entry (e.g., `CapsLock` -> `LeftCtrl`)
+    Remap { from: KeyCode, to: KeyCode },
+    /// A `combo` entry (e.g., `Ctrl+Q` -> `Alt+F4`)
+    Combo { from: KeyCombo, to: KeyCombo },
+    /// A `macro` entry
+    Macro {
+        trigger: KeyCode,
+        actions: Vec<MacroAction>,
+        policy: MacroPolicy,
+    },
+}
+
+/// A reusable macro defined in the top-level `macros` library
+///
+/// Profile `macro` blocks reference library macros by name with the `macro`
+/// property, and `niri-mapper run-macro <name>` plays them over IPC.
+/// Parameters are declared as properties with a default value and are
+/// substituted into key actions wherever `{name}` appears:
+///
+/// ```kdl
+/// macros {
+///     copy-paste {
+///         key "Ctrl+c"
+///         delay 50
+///         key "Ctrl+v"
+///     }
+///     switch-tab n="1" "Alt+{n}"
+/// }
+///
+/// device "My Keyboard" {
+///     profile "default" {
+///         macro {
+///             F5 macro="copy-paste"
+///             F6 macro="switch-tab" n="3"
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct NamedMacro {
+    /// Declared parameters, in declaration order
+    pub params: Vec<MacroParam>,
+    /// Action sequence as written in a `macro` block (e.g., `Alt+{n}` or
+    /// `delay(50)`), since actions may contain `{param}` placeholders
+    ///
+    /// Actions are parsed into [`MacroAction`]s by [`NamedMacro::instantiate`].
+    pub actions: Vec<String>,
+}
+
+/// A parameter of a [`NamedMacro`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct MacroParam {
+    /// Parameter name, referenced as `{name}` in key actions
+    pub name: String,
+    /// Value used when the caller does not pass one
+    pub default: Option<String>,
+}
+
+impl NamedMacro {
+    /// Substitute parameter values into the macro's actions and parse them.
+    ///
+    /// `args` overrides parameter defaults. Fails if an argument does not
+    /// match a declared parameter, a parameter has no value or an action
+    /// does not parse once substituted (e.g., an unknown key).
+    pub fn instantiate(&self, args: &HashMap<String, String>) -> Result<Vec<MacroAction>, String> {
+        self.substitute(args)?
+            .iter()
+            .map(|action| action.parse())
+            .collect()
+    }
+
+    /// Substitute parameter values into the macro's actions, returning them
+    /// as written in a `macro` block.
+    ///
+    /// `args` overrides parameter defaults. Fails if an argument does not
+    /// match a declared parameter or a parameter has no value.
+    pub fn substitute(&self, args: &HashMap<String, String>) -> Result<Vec<String>, String> {
+        if let Some(unknown) = args.keys().find(|name| !self.params.iter().any(|p| &p.name == *name)) {
+            return Err(format!("unknown parameter '{}'", unknown));
+        }
+
+        let mut values = Vec::with_capacity(self.params.len());
+        for param in &self.params {
+            match args.get(&param.name).or(param.default.as_ref()) {
+                Some(value) => values.push((format!("{{{}}}", param.name), value)),
+                None => return Err(format!("missing value for parameter '{}'", param.name)),
+            }
+        }
+
+        Ok(self
+            .actions
+            .iter()
+            .map(|action| {
+                let mut action = action.clone();
+                for (placeholder, value) in &values {
+                    action = action.replace(placeholder.as_str(), value);
+                }
+                action
+            })
+            .collect())
+    }
+}
+
+/// What to do when a macro is triggered while a previous run is still playing
+///
+/// Configured per macro with the `policy` property:
+///
+/// ```kdl
+/// macro {
+///     F5 "Ctrl+c" "delay(50)" "Ctrl+v" policy="restart"
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum MacroPolicy {
+    /// Ignore the new trigger until the running copy has finished
+    #[default]
+    Ignore,
+    /// Abort the running copy and start again from the first action
+    Restart,
+    /// Run the macro again once the running copy has finished
+    Queue,
+    /// Abort the running copy as soon as the trigger key is released
+    CancelOnRelease,
+}
+
+impl MacroPolicy {
+    /// The name of this policy as written in the configuration file
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ignore => "ignore",
+            Self::Restart => "restart",
+            Self::Queue => "queue",
+            Self::CancelOnRelease => "cancel-on-release",
+        }
+    }
+}
+
+impl std::fmt::Display for MacroPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for MacroPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ignore" => Ok(Self::Ignore),
+            "restart" => Ok(Self::Restart),
+            "queue" => Ok(Self::Queue),
+            "cancel-on-release" => Ok(Self::CancelOnRelease),
+            _ => Err(format!(
+                "Unknown macro policy: {} (expected one of: ignore, restart, queue, cancel-on-release)",
+                s
+            )),
+        }
+    }
 }
 
 /// A keybind to pass through to niri
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct NiriKeybind {
     /// The key combination (e.g., "Super+Return")
     pub key: String,
     /// The niri action (e.g., "spawn \"alacritty\"")
     pub action: String,
 }
+
+/// Serialize a map with its keys sorted, so that the output is stable
+fn sorted<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Ord + Serialize,
+    V: Serialize,
+    S: Serializer,
+{
+    serializer.collect_map(map.iter().collect::<std::collections::BTreeMap<_, _>>())
+}
+
+/// Serialize a regex by its pattern
+fn regex_pattern<S: Serializer>(regex: &Option<Regex>, serializer: S) -> Result<S::Ok, S::Error> {
+    regex.as_ref().map(Regex::as_str).serialize(serializer)
+}
@@ -2,36 +2,29 @@
 //!
 //! This module parses niri-mapper configuration files written in KDL format.
 //!
-//! # Per-Application Profile Parsing (v0.4.0)
+//! # Profile Hints
 //!
-//! The parser supports the `app-id-hint` field within profile blocks:
+//! Profiles can carry hints that activate them automatically from niri focus
+//! events (see [`DeviceConfig::select_profile`]):
 //!
 //! ```kdl
 //! profile "firefox" {
 //!     app-id-hint "org.mozilla.firefox"
+//!     workspace-hint "web"      // workspace name, or index if a number
+//!     output-hint "HDMI-A-1"
+//!     layout-hint "russian"
 //!     remap {
 //!         CapsLock "LeftCtrl"
 //!     }
 //! }
 //! ```
 //!
-//! ## Current Behavior (v0.4.0)
+//! Hints are stored in the [`Profile`] as strings; the parser does not check
+//! them against running apps, workspaces or outputs.
 //!
-//! - The `app-id-hint` field is parsed and stored in [`Profile::app_id_hint`]
-//! - The value is validated to be a string
-//! - **No automatic behavior** is triggered by this field; it is stored for
-//!   future use
+//! ## Manual Profile Switching
 //!
-//! ## Future Behavior (Backlog)
-//!
-//! In a future release, the daemon will:
-//! 1. Monitor niri focus change events
-//! 2. Match focused window's `app_id` against profiles' `app_id_hint` values
-//! 3. Automatically switch to the matching profile
-//!
-//! ## Manual Profile Switching (v0.4.0)
-//!
-//! Until automatic switching is implemented, users can switch profiles via:
+//! Profiles can also be switched manually via:
 //!
 //! - **Keybinds**: Configure `profile-switch` block in device config
 //! - **CLI**: `niri-mapper switch-profile <device> <profile>`
@@ -39,8 +32,11 @@
 //!
 //! See [`crate::model`] module documentation for more details.
 
-use std::path::Path;
-use crate::error::{ConfigError, InvalidKeyInfo, KeyPosition, SourceLocation};
+use std::path::{Path, PathBuf};
+use crate::conflicts::{duplicate_entry, ConflictChecker, RuleSite, RuleSites};
+use crate::error::{ConfigError, Errors, InvalidKeyInfo, KeyPosition, SourceFile, SourceLocation};
+use crate::formats::{Format, Translation};
+use crate::lints::{allow_annotations, known_lints, AllowAnnotation, ConfigWarning, Lint, Lints};
 use crate::model::*;
 
 /// Extract source location from a KDL node's name span
@@ -67,8 +63,177 @@ fn get_entry_location(entry: &kdl::KdlEntry, source: &str) -> SourceLocation {
     SourceLocation::new(line, column, offset, len)
 }
 
+/// An error about a node, shown at the node's name
+fn node_error(
+    node: &kdl::KdlNode,
+    source: &str,
+    message: impl Into<String>,
+    label: impl Into<String>,
+    help: impl Into<String>,
+) -> ConfigError {
+    ConfigError::invalid_at(source, get_node_location(node, source), message, label, help)
+}
+
+/// An error about an argument or property of a node, shown at the entry
+fn entry_error(
+    entry: &kdl::KdlEntry,
+    source: &str,
+    message: impl Into<String>,
+    label: impl Into<String>,
+    help: impl Into<String>,
+) -> ConfigError {
+    ConfigError::invalid_at(source, get_entry_location(entry, source), message, label, help)
+}
+
+/// Top-level nodes, other than `include` which is expanded while loading
+const TOP_LEVEL_NODES: &[&str] = &["version", "global", "device", "macros", "profile-template"];
+
+/// Configuration version written by `niri-mapper migrate`
+///
+/// Files without a `version` node are version 1. Older versions still load;
+/// `migrate` rewrites options they use that have a newer replacement.
+pub const CONFIG_VERSION: u32 = 2;
+
+/// The version of a top-level `version` node, which must not be newer than
+/// [`CONFIG_VERSION`]
+pub(crate) fn config_version(node: &kdl::KdlNode, source: &str) -> Result<u32, ConfigError> {
+    let entry = node.entries().first().ok_or_else(|| {
+        node_error(
+            node,
+            source,
+            "version requires a number",
+            "missing version",
+            format!("Use `version {}`", CONFIG_VERSION),
+        )
+    })?;
+    match entry.value().as_i64() {
+        Some(version) if (1..=CONFIG_VERSION as i64).contains(&version) => Ok(version as u32),
+        Some(version) if version > CONFIG_VERSION as i64 => Err(entry_error(
+            entry,
+            source,
+            format!(
+                "Configuration version {} is newer than this niri-mapper, which reads up to version {}",
+                version, CONFIG_VERSION
+            ),
+            "unsupported version",
+            "Update niri-mapper to load this configuration",
+        )),
+        _ => Err(entry_error(
+            entry,
+            source,
+            format!("Invalid configuration version '{}'", kdl_value_to_string(entry.value())),
+            "invalid version",
+            format!("Use a version from 1 to {}", CONFIG_VERSION),
+        )),
+    }
+}
+
+/// Child nodes of an `xkb` block
+const XKB_OPTIONS: &[&str] = &["rules", "model", "layout", "variant", "options", "file"];
+
+/// Child nodes of a `global` block
+const GLOBAL_OPTIONS: &[&str] = &[
+    "log-level",
+    "niri-keybinds-path",
+    "niri-ipc-enabled",
+    "niri-ipc-retry-count",
+    "macro-abort-key",
+    "macro-record-key",
+    "macro-record-quantize",
+    "macro-isolation",
+    "unicode-input",
+    "xkb",
+    "key-mode",
+    "allow",
+    "remap",
+    "combo",
+    "macro",
+    "when",
+];
+
+/// Child nodes of a `device` block
+const DEVICE_OPTIONS: &[&str] = &["vendor-product", "profile", "profile-switch", "when"];
+
+/// Child nodes of a profile
+const PROFILE_OPTIONS: &[&str] = &[
+    "app-id-hint",
+    "layout-hint",
+    "workspace-hint",
+    "output-hint",
+    "match",
+    "exclude",
+    "remap",
+    "combo",
+    "macro",
+    "niri-passthrough",
+];
+
+/// Properties of a profile
+const PROFILE_PROPERTIES: &[&str] = &["extends", "template"];
+
+/// Child nodes of a library macro, which are its actions
+const LIBRARY_ACTIONS: &[&str] = &["key", "delay", "text", "layout"];
+
+/// Node names known as children of the nodes named `parents`, outermost
+/// first, e.g. for completion
+///
+/// `&[]` gives the top-level nodes and `&["device", "profile"]` the options
+/// of a profile. Entries of `remap`, `combo` and `macro` blocks take `when`
+/// conditions. Nodes whose children are keys or free-form names have none.
+pub fn node_names(parents: &[&str]) -> Vec<&'static str> {
+    match parents {
+        [] => std::iter::once("include").chain(TOP_LEVEL_NODES.iter().copied()).collect(),
+        ["global"] => GLOBAL_OPTIONS.to_vec(),
+        ["global", "xkb"] => XKB_OPTIONS.to_vec(),
+        ["device"] => DEVICE_OPTIONS.to_vec(),
+        ["device", "profile"] | ["profile-template"] => PROFILE_OPTIONS.to_vec(),
+        ["macros", _] => LIBRARY_ACTIONS.to_vec(),
+        [.., "remap" | "combo" | "macro", _] => vec!["when"],
+        _ => Vec::new(),
+    }
+}
+
+/// A " (did you mean 'X'?)" hint for an unknown name, if one of `known` is close
+fn did_you_mean(name: &str, known: &[&str]) -> String {
+    crate::keys::closest_name(name, known.iter().copied())
+        .map(|suggestion| format!(" (did you mean '{}'?)", suggestion))
+        .unwrap_or_default()
+}
+
+/// Lint a node that is not one of the `known` children of its parent
+///
+/// `what` names the node in the message, e.g. "profile option".
+fn lint_unknown_node(node: &kdl::KdlNode, source: &str, what: &str, known: &[&str], lints: &mut Lints) {
+    let name = node.name().value();
+    lints.push(
+        Lint::UnknownNode,
+        source,
+        get_node_location(node, source),
+        format!("Unknown {} '{}'{}", what, name, did_you_mean(name, known)),
+        format!("unknown {}", what),
+        format!("Expected one of: {}", known.join(", ")),
+    );
+}
+
+/// Lint the properties of a node that takes none
+fn lint_properties(node: &kdl::KdlNode, source: &str, lints: &mut Lints) {
+    for entry in node.entries() {
+        let Some(property) = entry.name() else {
+            continue;
+        };
+        lints.push(
+            Lint::UnknownProperty,
+            source,
+            get_entry_location(entry, source),
+            format!("Unknown property '{}' on {}", property.value(), node.name().value()),
+            "unknown property",
+            format!("{} takes no properties; pass its value as an argument", node.name().value()),
+        );
+    }
+}
+
 /// Convert byte offset to line and column (1-indexed)
-fn offset_to_line_col(source: &str, offset: usize) -> (usize, usize) {
+pub(crate) fn offset_to_line_col(source: &str, offset: usize) -> (usize, usize) {
     let mut line = 1;
     let mut col = 1;
 
@@ -87,102 +252,930 @@ fn offset_to_line_col(source: &str, offset: usize) -> (usize, usize) {
     (line, col)
 }
 
+/// Name of the drop-in directory next to the main configuration file
+const DROP_IN_DIR: &str = "config.d";
+
 /// Parse a configuration file from the given path
+///
+/// `.json` and `.toml` files are read as the configuration `config dump
+/// --json` prints, with every field optional, and validated like KDL; see
+/// the README for their format. Any other file is read as KDL.
+///
+/// Files included with `include "path.kdl"` are loaded in place of the
+/// `include` node, with relative paths resolved against the including file.
+/// Afterwards, the `*.kdl`, `*.json` and `*.toml` files of the `config.d`
+/// directory next to the configuration file are loaded in file name order.
+///
+/// The files are merged in that order: `global` options set later override
+/// earlier ones, while devices and library macros may only be defined once.
+///
+/// `device` and `global` nodes with `when` guards that do not match the
+/// current host are left out.
+///
+/// Lints that are not allowed are logged as warnings; use
+/// [`parse_config_with_warnings`] to report them yourself.
 pub fn parse_config(path: &Path) -> Result<Config, ConfigError> {
+    parse_config_for_host(path, &HostContext::current())
+}
+
+/// Parse a configuration file as if it were loaded on `host`
+///
+/// Like [`parse_config`], but `when` guards are evaluated against `host`
+/// instead of the current machine.
+pub fn parse_config_for_host(path: &Path, host: &HostContext) -> Result<Config, ConfigError> {
+    parse_config_with_warnings(path, host, false).map(log_warnings)
+}
+
+/// Parse a configuration file as if it were loaded on `host`, returning the
+/// lints that are not allowed instead of logging them
+///
+/// In `strict` mode, unknown nodes and properties that are not allowed are
+/// errors rather than lints.
+pub fn parse_config_with_warnings(
+    path: &Path,
+    host: &HostContext,
+    strict: bool,
+) -> Result<(Config, Vec<ConfigWarning>), ConfigError> {
     let content = std::fs::read_to_string(path)?;
-    parse_config_str(&content)
+    load_config_at(&content, path, host, strict)
+}
+
+/// Parse configuration source as if it were stored at `path`
+///
+/// Used to validate an edited configuration before writing it, with its
+/// includes and drop-in files.
+pub(crate) fn parse_config_at(
+    content: &str,
+    path: &Path,
+    host: &HostContext,
+) -> Result<Config, ConfigError> {
+    load_config_at(content, path, host, false).map(log_warnings)
+}
+
+/// Parse the configuration file at `path` as if `file`, which it includes or
+/// which is in its `config.d` directory, had `content`
+///
+/// Used to validate an edit of an included file before writing it.
+pub(crate) fn parse_config_replacing(
+    path: &Path,
+    file: &Path,
+    content: &str,
+    host: &HostContext,
+) -> Result<Config, ConfigError> {
+    if file == path {
+        return parse_config_at(content, path, host);
+    }
+    let mut loader = ConfigLoader::new(host, false);
+    let file = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+    loader.replacement = Some((file, content.to_string()));
+    loader.load_with_drop_ins(std::fs::read_to_string(path)?, path)?;
+    loader.build().map(log_warnings)
+}
+
+/// The file defining the device named `device` in the configuration at
+/// `path`, which may be an included or drop-in file
+///
+/// `None` if no device of this host has that name.
+pub(crate) fn device_file(path: &Path, device: &str, host: &HostContext) -> Result<Option<PathBuf>, ConfigError> {
+    let mut loader = ConfigLoader::new(host, false);
+    loader.load_with_drop_ins(std::fs::read_to_string(path)?, path)?;
+    let file = loader.nodes.iter().find_map(|&(file, node)| {
+        let node = &loader.files[file].doc.nodes()[node];
+        let name = node.get(0).and_then(|entry| entry.value().as_string());
+        (node.name().value() == "device" && name == Some(device)).then_some(file)
+    });
+    Ok(file.and_then(|file| loader.files[file].name.as_ref()).map(PathBuf::from))
+}
+
+fn load_config_at(
+    content: &str,
+    path: &Path,
+    host: &HostContext,
+    strict: bool,
+) -> Result<(Config, Vec<ConfigWarning>), ConfigError> {
+    let mut loader = ConfigLoader::new(host, strict);
+    loader.load_with_drop_ins(content.to_string(), path)?;
+    loader.build()
+}
+
+/// Parse configuration source as if it were stored at `path`, returning the
+/// lints that are not allowed instead of logging them
+///
+/// Used for unsaved changes, e.g. in an editor: includes and the `config.d`
+/// directory are loaded relative to `path`. `strict` is as for
+/// [`parse_config_with_warnings`].
+pub fn parse_config_source_with_warnings(
+    content: &str,
+    path: &Path,
+    host: &HostContext,
+    strict: bool,
+) -> Result<(Config, Vec<ConfigWarning>), ConfigError> {
+    load_config_at(content, path, host, strict)
 }
 
 /// Parse configuration from a string
+///
+/// Relative `include` paths are resolved against the current directory, and
+/// no `config.d` directory is loaded.
 pub fn parse_config_str(content: &str) -> Result<Config, ConfigError> {
-    let doc: kdl::KdlDocument = content.parse().map_err(|e: kdl::KdlError| {
-        // Convert span from kdl's miette version to our miette version
-        // kdl uses an older miette version, so we need to extract offset/len manually
-        let offset = e.span.offset();
-        let len = e.span.len();
-        let span = miette::SourceSpan::from((offset, len));
-        ConfigError::ParseError {
-            src: content.to_string(),
-            span,
-            source: e,
+    parse_config_str_for_host(content, &HostContext::current())
+}
+
+/// Parse configuration from a string as if it were loaded on `host`
+pub fn parse_config_str_for_host(content: &str, host: &HostContext) -> Result<Config, ConfigError> {
+    parse_config_str_with_warnings(content, host, false).map(log_warnings)
+}
+
+/// Parse configuration from a string as if it were loaded on `host`,
+/// returning the lints that are not allowed instead of logging them
+///
+/// `strict` is as for [`parse_config_with_warnings`].
+pub fn parse_config_str_with_warnings(
+    content: &str,
+    host: &HostContext,
+    strict: bool,
+) -> Result<(Config, Vec<ConfigWarning>), ConfigError> {
+    let mut loader = ConfigLoader::new(host, strict);
+    loader.load(content.to_string(), None);
+    loader.build()
+}
+
+/// The configuration, after logging its lints
+fn log_warnings((config, warnings): (Config, Vec<ConfigWarning>)) -> Config {
+    for warning in warnings {
+        tracing::warn!("{} [{}]", warning, warning.lint);
+    }
+    config
+}
+
+/// The configuration files of a drop-in directory, sorted by file name
+fn drop_in_files(dir: &Path) -> Result<Vec<std::path::PathBuf>, ConfigError> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(ConfigError::Invalid {
+                message: format!("Failed to read directory '{}': {}", dir.display(), e),
+                span: None,
+            })
         }
-    })?;
+    };
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        if extension.is_some_and(|ext| crate::formats::EXTENSIONS.contains(&ext)) && path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// A parsed configuration file
+struct ConfigFile {
+    /// Path of the file (`None` for configuration parsed from a string)
+    name: Option<String>,
+    content: String,
+    doc: kdl::KdlDocument,
+    /// The file as written, for a JSON or TOML file that `content` is the
+    /// KDL translation of
+    translation: Option<Translation>,
+}
+
+/// Loads a configuration file with its includes
+struct ConfigLoader<'a> {
+    /// Host that `when` guards are evaluated against
+    host: &'a HostContext,
+    /// Whether unknown nodes and properties are errors rather than lints
+    strict: bool,
+    files: Vec<ConfigFile>,
+    /// Top-level nodes of all files in merge order, as (file, node) indices
+    nodes: Vec<(usize, usize)>,
+    /// Canonical paths of the files loaded so far
+    loaded: std::collections::HashSet<std::path::PathBuf>,
+    /// Canonical paths of the files currently being loaded, for cycle detection
+    stack: Vec<std::path::PathBuf>,
+    /// Errors found while loading, reported with those found by [`Self::build`]
+    errors: Errors,
+    /// Lints allowed for parts of the files by `(allow)` annotations
+    annotations: Vec<AllowAnnotation>,
+    /// Canonical path and content of a file to load instead of reading it,
+    /// for validating an edit before it is written
+    replacement: Option<(PathBuf, String)>,
+}
+
+impl<'a> ConfigLoader<'a> {
+    fn new(host: &'a HostContext, strict: bool) -> Self {
+        Self {
+            host,
+            strict,
+            files: Vec::new(),
+            nodes: Vec::new(),
+            loaded: std::collections::HashSet::new(),
+            stack: Vec::new(),
+            errors: Errors::default(),
+            annotations: Vec::new(),
+            replacement: None,
+        }
+    }
+
+    /// Load the configuration file at `path` with its includes and the files
+    /// of its `config.d` directory
+    fn load_with_drop_ins(&mut self, content: String, path: &Path) -> Result<(), ConfigError> {
+        self.load(content, Some(path));
+
+        if let Some(dir) = path.parent() {
+            for file in drop_in_files(&dir.join(DROP_IN_DIR))? {
+                if let Err(e) = self.include(&file) {
+                    self.errors.push(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Load configuration source, expanding its `include` nodes in place
+    ///
+    /// Errors are collected in `self.errors`, leaving out the nodes they are in.
+    fn load(&mut self, content: String, path: Option<&Path>) {
+        let name = path.map(|path| path.display().to_string());
+        let (content, translation) = match Format::of(path) {
+            Format::Kdl => (content, None),
+            format => {
+                let mut errors = Errors::default();
+                let translated = crate::formats::translate(&content, format, &mut errors);
+                if let Err(e) = errors.finish() {
+                    self.errors.push(e.in_file(name.as_deref()));
+                }
+                match translated {
+                    Some((kdl, translation)) => (kdl, Some(translation)),
+                    None => return,
+                }
+            }
+        };
+        let doc: kdl::KdlDocument = match content.parse() {
+            Ok(doc) => doc,
+            Err(e) => {
+                let e: kdl::KdlError = e;
+                // Convert span from kdl's miette version to our miette version
+                // kdl uses an older miette version, so we need to extract offset/len manually
+                let offset = e.span.offset();
+                let len = e.span.len();
+                let span = miette::SourceSpan::from((offset, len));
+                let error = ConfigError::ParseError {
+                    src: Box::new(SourceFile::new(content.as_str())),
+                    span,
+                    source: e,
+                };
+                self.errors.push(error.in_file(name.as_deref()));
+                return;
+            }
+        };
+
+        allow_annotations(doc.nodes(), &content, name.as_deref(), &mut self.annotations, &mut self.errors);
+
+        if let Some(path) = path {
+            let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+            self.loaded.insert(canonical.clone());
+            self.stack.push(canonical);
+        }
+
+        let base_dir = path
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let includes: Vec<Option<std::path::PathBuf>> = doc
+            .nodes()
+            .iter()
+            .map(|node| {
+                let include = include_path(node, &base_dir, &content);
+                self.errors.ok(include.map_err(|e| e.in_file(name.as_deref()))).flatten()
+            })
+            .collect();
+
+        let index = self.files.len();
+        self.files.push(ConfigFile { name, content, doc, translation });
+
+        for (node_index, include) in includes.into_iter().enumerate() {
+            let file = &self.files[index];
+            let node = &file.doc.nodes()[node_index];
+            match include {
+                Some(include) => {
+                    let location = get_node_location(node, &file.content);
+                    if let Err(e) = self.include(&include) {
+                        // Errors of the included file itself already have a span
+                        let e = match e {
+                            ConfigError::Invalid { message, span: None } => {
+                                let file = &self.files[index];
+                                ConfigError::invalid_at(
+                                    &file.content,
+                                    location,
+                                    message,
+                                    "included here",
+                                    "Check that the included file exists and is readable, and that files do not include each other",
+                                )
+                                .in_file(file.name.as_deref())
+                            }
+                            e => e,
+                        };
+                        self.errors.push(e);
+                    }
+                }
+                None => {
+                    let applies = host_guards_match(node, &file.content, self.host)
+                        .map_err(|e| e.in_file(file.name.as_deref()));
+                    match applies {
+                        Ok(true) => self.nodes.push((index, node_index)),
+                        Ok(false) => {
+                            tracing::debug!(
+                                "Skipping {} node, whose `when` guards do not match this host",
+                                node.name().value()
+                            );
+                        }
+                        Err(e) => self.errors.push(e),
+                    }
+                }
+            }
+        }
+
+        if path.is_some() {
+            self.stack.pop();
+        }
+    }
+
+    /// Load an included file, unless it was loaded already
+    ///
+    /// Errors of the included file are collected in `self.errors`; the
+    /// returned error is about the file as a whole.
+    fn include(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let canonical = path.canonicalize().map_err(|e| ConfigError::Invalid {
+            message: format!("Failed to read included file '{}': {}", path.display(), e),
+            span: None,
+        })?;
+
+        if self.stack.contains(&canonical) {
+            let mut chain: Vec<String> = self.stack.iter().map(|p| p.display().to_string()).collect();
+            chain.push(canonical.display().to_string());
+            return Err(ConfigError::Invalid {
+                message: format!("Include cycle: {}", chain.join(" -> ")),
+                span: None,
+            });
+        }
+        // A file included from several places is only loaded once
+        if self.loaded.contains(&canonical) {
+            tracing::debug!("Skipping '{}', which is already included", path.display());
+            return Ok(());
+        }
+
+        let content = match &self.replacement {
+            Some((replaced, content)) if *replaced == canonical => content.clone(),
+            _ => std::fs::read_to_string(path).map_err(|e| ConfigError::Invalid {
+                message: format!("Failed to read included file '{}': {}", path.display(), e),
+                span: None,
+            })?,
+        };
+        self.load(content, Some(path));
+        Ok(())
+    }
 
-    let mut config = Config::default();
+    /// Merge the loaded files into a configuration, with the lints that
+    /// are not allowed
+    ///
+    /// Every top-level node is parsed even if an earlier one has errors, so
+    /// the errors of all files are reported together.
+    fn build(self) -> Result<(Config, Vec<ConfigWarning>), ConfigError> {
+        let mut config = Config::default();
+        let mut errors = self.errors;
+        let mut lints = Lints::default();
+        let nodes = || {
+            self.nodes
+                .iter()
+                .map(|&(file, node)| (file, &self.files[file], &self.files[file].doc.nodes()[node]))
+        };
+
+        // The macro library is parsed first so that devices can reference it
+        // regardless of where it appears in the files
+        for (_, file, node) in nodes() {
+            if node.name().value() == "macros" {
+                let mut file_lints = Lints::default();
+                let result = parse_macro_library(node, &file.content, &mut config.macros, &mut file_lints);
+                errors.ok(result.map_err(|e| e.in_file(file.name.as_deref())));
+                lints.append(file_lints, file.name.as_deref());
+            }
+        }
 
-    for node in doc.nodes() {
-        match node.name().value() {
-            "global" => {
-                config.global = parse_global(node)?;
+        // Global blocks come next, as their rules are merged into every profile
+        let mut global_sites = RuleSites::default();
+        for (index, file, node) in nodes() {
+            if node.name().value() == "global" {
+                let mut sites = RuleSites::default();
+                let mut file_lints = Lints::default();
+                let result = parse_global(
+                    node,
+                    &file.content,
+                    &config.macros,
+                    &mut config.global,
+                    &mut sites,
+                    &mut file_lints,
+                );
+                errors.ok(result.map_err(|e| e.in_file(file.name.as_deref())));
+                lints.append(file_lints, file.name.as_deref());
+                global_sites = RuleSites::merged(&global_sites, sites.in_file(index, file.name.as_deref()));
+            }
+        }
+        let global = ResolvedProfile {
+            profile: config.global.rules.clone(),
+            sites: global_sites,
+        };
+        // Rules are checked for conflicts once resolved; rules inherited by
+        // several profiles are reported once
+        let mut checker = ConflictChecker::default();
+        checker.check_profile(&global.profile, &global.sites, &mut errors, &mut lints);
+
+        // Profile templates are resolved next, before the devices using them
+        let mut parsed_templates = std::collections::HashMap::new();
+        for (index, file, node) in nodes() {
+            if node.name().value() == "profile-template" {
+                let mut file_lints = Lints::default();
+                let result = parse_profile_template(node, &file.content, &config.macros, &mut file_lints)
+                    .map_err(|e| e.in_file(file.name.as_deref()));
+                lints.append(file_lints, file.name.as_deref());
+                let Some((name, mut template)) = errors.ok(result) else {
+                    continue;
+                };
+                template.origin.file = file.name.as_deref();
+                template.sites = std::mem::take(&mut template.sites).in_file(index, file.name.as_deref());
+                if parsed_templates.contains_key(&name) {
+                    errors.push(
+                        node_error(
+                            node,
+                            &file.content,
+                            format!("Profile template '{}' is defined more than once", name),
+                            "duplicate template",
+                            "Rename one of the templates or merge their rules",
+                        )
+                        .in_file(file.name.as_deref()),
+                    );
+                    continue;
+                }
+                parsed_templates.insert(name, template);
+            }
+        }
+        let no_templates = std::collections::HashMap::new();
+        let layers = ProfileLayers { templates: &no_templates, global: &global };
+        let templates = resolve_profiles("profile templates", parsed_templates, &layers, &mut errors);
+        let mut template_names: Vec<&String> = templates.keys().collect();
+        template_names.sort();
+        for name in template_names {
+            checker.check_profile(&templates[name].profile, &templates[name].sites, &mut errors, &mut lints);
+        }
+        config.profile_templates = templates
+            .iter()
+            .map(|(name, template)| (name.clone(), template.profile.clone()))
+            .collect();
+        let layers = ProfileLayers { templates: &templates, global: &global };
+
+        // File each device was defined in, to report duplicates
+        let mut device_files: std::collections::HashMap<String, Option<&str>> =
+            std::collections::HashMap::new();
+
+        for (index, file, node) in nodes() {
+            match node.name().value() {
+                "device" => {
+                    let mut file_lints = Lints::default();
+                    let result = parse_device(
+                        node,
+                        &file.content,
+                        index,
+                        &config.macros,
+                        &layers,
+                        &mut checker,
+                        &mut file_lints,
+                    )
+                    .map_err(|e| e.in_file(file.name.as_deref()));
+                    lints.append(file_lints, file.name.as_deref());
+                    let Some(device) = errors.ok(result) else {
+                        continue;
+                    };
+                    let name = device.name.clone().unwrap_or_default();
+                    if let Some(previous) = device_files.insert(name.clone(), file.name.as_deref()) {
+                        let location = match (previous, file.name.as_deref()) {
+                            (Some(previous), Some(current)) if previous != current => {
+                                format!(" (in '{}' and '{}')", previous, current)
+                            }
+                            (_, Some(current)) => format!(" in '{}'", current),
+                            _ => String::new(),
+                        };
+                        errors.push(
+                            node_error(
+                                node,
+                                &file.content,
+                                format!("Device '{}' is defined more than once{}", name, location),
+                                "duplicate device",
+                                "Define each device once; use `when` guards to configure it differently per host",
+                            )
+                            .in_file(file.name.as_deref()),
+                        );
+                        continue;
+                    }
+                    config.devices.push(device);
+                }
+                "version" => {
+                    let mut file_lints = Lints::default();
+                    lint_properties(node, &file.content, &mut file_lints);
+                    lints.append(file_lints, file.name.as_deref());
+                    if let Err(e) = config_version(node, &file.content) {
+                        errors.push(e.in_file(file.name.as_deref()));
+                    }
+                }
+                "global" | "macros" | "profile-template" => {}
+                _ => {
+                    let mut file_lints = Lints::default();
+                    lint_unknown_node(node, &file.content, "top-level node", TOP_LEVEL_NODES, &mut file_lints);
+                    lints.append(file_lints, file.name.as_deref());
+                }
             }
-            "device" => {
-                config.devices.push(parse_device(node, content)?);
+        }
+
+        let mut warnings = lints.finish(&self.annotations, &config.global.allow);
+        if self.strict {
+            let (strict, rest) = warnings.into_iter().partition(|w| Lint::STRICT.contains(&w.lint));
+            for warning in strict {
+                errors.push(ConfigWarning::into_error(warning));
             }
-            name => {
-                tracing::warn!("Unknown top-level node: {}", name);
+            warnings = rest;
+        }
+
+        if let Err(mut e) = errors.finish() {
+            e.for_each_source(&mut |src, locations| untranslate(&self.files, src, locations));
+            return Err(e);
+        }
+        for warning in &mut warnings {
+            for span in std::iter::once(&mut warning.span).chain(warning.related.as_mut()) {
+                untranslate(&self.files, &mut span.src, vec![&mut span.location]);
             }
         }
+        Ok((config, warnings))
+    }
+}
+
+/// Show a span found in the KDL translation of a JSON or TOML file in the
+/// file as written
+fn untranslate(files: &[ConfigFile], src: &mut SourceFile, locations: Vec<&mut SourceLocation>) {
+    let translated = files.iter().find_map(|file| {
+        let translation = file.translation.as_ref()?;
+        (file.name == src.name && file.content == src.content).then_some(translation)
+    });
+    if let Some(translation) = translated {
+        for location in locations {
+            *location = translation.map(*location);
+        }
+        src.content = translation.source.clone();
+    }
+}
+
+/// Whether the `when` guards of a top-level `device` or `global` node match
+/// the host, e.g. `when hostname="thinkpad"`
+///
+/// Nodes without guards always apply. The conditions of a `when` node must
+/// all match, and a node with several `when` nodes applies if any matches.
+fn host_guards_match(
+    node: &kdl::KdlNode,
+    source: &str,
+    host: &HostContext,
+) -> Result<bool, ConfigError> {
+    if !matches!(node.name().value(), "device" | "global") {
+        return Ok(true);
+    }
+    let guards: Vec<&kdl::KdlNode> = node
+        .children()
+        .map(|children| children.nodes().iter().filter(|c| c.name().value() == "when").collect())
+        .unwrap_or_default();
+    if guards.is_empty() {
+        return Ok(true);
+    }
+
+    let mut applies = false;
+    for guard in guards {
+        if guard.entries().is_empty() || guard.children().is_some() {
+            return Err(node_error(
+                guard,
+                source,
+                format!("when in {} expects conditions", node.name().value()),
+                "expected conditions",
+                "Write the conditions as properties, e.g. when hostname=\"thinkpad\" env=\"WAYLAND_DISPLAY\"",
+            ));
+        }
+
+        let mut all_match = true;
+        for entry in guard.entries() {
+            let name = entry.name().map(|name| name.value());
+            let value = entry.value().as_string().ok_or_else(|| {
+                entry_error(
+                    entry,
+                    source,
+                    format!("Invalid host condition {}: expected a string", entry),
+                    "expected a string",
+                    "Quote the value, e.g. hostname=\"thinkpad\"",
+                )
+            })?;
+            all_match &= match name {
+                Some("hostname") => host.matches_hostname(value),
+                Some("env") => host.matches_env(value),
+                _ => {
+                    return Err(entry_error(
+                        entry,
+                        source,
+                        format!("Unknown host condition '{}' in when", name.unwrap_or(value)),
+                        "unknown condition",
+                        "Use hostname=\"...\" or env=\"NAME\" (or env=\"NAME=value\")",
+                    ))
+                }
+            };
+        }
+        applies |= all_match;
+    }
+
+    Ok(applies)
+}
+
+/// The path of an `include "path.kdl"` node, or `None` for other nodes
+///
+/// `~` is expanded, and relative paths are resolved against `base_dir`.
+fn include_path(
+    node: &kdl::KdlNode,
+    base_dir: &Path,
+    source: &str,
+) -> Result<Option<std::path::PathBuf>, ConfigError> {
+    if node.name().value() != "include" {
+        return Ok(None);
     }
 
-    Ok(config)
+    let path = node
+        .entries()
+        .first()
+        .filter(|entry| entry.name().is_none())
+        .and_then(|entry| entry.value().as_string())
+        .ok_or_else(|| {
+            node_error(
+                node,
+                source,
+                "include expects a file path",
+                "expected a file path",
+                "Give the path as an argument, e.g. include \"devices.kdl\"",
+            )
+        })?;
+    let path = std::path::PathBuf::from(shellexpand::tilde(path).into_owned());
+    Ok(Some(base_dir.join(path)))
+}
+
+/// Read the xkb keymap settings from a niri configuration file
+///
+/// Returns the contents of niri's `input { keyboard { xkb { ... } } }` block,
+/// or `None` if the file does not configure one.
+pub fn parse_niri_xkb(content: &str) -> Result<Option<XkbSettings>, ConfigError> {
+    let doc: kdl::KdlDocument = content.parse().map_err(|e: kdl::KdlError| ConfigError::Invalid {
+        message: format!("Failed to parse niri configuration: {}", e),
+        span: None,
+    })?;
+
+    let xkb = doc
+        .get("input")
+        .and_then(|input| input.children())
+        .and_then(|input| input.get("keyboard"))
+        .and_then(|keyboard| keyboard.children())
+        .and_then(|keyboard| keyboard.get("xkb"));
+
+    // Options of niri's config that niri-mapper does not use are not a problem
+    Ok(xkb.map(|xkb| parse_xkb(xkb, content, &mut Lints::default())))
 }
 
-fn parse_global(node: &kdl::KdlNode) -> Result<GlobalConfig, ConfigError> {
-    let mut global = GlobalConfig::default();
+/// Parse an `xkb` block (shared by the global config and niri's config)
+fn parse_xkb(node: &kdl::KdlNode, source: &str, lints: &mut Lints) -> XkbSettings {
+    let mut xkb = XkbSettings::default();
 
     if let Some(children) = node.children() {
         for child in children.nodes() {
+            let Some(val) = child.entries().first().and_then(|e| e.value().as_string()) else {
+                continue;
+            };
             match child.name().value() {
+                "rules" => xkb.rules = val.to_string(),
+                "model" => xkb.model = val.to_string(),
+                "layout" => xkb.layout = val.to_string(),
+                "variant" => xkb.variant = val.to_string(),
+                "options" => xkb.options = Some(val.to_string()),
+                "file" => xkb.file = Some(shellexpand::tilde(val).into_owned().into()),
+                _ => lint_unknown_node(child, source, "xkb option", XKB_OPTIONS, lints),
+            }
+        }
+    }
+
+    xkb
+}
+
+/// Parse a `global` block into `global`
+///
+/// Only the options the block sets are changed, so a later block (e.g., from
+/// an included file) overrides individual options. Its `remap`, `combo` and
+/// `macro` entries are merged into the global rules the same way, and where
+/// they are written is stored in `sites`.
+fn parse_global<'a>(
+    node: &kdl::KdlNode,
+    source: &'a str,
+    library: &std::collections::HashMap<String, NamedMacro>,
+    global: &mut GlobalConfig,
+    sites: &mut RuleSites<'a>,
+    lints: &mut Lints,
+) -> Result<(), ConfigError> {
+    let mut errors = Errors::default();
+    // Rule blocks, parsed together like the blocks of a profile
+    let mut rule_blocks = kdl::KdlDocument::new();
+
+    // An option value that failed to parse, shown at the value
+    let invalid = |entry: &kdl::KdlEntry, message: String, label: &str, help: &str| {
+        entry_error(entry, source, message, label, help)
+    };
+
+    lint_properties(node, source, lints);
+    if let Some(children) = node.children() {
+        for child in children.nodes() {
+            let entry = child.entries().first();
+            let name = child.name().value();
+            // Host guards take their conditions as properties, and rule
+            // blocks are checked with the profile rules
+            if GLOBAL_OPTIONS.contains(&name) && !matches!(name, "when" | "remap" | "combo" | "macro") {
+                lint_properties(child, source, lints);
+            }
+            match name {
+                "remap" | "combo" | "macro" => {
+                    rule_blocks.nodes_mut().push(child.clone());
+                }
+                // Host guards, evaluated when the file is loaded
+                "when" => {}
                 "log-level" => {
-                    if let Some(entry) = child.entries().first() {
-                        if let Some(val) = entry.value().as_string() {
-                            global.log_level = val.parse().map_err(|e| ConfigError::Invalid {
-                                message: e,
-                            })?;
+                    if let Some((entry, val)) = entry.and_then(|e| Some((e, e.value().as_string()?))) {
+                        match val.parse() {
+                            Ok(level) => global.log_level = level,
+                            Err(e) => errors.push(invalid(
+                                entry,
+                                e,
+                                "unknown log level",
+                                "Use one of: trace, debug, info, warn, error",
+                            )),
                         }
                     }
                 }
                 "niri-keybinds-path" => {
-                    if let Some(entry) = child.entries().first() {
+                    if let Some(entry) = entry {
                         if let Some(val) = entry.value().as_string() {
                             global.niri_keybinds_path = shellexpand::tilde(val).into_owned().into();
                         }
                     }
                 }
                 "niri-ipc-enabled" => {
-                    if let Some(entry) = child.entries().first() {
+                    if let Some(entry) = entry {
                         if let Some(val) = entry.value().as_bool() {
                             global.niri_ipc_enabled = val;
                         }
                     }
                 }
                 "niri-ipc-retry-count" => {
-                    if let Some(entry) = child.entries().first() {
-                        if let Some(val) = entry.value().as_i64() {
-                            if val >= 0 {
-                                global.niri_ipc_retry_count = val as u32;
-                            } else {
-                                return Err(ConfigError::Invalid {
-                                    message: format!(
-                                        "niri-ipc-retry-count must be non-negative, got {}",
-                                        val
-                                    ),
-                                });
+                    if let Some((entry, val)) = entry.and_then(|e| Some((e, e.value().as_i64()?))) {
+                        if val >= 0 {
+                            global.niri_ipc_retry_count = val as u32;
+                        } else {
+                            errors.push(invalid(
+                                entry,
+                                format!("niri-ipc-retry-count must be non-negative, got {}", val),
+                                "negative count",
+                                "Use 0 to disable retries, or a positive number of attempts",
+                            ));
+                        }
+                    }
+                }
+                "macro-abort-key" | "macro-record-key" => {
+                    if let Some((entry, val)) = entry.and_then(|e| Some((e, e.value().as_string()?))) {
+                        match val.parse::<KeyCode>() {
+                            Ok(key) if child.name().value() == "macro-abort-key" => {
+                                global.macro_abort_key = Some(key);
                             }
+                            Ok(key) => global.macro_record_key = Some(key),
+                            Err(e) => errors.push(invalid(
+                                entry,
+                                format!(
+                                    "{} '{}' is not a valid key name: {}",
+                                    child.name().value(),
+                                    val,
+                                    e
+                                ),
+                                "unknown key",
+                                "Use a single key name, e.g. \"Pause\"",
+                            )),
+                        }
+                    }
+                }
+                "macro-record-quantize" => {
+                    if let Some((entry, val)) = entry.and_then(|e| Some((e, e.value().as_i64()?))) {
+                        if val >= 0 {
+                            global.macro_record_quantize = val as u64;
+                        } else {
+                            errors.push(invalid(
+                                entry,
+                                format!("macro-record-quantize must be non-negative, got {}", val),
+                                "negative step",
+                                "Use 0 to record exact delays, or a step in milliseconds",
+                            ));
+                        }
+                    }
+                }
+                "macro-isolation" => {
+                    if let Some((entry, val)) = entry.and_then(|e| Some((e, e.value().as_string()?))) {
+                        match val.parse() {
+                            Ok(isolation) => global.macro_isolation = isolation,
+                            Err(e) => errors.push(invalid(
+                                entry,
+                                e,
+                                "unknown isolation mode",
+                                "Use one of: off, queue, drop",
+                            )),
+                        }
+                    }
+                }
+                "unicode-input" => {
+                    if let Some((entry, val)) = entry.and_then(|e| Some((e, e.value().as_string()?))) {
+                        match val.parse() {
+                            Ok(method) => global.unicode_input = method,
+                            Err(e) => errors.push(invalid(
+                                entry,
+                                e,
+                                "unknown input method",
+                                "Use one of: ctrl-shift-u, none",
+                            )),
+                        }
+                    }
+                }
+                "xkb" => {
+                    global.xkb = Some(parse_xkb(child, source, lints));
+                }
+                "key-mode" => {
+                    if let Some((entry, val)) = entry.and_then(|e| Some((e, e.value().as_string()?))) {
+                        match val.parse() {
+                            Ok(mode) => global.key_mode = mode,
+                            Err(e) => errors.push(invalid(
+                                entry,
+                                e,
+                                "unknown key mode",
+                                "Use one of: keycode, keysym",
+                            )),
                         }
                     }
                 }
-                name => {
-                    tracing::warn!("Unknown global config option: {}", name);
+                "allow" => {
+                    for entry in child.entries() {
+                        let Some(val) = entry.value().as_string() else {
+                            continue;
+                        };
+                        match val.parse() {
+                            Ok(lint) => global.allow.push(lint),
+                            Err(e) => errors.push(invalid(
+                                entry,
+                                e,
+                                "unknown lint",
+                                &format!("Known lints: {}", known_lints()),
+                            )),
+                        }
+                    }
                 }
+                _ => lint_unknown_node(child, source, "global option", GLOBAL_OPTIONS, lints),
             }
         }
     }
 
-    Ok(global)
+    if !rule_blocks.nodes().is_empty() {
+        let mut rules_node = kdl::KdlNode::new("global");
+        rules_node.set_children(rule_blocks);
+        if let Some((rules, unset, rule_sites)) = errors.ok(parse_profile(&rules_node, source, library, lints)) {
+            global.rules = merge_profile(&global.rules, rules, &unset);
+            *sites = rule_sites;
+        }
+    }
+
+    errors.finish()
 }
 
-fn parse_device(node: &kdl::KdlNode, source: &str) -> Result<DeviceConfig, ConfigError> {
+fn parse_device<'a>(
+    node: &kdl::KdlNode,
+    source: &'a str,
+    file_index: usize,
+    library: &std::collections::HashMap<String, NamedMacro>,
+    layers: &ProfileLayers<'_, 'a>,
+    checker: &mut ConflictChecker,
+    lints: &mut Lints,
+) -> Result<DeviceConfig, ConfigError> {
+    let mut errors = Errors::default();
     let name = node
         .entries()
         .first()
@@ -195,113 +1188,350 @@ fn parse_device(node: &kdl::KdlNode, source: &str) -> Result<DeviceConfig, Confi
         profiles: std::collections::HashMap::new(),
         profile_switch: std::collections::HashMap::new(),
     };
+    // Profiles as written, before `extends` is resolved
+    let mut parsed_profiles = std::collections::HashMap::new();
+    let mut profile_switch_node = None;
+    let mut switch_sites = std::collections::HashMap::new();
 
+    lint_properties(node, source, lints);
     if let Some(children) = node.children() {
         for child in children.nodes() {
-            match child.name().value() {
+            let name = child.name().value();
+            // Profile properties are checked by `parse_profile`, and host
+            // guards take their conditions as properties
+            if DEVICE_OPTIONS.contains(&name) && !matches!(name, "profile" | "when") {
+                lint_properties(child, source, lints);
+            }
+            match name {
                 "vendor-product" => {
                     if let Some(entry) = child.entries().first() {
                         device.vendor_product = entry.value().as_string().map(|s| s.to_string());
                     }
                 }
                 "profile" => {
+                    // An unnamed profile instantiating a template takes the template's name
+                    let template = child
+                        .get("template")
+                        .and_then(|e| e.value().as_string());
                     let profile_name = child
                         .entries()
-                        .first()
+                        .iter()
+                        .find(|e| e.name().is_none())
                         .and_then(|e| e.value().as_string())
+                        .or(template)
                         .unwrap_or("default")
                         .to_string();
 
                     // Check for duplicate profile names
-                    if device.profiles.contains_key(&profile_name) {
-                        return Err(ConfigError::Invalid {
-                            message: format!(
-                                "Duplicate profile name '{}' in device '{}'. Profile names must be unique within a device.",
+                    if parsed_profiles.contains_key(&profile_name) {
+                        errors.push(node_error(
+                            child,
+                            source,
+                            format!(
+                                "Duplicate profile name '{}' in device '{}'",
                                 profile_name,
                                 device.name.as_deref().unwrap_or("<unnamed>")
                             ),
-                        });
+                            "duplicate profile",
+                            "Profile names must be unique within a device; rename one of the profiles or merge their rules",
+                        ));
+                        continue;
                     }
 
-                    let profile = parse_profile(child, source)?;
-                    device.profiles.insert(profile_name, profile);
+                    // A profile with errors is kept empty, so that profiles
+                    // extending it do not report it as unknown
+                    let (profile, unset, sites) =
+                        errors.ok(parse_profile(child, source, library, lints)).unwrap_or_default();
+                    let sites = sites.in_file(file_index, None);
+                    let origin = ProfileOrigin::of(child, source);
+                    parsed_profiles.insert(profile_name, ParsedProfile { profile, unset, sites, origin });
                 }
                 "profile-switch" => {
-                    device.profile_switch = parse_profile_switch(child)?;
-                }
-                name => {
-                    tracing::warn!("Unknown device config option: {}", name);
+                    if let Some((profile_switch, locations)) = errors.ok(parse_profile_switch(child, source)) {
+                        device.profile_switch = profile_switch;
+                        switch_sites = locations
+                            .into_iter()
+                            .map(|(keybind, location)| {
+                                let site = RuleSite { file_index: Some(file_index), ..RuleSite::new(source, location) };
+                                (keybind, site)
+                            })
+                            .collect();
+                    }
+                    profile_switch_node = Some(child);
                 }
+                // Host guards, evaluated when the file is loaded
+                "when" => {}
+                _ => lint_unknown_node(child, source, "device option", DEVICE_OPTIONS, lints),
             }
         }
     }
 
     // Validation: device must have a name (required in v0.1.0)
     if device.name.is_none() {
-        return Err(ConfigError::MissingField {
-            field: "device name (e.g., `device \"My Keyboard\" { ... }`)".to_string(),
-        });
+        errors.push(ConfigError::missing_at(
+            source,
+            get_node_location(node, source),
+            "device name (e.g., `device \"My Keyboard\" { ... }`)",
+            "device without a name",
+            "Add a name argument to the device block, e.g.: device \"My Keyboard\" { ... }",
+        ));
+        return errors.finish().map(|()| device);
     }
 
-    // Validation: if device has profiles with remappings, it must have a "default" profile
-    let has_remappings = device.profiles.values().any(|p| {
-        !p.remap.is_empty() || !p.combo.is_empty() || !p.macros.is_empty()
-    });
-
-    if has_remappings && !device.profiles.contains_key("default") {
-        return Err(ConfigError::Invalid {
-            message: format!(
-                "Device '{}' has remapping rules but no 'default' profile. \
-                 Add a profile named 'default' or rename an existing profile.",
+    // Validation: if device has profiles with remappings, it must have a "default" profile.
+    // Checked before global rules are merged in, which every profile gets.
+    let has_remappings = parsed_profiles
+        .values()
+        .any(|parsed: &ParsedProfile| has_rules(&parsed.profile) || parsed.profile.template.is_some());
+
+    if has_remappings && !parsed_profiles.contains_key("default") {
+        errors.push(node_error(
+            node,
+            source,
+            format!(
+                "Device '{}' has remapping rules but no 'default' profile",
                 device.name.as_ref().unwrap()
             ),
-        });
+            "no 'default' profile",
+            "Add a profile named 'default' or rename an existing profile.",
+        ));
+    }
+
+    let scope = format!("device '{}'", device.name.as_deref().unwrap());
+    let mut profiles = resolve_profiles(&scope, parsed_profiles, layers, &mut errors);
+
+    // Global rules apply to devices without profiles of their own too
+    if !profiles.contains_key("default") && has_rules(&layers.global.profile) {
+        profiles.insert("default".to_string(), layers.global.clone());
+    }
+
+    let mut names: Vec<&String> = profiles.keys().collect();
+    names.sort();
+    for name in names {
+        let resolved = &profiles[name];
+        checker.check_profile(&resolved.profile, &resolved.sites, &mut errors, lints);
+        checker.check_profile_switch(&switch_sites, &resolved.profile, &resolved.sites, &mut errors);
     }
+    device.profiles = profiles.into_iter().map(|(name, resolved)| (name, resolved.profile)).collect();
 
     // Validation: profile-switch must reference existing profiles
-    for (keybind, profile_name) in &device.profile_switch {
+    let switch_entries = profile_switch_node
+        .and_then(|node| node.children())
+        .map(|children| children.nodes())
+        .unwrap_or_default();
+    for child in switch_entries {
+        let Some(entry) = child.entries().first() else {
+            continue;
+        };
+        let Some(profile_name) = entry.value().as_string() else {
+            continue;
+        };
         if !device.profiles.contains_key(profile_name) {
-            let available_profiles: Vec<&str> = device.profiles.keys().map(|s| s.as_str()).collect();
+            let mut available_profiles: Vec<&str> = device.profiles.keys().map(|s| s.as_str()).collect();
+            available_profiles.sort();
             let available_str = if available_profiles.is_empty() {
                 "no profiles defined".to_string()
             } else {
                 format!("available profiles: {}", available_profiles.join(", "))
             };
-            return Err(ConfigError::Invalid {
-                message: format!(
+            errors.push(entry_error(
+                entry,
+                source,
+                format!(
                     "profile-switch in device '{}' references non-existent profile '{}' for keybind '{}'. {}",
                     device.name.as_deref().unwrap_or("<unnamed>"),
                     profile_name,
-                    keybind,
+                    child.name().value(),
                     available_str
                 ),
-            });
+                "unknown profile",
+                "Switch to a profile defined in this device, or fix the profile name",
+            ));
         }
     }
 
-    // Warning: device has multiple profiles but no profile-switch keybinds
-    // This is a usability warning - user might have forgotten to add keybinds to switch between profiles
-    if device.profiles.len() >= 2 && device.profile_switch.is_empty() {
-        let profile_names: Vec<&str> = device.profiles.keys().map(|s| s.as_str()).collect();
-        tracing::warn!(
-            "Device '{}' has {} profiles ({}) but no profile-switch keybinds configured. \
-             Consider adding a profile-switch block to enable switching between profiles.",
-            device.name.as_deref().unwrap_or("<unnamed>"),
-            device.profiles.len(),
-            profile_names.join(", ")
+    // The user might have forgotten to add keybinds to switch between profiles
+    if errors.is_empty() && device.profiles.len() >= 2 && device.profile_switch.is_empty() {
+        let mut profile_names: Vec<&str> = device.profiles.keys().map(|s| s.as_str()).collect();
+        profile_names.sort();
+        lints.push(
+            Lint::NoProfileSwitch,
+            source,
+            get_node_location(node, source),
+            format!(
+                "Device '{}' has {} profiles ({}) but no profile-switch keybinds",
+                device.name.as_deref().unwrap_or("<unnamed>"),
+                device.profiles.len(),
+                profile_names.join(", ")
+            ),
+            "no profile-switch",
+            "Add a profile-switch block to switch between the profiles, unless niri's focus switches them",
         );
     }
 
+    errors.finish()?;
     Ok(device)
 }
 
-fn parse_profile(node: &kdl::KdlNode, source: &str) -> Result<Profile, ConfigError> {
+/// Parse a top-level `profile-template "name" { ... }` node
+///
+/// The body is the same as a device profile's. Templates can extend other
+/// templates, but cannot instantiate one.
+fn parse_profile_template<'a>(
+    node: &kdl::KdlNode,
+    source: &'a str,
+    library: &std::collections::HashMap<String, NamedMacro>,
+    lints: &mut Lints,
+) -> Result<(String, ParsedProfile<'a>), ConfigError> {
+    let name = node
+        .entries()
+        .iter()
+        .find(|e| e.name().is_none())
+        .and_then(|e| e.value().as_string())
+        .ok_or_else(|| {
+            ConfigError::missing_at(
+                source,
+                get_node_location(node, source),
+                "profile template name (e.g., `profile-template \"coding\" { ... }`)",
+                "template without a name",
+                "Add a name argument, e.g.: profile-template \"coding\" { ... }",
+            )
+        })?
+        .to_string();
+
+    let (profile, unset, sites) = parse_profile(node, source, library, lints)?;
+    if profile.template.is_some() {
+        let entry = node.get("template").expect("template= was parsed");
+        return Err(entry_error(
+            entry,
+            source,
+            format!("Profile template '{}' cannot use template=", name),
+            "template= in a template",
+            "Use extends= to build on another template",
+        ));
+    }
+    let origin = ProfileOrigin::of(node, source);
+    Ok((name, ParsedProfile { profile, unset, sites, origin }))
+}
+
+/// A profile as written, before `extends` and `template` are resolved
+struct ParsedProfile<'a> {
+    profile: Profile,
+    unset: UnsetEntries,
+    sites: RuleSites<'a>,
+    origin: ProfileOrigin<'a>,
+}
+
+/// A profile with the rules it inherits merged in
+#[derive(Debug, Clone, Default)]
+struct ResolvedProfile<'a> {
+    profile: Profile,
+    /// Where each of the profile's rules is written
+    sites: RuleSites<'a>,
+}
+
+/// Where a profile is written, for errors found while resolving it
+#[derive(Debug, Clone, Copy)]
+struct ProfileOrigin<'a> {
+    source: &'a str,
+    /// File the profile is in, if not named by the caller
+    file: Option<&'a str>,
+    /// Location of the profile's `extends=` or `template=`, or else its name
+    location: SourceLocation,
+}
+
+impl<'a> ProfileOrigin<'a> {
+    fn of(node: &kdl::KdlNode, source: &'a str) -> Self {
+        let location = match node.get("extends").or_else(|| node.get("template")) {
+            Some(entry) => get_entry_location(entry, source),
+            None => get_node_location(node, source),
+        };
+        Self { source, file: None, location }
+    }
+
+    /// An error about the profile's parent
+    fn error(&self, message: String, label: &str, help: &str) -> ConfigError {
+        ConfigError::invalid_at(self.source, self.location, message, label, help).in_file(self.file)
+    }
+}
+
+/// Inherited entries a profile unsets by setting them to `null`
+#[derive(Debug, Default)]
+struct UnsetEntries {
+    remap: Vec<KeyCode>,
+    combo: Vec<KeyCombo>,
+    macros: Vec<KeyCode>,
+    niri_passthrough: Vec<String>,
+}
+
+/// Parse a profile as written, without the rules of the profile it extends
+///
+/// Returns the profile, the inherited entries it unsets and where each of
+/// its rules is written. Conflicts between rules are found once profiles are
+/// resolved, by [`ConflictChecker`].
+fn parse_profile<'a>(
+    node: &kdl::KdlNode,
+    source: &'a str,
+    library: &std::collections::HashMap<String, NamedMacro>,
+    lints: &mut Lints,
+) -> Result<(Profile, UnsetEntries, RuleSites<'a>), ConfigError> {
     let mut profile = Profile::default();
-    let mut all_invalid_keys = Vec::new();
+    let mut unset = UnsetEntries::default();
+    let mut sites = RuleSites::default();
+    let mut errors = Errors::default();
+    let site = |location| RuleSite::new(source, location);
+
+    for entry in node.entries() {
+        match entry.name().map(|name| name.value()) {
+            Some("extends") => match entry.value().as_string() {
+                Some(parent) => profile.extends = Some(parent.to_string()),
+                None => errors.push(entry_error(
+                    entry,
+                    source,
+                    format!("Invalid extends={}: expected a profile name", entry.value()),
+                    "expected a profile name",
+                    "Quote the name of the profile to extend, e.g. extends=\"default\"",
+                )),
+            },
+            Some("template") => match entry.value().as_string() {
+                Some(template) => profile.template = Some(template.to_string()),
+                None => errors.push(entry_error(
+                    entry,
+                    source,
+                    format!("Invalid template={}: expected a profile template name", entry.value()),
+                    "expected a template name",
+                    "Quote the name of a profile-template, e.g. template=\"coding\"",
+                )),
+            },
+            Some(other) => errors.push(entry_error(
+                entry,
+                source,
+                format!("Unknown profile property '{}'{}", other, did_you_mean(other, PROFILE_PROPERTIES)),
+                "unknown property",
+                "Profiles support the extends= and template= properties",
+            )),
+            None => {}
+        }
+    }
+    if profile.extends.is_some() && profile.template.is_some() {
+        let entry = node.get("template").expect("template= was parsed");
+        errors.push(entry_error(
+            entry,
+            source,
+            "A profile cannot have both extends= and template=",
+            "conflicts with extends=",
+            "Use extends= to build on a profile of the same device, or template= to instantiate a profile-template",
+        ));
+    }
 
     if let Some(children) = node.children() {
         for child in children.nodes() {
-            match child.name().value() {
+            let name = child.name().value();
+            // Window matches take their criteria as properties
+            if PROFILE_OPTIONS.contains(&name) && !matches!(name, "match" | "exclude") {
+                lint_properties(child, source, lints);
+            }
+            match name {
                 "app-id-hint" => {
                     if let Some(entry) = child.entries().first() {
                         if let Some(val) = entry.value().as_string() {
@@ -309,95 +1539,341 @@ fn parse_profile(node: &kdl::KdlNode, source: &str) -> Result<Profile, ConfigErr
                         }
                     }
                 }
+                "layout-hint" => {
+                    if let Some(entry) = child.entries().first() {
+                        if let Some(val) = entry.value().as_string() {
+                            profile.layout_hint = Some(val.to_string());
+                        }
+                    }
+                }
+                "workspace-hint" => {
+                    if let Some(entry) = child.entries().first() {
+                        profile.workspace_hint = Some(kdl_value_to_string(entry.value()));
+                    }
+                }
+                "output-hint" => {
+                    if let Some(entry) = child.entries().first() {
+                        if let Some(val) = entry.value().as_string() {
+                            profile.output_hint = Some(val.to_string());
+                        }
+                    }
+                }
+                "match" => {
+                    profile.match_rules.extend(errors.ok(parse_window_match(child, source)));
+                }
+                "exclude" => {
+                    profile.exclude_rules.extend(errors.ok(parse_window_match(child, source)));
+                }
                 "remap" => {
-                    match parse_key_value_block(child, "remap", source) {
-                        Ok(remap) => profile.remap = remap,
-                        Err(ConfigError::InvalidKeys { invalid_keys, .. }) => {
-                            all_invalid_keys.extend(invalid_keys);
+                    let entry = |from, to| ConditionalEntry::Remap { from, to };
+                    if let Some(block) = errors.ok(parse_key_value_block(child, "remap", source, entry)) {
+                        profile.remap = block.entries;
+                        sites.remap = block.locations.into_iter().map(|(k, l)| (k, site(l))).collect();
+                        for (rule, location) in block.conditional {
+                            profile.conditional_rules.push(rule);
+                            sites.conditional.push(site(location));
                         }
-                        Err(e) => return Err(e),
+                        unset.remap = block.unset;
                     }
                 }
                 "combo" => {
-                    match parse_key_value_block(child, "combo", source) {
-                        Ok(combo) => profile.combo = combo,
-                        Err(ConfigError::InvalidKeys { invalid_keys, .. }) => {
-                            all_invalid_keys.extend(invalid_keys);
+                    let entry = |from, to| ConditionalEntry::Combo { from, to };
+                    if let Some(block) = errors.ok(parse_key_value_block(child, "combo", source, entry)) {
+                        profile.combo = block.entries;
+                        sites.combo = block.locations.into_iter().map(|(k, l)| (k, site(l))).collect();
+                        for (rule, location) in block.conditional {
+                            profile.conditional_rules.push(rule);
+                            sites.conditional.push(site(location));
                         }
-                        Err(e) => return Err(e),
+                        unset.combo = block.unset;
                     }
                 }
                 "macro" => {
-                    match parse_macro_block(child, source) {
-                        Ok(macros) => profile.macros = macros,
-                        Err(ConfigError::InvalidKeys { invalid_keys, .. }) => {
-                            all_invalid_keys.extend(invalid_keys);
+                    if let Some(block) = errors.ok(parse_macro_block(child, source, library, lints)) {
+                        profile.macros = block.macros;
+                        profile.macro_policies = block.policies;
+                        sites.macros = block.locations.into_iter().map(|(k, l)| (k, site(l))).collect();
+                        for (rule, location) in block.conditional {
+                            profile.conditional_rules.push(rule);
+                            sites.conditional.push(site(location));
                         }
-                        Err(e) => return Err(e),
+                        unset.macros = block.unset;
                     }
                 }
                 "niri-passthrough" => {
-                    profile.niri_passthrough = parse_niri_passthrough(child)?;
-                }
-                name => {
-                    tracing::warn!("Unknown profile option: {}", name);
+                    if let Some(block) = errors.ok(parse_niri_passthrough(child, source)) {
+                        profile.niri_passthrough = block.keybinds;
+                        sites.niri_passthrough =
+                            block.locations.into_iter().map(|(k, l)| (k, site(l))).collect();
+                        unset.niri_passthrough = block.unset;
+                    }
                 }
+                _ => lint_unknown_node(child, source, "profile option", PROFILE_OPTIONS, lints),
             }
         }
     }
 
-    // If we accumulated any invalid keys, return them all together with source context
-    if !all_invalid_keys.is_empty() {
-        return Err(ConfigError::InvalidKeys {
-            src: Some(source.to_string()),
-            invalid_keys: all_invalid_keys,
-        });
-    }
-
-    // Check for macro trigger key conflicts with remap source keys and combo triggers
-    let mut conflicts = Vec::new();
-
-    for macro_key in profile.macros.keys() {
-        let macro_key_upper = macro_key.to_uppercase();
+    errors.finish()?;
+    Ok((profile, unset, sites))
+}
 
-        // Check conflict with remap source keys
-        for remap_key in profile.remap.keys() {
-            if remap_key.to_uppercase() == macro_key_upper {
-                conflicts.push(format!(
-                    "Macro trigger key '{}' conflicts with remap source key '{}'",
-                    macro_key, remap_key
-                ));
+/// Resolve `extends` and `template` for every profile of a device (or for
+/// the profile templates), merging in inherited rules
+///
+/// Parents are resolved before their children, so chains like
+/// `default <- browser <- firefox` merge layer by layer, and the global rules
+/// of `layers` are merged under the profiles that have no parent. `scope`
+/// names where the profiles are defined in error messages, e.g.
+/// `device 'Keyboard'`.
+///
+/// Errors are added to `errors`; profiles that cannot be resolved are left
+/// empty, so that their children do not report errors of their own.
+fn resolve_profiles<'a>(
+    scope: &str,
+    mut parsed: std::collections::HashMap<String, ParsedProfile<'a>>,
+    layers: &ProfileLayers<'_, 'a>,
+    errors: &mut Errors,
+) -> std::collections::HashMap<String, ResolvedProfile<'a>> {
+    let mut resolved = std::collections::HashMap::new();
+    let mut names: Vec<String> = parsed.keys().cloned().collect();
+    names.sort();
+
+    for name in names {
+        let mut chain = Vec::new();
+        if let Err(e) = resolve_profile(scope, &name, &mut parsed, layers, &mut resolved, &mut chain) {
+            errors.push(e);
+            for name in chain {
+                parsed.remove(&name);
+                resolved.insert(name, ResolvedProfile::default());
             }
         }
+    }
+
+    resolved
+}
 
-        // Check conflict with combo trigger keys
-        // Combo keys may contain modifiers like "Ctrl+A", so we extract the base key
-        for combo_key in profile.combo.keys() {
-            // Get the last component after '+' (the trigger key without modifiers)
-            let combo_base = combo_key
-                .rsplit('+')
-                .next()
-                .unwrap_or(combo_key);
+/// Rules that profiles without a parent profile build on
+struct ProfileLayers<'l, 'a> {
+    templates: &'l std::collections::HashMap<String, ResolvedProfile<'a>>,
+    global: &'l ResolvedProfile<'a>,
+}
 
-            if combo_base.to_uppercase() == macro_key_upper {
-                conflicts.push(format!(
-                    "Macro trigger key '{}' conflicts with combo trigger '{}' (base key '{}')",
-                    macro_key, combo_key, combo_base
+fn resolve_profile<'a>(
+    scope: &str,
+    name: &str,
+    parsed: &mut std::collections::HashMap<String, ParsedProfile<'a>>,
+    layers: &ProfileLayers<'_, 'a>,
+    resolved: &mut std::collections::HashMap<String, ResolvedProfile<'a>>,
+    chain: &mut Vec<String>,
+) -> Result<(), ConfigError> {
+    if resolved.contains_key(name) {
+        return Ok(());
+    }
+    let origin = parsed[name].origin;
+    if chain.iter().any(|n| n == name) {
+        chain.push(name.to_string());
+        return Err(origin.error(
+            format!("Profile inheritance cycle in {}: {}", scope, chain.join(" extends ")),
+            "completes the cycle",
+            "Remove extends= from one of the profiles in the cycle",
+        ));
+    }
+    chain.push(name.to_string());
+
+    let parent = parsed[name].profile.extends.clone();
+    let template = parsed[name].profile.template.clone();
+    let profile = match (parent, template) {
+        (Some(parent), _) => {
+            if !parsed.contains_key(&parent) && !resolved.contains_key(&parent) {
+                let mut known: Vec<&str> = parsed.keys().chain(resolved.keys()).map(String::as_str).collect();
+                known.sort();
+                return Err(origin.error(
+                    format!("Profile '{}' in {} extends unknown profile '{}'", name, scope, parent),
+                    "unknown profile",
+                    &format!("Profiles can extend other profiles of the same device: {}", known.join(", ")),
+                ));
+            }
+            resolve_profile(scope, &parent, parsed, layers, resolved, chain)?;
+            let child = parsed.remove(name).expect("profile was parsed");
+            let parent = &resolved[&parent];
+            ResolvedProfile {
+                profile: merge_profile(&parent.profile, child.profile, &child.unset),
+                sites: RuleSites::merged(&parent.sites, child.sites),
+            }
+        }
+        (None, Some(template)) => {
+            let Some(base) = layers.templates.get(&template) else {
+                let mut known: Vec<&str> = layers.templates.keys().map(String::as_str).collect();
+                known.sort();
+                let help = if known.is_empty() {
+                    "Define it with profile-template \"name\" { ... }".to_string()
+                } else {
+                    format!("Defined profile templates: {}", known.join(", "))
+                };
+                return Err(origin.error(
+                    format!("Profile '{}' in {} uses unknown profile template '{}'", name, scope, template),
+                    "unknown profile template",
+                    &help,
                 ));
+            };
+            let child = parsed.remove(name).expect("profile was parsed");
+            ResolvedProfile {
+                profile: instantiate_template(&base.profile, child.profile, &child.unset),
+                sites: RuleSites::merged(&base.sites, child.sites),
+            }
+        }
+        (None, None) => {
+            let child = parsed.remove(name).expect("profile was parsed");
+            ResolvedProfile {
+                profile: merge_profile(&layers.global.profile, child.profile, &child.unset),
+                sites: RuleSites::merged(&layers.global.sites, child.sites),
             }
         }
+    };
+
+    chain.pop();
+    resolved.insert(name.to_string(), profile);
+    Ok(())
+}
+
+/// Merge a parent profile's rules into a child profile
+///
+/// Entries of the child replace inherited entries for the same key, and a
+/// child's macro replaces inherited remaps and combos on its trigger key (and
+/// vice versa) so the result has no key conflicts. Entries the child unsets
+/// with `null` are dropped. The child's conditional entries come first, so
+/// they take precedence over inherited ones.
+///
+/// Hints and `match`/`exclude` rules are not inherited: they decide when a
+/// profile activates, which is specific to each profile.
+fn merge_profile(parent: &Profile, child: Profile, unset: &UnsetEntries) -> Profile {
+    let any_same = |keys: &[String], key: &str| keys.iter().any(|k| k.eq_ignore_ascii_case(key));
+
+    // Keys whose inherited entries are replaced by the child's entries
+    let child_remaps: Vec<KeyCode> = child.remap.keys().copied().collect();
+    let child_combos: Vec<KeyCombo> = child.combo.keys().cloned().collect();
+    let child_combo_keys: Vec<KeyCode> = child.combo.keys().map(|combo| combo.key).collect();
+    let child_macros: Vec<KeyCode> = child.macros.keys().copied().collect();
+    let child_passthrough: Vec<String> =
+        child.niri_passthrough.iter().map(|kb| kb.key.clone()).collect();
+
+    let mut remap: std::collections::HashMap<KeyCode, KeyCode> = parent
+        .remap
+        .iter()
+        .filter(|(key, _)| {
+            !unset.remap.contains(key) && !child_remaps.contains(key) && !child_macros.contains(key)
+        })
+        .map(|(k, v)| (*k, *v))
+        .collect();
+    remap.extend(child.remap);
+
+    let mut combo: std::collections::HashMap<KeyCombo, KeyCombo> = parent
+        .combo
+        .iter()
+        .filter(|(combo, _)| {
+            !unset.combo.contains(combo)
+                && !child_combos.contains(combo)
+                && !child_macros.contains(&combo.key)
+        })
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    combo.extend(child.combo);
+
+    let mut macros: std::collections::HashMap<KeyCode, Vec<MacroAction>> = parent
+        .macros
+        .iter()
+        .filter(|(key, _)| {
+            !unset.macros.contains(key)
+                && !child_macros.contains(key)
+                && !child_remaps.contains(key)
+                && !child_combo_keys.contains(key)
+        })
+        .map(|(k, v)| (*k, v.clone()))
+        .collect();
+    let mut macro_policies: std::collections::HashMap<KeyCode, MacroPolicy> = parent
+        .macro_policies
+        .iter()
+        .filter(|(key, _)| macros.contains_key(*key))
+        .map(|(k, v)| (*k, *v))
+        .collect();
+    macros.extend(child.macros);
+    macro_policies.extend(child.macro_policies);
+
+    let mut niri_passthrough: Vec<NiriKeybind> = parent
+        .niri_passthrough
+        .iter()
+        .filter(|kb| {
+            !any_same(&unset.niri_passthrough, &kb.key) && !any_same(&child_passthrough, &kb.key)
+        })
+        .cloned()
+        .collect();
+    niri_passthrough.extend(child.niri_passthrough);
+
+    let mut conditional_rules = child.conditional_rules;
+    conditional_rules.extend(parent.conditional_rules.iter().cloned());
+
+    Profile {
+        remap,
+        combo,
+        macros,
+        macro_policies,
+        niri_passthrough,
+        conditional_rules,
+        ..child
     }
+}
+
+/// Whether a profile has any remap, combo or macro rules
+fn has_rules(profile: &Profile) -> bool {
+    !profile.remap.is_empty()
+        || !profile.combo.is_empty()
+        || !profile.macros.is_empty()
+        || !profile.conditional_rules.is_empty()
+}
 
-    if !conflicts.is_empty() {
-        return Err(ConfigError::Invalid {
-            message: format!(
-                "Key conflicts detected in profile:\n  - {}",
-                conflicts.join("\n  - ")
+/// Merge a profile template into a device profile that instantiates it
+///
+/// Rules are merged as with [`merge_profile`]. The template's hints apply
+/// unless the profile sets its own, and its `match`/`exclude` rules are kept
+/// alongside the profile's.
+fn instantiate_template(template: &Profile, child: Profile, unset: &UnsetEntries) -> Profile {
+    let mut profile = merge_profile(template, child, unset);
+
+    profile.app_id_hint = profile.app_id_hint.or_else(|| template.app_id_hint.clone());
+    profile.layout_hint = profile.layout_hint.or_else(|| template.layout_hint.clone());
+    profile.workspace_hint = profile.workspace_hint.or_else(|| template.workspace_hint.clone());
+    profile.output_hint = profile.output_hint.or_else(|| template.output_hint.clone());
+
+    let mut match_rules = template.match_rules.clone();
+    match_rules.append(&mut profile.match_rules);
+    profile.match_rules = match_rules;
+    let mut exclude_rules = template.exclude_rules.clone();
+    exclude_rules.append(&mut profile.exclude_rules);
+    profile.exclude_rules = exclude_rules;
+
+    profile
+}
+
+/// Whether a block entry is set to `null`, unsetting an entry inherited
+/// through `extends` (e.g., `CapsLock null`)
+fn is_unset_entry(node: &kdl::KdlNode, source: &str) -> Result<bool, ConfigError> {
+    let is_null = node
+        .entries()
+        .first()
+        .is_some_and(|entry| entry.name().is_none() && entry.value().is_null());
+    if is_null && (node.entries().len() > 1 || node.children().is_some()) {
+        return Err(node_error(
+            node,
+            source,
+            format!(
+                "Entry '{}' is set to null to unset an inherited entry and cannot have other values",
+                node.name().value()
             ),
-        });
+            "null with other values",
+            "Remove the other values to unset the inherited entry, or replace null to redefine it",
+        ));
     }
-
-    Ok(profile)
+    Ok(is_null)
 }
 
 /// Validate a key combo string (e.g., "Ctrl+C", "A", "Shift+Alt+X")
@@ -408,7 +1884,7 @@ fn validate_key_combo(combo: &str) -> Vec<String> {
     // Split on '+' to handle combos like "Ctrl+C" or "Shift+Alt+X"
     for part in combo.split('+') {
         let trimmed = part.trim();
-        if !trimmed.is_empty() && !is_valid_key(trimmed) {
+        if !trimmed.is_empty() && !is_valid_key(trimmed) && trimmed.parse::<Modifier>().is_err() {
             invalid_keys.push(trimmed.to_string());
         }
     }
@@ -417,244 +1893,649 @@ fn validate_key_combo(combo: &str) -> Vec<String> {
 }
 
 /// Check if a key name is valid
-/// Returns true if the key is recognized, false otherwise
-/// NOTE: This must stay in sync with parse_key() in niri-mapper-daemon/src/remapper.rs
+/// Returns true if the key is in the shared key registry (see [`crate::keys`])
 fn is_valid_key(name: &str) -> bool {
-    match name.to_uppercase().as_str() {
-        // Special keys
-        "CAPSLOCK" | "CAPS_LOCK" | "CAPS" => true,
-        "ESCAPE" | "ESC" => true,
-        "ENTER" | "RETURN" => true,
-        "TAB" => true,
-        "SPACE" => true,
-        "BACKSPACE" => true,
-
-        // Letters
-        "A" | "B" | "C" | "D" | "E" | "F" | "G" | "H" | "I" | "J" | "K" | "L" | "M" | "N" | "O"
-        | "P" | "Q" | "R" | "S" | "T" | "U" | "V" | "W" | "X" | "Y" | "Z" => true,
-
-        // Modifiers
-        "LEFTCTRL" | "LCTRL" | "CTRL" => true,
-        "RIGHTCTRL" | "RCTRL" => true,
-        "LEFTSHIFT" | "LSHIFT" | "SHIFT" => true,
-        "RIGHTSHIFT" | "RSHIFT" => true,
-        "LEFTALT" | "LALT" | "ALT" => true,
-        "RIGHTALT" | "RALT" => true,
-        "LEFTMETA" | "LMETA" | "SUPER" | "META" => true,
-        "RIGHTMETA" | "RMETA" => true,
-
-        // Number keys
-        "0" | "KEY_0" => true,
-        "1" | "KEY_1" => true,
-        "2" | "KEY_2" => true,
-        "3" | "KEY_3" => true,
-        "4" | "KEY_4" => true,
-        "5" | "KEY_5" => true,
-        "6" | "KEY_6" => true,
-        "7" | "KEY_7" => true,
-        "8" | "KEY_8" => true,
-        "9" | "KEY_9" => true,
-
-        // Symbol keys
-        "MINUS" | "-" => true,
-        "EQUALS" | "EQUAL" | "=" => true,
-        "LEFTBRACE" | "LBRACE" | "[" => true,
-        "RIGHTBRACE" | "RBRACE" | "]" => true,
-        "SEMICOLON" | ";" => true,
-        "APOSTROPHE" | "'" => true,
-        "GRAVE" | "`" => true,
-        "BACKSLASH" | "\\" => true,
-        "COMMA" | "," => true,
-        "DOT" | "PERIOD" | "." => true,
-        "SLASH" | "/" => true,
-
-        // Arrow keys
-        "UP" | "UPARROW" => true,
-        "DOWN" | "DOWNARROW" => true,
-        "LEFT" | "LEFTARROW" => true,
-        "RIGHT" | "RIGHTARROW" => true,
-
-        // Navigation keys
-        "HOME" => true,
-        "END" => true,
-        "PAGEUP" | "PGUP" => true,
-        "PAGEDOWN" | "PGDN" | "PGDOWN" => true,
-        "INSERT" | "INS" => true,
-        "DELETE" | "DEL" => true,
-
-        // Function keys F1-F12
-        "F1" | "F2" | "F3" | "F4" | "F5" | "F6" | "F7" | "F8" | "F9" | "F10" | "F11" | "F12" => true,
-
-        // Function keys F13-F24
-        "F13" | "F14" | "F15" | "F16" | "F17" | "F18" | "F19" | "F20" | "F21" | "F22" | "F23" | "F24" => true,
-
-        // Numpad keys
-        "KP0" | "NUMPAD0" => true,
-        "KP1" | "NUMPAD1" => true,
-        "KP2" | "NUMPAD2" => true,
-        "KP3" | "NUMPAD3" => true,
-        "KP4" | "NUMPAD4" => true,
-        "KP5" | "NUMPAD5" => true,
-        "KP6" | "NUMPAD6" => true,
-        "KP7" | "NUMPAD7" => true,
-        "KP8" | "NUMPAD8" => true,
-        "KP9" | "NUMPAD9" => true,
-        "KPDOT" | "KPDECIMAL" | "NUMPAD_DOT" => true,
-        "KPENTER" | "NUMPAD_ENTER" => true,
-        "KPPLUS" | "KPADD" | "NUMPAD_PLUS" => true,
-        "KPMINUS" | "KPSUBTRACT" | "NUMPAD_MINUS" => true,
-        "KPASTERISK" | "KPMULTIPLY" | "NUMPAD_MULTIPLY" => true,
-        "KPSLASH" | "KPDIVIDE" | "NUMPAD_DIVIDE" => true,
-        "NUMLOCK" | "NUM_LOCK" => true,
-
-        // Media keys
-        "XF86BACK" | "XF86FORWARD" => true,
-
-        _ => {
-            // Fallback: Accept KEY_* format strings as valid
-            // These will be parsed by evdev's FromStr in the daemon
-            name.to_uppercase().starts_with("KEY_")
-        }
-    }
-}
-
-fn parse_key_value_block(
+    crate::keys::key_code(name).is_some()
+}
+
+/// Parse a key of a block entry, or a key combo if `combo` is set
+///
+/// Unknown key names are collected in `invalid_keys`, so every unknown key is
+/// reported together, and give `None`.
+fn parse_entry_key<K: std::str::FromStr<Err = String>>(
+    text: &str,
+    combo: bool,
+    context: &str,
+    position: KeyPosition,
+    source: &str,
+    location: SourceLocation,
+    invalid_keys: &mut Vec<InvalidKeyInfo>,
+) -> Result<Option<K>, ConfigError> {
+    let unknown = if combo {
+        validate_key_combo(text)
+    } else if is_valid_key(text) {
+        Vec::new()
+    } else {
+        vec![text.to_string()]
+    };
+    if !unknown.is_empty() {
+        invalid_keys.extend(unknown.into_iter().map(|key| InvalidKeyInfo {
+            key,
+            position,
+            context: context.to_string(),
+            location,
+        }));
+        return Ok(None);
+    }
+
+    text.parse().map(Some).map_err(|e| {
+        let (label, help) = if combo {
+            (
+                "invalid key combo",
+                "Write modifiers and one key joined by '+', e.g. Ctrl+Shift+T",
+            )
+        } else {
+            ("expected a single key", "Use a single key name here, e.g. F5")
+        };
+        ConfigError::invalid_at(source, location, format!("{} in {} block", e, context), label, help)
+    })
+}
+
+/// Entries of a `remap` block (keyed by [`KeyCode`]) or `combo` block
+/// (keyed by [`KeyCombo`])
+#[derive(Debug)]
+struct KeyValueBlock<K> {
+    /// Unconditional entries keyed by "from" key
+    entries: std::collections::HashMap<K, K>,
+    /// Where the "from" key of each unconditional entry is written
+    locations: std::collections::HashMap<K, SourceLocation>,
+    /// Entries with `when` conditions, with where their "from" key is written
+    conditional: Vec<(ConditionalRule, SourceLocation)>,
+    /// Keys set to `null`, which unset an inherited entry
+    unset: Vec<K>,
+}
+
+impl<K> Default for KeyValueBlock<K> {
+    fn default() -> Self {
+        Self {
+            entries: std::collections::HashMap::new(),
+            locations: std::collections::HashMap::new(),
+            conditional: Vec::new(),
+            unset: Vec::new(),
+        }
+    }
+}
+
+/// Parse a `remap` or `combo` block
+///
+/// `entry` builds the [`ConditionalEntry`] for entries with `when` conditions.
+/// Unconditional entries whose keys resolve to the same key (e.g., `Esc` and
+/// `Escape`, or `Ctrl+Shift+A` and `Shift+Ctrl+A`) are reported as duplicates.
+fn parse_key_value_block<K>(
     node: &kdl::KdlNode,
     context: &str,
     source: &str,
-) -> Result<std::collections::HashMap<String, String>, ConfigError> {
-    let mut map = std::collections::HashMap::new();
+    entry: fn(K, K) -> ConditionalEntry,
+) -> Result<KeyValueBlock<K>, ConfigError>
+where
+    K: std::str::FromStr<Err = String> + Eq + std::hash::Hash + Clone + std::fmt::Display,
+{
+    let mut block = KeyValueBlock::default();
     let mut invalid_keys = Vec::new();
+    let mut errors = Errors::default();
+    // Combo entries map key combinations, remap entries single keys
+    let combo = context == "combo";
 
     if let Some(children) = node.children() {
         for child in children.nodes() {
-            let from_key = child.name().value().to_string();
-
-            // Validate the "from" key
-            if !is_valid_key(&from_key) {
-                invalid_keys.push(InvalidKeyInfo {
-                    key: from_key.clone(),
-                    position: KeyPosition::From,
-                    context: context.to_string(),
-                    location: get_node_location(child, source),
-                });
+            let from = parse_entry_key(
+                child.name().value(),
+                combo,
+                context,
+                KeyPosition::From,
+                source,
+                get_node_location(child, source),
+                &mut invalid_keys,
+            );
+            let Some(from) = errors.ok(from) else {
+                continue;
+            };
+
+            match errors.ok(is_unset_entry(child, source)) {
+                Some(true) => {
+                    block.unset.extend(from);
+                    continue;
+                }
+                Some(false) => {}
+                None => continue,
             }
 
-            if let Some(entry) = child.entries().first() {
-                if let Some(to_key) = entry.value().as_string() {
-                    // Validate the "to" key
-                    if !is_valid_key(to_key) {
-                        invalid_keys.push(InvalidKeyInfo {
-                            key: to_key.to_string(),
-                            position: KeyPosition::To,
-                            context: context.to_string(),
-                            location: get_entry_location(entry, source),
-                        });
+            let Some(when) = errors.ok(parse_when_block(child, source)) else {
+                continue;
+            };
+            if let Some(to_entry) = child.entries().first() {
+                if let Some(to_key) = to_entry.value().as_string() {
+                    let to = parse_entry_key(
+                        to_key,
+                        combo,
+                        context,
+                        KeyPosition::To,
+                        source,
+                        get_entry_location(to_entry, source),
+                        &mut invalid_keys,
+                    );
+                    let (Some(from), Some(Some(to))) = (from, errors.ok(to)) else {
+                        continue;
+                    };
+                    let location = get_node_location(child, source);
+                    if !when.is_empty() {
+                        let rule = ConditionalRule {
+                            when,
+                            entry: entry(from, to),
+                        };
+                        block.conditional.push((rule, location));
+                    } else if let Some(&first) = block.locations.get(&from) {
+                        errors.push(duplicate_entry(source, first, location, context, &from));
+                    } else {
+                        block.locations.insert(from.clone(), location);
+                        block.entries.insert(from, to);
                     }
-                    map.insert(from_key, to_key.to_string());
                 }
             }
         }
     }
 
-    // Return error with all invalid keys if any were found
+    // Report all invalid keys found in the block together
     if !invalid_keys.is_empty() {
-        return Err(ConfigError::InvalidKeys {
-            src: None, // Source will be added by caller if needed
+        errors.push(ConfigError::InvalidKeys {
+            src: Some(SourceFile::new(source)),
             invalid_keys,
         });
     }
 
-    Ok(map)
+    errors.finish()?;
+    Ok(block)
+}
+
+/// Entries of a `macro` block
+#[derive(Debug, Default)]
+struct MacroBlock {
+    /// Unconditional macros keyed by trigger
+    macros: std::collections::HashMap<KeyCode, Vec<MacroAction>>,
+    /// Policies of the unconditional macros that set one explicitly
+    policies: std::collections::HashMap<KeyCode, MacroPolicy>,
+    /// Where the trigger of each unconditional macro is written
+    locations: std::collections::HashMap<KeyCode, SourceLocation>,
+    /// Macros with `when` conditions, with where their trigger is written
+    conditional: Vec<(ConditionalRule, SourceLocation)>,
+    /// Triggers set to `null`, which unset an inherited macro
+    unset: Vec<KeyCode>,
 }
 
 fn parse_macro_block(
     node: &kdl::KdlNode,
     source: &str,
-) -> Result<std::collections::HashMap<String, Vec<MacroAction>>, ConfigError> {
-    let mut map = std::collections::HashMap::new();
+    library: &std::collections::HashMap<String, NamedMacro>,
+    lints: &mut Lints,
+) -> Result<MacroBlock, ConfigError> {
+    let mut block = MacroBlock::default();
     let mut invalid_keys = Vec::new();
+    let mut errors = Errors::default();
 
     if let Some(children) = node.children() {
         for child in children.nodes() {
-            let key = child.name().value().to_string();
+            let key = child.name().value();
+            let location = get_node_location(child, source);
+            let trigger = parse_entry_key(
+                key,
+                false,
+                "macro",
+                KeyPosition::From,
+                source,
+                location,
+                &mut invalid_keys,
+            );
+            let Some(trigger) = errors.ok(trigger) else {
+                continue;
+            };
 
-            // Validate the macro trigger key
-            if !is_valid_key(&key) {
-                invalid_keys.push(InvalidKeyInfo {
-                    key: key.clone(),
-                    position: KeyPosition::From,
-                    context: "macro".to_string(),
-                    location: get_node_location(child, source),
-                });
+            match errors.ok(is_unset_entry(child, source)) {
+                Some(true) => {
+                    block.unset.extend(trigger);
+                    continue;
+                }
+                Some(false) => {}
+                None => continue,
             }
 
+            let Some(when) = errors.ok(parse_when_block(child, source)) else {
+                continue;
+            };
             let mut actions = Vec::new();
+            let mut policy = None;
+            let mut reference: Option<(&str, &kdl::KdlEntry)> = None;
+            let mut args = std::collections::HashMap::new();
+            // Whether an error was found in the entry's actions or properties
+            let mut failed = false;
 
             for entry in child.entries() {
-                if let Some(val) = entry.value().as_string() {
-                    if val.starts_with("delay(") && val.ends_with(')') {
-                        // Parse delay(ms)
-                        let ms_str = &val[6..val.len() - 1];
-                        if let Ok(ms) = ms_str.parse::<u64>() {
-                            // Validate delay value: must be positive and <= 10000ms (10 seconds)
-                            if ms == 0 {
-                                return Err(ConfigError::Invalid {
-                                    message: format!(
-                                        "Invalid delay value '{}': delay must be a positive integer (got 0)",
-                                        val
-                                    ),
-                                });
-                            }
-                            const MAX_DELAY_MS: u64 = 10000;
-                            if ms > MAX_DELAY_MS {
-                                return Err(ConfigError::Invalid {
-                                    message: format!(
-                                        "Invalid delay value '{}': maximum delay is {}ms (10 seconds), got {}ms",
-                                        val, MAX_DELAY_MS, ms
-                                    ),
-                                });
+                // Properties configure the macro itself rather than adding actions
+                if let Some(name) = entry.name() {
+                    match name.value() {
+                        "policy" => {
+                            let value = entry.value().as_string().unwrap_or_default();
+                            match value.parse::<MacroPolicy>() {
+                                Ok(value) => policy = Some(value),
+                                Err(e) => {
+                                    errors.push(entry_error(
+                                        entry,
+                                        source,
+                                        format!("Macro '{}': {}", key, e),
+                                        "unknown policy",
+                                        "Use one of: ignore, restart, queue, cancel-on-release",
+                                    ));
+                                    failed = true;
+                                }
                             }
-                            actions.push(MacroAction::Delay(ms));
                         }
-                    } else {
-                        // Validate the key/combo in the action
-                        let invalid_action_keys = validate_key_combo(val);
-                        for invalid_key in invalid_action_keys {
-                            invalid_keys.push(InvalidKeyInfo {
-                                key: invalid_key,
-                                position: KeyPosition::Action,
-                                context: "macro".to_string(),
-                                location: get_entry_location(entry, source),
-                            });
+                        "macro" => {
+                            let value = entry.value().as_string().unwrap_or_default();
+                            reference = Some((value, entry));
+                        }
+                        // Any other property is an argument for a referenced macro
+                        other => {
+                            args.insert(other.to_string(), kdl_value_to_string(entry.value()));
+                        }
+                    }
+                    continue;
+                }
+
+                if let Some(val) = entry.value().as_string() {
+                    match errors.ok(parse_macro_action(val, entry, source, &mut invalid_keys)) {
+                        Some(action) => actions.extend(action),
+                        None => failed = true,
+                    }
+                }
+            }
+
+            match reference {
+                Some((name, entry)) => {
+                    if !actions.is_empty() {
+                        errors.push(entry_error(
+                            entry,
+                            source,
+                            format!(
+                                "Macro '{}' references macro '{}' and also lists actions",
+                                key, name
+                            ),
+                            "reference with actions",
+                            "Use either macro=\"name\" or a list of actions, not both",
+                        ));
+                        continue;
+                    }
+
+                    let Some(named) = library.get(name) else {
+                        let mut known: Vec<&str> = library.keys().map(String::as_str).collect();
+                        known.sort();
+                        let help = if known.is_empty() {
+                            "Define the macro in the top-level macros block".to_string()
+                        } else {
+                            format!("Macros in the library: {}", known.join(", "))
+                        };
+                        errors.push(entry_error(
+                            entry,
+                            source,
+                            format!("Macro '{}' references unknown macro '{}'", key, name),
+                            "unknown macro",
+                            help,
+                        ));
+                        continue;
+                    };
+                    let substituted = match named.substitute(&args) {
+                        Ok(substituted) => substituted,
+                        Err(e) => {
+                            errors.push(entry_error(
+                                entry,
+                                source,
+                                format!("Macro '{}' (using '{}'): {}", key, name, e),
+                                "invalid arguments",
+                                format!("Parameters of '{}': {}", name, describe_params(named)),
+                            ));
+                            continue;
+                        }
+                    };
+
+                    // Placeholders are only resolved now, so parse the result
+                    for action in &substituted {
+                        match errors.ok(parse_macro_action(action, entry, source, &mut invalid_keys)) {
+                            Some(action) => actions.extend(action),
+                            None => failed = true,
+                        }
+                    }
+                }
+                None => {
+                    let arguments = child
+                        .entries()
+                        .iter()
+                        .filter(|entry| entry.name().is_some_and(|name| args.contains_key(name.value())));
+                    for entry in arguments {
+                        lints.push(
+                            Lint::UnknownProperty,
+                            source,
+                            get_entry_location(entry, source),
+                            format!(
+                                "Unknown macro property '{}' on macro '{}'",
+                                entry.name().map(|name| name.value()).unwrap_or_default(),
+                                key
+                            ),
+                            "unknown property",
+                            "Macros support policy= and macro=; other properties are arguments of the macro=\"name\" they reference",
+                        );
+                    }
+                }
+            }
+
+            let Some(trigger) = trigger else {
+                continue;
+            };
+            if failed {
+                continue;
+            }
+            if !when.is_empty() {
+                let rule = ConditionalRule {
+                    when,
+                    entry: ConditionalEntry::Macro {
+                        trigger,
+                        actions,
+                        policy: policy.unwrap_or_default(),
+                    },
+                };
+                block.conditional.push((rule, location));
+                continue;
+            }
+            if let Some(&first) = block.locations.get(&trigger) {
+                errors.push(duplicate_entry(source, first, location, "macro", &trigger));
+                continue;
+            }
+            if let Some(policy) = policy {
+                block.policies.insert(trigger, policy);
+            }
+            block.macros.insert(trigger, actions);
+            block.locations.insert(trigger, location);
+        }
+    }
+
+    // Report all invalid keys found in the block together
+    if !invalid_keys.is_empty() {
+        errors.push(ConfigError::InvalidKeys {
+            src: Some(SourceFile::new(source)),
+            invalid_keys,
+        });
+    }
+
+    errors.finish()?;
+    Ok(block)
+}
+
+/// The parameters of a library macro, e.g. `n (default "1"), app (required)`
+fn describe_params(named: &NamedMacro) -> String {
+    if named.params.is_empty() {
+        return "none".to_string();
+    }
+    named
+        .params
+        .iter()
+        .map(|param| match &param.default {
+            Some(default) => format!("{} (default \"{}\")", param.name, default),
+            None => format!("{} (required)", param.name),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Parse a single macro action string: a key or combo (e.g., `Ctrl+C`),
+/// `delay(ms)`, `text(...)` or `layout(...)`.
+///
+/// Unknown key names are collected in `invalid_keys` and give `None`.
+fn parse_macro_action(
+    val: &str,
+    entry: &kdl::KdlEntry,
+    source: &str,
+    invalid_keys: &mut Vec<InvalidKeyInfo>,
+) -> Result<Option<MacroAction>, ConfigError> {
+    let is_function = ["delay(", "text(", "layout("]
+        .iter()
+        .any(|function| val.starts_with(function))
+        && val.ends_with(')');
+    if is_function {
+        return val.parse().map(Some).map_err(|message: String| {
+            entry_error(
+                entry,
+                source,
+                message,
+                "invalid action",
+                format!(
+                    "Actions are keys or combos (Ctrl+C), delay(ms) with 1 to {} ms, text(...) or layout(...)",
+                    MAX_MACRO_DELAY_MS
+                ),
+            )
+        });
+    }
+
+    let combo = parse_entry_key(
+        val,
+        true,
+        "macro",
+        KeyPosition::Action,
+        source,
+        get_entry_location(entry, source),
+        invalid_keys,
+    )?;
+    Ok(combo.map(MacroAction::Key))
+}
+
+/// Validate an action of a library macro, unless it contains `{param}`
+/// placeholders: their keys are only known once parameters are substituted.
+fn validate_library_action(
+    val: &str,
+    entry: &kdl::KdlEntry,
+    source: &str,
+    invalid_keys: &mut Vec<InvalidKeyInfo>,
+) -> Result<(), ConfigError> {
+    if !val.contains('{') {
+        parse_macro_action(val, entry, source, invalid_keys)?;
+    }
+    Ok(())
+}
+
+/// Parse the top-level `macros` library
+///
+/// Example KDL:
+/// ```kdl
+/// macros {
+///     copy-paste "Ctrl+c" "delay(50)" "Ctrl+v"
+///     switch-tab n="1" {
+///         key "Alt+{n}"
+///         delay 20
+///     }
+///     sign-off {
+///         text "Cheers,\n— Jo"
+///     }
+/// }
+/// ```
+///
+/// Actions may be given as arguments (as in a profile `macro` block), as
+/// `key`/`delay`/`text` child nodes, or both (arguments first). Properties declare
+/// parameters with their default value; `null` declares a required parameter.
+fn parse_macro_library(
+    node: &kdl::KdlNode,
+    source: &str,
+    library: &mut std::collections::HashMap<String, NamedMacro>,
+    lints: &mut Lints,
+) -> Result<(), ConfigError> {
+    let mut invalid_keys = Vec::new();
+    let mut errors = Errors::default();
+
+    if let Some(children) = node.children() {
+        for child in children.nodes() {
+            let name = child.name().value().to_string();
+            if library.contains_key(&name) {
+                errors.push(node_error(
+                    child,
+                    source,
+                    format!("Duplicate macro name '{}' in macros library", name),
+                    "duplicate macro",
+                    "Rename one of the macros; names must be unique across all files",
+                ));
+                continue;
+            }
+
+            let mut named = NamedMacro::default();
+            let invalid_before = invalid_keys.len();
+            let mut failed = false;
+
+            for entry in child.entries() {
+                match entry.name() {
+                    Some(param) => named.params.push(MacroParam {
+                        name: param.value().to_string(),
+                        default: (!entry.value().is_null()).then(|| kdl_value_to_string(entry.value())),
+                    }),
+                    None => {
+                        if let Some(val) = entry.value().as_string() {
+                            let valid = validate_library_action(val, entry, source, &mut invalid_keys);
+                            failed |= errors.ok(valid).is_none();
+                            named.actions.push(val.to_string());
                         }
-                        actions.push(MacroAction::Key(val.to_string()));
                     }
                 }
             }
 
-            map.insert(key, actions);
+            if let Some(action_nodes) = child.children() {
+                for action_node in action_nodes.nodes() {
+                    let Some(entry) = action_node.entries().first() else {
+                        continue;
+                    };
+                    // Other actions are stored as written in a `macro` block
+                    let action = match action_node.name().value() {
+                        "key" => match entry.value().as_string() {
+                            Some(val) => val.to_string(),
+                            None => continue,
+                        },
+                        // As written, so a bad value is reported as is and a
+                        // placeholder is filled in when the macro is called
+                        "delay" => format!("delay({})", kdl_value_to_string(entry.value())),
+                        "text" => match entry.value().as_string() {
+                            Some(val) => format!("text({})", val),
+                            None => continue,
+                        },
+                        "layout" => format!("layout({})", kdl_value_to_string(entry.value())),
+                        other => {
+                            lints.push(
+                                Lint::UnknownAction,
+                                source,
+                                get_node_location(action_node, source),
+                                format!("Unknown action '{}' in macro '{}'", other, name),
+                                "unknown action",
+                                "Library macro actions are key, delay, text and layout nodes",
+                            );
+                            continue;
+                        }
+                    };
+                    let valid = validate_library_action(&action, entry, source, &mut invalid_keys);
+                    failed |= errors.ok(valid).is_none();
+                    named.actions.push(action);
+                }
+            }
+
+            if named.actions.is_empty() {
+                errors.push(node_error(
+                    child,
+                    source,
+                    format!("Macro '{}' in macros library has no actions", name),
+                    "no actions",
+                    "Add actions as arguments (\"Ctrl+C\" \"delay(50)\") or as key/delay/text/layout child nodes",
+                ));
+                continue;
+            }
+
+            // Actions with placeholders must parse with the default parameter values
+            let valid_so_far = !failed && invalid_keys.len() == invalid_before;
+            if valid_so_far && named.params.iter().all(|param| param.default.is_some()) {
+                if let Err(e) = named.instantiate(&std::collections::HashMap::new()) {
+                    errors.push(node_error(
+                        child,
+                        source,
+                        format!("Macro '{}' in macros library: {}", name, e),
+                        "invalid with the default parameters",
+                        format!("Check the actions using the parameters: {}", describe_params(&named)),
+                    ));
+                }
+            }
+
+            library.insert(name, named);
         }
     }
 
-    // Return error with all invalid keys if any were found
     if !invalid_keys.is_empty() {
-        return Err(ConfigError::InvalidKeys {
-            src: None, // Source will be added by caller if needed
+        errors.push(ConfigError::InvalidKeys {
+            src: Some(SourceFile::new(source)),
             invalid_keys,
         });
     }
 
-    Ok(map)
+    errors.finish()
+}
+
+/// Convert a KDL value to the string used for a macro parameter
+fn kdl_value_to_string(value: &kdl::KdlValue) -> String {
+    match value.as_string() {
+        Some(s) => s.to_string(),
+        None => value.to_string(),
+    }
+}
+
+/// Entries of a `niri-passthrough` block
+#[derive(Debug, Default)]
+struct PassthroughBlock {
+    keybinds: Vec<NiriKeybind>,
+    /// Where the key of each keybind is written
+    locations: std::collections::HashMap<String, SourceLocation>,
+    /// Keys set to `null`, which unset inherited keybinds
+    unset: Vec<String>,
 }
 
-fn parse_niri_passthrough(node: &kdl::KdlNode) -> Result<Vec<NiriKeybind>, ConfigError> {
-    let mut keybinds = Vec::new();
+/// Parse a `niri-passthrough` block
+///
+/// Keys that resolve to the same combo (e.g., `Mod+T` and `Super+T`) are
+/// reported as duplicates.
+fn parse_niri_passthrough(node: &kdl::KdlNode, source: &str) -> Result<PassthroughBlock, ConfigError> {
+    let mut block = PassthroughBlock::default();
+    let mut errors = Errors::default();
+    // Keys known to the key registry, to find duplicates
+    let mut combos = std::collections::HashMap::new();
 
     if let Some(children) = node.children() {
         for child in children.nodes() {
             let key = child.name().value().to_string();
 
+            match errors.ok(is_unset_entry(child, source)) {
+                Some(true) => {
+                    block.unset.push(key);
+                    continue;
+                }
+                Some(false) => {}
+                None => continue,
+            }
+
+            let location = get_node_location(child, source);
+            if let Ok(combo) = key.parse::<KeyCombo>() {
+                if let Some(&first) = combos.get(&combo) {
+                    errors.push(duplicate_entry(source, first, location, "niri-passthrough", &combo));
+                    continue;
+                }
+                combos.insert(combo, location);
+            }
+
             // The action is in the child's children block
             if let Some(action_children) = child.children() {
                 // Reconstruct the action from the children
@@ -675,7 +2556,8 @@ fn parse_niri_passthrough(node: &kdl::KdlNode) -> Result<Vec<NiriKeybind>, Confi
                     .collect::<Vec<_>>()
                     .join("; ");
 
-                keybinds.push(NiriKeybind {
+                block.locations.insert(key.clone(), location);
+                block.keybinds.push(NiriKeybind {
                     key,
                     action: format!("{};", action),
                 });
@@ -683,10 +2565,138 @@ fn parse_niri_passthrough(node: &kdl::KdlNode) -> Result<Vec<NiriKeybind>, Confi
         }
     }
 
-    Ok(keybinds)
+    errors.finish()?;
+    Ok(block)
+}
+
+/// Parse a `match`, `exclude` or `when` rule from its properties.
+///
+/// ```kdl
+/// match app-id="^firefox$" title="YouTube" is-floating=false workspace="web" output="DP-1" priority=10
+/// ```
+fn parse_window_match(node: &kdl::KdlNode, source: &str) -> Result<WindowMatch, ConfigError> {
+    let kind = node.name().value();
+    let mut rule = WindowMatch::default();
+    let properties = if kind == "match" {
+        "app-id, title, is-floating, workspace, output, layout and priority"
+    } else {
+        "app-id, title, is-floating, workspace, output and layout"
+    };
+
+    for entry in node.entries() {
+        let Some(name) = entry.name() else {
+            return Err(entry_error(
+                entry,
+                source,
+                format!("'{}' rules take properties like app-id=\"...\", not arguments", kind),
+                "expected a property",
+                format!("Write conditions as properties, e.g. {} app-id=\"^firefox$\"", kind),
+            ));
+        };
+        let value = entry.value();
+        let invalid = |expected: &str| {
+            entry_error(
+                entry,
+                source,
+                format!(
+                    "Invalid {} property '{}={}': expected {}",
+                    kind,
+                    name.value(),
+                    value,
+                    expected
+                ),
+                format!("expected {}", expected),
+                format!("'{}' takes {}", name.value(), expected),
+            )
+        };
+        let regex = |pattern: Option<&str>| -> Result<regex::Regex, ConfigError> {
+            let pattern = pattern.ok_or_else(|| invalid("a regular expression string"))?;
+            regex::Regex::new(pattern).map_err(|e| {
+                entry_error(
+                    entry,
+                    source,
+                    format!("Invalid regex in {} property '{}': {}", kind, name.value(), e),
+                    "invalid regular expression",
+                    "Escape special characters with a backslash, e.g. \"org\\\\.mozilla\"",
+                )
+            })
+        };
+
+        match name.value() {
+            "app-id" => rule.app_id = Some(regex(value.as_string())?),
+            "title" => rule.title = Some(regex(value.as_string())?),
+            "is-floating" => {
+                rule.is_floating = Some(value.as_bool().ok_or_else(|| invalid("true or false"))?);
+            }
+            "workspace" => rule.workspace = Some(kdl_value_to_string(value)),
+            "output" => {
+                rule.output = Some(value.as_string().ok_or_else(|| invalid("a string"))?.to_string());
+            }
+            "layout" => {
+                rule.layout = Some(value.as_string().ok_or_else(|| invalid("a string"))?.to_string());
+            }
+            "priority" if kind == "match" => {
+                let priority = value.as_i64().ok_or_else(|| invalid("an integer"))?;
+                rule.priority = i32::try_from(priority).map_err(|_| invalid("an integer"))?;
+            }
+            other => {
+                return Err(entry_error(
+                    entry,
+                    source,
+                    format!("Unknown {} property '{}'", kind, other),
+                    "unknown property",
+                    format!("'{}' rules support {}", kind, properties),
+                ));
+            }
+        }
+    }
+
+    Ok(rule)
+}
+
+/// Parse the `when` conditions in the child block of a `remap`, `combo` or
+/// `macro` entry
+///
+/// Returns an empty list for entries without a child block.
+fn parse_when_block(node: &kdl::KdlNode, source: &str) -> Result<Vec<WindowMatch>, ConfigError> {
+    let Some(children) = node.children() else {
+        return Ok(Vec::new());
+    };
+
+    let mut conditions = Vec::new();
+    for child in children.nodes() {
+        if child.name().value() != "when" {
+            return Err(node_error(
+                child,
+                source,
+                format!(
+                    "Unknown '{}' in the block of '{}'; expected 'when' conditions",
+                    child.name().value(),
+                    node.name().value()
+                ),
+                "expected 'when'",
+                "Entry blocks only hold conditions, e.g. when app-id=\"^kitty$\"",
+            ));
+        }
+        conditions.push(parse_window_match(child, source)?);
+    }
+
+    if conditions.is_empty() {
+        return Err(node_error(
+            node,
+            source,
+            format!("Entry '{}' has an empty block", node.name().value()),
+            "empty block",
+            "Add 'when' conditions or remove the braces",
+        ));
+    }
+
+    Ok(conditions)
 }
 
-/// Parse profile-switch block: maps keybind strings to profile names
+/// Parse profile-switch block: maps keybinds to profile names, and gives
+/// where each keybind is written
+///
 /// Example KDL:
 /// ```kdl
 /// profile-switch {
@@ -696,30 +2706,70 @@ fn parse_niri_passthrough(node: &kdl::KdlNode) -> Result<Vec<NiriKeybind>, Confi
 /// ```
 fn parse_profile_switch(
     node: &kdl::KdlNode,
-) -> Result<std::collections::HashMap<String, String>, ConfigError> {
+    source: &str,
+) -> Result<ProfileSwitchBlock, ConfigError> {
     let mut map = std::collections::HashMap::new();
+    let mut locations = std::collections::HashMap::new();
+    let mut errors = Errors::default();
 
     if let Some(children) = node.children() {
         for child in children.nodes() {
-            // The node name is the keybind string (e.g., "Ctrl+Shift+1")
-            let keybind = child.name().value().to_string();
+            // The node name is the keybind (e.g., "Ctrl+Shift+1")
+            let name = child.name().value();
+            let keybind = match name.parse::<KeyCombo>() {
+                Ok(keybind) => keybind,
+                Err(e) => {
+                    errors.push(node_error(
+                        child,
+                        source,
+                        format!("Invalid profile-switch keybind '{}': {}", name, e),
+                        "invalid keybind",
+                        "Write modifiers and one key joined by '+', e.g. Ctrl+Shift+1",
+                    ));
+                    continue;
+                }
+            };
+
+            let location = get_node_location(child, source);
+            if let Some(&first) = locations.get(&keybind) {
+                errors.push(duplicate_entry(source, first, location, "profile-switch", &keybind));
+                continue;
+            }
 
             // The first argument is the profile name
             if let Some(entry) = child.entries().first() {
                 if let Some(profile_name) = entry.value().as_string() {
+                    locations.insert(keybind.clone(), location);
                     map.insert(keybind, profile_name.to_string());
                 }
             }
         }
     }
 
-    Ok(map)
+    errors.finish()?;
+    Ok((map, locations))
 }
 
+/// Keybinds of a `profile-switch` block, and where each is written
+type ProfileSwitchBlock = (
+    std::collections::HashMap<KeyCombo, String>,
+    std::collections::HashMap<KeyCombo, SourceLocation>,
+);
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// A key by name, for looking up parsed rules
+    fn key(name: &str) -> KeyCode {
+        name.parse().unwrap()
+    }
+
+    /// A key combo by name, for looking up parsed rules
+    fn combo(name: &str) -> KeyCombo {
+        name.parse().unwrap()
+    }
+
     #[test]
     fn test_parse_basic_config() {
         let config = r#"
@@ -758,7 +2808,7 @@ mod tests {
         assert!(result.is_err());
         let err = result.unwrap_err();
         match err {
-            ConfigError::MissingField { field } => {
+            ConfigError::MissingField { field, .. } => {
                 assert!(field.contains("device name"));
             }
             _ => panic!("Expected MissingField error, got: {:?}", err),
@@ -781,7 +2831,7 @@ mod tests {
         assert!(result.is_err());
         let err = result.unwrap_err();
         match err {
-            ConfigError::Invalid { message } => {
+            ConfigError::Invalid { message, .. } => {
                 assert!(message.contains("default"));
                 assert!(message.contains("Test Keyboard"));
             }
@@ -974,79 +3024,361 @@ mod tests {
     }
 
     #[test]
-    fn test_tilde_expansion_in_niri_keybinds_path() {
+    fn test_media_and_button_keys_valid() {
         let config = r#"
-            global {
-                niri-keybinds-path "~/.config/niri/keybinds.kdl"
+            device "Test Keyboard" {
+                profile "default" {
+                    remap {
+                        XF86AudioMute "VolumeDown"
+                        KEY_MACRO1 "BrightnessUp"
+                        MouseSide "BTN_EXTRA"
+                    }
+                    combo {
+                        Super+PLAYPAUSE "Ctrl+Alt+XF86AudioNext"
+                    }
+                }
             }
         "#;
 
-        let result = parse_config_str(config).unwrap();
-
-        // The path should NOT contain a tilde after expansion
-        let path_str = result.global.niri_keybinds_path.to_string_lossy();
-        assert!(
-            !path_str.starts_with('~'),
-            "Tilde should be expanded, but got: {}",
-            path_str
-        );
-
-        // The path should contain the home directory
-        let home = std::env::var("HOME").expect("HOME environment variable not set");
-        assert!(
-            path_str.starts_with(&home),
-            "Path should start with home directory '{}', but got: {}",
-            home,
-            path_str
-        );
-
-        // The path should end with the correct suffix
-        assert!(
-            path_str.ends_with("/.config/niri/keybinds.kdl"),
-            "Path should end with '/.config/niri/keybinds.kdl', but got: {}",
-            path_str
-        );
+        let result = parse_config_str(config);
+        assert!(result.is_ok(), "{:?}", result.err());
     }
 
     #[test]
-    fn test_absolute_path_unchanged_in_niri_keybinds_path() {
+    fn test_invalid_key_suggests_close_name() {
         let config = r#"
-            global {
-                niri-keybinds-path "/etc/niri/keybinds.kdl"
+            device "Test Keyboard" {
+                profile "default" {
+                    remap {
+                        CapsLock "Escpe"
+                    }
+                }
             }
         "#;
 
-        let result = parse_config_str(config).unwrap();
-
-        // Absolute paths should remain unchanged
-        let path_str = result.global.niri_keybinds_path.to_string_lossy();
-        assert_eq!(
-            path_str, "/etc/niri/keybinds.kdl",
-            "Absolute path should remain unchanged"
+        let err = parse_config_str(config).unwrap_err();
+        assert!(
+            err.to_string().contains("did you mean 'Escape'?"),
+            "unexpected message: {}",
+            err
         );
     }
 
     #[test]
-    fn test_minimal_valid_config() {
-        // Minimal valid config: single device with name, single default profile, one remap entry
+    fn test_invalid_combo_part_reported() {
         let config = r#"
             device "Test Keyboard" {
                 profile "default" {
-                    remap {
-                        CapsLock "Escape"
+                    combo {
+                        Ctrl+Shft+Q "Alt+F4"
                     }
                 }
             }
         "#;
 
-        // Should parse without error
-        let result = parse_config_str(config);
-        assert!(result.is_ok(), "Minimal valid config should parse successfully");
+        match parse_config_str(config).unwrap_err() {
+            ConfigError::InvalidKeys { invalid_keys, .. } => {
+                assert_eq!(invalid_keys.len(), 1);
+                assert_eq!(invalid_keys[0].key, "Shft");
+            }
+            err => panic!("Expected InvalidKeys error, got: {:?}", err),
+        }
+    }
 
-        let config = result.unwrap();
+    #[test]
+    fn test_errors_collected_with_spans() {
+        use miette::Diagnostic;
 
-        // Verify parsed values are correct
-        assert_eq!(config.devices.len(), 1, "Should have exactly one device");
+        let config = r#"
+            global {
+                log-level "loud"
+            }
+
+            device "Test Keyboard" {
+                profile "default" {
+                    macro {
+                        F5 "delay(0)"
+                    }
+                }
+                profile "default" {
+                }
+            }
+        "#;
+
+        let errors = match parse_config_str(config).unwrap_err() {
+            ConfigError::Multiple { errors } => errors,
+            err => panic!("Expected Multiple error, got: {:?}", err),
+        };
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+
+        // Every error points at the offending value, with a label and help
+        let expected = [
+            ("\"loud\"", "unknown log level"),
+            ("\"delay(0)\"", "invalid action"),
+            ("profile", "duplicate profile"),
+        ];
+        for (error, (text, label)) in errors.iter().zip(expected) {
+            let labels: Vec<_> = error.labels().expect("error has a label").collect();
+            assert_eq!(labels.len(), 1);
+            assert_eq!(labels[0].label(), Some(label));
+            let span = labels[0].inner();
+            assert_eq!(config[span.offset()..span.offset() + span.len()].trim(), text);
+            assert!(error.help().is_some());
+        }
+    }
+
+    #[test]
+    fn test_invalid_keys_of_all_profiles_reported_together() {
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {
+                    remap {
+                        CapsLck "Escape"
+                    }
+                }
+                profile "gaming" {
+                    macro {
+                        F5 "Ctrl+Shft+A"
+                    }
+                }
+            }
+        "#;
+
+        match parse_config_str(config).unwrap_err() {
+            ConfigError::InvalidKeys { invalid_keys, .. } => {
+                let keys: Vec<&str> = invalid_keys.iter().map(|k| k.key.as_str()).collect();
+                assert_eq!(keys, vec!["CapsLck", "Shft"]);
+            }
+            err => panic!("Expected InvalidKeys error, got: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_conflicts_reported_with_both_locations() {
+        use miette::Diagnostic;
+
+        // Each error, with the text labelled at the rule and at the other rule
+        fn check(config: &str, expected: &[(&str, &str, &str)]) {
+            let errors = match parse_config_str(config).unwrap_err() {
+                ConfigError::Multiple { errors } => errors,
+                err => panic!("Expected Multiple error, got: {:?}", err),
+            };
+            let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            assert_eq!(errors.len(), expected.len(), "{:#?}", messages);
+
+            for (error, (message, rule, other)) in errors.iter().zip(expected) {
+                assert!(matches!(error, ConfigError::Conflict { .. }), "{:?}", error);
+                assert!(error.to_string().starts_with(message), "{}", error);
+                let labels: Vec<_> = error.labels().expect("error has labels").collect();
+                assert_eq!(labels.len(), 2, "{}", error);
+                let text = |i: usize| {
+                    let span = labels[i].inner();
+                    &config[span.offset()..span.offset() + span.len()]
+                };
+                assert_eq!((text(0), text(1)), (*rule, *other), "{}", error);
+            }
+        }
+
+        check(
+            r#"
+            device "Test Keyboard" {
+                profile "default" {
+                    remap {
+                        Esc "CapsLock"
+                        Escape "LeftCtrl"
+                    }
+                    combo {
+                        Ctrl+Shift+A "B"
+                        Shift+Ctrl+A "C"
+                    }
+                }
+            }
+            "#,
+            &[
+                ("Duplicate remap entry 'Escape': 'Esc' and 'Escape'", "Escape", "Esc"),
+                ("Duplicate combo entry 'Ctrl+Shift+A'", "Shift+Ctrl+A", "Ctrl+Shift+A"),
+            ],
+        );
+
+        // Rules inherited by several profiles are reported once
+        check(
+            r#"
+            global {
+                remap {
+                    F1 "F2"
+                }
+                macro {
+                    F1 "A"
+                }
+            }
+
+            device "Test Keyboard" {
+                profile "default" {
+                    combo {
+                        Ctrl+Shift+1 "D"
+                    }
+                    macro {
+                        F5 "X"
+                    }
+                    niri-passthrough {
+                        Mod+F5 {
+                            spawn "foot"
+                        }
+                    }
+                }
+                profile "gaming" extends="default" {
+                }
+                profile-switch {
+                    Ctrl+Shift+1 "default"
+                    Ctrl+Shift+2 "gaming"
+                }
+            }
+            "#,
+            &[
+                ("Macro trigger key 'F1' conflicts with remap source key 'F1'", "F1", "F1"),
+                ("niri-passthrough keybind 'Mod+F5' is intercepted by macro on 'F5'", "Mod+F5", "F5"),
+                ("Combo 'Ctrl+Shift+1' is shadowed by the profile-switch keybind", "Ctrl+Shift+1", "Ctrl+Shift+1"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_profile_switch_and_remap_chains_allowed() {
+        // A swap, and a profile-switch keybind whose key is only remapped
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {
+                    remap {
+                        CapsLock "Escape"
+                        Escape "CapsLock"
+                        Q "W"
+                    }
+                    macro {
+                        F5 "X"
+                    }
+                }
+                profile-switch {
+                    Ctrl+Q "default"
+                }
+            }
+        "#;
+        assert!(parse_config_str(config).is_ok());
+
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {
+                    macro {
+                        Q "X"
+                    }
+                }
+                profile-switch {
+                    Ctrl+Q "default"
+                }
+            }
+        "#;
+        match parse_config_str(config).unwrap_err() {
+            ConfigError::Conflict { message, .. } => {
+                assert!(message.contains("while Ctrl is held"), "{}", message)
+            }
+            err => panic!("Expected Conflict error, got: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_unknown_parent_profile_points_at_extends() {
+        use miette::Diagnostic;
+
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" extends="base" {
+                    remap {
+                        CapsLock "Escape"
+                    }
+                }
+            }
+        "#;
+
+        let err = parse_config_str(config).unwrap_err();
+        let labels: Vec<_> = err.labels().expect("error has a label").collect();
+        let span = labels[0].inner();
+        assert_eq!(&config[span.offset()..span.offset() + span.len()], "extends=\"base\"");
+        assert!(err.to_string().contains("at line 3, column 35"), "{}", err);
+    }
+
+    #[test]
+    fn test_tilde_expansion_in_niri_keybinds_path() {
+        let config = r#"
+            global {
+                niri-keybinds-path "~/.config/niri/keybinds.kdl"
+            }
+        "#;
+
+        let result = parse_config_str(config).unwrap();
+
+        // The path should NOT contain a tilde after expansion
+        let path_str = result.global.niri_keybinds_path.to_string_lossy();
+        assert!(
+            !path_str.starts_with('~'),
+            "Tilde should be expanded, but got: {}",
+            path_str
+        );
+
+        // The path should contain the home directory
+        let home = std::env::var("HOME").expect("HOME environment variable not set");
+        assert!(
+            path_str.starts_with(&home),
+            "Path should start with home directory '{}', but got: {}",
+            home,
+            path_str
+        );
+
+        // The path should end with the correct suffix
+        assert!(
+            path_str.ends_with("/.config/niri/keybinds.kdl"),
+            "Path should end with '/.config/niri/keybinds.kdl', but got: {}",
+            path_str
+        );
+    }
+
+    #[test]
+    fn test_absolute_path_unchanged_in_niri_keybinds_path() {
+        let config = r#"
+            global {
+                niri-keybinds-path "/etc/niri/keybinds.kdl"
+            }
+        "#;
+
+        let result = parse_config_str(config).unwrap();
+
+        // Absolute paths should remain unchanged
+        let path_str = result.global.niri_keybinds_path.to_string_lossy();
+        assert_eq!(
+            path_str, "/etc/niri/keybinds.kdl",
+            "Absolute path should remain unchanged"
+        );
+    }
+
+    #[test]
+    fn test_minimal_valid_config() {
+        // Minimal valid config: single device with name, single default profile, one remap entry
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {
+                    remap {
+                        CapsLock "Escape"
+                    }
+                }
+            }
+        "#;
+
+        // Should parse without error
+        let result = parse_config_str(config);
+        assert!(result.is_ok(), "Minimal valid config should parse successfully");
+
+        let config = result.unwrap();
+
+        // Verify parsed values are correct
+        assert_eq!(config.devices.len(), 1, "Should have exactly one device");
 
         let device = &config.devices[0];
         assert_eq!(
@@ -1070,8 +3402,8 @@ mod tests {
             "Default profile should have exactly one remap entry"
         );
         assert_eq!(
-            default_profile.remap.get("CapsLock"),
-            Some(&"Escape".to_string()),
+            default_profile.remap.get(&key("CapsLock")),
+            Some(&key("Escape")),
             "CapsLock should be remapped to Escape"
         );
     }
@@ -1105,11 +3437,11 @@ mod tests {
         // Verify profile-switch entries were parsed
         assert_eq!(device.profile_switch.len(), 2);
         assert_eq!(
-            device.profile_switch.get("Ctrl+Shift+1"),
+            device.profile_switch.get(&combo("Ctrl+Shift+1")),
             Some(&"default".to_string())
         );
         assert_eq!(
-            device.profile_switch.get("Ctrl+Shift+2"),
+            device.profile_switch.get(&combo("Ctrl+Shift+2")),
             Some(&"gaming".to_string())
         );
 
@@ -1179,7 +3511,7 @@ mod tests {
         assert!(result.is_err());
         let err = result.unwrap_err();
         match err {
-            ConfigError::Invalid { message } => {
+            ConfigError::Invalid { message, .. } => {
                 assert!(
                     message.contains("Duplicate profile name"),
                     "Error should mention duplicate profile: {}",
@@ -1270,7 +3602,7 @@ mod tests {
         assert!(result.is_ok());
         let config = result.unwrap();
         let profile = &config.devices[0].profiles["default"];
-        assert!(profile.macros.contains_key("F12"));
+        assert!(profile.macros.contains_key(&key("F12")));
     }
 
     #[test]
@@ -1292,7 +3624,7 @@ mod tests {
         assert!(result.is_err());
         let err = result.unwrap_err();
         match err {
-            ConfigError::Invalid { message } => {
+            ConfigError::Conflict { message, .. } => {
                 assert!(
                     message.contains("conflicts"),
                     "Error should mention conflict: {}",
@@ -1309,7 +3641,7 @@ mod tests {
                     message
                 );
             }
-            _ => panic!("Expected Invalid error, got: {:?}", err),
+            _ => panic!("Expected Conflict error, got: {:?}", err),
         }
     }
 
@@ -1335,7 +3667,7 @@ mod tests {
         assert!(result.is_err());
         let err = result.unwrap_err();
         match err {
-            ConfigError::Invalid { message } => {
+            ConfigError::Conflict { message, .. } => {
                 assert!(
                     message.contains("conflicts"),
                     "Error should mention conflict: {}",
@@ -1347,7 +3679,7 @@ mod tests {
                     message
                 );
             }
-            _ => panic!("Expected Invalid error, got: {:?}", err),
+            _ => panic!("Expected Conflict error, got: {:?}", err),
         }
     }
 
@@ -1421,14 +3753,14 @@ mod tests {
         assert!(result.is_err());
         let err = result.unwrap_err();
         match err {
-            ConfigError::Invalid { message } => {
+            ConfigError::Conflict { message, .. } => {
                 assert!(
                     message.contains("conflicts"),
                     "Error should mention conflict: {}",
                     message
                 );
             }
-            _ => panic!("Expected Invalid error, got: {:?}", err),
+            _ => panic!("Expected Conflict error, got: {:?}", err),
         }
     }
 
@@ -1527,7 +3859,7 @@ mod tests {
         assert!(result.is_ok());
         let config = result.unwrap();
         let profile = &config.devices[0].profiles["default"];
-        let actions = profile.macros.get("F12").unwrap();
+        let actions = profile.macros.get(&key("F12")).unwrap();
         assert_eq!(actions.len(), 4);
     }
 
@@ -1583,7 +3915,7 @@ mod tests {
         assert!(result.is_err());
         let err = result.unwrap_err();
         match err {
-            ConfigError::Invalid { message } => {
+            ConfigError::Invalid { message, .. } => {
                 assert!(
                     message.contains("10000"),
                     "Error should mention maximum delay: {}",
@@ -1616,7 +3948,7 @@ mod tests {
         assert!(result.is_err());
         let err = result.unwrap_err();
         match err {
-            ConfigError::Invalid { message } => {
+            ConfigError::Invalid { message, .. } => {
                 assert!(
                     message.contains("positive"),
                     "Error should mention positive integer: {}",
@@ -1644,7 +3976,7 @@ mod tests {
         assert!(result.is_ok());
         let config = result.unwrap();
         let profile = &config.devices[0].profiles["default"];
-        let actions = profile.macros.get("F12").unwrap();
+        let actions = profile.macros.get(&key("F12")).unwrap();
         assert_eq!(actions.len(), 3);
         assert!(matches!(actions[1], MacroAction::Delay(10000)));
     }
@@ -1666,7 +3998,7 @@ mod tests {
         assert!(result.is_ok());
         let config = result.unwrap();
         let profile = &config.devices[0].profiles["default"];
-        let actions = profile.macros.get("F12").unwrap();
+        let actions = profile.macros.get(&key("F12")).unwrap();
         assert_eq!(actions.len(), 5);
         assert!(matches!(actions[0], MacroAction::Delay(1)));
         assert!(matches!(actions[1], MacroAction::Delay(50)));
@@ -1692,7 +4024,7 @@ mod tests {
         assert!(result.is_err());
         let err = result.unwrap_err();
         match err {
-            ConfigError::Invalid { message } => {
+            ConfigError::Invalid { message, .. } => {
                 assert!(
                     message.contains("maximum"),
                     "Error should mention maximum delay: {}",
@@ -1723,7 +4055,7 @@ mod tests {
         assert!(result.is_err());
         let err = result.unwrap_err();
         match err {
-            ConfigError::Invalid { message } => {
+            ConfigError::Invalid { message, .. } => {
                 assert!(
                     message.contains("nonexistent"),
                     "Error should mention the invalid profile name: {}",
@@ -1794,7 +4126,7 @@ mod tests {
         assert!(result.is_err());
         let err = result.unwrap_err();
         match err {
-            ConfigError::Invalid { message } => {
+            ConfigError::Invalid { message, .. } => {
                 assert!(
                     message.contains("non-existent profile"),
                     "Error should mention non-existent profile: {}",
@@ -1811,9 +4143,9 @@ mod tests {
     }
 
     #[test]
-    fn test_profile_switch_multiple_invalid_refs_first_reported() {
+    fn test_profile_switch_multiple_invalid_refs_all_reported() {
         // When multiple profile-switch entries reference non-existent profiles,
-        // the first one encountered should be reported
+        // each of them is reported
         let config = r#"
             device "Test Keyboard" {
                 profile-switch {
@@ -1832,15 +4164,13 @@ mod tests {
         assert!(result.is_err());
         let err = result.unwrap_err();
         match err {
-            ConfigError::Invalid { message } => {
-                // Should report one of the invalid profiles
-                assert!(
-                    message.contains("nonexistent1") || message.contains("nonexistent2"),
-                    "Error should mention an invalid profile name: {}",
-                    message
-                );
+            ConfigError::Multiple { errors } => {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                assert_eq!(messages.len(), 2, "{:?}", messages);
+                assert!(messages[0].contains("nonexistent1"), "{}", messages[0]);
+                assert!(messages[1].contains("nonexistent2"), "{}", messages[1]);
             }
-            _ => panic!("Expected Invalid error, got: {:?}", err),
+            _ => panic!("Expected Multiple error, got: {:?}", err),
         }
     }
 
@@ -1863,11 +4193,14 @@ mod tests {
             }
         "#;
 
-        let result = parse_config_str(config);
+        let result = parse_config_str_with_warnings(config, &HostContext::default(), false);
         assert!(result.is_ok(), "Parsing should succeed even without profile-switch");
 
-        let config = result.unwrap();
+        let (config, warnings) = result.unwrap();
         assert_eq!(config.devices.len(), 1);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].lint, Lint::NoProfileSwitch);
+        assert!(warnings[0].message.contains("(default, gaming)"));
 
         let device = &config.devices[0];
         assert_eq!(device.profiles.len(), 2);
@@ -1890,86 +4223,266 @@ mod tests {
             }
         "#;
 
-        let result = parse_config_str(config);
+        let result = parse_config_str_with_warnings(config, &HostContext::default(), false);
         assert!(result.is_ok());
 
-        let config = result.unwrap();
+        let (config, warnings) = result.unwrap();
+        assert!(warnings.is_empty());
         let device = &config.devices[0];
         assert_eq!(device.profiles.len(), 1);
         assert!(device.profile_switch.is_empty());
     }
 
     #[test]
-    fn test_app_id_hint_parsing() {
+    fn test_lints_reported_with_codes_and_spans() {
         let config = r#"
             device "Test Keyboard" {
+                colour "red"
                 profile "default" {
                     remap {
-                        CapsLock "Escape"
+                        A "B"
+                        B "C"
                     }
-                }
-                profile "firefox" {
-                    app-id-hint "org.mozilla.firefox"
-                    remap {
-                        CapsLock "LeftCtrl"
+                    macro {
+                        F1 "x" speed=2
                     }
                 }
             }
+            keyboard-layout "us"
         "#;
 
-        let result = parse_config_str(config);
-        assert!(result.is_ok());
-
-        let config = result.unwrap();
-        let device = &config.devices[0];
-
-        // Default profile should have no app-id-hint
-        let default_profile = &device.profiles["default"];
-        assert!(
-            default_profile.app_id_hint.is_none(),
-            "default profile should have no app-id-hint"
-        );
-
-        // Firefox profile should have app-id-hint
-        let firefox_profile = &device.profiles["firefox"];
+        let (_, warnings) = parse_config_str_with_warnings(config, &HostContext::default(), false).unwrap();
+        let found: Vec<(Lint, &str)> =
+            warnings.iter().map(|w| (w.lint, w.span.label.as_str())).collect();
         assert_eq!(
-            firefox_profile.app_id_hint,
-            Some("org.mozilla.firefox".to_string()),
-            "firefox profile should have app-id-hint"
+            found,
+            vec![
+                (Lint::UnknownNode, "unknown device option"),
+                (Lint::UnknownProperty, "unknown property"),
+                (Lint::RemapChain, "emits 'B'"),
+                (Lint::UnknownNode, "unknown top-level node"),
+            ]
         );
+        assert_eq!(warnings[0].message, "Unknown device option 'colour'");
+        let chain = &warnings[2];
+        assert_eq!(&config[chain.span.location.offset..][..1], "A");
+        assert_eq!(miette::Diagnostic::severity(chain), Some(miette::Severity::Warning));
+        assert_eq!(miette::Diagnostic::code(chain).unwrap().to_string(), "remap-chain");
     }
 
     #[test]
-    fn test_app_id_hint_missing_is_none() {
-        // Profile without app-id-hint should have None
-        let config = r#"
+    fn test_resolved_config_serializes_as_written() {
+        let config = parse_config_str(
+            r#"
+            global {
+                remap {
+                    Esc "CapsLock"
+                }
+            }
             device "Test Keyboard" {
                 profile "default" {
+                    match app-id="^firefox$"
                     remap {
-                        CapsLock "Escape"
+                        KEY_Z "Y"
+                        A "B"
+                    }
+                    macro {
+                        F1 "Shift+Ctrl+c" "delay(50)" policy="restart"
                     }
                 }
             }
-        "#;
-
-        let result = parse_config_str(config);
-        assert!(result.is_ok());
-
-        let config = result.unwrap();
-        let device = &config.devices[0];
-        let profile = &device.profiles["default"];
-
-        assert!(
-            profile.app_id_hint.is_none(),
-            "profile without app-id-hint should have None"
-        );
+            "#,
+        )
+        .unwrap();
+
+        // Keys by their display name, in key code order, with global rules merged in
+        let text = serde_json::to_string(&config).unwrap();
+        assert!(text.contains(r#""remap":{"Escape":"CapsLock","A":"B","Z":"Y"}"#), "{}", text);
+
+        let json = serde_json::to_value(&config).unwrap();
+        let profile = &json["devices"][0]["profiles"]["default"];
+        assert_eq!(profile["macros"]["F1"], serde_json::json!(["Ctrl+Shift+C", "delay(50)"]));
+        assert_eq!(profile["macro-policies"]["F1"], "restart");
+        assert_eq!(profile["match-rules"][0]["app-id"], "^firefox$");
+        assert_eq!(json["global"]["key-mode"], "keycode");
+
+        let schema = serde_json::to_value(Config::json_schema()).unwrap();
+        assert_eq!(schema["title"], "Config");
+        assert_eq!(schema["$defs"]["KeyCombo"]["type"], "string");
     }
 
     #[test]
-    fn test_app_id_hint_with_simple_string() {
+    fn test_strict_mode_rejects_unknown_nodes_with_suggestions() {
         let config = r#"
-            device "Test Keyboard" {
-                profile "default" {
+            global {
+                log-levle "debug"
+            }
+            device "Test Keyboard" key="x" {
+                profile "default" {
+                    app-id-hnit "firefox"
+                    niri-passthru {
+                        Super+F "maximize-column"
+                    }
+                    remap strict=true {
+                        A "B"
+                    }
+                }
+            }
+        "#;
+
+        // Without strict mode, they are only lints
+        let (_, warnings) = parse_config_str_with_warnings(config, &HostContext::default(), false).unwrap();
+        assert_eq!(warnings.len(), 5);
+
+        let err = parse_config_str_with_warnings(config, &HostContext::default(), true).unwrap_err();
+        let ConfigError::Multiple { errors } = err else {
+            panic!("Expected every unknown name reported, got: {:?}", err);
+        };
+        let found: Vec<(String, &str)> = errors
+            .iter()
+            .map(|e| match e {
+                ConfigError::Invalid { message, span: Some(span) } => {
+                    (message.clone(), &config[span.location.offset..][..span.location.len])
+                }
+                other => panic!("Expected an error with a span, got: {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("Unknown global option 'log-levle' (did you mean 'log-level'?)".to_string(), "log-levle"),
+                ("Unknown property 'key' on device".to_string(), "key=\"x\""),
+                ("Unknown profile option 'app-id-hnit' (did you mean 'app-id-hint'?)".to_string(), "app-id-hnit"),
+                (
+                    "Unknown profile option 'niri-passthru' (did you mean 'niri-passthrough'?)".to_string(),
+                    "niri-passthru"
+                ),
+                ("Unknown property 'strict' on remap".to_string(), "strict=true"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lints_allowed_by_annotation_or_global_list() {
+        let config = r#"
+            global {
+                allow "no-profile-switch"
+            }
+            (allow:unknown-node)device "Test Keyboard" {
+                colour "red"
+                profile "default" {
+                    (allow)remap {
+                        A "B"
+                        B "C"
+                    }
+                }
+                profile "gaming" {
+                    ("allow:remap-chain,unknown-property")macro {
+                        F1 "x" speed=2
+                    }
+                    hint "game"
+                }
+            }
+            (allow:remap-chain)keyboard-layout "us"
+        "#;
+
+        let (_, warnings) = parse_config_str_with_warnings(config, &HostContext::default(), false).unwrap();
+        // Only the unknown top-level node is left, as its annotation allows another lint
+        let found: Vec<&str> = warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(found, vec!["Unknown top-level node 'keyboard-layout'"]);
+
+        // Allowed lints are not errors in strict mode either
+        let result = parse_config_str_with_warnings(config, &HostContext::default(), true);
+        let err = result.expect_err("the unknown top-level node is an error in strict mode");
+        assert!(err.to_string().contains("Unknown top-level node 'keyboard-layout'"), "{}", err);
+
+        let unknown = r#"
+            global {
+                allow "unknown-nodes"
+            }
+            (allow:remap-chains)device "Test Keyboard" {}
+        "#;
+        let err = parse_config_str(unknown).unwrap_err();
+        let ConfigError::Multiple { errors } = err else {
+            panic!("Expected both unknown lints, got: {:?}", err);
+        };
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].contains("Unknown lint: remap-chains"), "{:?}", messages);
+        assert!(messages[1].contains("Unknown lint: unknown-nodes"), "{:?}", messages);
+    }
+
+    #[test]
+    fn test_app_id_hint_parsing() {
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {
+                    remap {
+                        CapsLock "Escape"
+                    }
+                }
+                profile "firefox" {
+                    app-id-hint "org.mozilla.firefox"
+                    remap {
+                        CapsLock "LeftCtrl"
+                    }
+                }
+            }
+        "#;
+
+        let result = parse_config_str(config);
+        assert!(result.is_ok());
+
+        let config = result.unwrap();
+        let device = &config.devices[0];
+
+        // Default profile should have no app-id-hint
+        let default_profile = &device.profiles["default"];
+        assert!(
+            default_profile.app_id_hint.is_none(),
+            "default profile should have no app-id-hint"
+        );
+
+        // Firefox profile should have app-id-hint
+        let firefox_profile = &device.profiles["firefox"];
+        assert_eq!(
+            firefox_profile.app_id_hint,
+            Some("org.mozilla.firefox".to_string()),
+            "firefox profile should have app-id-hint"
+        );
+    }
+
+    #[test]
+    fn test_app_id_hint_missing_is_none() {
+        // Profile without app-id-hint should have None
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {
+                    remap {
+                        CapsLock "Escape"
+                    }
+                }
+            }
+        "#;
+
+        let result = parse_config_str(config);
+        assert!(result.is_ok());
+
+        let config = result.unwrap();
+        let device = &config.devices[0];
+        let profile = &device.profiles["default"];
+
+        assert!(
+            profile.app_id_hint.is_none(),
+            "profile without app-id-hint should have None"
+        );
+    }
+
+    #[test]
+    fn test_app_id_hint_with_simple_string() {
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {
                     app-id-hint "firefox"
                     remap {
                         CapsLock "Escape"
@@ -1991,4 +4504,1350 @@ mod tests {
             "app-id-hint should be parsed correctly"
         );
     }
+
+    #[test]
+    fn test_macro_policy_parsing() {
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {
+                    macro {
+                        F5 "Ctrl+c" "delay(50)" "Ctrl+v" policy="restart"
+                        F6 "A" "B" policy="cancel-on-release"
+                        F7 "C"
+                    }
+                }
+            }
+        "#;
+
+        let config = parse_config_str(config).unwrap();
+        let profile = &config.devices[0].profiles["default"];
+
+        assert_eq!(profile.macro_policies.get(&key("F5")), Some(&MacroPolicy::Restart));
+        assert_eq!(profile.macro_policies.get(&key("F6")), Some(&MacroPolicy::CancelOnRelease));
+        assert_eq!(profile.macro_policies.get(&key("F7")), None, "unset policy should use the default");
+
+        // The policy property must not be treated as a macro action
+        assert_eq!(profile.macros[&key("F5")].len(), 3);
+        assert_eq!(profile.macros[&key("F6")].len(), 2);
+    }
+
+    #[test]
+    fn test_macro_unknown_policy_fails() {
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {
+                    macro {
+                        F5 "A" policy="parallel"
+                    }
+                }
+            }
+        "#;
+
+        let err = parse_config_str(config).unwrap_err();
+        match err {
+            ConfigError::Invalid { message, .. } => {
+                assert!(message.contains("F5"));
+                assert!(message.contains("parallel"));
+            }
+            _ => panic!("Expected Invalid error, got: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_macro_abort_key_parsing() {
+        let config = r#"
+            global {
+                macro-abort-key "Pasue"
+            }
+        "#;
+        let config = parse_config_str(config);
+        assert!(config.is_err(), "Pasue is not a recognized key name");
+
+        let config = r#"
+            global {
+                macro-abort-key "F12"
+            }
+        "#;
+        let config = parse_config_str(config).unwrap();
+        assert_eq!(config.global.macro_abort_key, Some(key("F12")));
+    }
+
+    #[test]
+    fn test_macro_isolation_parsing() {
+        let config = parse_config_str("global {}").unwrap();
+        assert_eq!(config.global.macro_isolation, MacroIsolation::Off);
+
+        let config = r#"
+            global {
+                macro-isolation "queue"
+            }
+        "#;
+        let config = parse_config_str(config).unwrap();
+        assert_eq!(config.global.macro_isolation, MacroIsolation::Queue);
+
+        let config = r#"
+            global {
+                macro-isolation "buffer"
+            }
+        "#;
+        assert!(parse_config_str(config).is_err());
+    }
+
+    #[test]
+    fn test_macro_record_settings_parsing() {
+        let config = r#"
+            global {
+                macro-record-key "F11"
+                macro-record-quantize 10
+            }
+        "#;
+        let config = parse_config_str(config).unwrap();
+        assert_eq!(config.global.macro_record_key, Some(key("F11")));
+        assert_eq!(config.global.macro_record_quantize, 10);
+
+        let config = r#"
+            global {
+                macro-record-quantize -5
+            }
+        "#;
+        assert!(parse_config_str(config).is_err());
+    }
+
+    #[test]
+    fn test_macro_library_parsing() {
+        let config = r#"
+            macros {
+                copy-paste "Ctrl+c" "delay(50)" "Ctrl+v"
+                switch-tab n="1" {
+                    key "Alt+{n}"
+                    delay 20
+                }
+                open-app app=null "Super+d"
+            }
+        "#;
+
+        let config = parse_config_str(config).unwrap();
+        assert_eq!(config.macros.len(), 3);
+
+        let copy_paste = &config.macros["copy-paste"];
+        assert!(copy_paste.params.is_empty());
+        assert_eq!(copy_paste.actions.len(), 3);
+
+        let switch_tab = &config.macros["switch-tab"];
+        assert_eq!(
+            switch_tab.params,
+            vec![MacroParam { name: "n".to_string(), default: Some("1".to_string()) }]
+        );
+        assert_eq!(
+            switch_tab.actions,
+            vec!["Alt+{n}".to_string(), "delay(20)".to_string()]
+        );
+
+        assert_eq!(config.macros["open-app"].params[0].default, None);
+    }
+
+    #[test]
+    fn test_macro_library_duplicate_and_empty_fail() {
+        let config = r#"
+            macros {
+                copy "Ctrl+c"
+                copy "Ctrl+Insert"
+            }
+        "#;
+        assert!(parse_config_str(config).is_err(), "duplicate macro names should fail");
+
+        let config = r#"
+            macros {
+                nothing
+            }
+        "#;
+        assert!(parse_config_str(config).is_err(), "macros without actions should fail");
+    }
+
+    #[test]
+    fn test_macro_library_delay_as_written() {
+        use miette::Diagnostic;
+
+        for (delay, written) in [("\"abc\"", "delay(abc)"), ("-5", "delay(-5)")] {
+            let config = format!("macros {{\n    wait {{\n        delay {}\n    }}\n}}\n", delay);
+            let err = parse_config_str(&config).unwrap_err();
+            assert!(err.to_string().contains(&format!("Invalid delay value '{}'", written)), "{}", err);
+            let label = err.labels().and_then(|mut labels| labels.next()).expect("error has a label");
+            assert_eq!(&config[label.offset()..label.offset() + label.len()], delay, "{}", err);
+        }
+
+        // A placeholder is filled in when the macro is called
+        let config = r#"
+            macros {
+                wait ms="30" {
+                    delay "{ms}"
+                }
+            }
+        "#;
+        let config = parse_config_str(config).unwrap();
+        assert_eq!(config.macros["wait"].actions, vec!["delay({ms})".to_string()]);
+    }
+
+    #[test]
+    fn test_macro_reference_instantiates_library_macro() {
+        // The library may appear after the devices that use it
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {
+                    macro {
+                        F5 macro="switch-tab" n="3" policy="restart"
+                        F6 macro="switch-tab"
+                    }
+                }
+            }
+
+            macros {
+                switch-tab n="1" "Alt+{n}"
+            }
+        "#;
+
+        let config = parse_config_str(config).unwrap();
+        let profile = &config.devices[0].profiles["default"];
+
+        assert_eq!(profile.macros[&key("F5")], vec![MacroAction::Key(combo("Alt+3"))]);
+        assert_eq!(profile.macros[&key("F6")], vec![MacroAction::Key(combo("Alt+1"))]);
+        assert_eq!(profile.macro_policies.get(&key("F5")), Some(&MacroPolicy::Restart));
+    }
+
+    #[test]
+    fn test_macro_errors_caught_at_parse_time() {
+        // Macro triggers are single keys
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {
+                    macro {
+                        Ctrl+Shift+D "Ctrl+c"
+                    }
+                }
+            }
+        "#;
+        assert!(parse_config_str(config).is_err(), "combo macro triggers should fail");
+
+        // A library macro whose defaults produce an invalid action fails even if unused
+        let config = r#"
+            macros {
+                switch-tab n="1+2" "Alt+{n}"
+            }
+        "#;
+        assert!(parse_config_str(config).is_err(), "invalid default actions should fail");
+
+        // A reference whose arguments produce an invalid action fails
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {
+                    macro {
+                        F5 macro="switch-tab" n="Ctrl"
+                    }
+                }
+            }
+
+            macros {
+                switch-tab n="1" "Alt+{n}"
+            }
+        "#;
+        assert!(parse_config_str(config).is_err(), "invalid macro arguments should fail");
+    }
+
+    #[test]
+    fn test_macro_reference_errors() {
+        let with_macro = |body: &str| {
+            format!(
+                r#"
+                macros {{
+                    switch-tab n=null "Alt+{{n}}"
+                }}
+                device "Test Keyboard" {{
+                    profile "default" {{
+                        macro {{
+                            {}
+                        }}
+                    }}
+                }}
+                "#,
+                body
+            )
+        };
+
+        // Unknown library macro
+        assert!(parse_config_str(&with_macro(r#"F5 macro="missing""#)).is_err());
+        // Missing required parameter
+        assert!(parse_config_str(&with_macro(r#"F5 macro="switch-tab""#)).is_err());
+        // Unknown parameter
+        assert!(parse_config_str(&with_macro(r#"F5 macro="switch-tab" n="2" m="3""#)).is_err());
+        // Reference combined with inline actions
+        assert!(parse_config_str(&with_macro(r#"F5 "A" macro="switch-tab" n="2""#)).is_err());
+        // Substituted key must be valid
+        assert!(parse_config_str(&with_macro(r#"F5 macro="switch-tab" n="Bogus""#)).is_err());
+        // Valid reference
+        assert!(parse_config_str(&with_macro(r#"F5 macro="switch-tab" n="2""#)).is_ok());
+    }
+
+    #[test]
+    fn test_macro_text_action_parsing() {
+        let config = r#"
+            macros {
+                greet name="world" {
+                    text "Hello, {name}!"
+                }
+            }
+            device "Test Keyboard" {
+                profile "default" {
+                    macro {
+                        F5 "text(café → ok)" "Return"
+                        F6 macro="greet" name="niri"
+                    }
+                }
+            }
+        "#;
+
+        let config = parse_config_str(config).unwrap();
+        let profile = &config.devices[0].profiles["default"];
+        assert_eq!(
+            profile.macros[&key("F5")],
+            vec![
+                MacroAction::Text("café → ok".to_string()),
+                MacroAction::Key(combo("Return"))
+            ]
+        );
+        assert_eq!(profile.macros[&key("F6")], vec![MacroAction::Text("Hello, niri!".to_string())]);
+
+        // Text round-trips through its written form
+        assert_eq!(profile.macros[&key("F5")][0].to_string(), "text(café → ok)");
+
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {
+                    macro {
+                        F5 "text()"
+                    }
+                }
+            }
+        "#;
+        assert!(parse_config_str(config).is_err(), "empty text should fail");
+    }
+
+    #[test]
+    fn test_text_input_settings_parsing() {
+        let config = parse_config_str("global {}").unwrap();
+        assert_eq!(config.global.unicode_input, UnicodeInput::CtrlShiftU);
+        assert_eq!(config.global.xkb, None);
+
+        let config = r#"
+            global {
+                unicode-input "none"
+                xkb {
+                    layout "us,de"
+                    variant ",nodeadkeys"
+                    options "grp:alt_shift_toggle"
+                }
+            }
+        "#;
+        let config = parse_config_str(config).unwrap();
+        assert_eq!(config.global.unicode_input, UnicodeInput::None);
+        assert_eq!(
+            config.global.xkb,
+            Some(XkbSettings {
+                layout: "us,de".to_string(),
+                variant: ",nodeadkeys".to_string(),
+                options: Some("grp:alt_shift_toggle".to_string()),
+                ..Default::default()
+            })
+        );
+
+        assert!(parse_config_str(r#"global { unicode-input "compose"; }"#).is_err());
+    }
+
+    #[test]
+    fn test_layout_hint_profile_selection() {
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {
+                    remap { CapsLock "Escape"; }
+                }
+                profile "cyrillic" {
+                    layout-hint "russian"
+                    remap { CapsLock "LeftCtrl"; }
+                }
+            }
+        "#;
+        let config = parse_config_str(config).unwrap();
+        let device = &config.devices[0];
+        assert_eq!(
+            device.profiles["cyrillic"].layout_hint,
+            Some("russian".to_string())
+        );
+
+        let layout = |name: &str| FocusContext {
+            layout: Some(name.to_string()),
+            ..Default::default()
+        };
+
+        // Matching ignores case and accepts layout variants
+        assert_eq!(device.select_profile("default", &layout("Russian (phonetic)")), Some("cyrillic"));
+        assert_eq!(device.select_profile("cyrillic", &layout("Russian")), None);
+
+        // Leaving the layout returns to default, but only from a hinted profile
+        assert_eq!(device.select_profile("cyrillic", &layout("English (US)")), Some("default"));
+        assert_eq!(device.select_profile("default", &layout("English (US)")), None);
+    }
+
+    #[test]
+    fn test_focus_hint_precedence() {
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {}
+                profile "art" {
+                    output-hint "HDMI-A-1"
+                }
+                profile "browser" {
+                    app-id-hint "firefox"
+                }
+                profile "gaming" {
+                    workspace-hint "games"
+                }
+                profile "mail" {
+                    workspace-hint 2
+                }
+                profile "manual" {}
+            }
+        "#;
+        let config = parse_config_str(config).unwrap();
+        let device = &config.devices[0];
+        assert_eq!(device.profiles["mail"].workspace_hint, Some("2".to_string()));
+
+        let focus = |app_id: &str, workspace: &str, idx: u8, output: &str| FocusContext {
+            app_id: Some(app_id.to_string()),
+            workspace_name: Some(workspace.to_string()),
+            workspace_idx: Some(idx),
+            output: Some(output.to_string()),
+            ..Default::default()
+        };
+
+        // app-id beats workspace, workspace beats output
+        assert_eq!(device.select_profile("default", &focus("firefox", "games", 1, "HDMI-A-1")), Some("browser"));
+        assert_eq!(device.select_profile("default", &focus("kitty", "games", 1, "HDMI-A-1")), Some("gaming"));
+        assert_eq!(device.select_profile("default", &focus("kitty", "", 2, "HDMI-A-1")), Some("mail"));
+        assert_eq!(device.select_profile("default", &focus("kitty", "", 1, "HDMI-A-1")), Some("art"));
+        assert_eq!(device.select_profile("art", &focus("kitty", "", 1, "DP-1")), Some("default"));
+
+        // A manually selected profile without hints is kept
+        assert_eq!(device.select_profile("manual", &focus("kitty", "", 1, "DP-1")), None);
+        assert_eq!(device.select_profile("manual", &focus("firefox", "", 1, "DP-1")), Some("browser"));
+    }
+
+    #[test]
+    fn test_window_match_rules() {
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {}
+                profile "browser" {
+                    app-id-hint "firefox"
+                }
+                profile "youtube" {
+                    match app-id="^firefox$" title="YouTube" priority=10
+                }
+                profile "vim" {
+                    match title="^vim "
+                    exclude is-floating=true
+                }
+                profile "art" {
+                    match output="HDMI-A-1" workspace=2
+                }
+            }
+        "#;
+        let config = parse_config_str(config).unwrap();
+        let device = &config.devices[0];
+
+        let window = |app_id: &str, title: &str, is_floating: bool| FocusContext {
+            app_id: Some(app_id.to_string()),
+            title: Some(title.to_string()),
+            is_floating: Some(is_floating),
+            ..Default::default()
+        };
+
+        // Match rules win over hints
+        assert_eq!(device.select_profile("default", &window("firefox", "YouTube - Mozilla Firefox", false)), Some("youtube"));
+        assert_eq!(device.select_profile("default", &window("firefox", "Mozilla Firefox", false)), Some("browser"));
+        assert_eq!(device.select_profile("default", &window("firefox-nightly", "YouTube", false)), None);
+
+        // Exclude rules negate matches
+        assert_eq!(device.select_profile("default", &window("kitty", "vim main.rs", false)), Some("vim"));
+        assert_eq!(device.select_profile("default", &window("kitty", "vim main.rs", true)), None);
+
+        // Workspace and output criteria, with priority between matching profiles
+        let mut focus = window("firefox", "YouTube", false);
+        focus.workspace_idx = Some(2);
+        focus.output = Some("HDMI-A-1".to_string());
+        assert_eq!(device.select_profile("default", &focus), Some("youtube"));
+        focus.title = Some("Inkscape".to_string());
+        assert_eq!(device.select_profile("default", &focus), Some("art"));
+    }
+
+    #[test]
+    fn test_exclude_rules_with_hints() {
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {}
+                profile "browser" {
+                    app-id-hint "firefox"
+                    exclude title="Private Browsing"
+                }
+                profile "gaming" {
+                    workspace-hint "games"
+                    exclude app-id="^discord$"
+                }
+            }
+        "#;
+        let config = parse_config_str(config).unwrap();
+        let device = &config.devices[0];
+
+        let window = |app_id: &str, title: &str, workspace: &str| FocusContext {
+            app_id: Some(app_id.to_string()),
+            title: Some(title.to_string()),
+            workspace_name: Some(workspace.to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(device.select_profile("default", &window("firefox", "Mozilla Firefox", "web")), Some("browser"));
+        assert_eq!(device.select_profile("default", &window("firefox", "Private Browsing", "web")), None);
+        assert_eq!(device.select_profile("browser", &window("firefox", "Private Browsing", "web")), Some("default"));
+
+        // An excluded profile falls through to the next hint
+        assert_eq!(device.select_profile("default", &window("firefox", "Private Browsing", "games")), Some("gaming"));
+        assert_eq!(device.select_profile("default", &window("discord", "Discord", "games")), None);
+    }
+
+    #[test]
+    fn test_window_match_rule_errors() {
+        let invalid = [
+            r#"device "K" { profile "p" { match app-id="(unclosed"; } }"#,
+            r#"device "K" { profile "p" { match "firefox"; } }"#,
+            r#"device "K" { profile "p" { match class="firefox"; } }"#,
+            r#"device "K" { profile "p" { match is-floating="yes"; } }"#,
+            r#"device "K" { profile "p" { exclude app-id="x" priority=3; } }"#,
+        ];
+        for config in invalid {
+            assert!(parse_config_str(config).is_err(), "should fail: {}", config);
+        }
+    }
+
+    #[test]
+    fn test_conditional_rules() {
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {
+                    remap {
+                        CapsLock "Escape"
+                        CapsLock "LeftCtrl" {
+                            when app-id="^Alacritty$"
+                            when app-id="^kitty$"
+                        }
+                    }
+                    combo {
+                        F1 "F2" {
+                            when workspace="terminals"
+                            when layout="russian"
+                        }
+                    }
+                    macro {
+                        F5 "Ctrl+r" policy="restart" {
+                            when title="YouTube"
+                        }
+                    }
+                }
+            }
+        "#;
+        let config = parse_config_str(config).unwrap();
+        let profile = &config.devices[0].profiles["default"];
+        assert_eq!(profile.remap.len(), 1);
+        assert_eq!(profile.remap[&key("CapsLock")], key("Escape"));
+        assert!(profile.combo.is_empty());
+        assert!(profile.macros.is_empty());
+        assert!(profile.macro_policies.is_empty());
+
+        let rules = &profile.conditional_rules;
+        assert_eq!(rules.len(), 3);
+        assert_eq!(
+            rules[0].entry,
+            ConditionalEntry::Remap {
+                from: key("CapsLock"),
+                to: key("LeftCtrl")
+            }
+        );
+        assert_eq!(rules[0].when.len(), 2);
+        assert_eq!(
+            rules[1].entry,
+            ConditionalEntry::Combo {
+                from: combo("F1"),
+                to: combo("F2")
+            }
+        );
+        assert_eq!(
+            rules[2].entry,
+            ConditionalEntry::Macro {
+                trigger: key("F5"),
+                actions: vec![MacroAction::Key(combo("Ctrl+r"))],
+                policy: MacroPolicy::Restart,
+            }
+        );
+
+        let focus = |app_id: &str| FocusContext {
+            app_id: Some(app_id.to_string()),
+            ..Default::default()
+        };
+        assert!(rules[0].applies(&focus("Alacritty")));
+        assert!(rules[0].applies(&focus("kitty")));
+        assert!(!rules[0].applies(&focus("firefox")));
+        assert!(!rules[0].applies(&FocusContext::default()));
+
+        let layout = |name: &str| FocusContext {
+            layout: Some(name.to_string()),
+            ..Default::default()
+        };
+        assert!(rules[1].applies(&layout("Russian (phonetic)")));
+        assert!(!rules[1].applies(&layout("English (US)")));
+    }
+
+    #[test]
+    fn test_conditional_rule_errors() {
+        let invalid = [
+            r#"device "K" { profile "default" { remap { CapsLock "Escape" { if app-id="x"; }; }; } }"#,
+            r#"device "K" { profile "default" { remap { CapsLock "Escape" { }; }; } }"#,
+            r#"device "K" { profile "default" { combo { F1 "F2" { when app-id="(x"; }; }; } }"#,
+            r#"device "K" { profile "default" { macro { F5 "A" { when priority=1; }; }; } }"#,
+            r#"device "K" { profile "default" { remap { A "B" { when layout=1; }; }; } }"#,
+        ];
+        for config in invalid {
+            assert!(parse_config_str(config).is_err(), "should fail: {}", config);
+        }
+
+        // Conditional entries alone still require a default profile
+        let config = r#"device "K" { profile "p" { remap { A "B" { when app-id="x"; }; }; } }"#;
+        assert!(parse_config_str(config).is_err());
+    }
+
+    #[test]
+    fn test_profile_extends_merges_layers() {
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {
+                    remap {
+                        CapsLock "Escape"
+                        A "B"
+                        C "D"
+                    }
+                    combo {
+                        F1 "F2"
+                    }
+                    macro {
+                        F5 "Ctrl+c" policy="restart"
+                        F6 "X"
+                    }
+                    niri-passthrough {
+                        Super+Return { spawn "alacritty"; }
+                    }
+                }
+                profile "browser" extends="default" {
+                    app-id-hint "firefox"
+                    remap {
+                        A null
+                        F6 "Y"
+                    }
+                    niri-passthrough {
+                        Super+Return null
+                    }
+                }
+                profile "firefox" extends="browser" {
+                    remap {
+                        C "E"
+                    }
+                    macro {
+                        CapsLock "Ctrl+z"
+                        F1 null
+                    }
+                }
+            }
+        "#;
+        let config = parse_config_str(config).unwrap();
+        let profiles = &config.devices[0].profiles;
+
+        let browser = &profiles["browser"];
+        assert_eq!(browser.extends.as_deref(), Some("default"));
+        assert_eq!(browser.app_id_hint.as_deref(), Some("firefox"));
+        assert_eq!(browser.remap.len(), 3);
+        assert_eq!(browser.remap[&key("CapsLock")], key("Escape"));
+        assert_eq!(browser.remap[&key("F6")], key("Y"));
+        assert!(!browser.remap.contains_key(&key("A")), "A was unset");
+        assert_eq!(browser.combo[&combo("F1")], combo("F2"));
+        // The remap on F6 replaces the inherited F6 macro
+        assert_eq!(browser.macros.len(), 1);
+        assert_eq!(browser.macro_policies[&key("F5")], MacroPolicy::Restart);
+        assert!(browser.niri_passthrough.is_empty());
+
+        let firefox = &profiles["firefox"];
+        assert_eq!(firefox.extends.as_deref(), Some("browser"));
+        assert_eq!(firefox.app_id_hint, None, "hints are not inherited");
+        assert_eq!(firefox.remap[&key("C")], key("E"));
+        // The CapsLock macro replaces the inherited CapsLock remap
+        assert!(!firefox.remap.contains_key(&key("CapsLock")));
+        assert_eq!(firefox.macros[&key("CapsLock")], vec![MacroAction::Key(combo("Ctrl+z"))]);
+        assert!(firefox.macros.contains_key(&key("F5")));
+
+        // The parent itself is unchanged
+        assert_eq!(profiles["default"].remap.len(), 3);
+        assert_eq!(profiles["default"].niri_passthrough.len(), 1);
+    }
+
+    #[test]
+    fn test_profile_extends_errors() {
+        let cycle = r#"
+            device "K" {
+                profile "default" extends="c" { remap { A "B"; }; }
+                profile "b" extends="default" { }
+                profile "c" extends="b" { }
+            }
+        "#;
+        match parse_config_str(cycle) {
+            Err(ConfigError::Invalid { message, .. }) => {
+                assert!(message.contains("cycle"), "unexpected message: {}", message);
+                assert!(message.contains("b extends default extends c extends b"), "unexpected message: {}", message);
+            }
+            other => panic!("Expected cycle error, got {:?}", other),
+        }
+
+        let invalid = [
+            r#"device "K" { profile "default" extends="default" { }; }"#,
+            r#"device "K" { profile "default" extends="missing" { }; }"#,
+            r#"device "K" { profile "default" extends=1 { }; }"#,
+            r#"device "K" { profile "default" inherits="x" { }; }"#,
+            r#"device "K" { profile "default" { remap { A null "B"; }; }; }"#,
+        ];
+        for config in invalid {
+            assert!(parse_config_str(config).is_err(), "should fail: {}", config);
+        }
+    }
+
+    #[test]
+    fn test_profile_templates_shared_across_devices() {
+        let config = r#"
+            profile-template "base" {
+                remap {
+                    CapsLock "Escape"
+                }
+            }
+            profile-template "coding" extends="base" {
+                app-id-hint "code"
+                match app-id="^jetbrains-"
+                remap {
+                    F1 "F2"
+                }
+            }
+            device "Laptop" {
+                profile "default" { }
+                profile template="coding"
+            }
+            device "Macro Pad" {
+                profile "default" { }
+                profile "code" template="coding" {
+                    app-id-hint "codium"
+                    match app-id="^zed$"
+                    remap {
+                        CapsLock null
+                        F3 "F4"
+                    }
+                }
+            }
+        "#;
+        let config = parse_config_str(config).unwrap();
+
+        let template = &config.profile_templates["coding"];
+        assert_eq!(template.remap.len(), 2, "templates can extend templates");
+
+        // An unnamed profile takes the template's name and its hints and rules
+        let laptop = &config.devices[0].profiles["coding"];
+        assert_eq!(laptop.template.as_deref(), Some("coding"));
+        assert_eq!(laptop.app_id_hint.as_deref(), Some("code"));
+        assert_eq!(laptop.match_rules.len(), 1);
+        assert_eq!(laptop.remap[&key("CapsLock")], key("Escape"));
+        assert_eq!(laptop.remap[&key("F1")], key("F2"));
+
+        // Device-specific overrides are layered over the template
+        let pad = &config.devices[1].profiles["code"];
+        assert_eq!(pad.app_id_hint.as_deref(), Some("codium"));
+        assert_eq!(pad.match_rules.len(), 2);
+        assert!(!pad.remap.contains_key(&key("CapsLock")));
+        assert_eq!(pad.remap[&key("F1")], key("F2"));
+        assert_eq!(pad.remap[&key("F3")], key("F4"));
+    }
+
+    #[test]
+    fn test_profile_template_errors() {
+        let invalid = [
+            r#"device "K" { profile "default" template="missing" { }; }"#,
+            r#"profile-template "a" { }; device "K" { profile "default" template="a" extends="b" { }; }"#,
+            r#"profile-template "a" { }; profile-template "a" { }"#,
+            r#"profile-template "a" { }; profile-template "b" template="a" { }"#,
+            r#"profile-template "a" extends="b" { }; profile-template "b" extends="a" { }"#,
+            r#"profile-template { }"#,
+        ];
+        for config in invalid {
+            assert!(parse_config_str(config).is_err(), "should fail: {}", config);
+        }
+
+        match parse_config_str(r#"device "K" { profile "default" template="missing" { }; }"#) {
+            Err(ConfigError::Invalid { message, .. }) => {
+                assert!(message.contains("unknown profile template 'missing'"), "unexpected message: {}", message);
+            }
+            other => panic!("Expected unknown template error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_global_rules_merged_under_every_profile() {
+        let config = r#"
+            global {
+                log-level "debug"
+                remap {
+                    CapsLock "Escape"
+                    A "B"
+                }
+                combo {
+                    F1 "F2"
+                }
+            }
+            global {
+                remap {
+                    A null
+                }
+            }
+            device "Keyboard" {
+                profile "default" {
+                    remap {
+                        CapsLock "LeftCtrl"
+                    }
+                }
+                profile "gaming" {
+                    workspace-hint "games"
+                    remap {
+                        CapsLock null
+                    }
+                }
+                profile "web" extends="default" {
+                    macro {
+                        F1 "X"
+                    }
+                }
+            }
+            device "Mouse" { }
+            device "Pad" {
+                profile "gaming" {
+                    workspace-hint "games"
+                }
+            }
+        "#;
+        let config = parse_config_str(config).unwrap();
+
+        // A later global block unsets an earlier global rule
+        let global = &config.global.rules;
+        assert_eq!(config.global.log_level, LogLevel::Debug);
+        assert_eq!(global.remap.len(), 1);
+        assert_eq!(global.combo[&combo("F1")], combo("F2"));
+
+        // Profile entries win over global ones for the same key
+        let keyboard = &config.devices[0].profiles;
+        assert_eq!(keyboard["default"].remap[&key("CapsLock")], key("LeftCtrl"));
+        assert_eq!(keyboard["default"].combo[&combo("F1")], combo("F2"));
+        assert!(keyboard["gaming"].remap.is_empty());
+        assert_eq!(keyboard["gaming"].combo[&combo("F1")], combo("F2"));
+        assert_eq!(keyboard["web"].remap[&key("CapsLock")], key("LeftCtrl"));
+        assert!(keyboard["web"].combo.is_empty(), "the F1 macro replaces the global combo");
+
+        // Devices without a default profile get one with the global rules
+        let mouse = &config.devices[1].profiles;
+        assert_eq!(mouse["default"].remap[&key("CapsLock")], key("Escape"));
+        let pad = &config.devices[2].profiles;
+        assert_eq!(pad.len(), 2);
+        assert_eq!(pad["gaming"].remap[&key("CapsLock")], key("Escape"));
+    }
+
+    #[test]
+    fn test_host_guards() {
+        let config = r#"
+            global {
+                log-level "debug"
+            }
+            global {
+                when hostname="desktop"
+                log-level "warn"
+            }
+            device "AT Translated Set 2 keyboard" {
+                when hostname="thinkpad"
+                when hostname="x1" env="NIRI_MAPPER_LAPTOP=1"
+                profile "default" { remap { CapsLock "Escape"; }; }
+            }
+            device "AT Translated Set 2 keyboard" {
+                when hostname="framework"
+                profile "default" { remap { CapsLock "LeftCtrl"; }; }
+            }
+            device "Keychron K3 Pro" {
+                when env="WAYLAND_DISPLAY"
+            }
+        "#;
+        let host = |hostname: &str, env: &[(&str, &str)]| HostContext {
+            hostname: Some(hostname.to_string()),
+            env: env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        };
+        let devices = |config: &Config| -> Vec<String> {
+            config
+                .devices
+                .iter()
+                .map(|d| format!("{}: {}", d.name.as_deref().unwrap(), d.profiles.len()))
+                .collect()
+        };
+
+        let thinkpad = parse_config_str_for_host(config, &host("ThinkPad", &[])).unwrap();
+        assert_eq!(thinkpad.global.log_level, LogLevel::Debug);
+        assert_eq!(devices(&thinkpad), ["AT Translated Set 2 keyboard: 1"]);
+        assert_eq!(thinkpad.devices[0].profiles["default"].remap[&key("CapsLock")], key("Escape"));
+
+        // All conditions of a `when` node must match
+        let x1 = parse_config_str_for_host(config, &host("x1", &[])).unwrap();
+        assert!(x1.devices.is_empty());
+        let x1 = host("x1", &[("NIRI_MAPPER_LAPTOP", "1"), ("WAYLAND_DISPLAY", "wayland-1")]);
+        let x1 = parse_config_str_for_host(config, &x1).unwrap();
+        assert_eq!(devices(&x1), ["AT Translated Set 2 keyboard: 1", "Keychron K3 Pro: 0"]);
+
+        let framework = parse_config_str_for_host(config, &host("framework", &[])).unwrap();
+        assert_eq!(framework.devices[0].profiles["default"].remap[&key("CapsLock")], key("LeftCtrl"));
+
+        let desktop = parse_config_str_for_host(config, &host("desktop", &[])).unwrap();
+        assert_eq!(desktop.global.log_level, LogLevel::Warn);
+        assert!(desktop.devices.is_empty());
+
+        let invalid = [
+            r#"device "K" { when; }"#,
+            r#"device "K" { when app-id="firefox"; }"#,
+            r#"global { when hostname=1; }"#,
+        ];
+        for config in invalid {
+            assert!(
+                parse_config_str_for_host(config, &host("desktop", &[])).is_err(),
+                "should fail: {}",
+                config
+            );
+        }
+    }
+
+    /// Create an empty directory for config file tests
+    fn test_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("niri-mapper-test-{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_include_and_drop_in_files() {
+        let dir = test_dir("include");
+        std::fs::create_dir_all(dir.join("shared")).unwrap();
+        std::fs::create_dir_all(dir.join("config.d")).unwrap();
+        std::fs::write(
+            dir.join("config.kdl"),
+            r#"
+            global { log-level "debug"; niri-ipc-retry-count 7; }
+            include "shared/base.kdl"
+            global { log-level "warn"; }
+            "#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("shared/base.kdl"),
+            r#"
+            global { log-level "error"; }
+            macros { greet "H" "I"; }
+            device "Base Keyboard" { profile "default" { macro { F1 macro="greet"; }; }; }
+            "#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("config.d/20-mouse.kdl"), r#"device "Mouse" { }"#).unwrap();
+        std::fs::write(dir.join("config.d/10-laptop.kdl"), r#"device "Laptop" { }"#).unwrap();
+        std::fs::write(dir.join("config.d/notes.txt"), "not a config").unwrap();
+
+        let config = parse_config(&dir.join("config.kdl")).unwrap();
+        let names: Vec<_> = config.devices.iter().map(|d| d.name.as_deref().unwrap()).collect();
+        assert_eq!(names, ["Base Keyboard", "Laptop", "Mouse"]);
+        // Later global blocks override the options they set
+        assert_eq!(config.global.log_level, LogLevel::Warn);
+        assert_eq!(config.global.niri_ipc_retry_count, 7);
+        // Devices can use macros from included files
+        assert_eq!(config.devices[0].profiles["default"].macros[&key("F1")].len(), 2);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let dir = test_dir("include-errors");
+
+        std::fs::write(dir.join("a.kdl"), r#"include "b.kdl""#).unwrap();
+        std::fs::write(dir.join("b.kdl"), r#"include "a.kdl""#).unwrap();
+        match parse_config(&dir.join("a.kdl")) {
+            Err(ConfigError::Invalid { message, .. }) => {
+                assert!(message.starts_with("Include cycle:"), "unexpected message: {}", message);
+                assert!(message.contains("b.kdl"), "unexpected message: {}", message);
+            }
+            other => panic!("Expected include cycle error, got {:?}", other),
+        }
+
+        std::fs::write(dir.join("main.kdl"), r#"device "K" { }; include "other.kdl""#).unwrap();
+        std::fs::write(dir.join("other.kdl"), r#"device "K" { }"#).unwrap();
+        match parse_config(&dir.join("main.kdl")) {
+            Err(ConfigError::Invalid { message, .. }) => {
+                assert!(message.contains("Device 'K' is defined more than once"), "unexpected message: {}", message);
+                assert!(message.contains("main.kdl") && message.contains("other.kdl"), "unexpected message: {}", message);
+            }
+            other => panic!("Expected duplicate device error, got {:?}", other),
+        }
+
+        std::fs::write(dir.join("missing.kdl"), r#"include "nowhere.kdl""#).unwrap();
+        assert!(parse_config(&dir.join("missing.kdl")).is_err());
+
+        // Errors in an included file name that file
+        std::fs::write(dir.join("typo.kdl"), r#"include "keys.kdl""#).unwrap();
+        std::fs::write(
+            dir.join("keys.kdl"),
+            r#"device "K" { profile "default" { remap { CapsLok "Escape"; }; }; }"#,
+        )
+        .unwrap();
+        match parse_config(&dir.join("typo.kdl")) {
+            Err(err @ ConfigError::InvalidKeys { .. }) => {
+                assert!(err.to_string().contains("keys.kdl"), "unexpected message: {}", err);
+                let ConfigError::InvalidKeys { src: Some(src), .. } = err else { unreachable!() };
+                assert!(src.name.unwrap().ends_with("keys.kdl"));
+                assert!(src.content.contains("CapsLok"));
+            }
+            other => panic!("Expected invalid key error, got {:?}", other),
+        }
+
+        std::fs::write(dir.join("broken.kdl"), r#"include "syntax.kdl""#).unwrap();
+        std::fs::write(dir.join("syntax.kdl"), r#"device "K" {"#).unwrap();
+        match parse_config(&dir.join("broken.kdl")) {
+            Err(err @ ConfigError::ParseError { .. }) => {
+                assert!(err.to_string().contains("syntax.kdl"), "unexpected message: {}", err);
+            }
+            other => panic!("Expected parse error, got {:?}", other),
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_json_and_toml_configs_match_kdl() {
+        let dir = test_dir("formats");
+        std::fs::write(
+            dir.join("config.kdl"),
+            r#"
+            global { log-level "debug"; remap { CapsLock "Escape"; }; }
+            macros { switch-tab n="1" "Alt+{n}"; }
+            device "Keyboard" {
+                profile "default" {
+                    combo { Ctrl+Shift+Q "Alt+F4"; }
+                    macro {
+                        F8 macro="switch-tab" n="2"
+                        F9 "Down" "delay(100)" policy="restart"
+                        A "B" { when app-id="^kitty$"; }
+                    }
+                    niri-passthrough { Super+Return { spawn "alacritty"; }; }
+                }
+                profile "youtube" extends="default" {
+                    match app-id="^firefox$" priority=10
+                    exclude is-floating=true
+                }
+                profile-switch { Ctrl+Shift+1 "default"; Ctrl+Shift+2 "youtube"; }
+            }
+            "#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("config.json"),
+            r#"{
+                "global": { "log-level": "debug", "rules": { "remap": { "CapsLock": "Escape" } } },
+                "macros": { "switch-tab": { "params": [{ "name": "n", "default": "1" }], "actions": ["Alt+{n}"] } },
+                "devices": [{
+                    "name": "Keyboard",
+                    "vendor-product": null,
+                    "profiles": {
+                        "default": {
+                            "combo": { "Ctrl+Shift+Q": "Alt+F4" },
+                            "macros": { "F8": { "macro": "switch-tab", "n": "2" }, "F9": ["Down", "delay(100)"] },
+                            "macro-policies": { "F9": "restart" },
+                            "conditional-rules": [{
+                                "when": [{ "app-id": "^kitty$" }],
+                                "entry": { "macro": { "trigger": "A", "actions": ["B"] } }
+                            }],
+                            "niri-passthrough": [{ "key": "Super+Return", "action": "spawn \"alacritty\";" }]
+                        },
+                        "youtube": {
+                            "extends": "default",
+                            "match-rules": [{ "app-id": "^firefox$", "priority": 10 }],
+                            "exclude-rules": [{ "is-floating": true, "priority": 0 }]
+                        }
+                    },
+                    "profile-switch": { "Ctrl+Shift+1": "default", "Ctrl+Shift+2": "youtube" }
+                }]
+            }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("config.toml"),
+            r#"
+            global = { log-level = "debug", rules = { remap = { CapsLock = "Escape" } } }
+            macros.switch-tab = { params = [{ name = "n", default = "1" }], actions = ["Alt+{n}"] }
+
+            [[devices]]
+            name = "Keyboard"
+            profile-switch = { "Ctrl+Shift+1" = "default", "Ctrl+Shift+2" = "youtube" }
+
+            [devices.profiles.default]
+            combo = { "Ctrl+Shift+Q" = "Alt+F4" }
+            macros = { F8 = { macro = "switch-tab", n = "2" }, F9 = ["Down", "delay(100)"] }
+            macro-policies = { F9 = "restart" }
+            conditional-rules = [
+                { when = [{ app-id = "^kitty$" }], entry = { macro = { trigger = "A", actions = ["B"] } } },
+            ]
+            niri-passthrough = [{ key = "Super+Return", action = 'spawn "alacritty";' }]
+
+            [devices.profiles.youtube]
+            extends = "default"
+            match-rules = [{ app-id = "^firefox$", priority = 10 }]
+            exclude-rules = [{ is-floating = true }]
+            "#,
+        )
+        .unwrap();
+
+        let dump = |file: &str| serde_json::to_string(&parse_config(&dir.join(file)).unwrap()).unwrap();
+        let kdl = dump("config.kdl");
+        assert!(kdl.contains(r#""niri-passthrough":[{"key":"Super+Return","action":"spawn \"alacritty\";"}]"#));
+        assert_eq!(dump("config.json"), kdl);
+        assert_eq!(dump("config.toml"), kdl);
+
+        // JSON and TOML files can be included and dropped into config.d
+        std::fs::create_dir_all(dir.join("config.d")).unwrap();
+        std::fs::write(dir.join("config.d/10-mouse.toml"), "[[devices]]\nname = \"Mouse\"\n").unwrap();
+        std::fs::write(dir.join("main.kdl"), r#"include "config.json""#).unwrap();
+        let config = parse_config(&dir.join("main.kdl")).unwrap();
+        let names: Vec<_> = config.devices.iter().map(|d| d.name.as_deref().unwrap()).collect();
+        assert_eq!(names, ["Keyboard", "Mouse"]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_json_dump_loads_back_unchanged() {
+        let dir = test_dir("dump-round-trip");
+        std::fs::write(
+            dir.join("config.kdl"),
+            r#"
+            global { remap { F13 "Mute"; }; }
+            macros { greet who="you" "text(hi {who})"; }
+            profile-template "coding" {
+                match app-id="^code$"
+                remap { CapsLock "Escape"; }
+            }
+            device "Keyboard" {
+                profile "default" {
+                    remap { CapsLock "LeftCtrl"; A "B"; }
+                    macro { F8 macro="greet" who="me"; }
+                }
+                profile "gaming" extends="default" {
+                    app-id-hint "steam"
+                    remap { CapsLock null; }
+                }
+                profile "coding" template="coding" {
+                    remap { Z "Y"; }
+                }
+                profile "quiet" {
+                    remap { F13 null; A "C"; }
+                }
+                profile-switch { Ctrl+Shift+1 "default"; }
+            }
+            "#,
+        )
+        .unwrap();
+
+        let dump = parse_config(&dir.join("config.kdl")).unwrap();
+        let json = serde_json::to_value(&dump).unwrap();
+        let gaming = &json["devices"][0]["profiles"]["gaming"];
+        assert_eq!(gaming["remap"], serde_json::json!({ "A": "B", "F13": "Mute" }));
+        assert!(gaming.get("extends").is_none());
+        assert!(json["devices"][0]["profiles"]["coding"].get("template").is_none());
+        assert_eq!(json["devices"][0]["profiles"]["quiet"]["remap"], serde_json::json!({ "A": "C" }));
+        assert!(json["global"].get("rules").is_none());
+
+        std::fs::write(dir.join("dump.json"), serde_json::to_string_pretty(&json).unwrap()).unwrap();
+        let reloaded = parse_config(&dir.join("dump.json")).unwrap();
+        assert_eq!(serde_json::to_value(&reloaded).unwrap(), json);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_json_and_toml_errors_point_into_the_file() {
+        let dir = test_dir("format-errors");
+        let json = r#"{
+  "global": { "log-level": "loud" },
+  "devices": [
+    { "name": "K", "profiles": { "default": { "remap": { "CapsLok": "Escape", "A": "B", "B": "C" } } } },
+    { "name": "L", "profile-switch": ["Ctrl+1"] }
+  ]
+}"#;
+        std::fs::write(dir.join("config.json"), json).unwrap();
+        let Err(ConfigError::Multiple { errors }) = parse_config(&dir.join("config.json")) else {
+            panic!("Expected several errors");
+        };
+        // The structure is checked while translating, the rest like KDL
+        let spans: Vec<(String, usize, usize)> = errors
+            .iter()
+            .map(|error| match error {
+                ConfigError::Invalid { span: Some(span), .. } => {
+                    assert_eq!(span.src.content, json);
+                    assert!(span.src.name.as_deref().unwrap().ends_with("config.json"));
+                    (span.label.clone(), span.location.line, span.location.column)
+                }
+                ConfigError::InvalidKeys { src: Some(src), invalid_keys } => {
+                    assert_eq!(src.content, json);
+                    let location = invalid_keys[0].location;
+                    (invalid_keys[0].key.clone(), location.line, location.column)
+                }
+                other => panic!("Unexpected error {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            spans,
+            [
+                ("expected an object".to_string(), 5, 38),
+                ("unknown log level".to_string(), 2, 28),
+                ("CapsLok".to_string(), 4, 58),
+            ]
+        );
+        let span = match &errors[2] {
+            ConfigError::InvalidKeys { invalid_keys, .. } => invalid_keys[0].location,
+            _ => unreachable!(),
+        };
+        assert_eq!(&json[span.offset..span.offset + span.len], "\"CapsLok\"");
+
+        // Lints too, including the other rule their message is about
+        let json = r#"{ "devices": [{ "name": "K", "profiles": { "default": { "remap": { "A": "B", "B": "C" } } } }] }"#;
+        std::fs::write(dir.join("chain.json"), json).unwrap();
+        let (_, warnings) = parse_config_with_warnings(&dir.join("chain.json"), &HostContext::default(), true).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].lint, Lint::RemapChain);
+        assert_eq!(&json[warnings[0].span.location.offset..][..3], "\"A\"");
+        let related = warnings[0].related.as_ref().unwrap();
+        assert_eq!(related.src.content, json);
+        assert_eq!((related.location.line, related.location.column), (1, 78));
+        assert!(warnings[0].to_string().contains("'B' -> 'C' at line 1, column 78"), "{}", warnings[0]);
+
+        std::fs::write(dir.join("syntax.toml"), "[global]\nlog-level = \n").unwrap();
+        match parse_config(&dir.join("syntax.toml")) {
+            Err(ConfigError::Invalid { message, span: Some(span) }) => {
+                assert!(message.starts_with("Failed to parse TOML configuration"), "{}", message);
+                assert_eq!((span.location.line, span.location.column), (2, 13));
+            }
+            other => panic!("Expected syntax error, got {:?}", other),
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_layout_macro_action_parsing() {
+        let config = r#"
+            macros {
+                next-layout {
+                    layout "next"
+                }
+                first-layout {
+                    layout 0
+                }
+            }
+            device "Test Keyboard" {
+                profile "default" {
+                    macro {
+                        F5 "layout(prev)" "Ctrl+v"
+                    }
+                }
+            }
+        "#;
+        let config = parse_config_str(config).unwrap();
+        assert_eq!(
+            config.macros["next-layout"].instantiate(&Default::default()),
+            Ok(vec![MacroAction::SwitchLayout(LayoutSwitch::Next)])
+        );
+        assert_eq!(
+            config.macros["first-layout"].instantiate(&Default::default()),
+            Ok(vec![MacroAction::SwitchLayout(LayoutSwitch::Index(0))])
+        );
+        let actions = &config.devices[0].profiles["default"].macros[&key("F5")];
+        assert_eq!(actions[0], MacroAction::SwitchLayout(LayoutSwitch::Prev));
+        assert_eq!(actions[0].to_string(), "layout(prev)");
+
+        let config = r#"
+            device "Test Keyboard" {
+                profile "default" {
+                    macro { F5 "layout(russian)"; }
+                }
+            }
+        "#;
+        assert!(parse_config_str(config).is_err());
+    }
+
+    #[test]
+    fn test_key_mode_parsing() {
+        let config = parse_config_str("global {}").unwrap();
+        assert_eq!(config.global.key_mode, KeyMode::Keycode);
+
+        let config = parse_config_str(r#"global { key-mode "keysym"; }"#).unwrap();
+        assert_eq!(config.global.key_mode, KeyMode::Keysym);
+
+        assert!(parse_config_str(r#"global { key-mode "scancode"; }"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_niri_xkb() {
+        let niri_config = r#"
+            input {
+                keyboard {
+                    xkb {
+                        layout "fr"
+                    }
+                    repeat-delay 600
+                }
+                touchpad { tap; }
+            }
+            binds {
+                Mod+T { spawn "alacritty"; }
+            }
+        "#;
+        let xkb = parse_niri_xkb(niri_config).unwrap().unwrap();
+        assert_eq!(xkb.layout, "fr");
+        assert_eq!(xkb.options, None);
+
+        assert_eq!(parse_niri_xkb("input { keyboard {}; }").unwrap(), None);
+        assert_eq!(parse_niri_xkb("").unwrap(), None);
+    }
+
+    #[test]
+    fn test_node_names() {
+        assert!(node_names(&[]).contains(&"include"));
+        assert!(node_names(&["device", "profile"]).contains(&"app-id-hint"));
+        assert_eq!(node_names(&["macros", "switch-tab"]), ["key", "delay", "text", "layout"]);
+        assert_eq!(node_names(&["global", "remap", "CapsLock"]), ["when"]);
+        assert!(node_names(&["device", "profile", "remap"]).is_empty());
+    }
 }
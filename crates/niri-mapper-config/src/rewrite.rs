@@ -0,0 +1,742 @@
+//! Canonicalize and migrate niri-mapper configuration files
+//!
+//! [`format_config`] normalizes how keys are written and sorts rule blocks,
+//! while [`migrate_config`] rewrites options of older configuration versions.
+//! Like [`crate::write_macro`], both edit the parsed KDL document, so comments
+//! and the formatting of untouched nodes are preserved.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use kdl::{KdlDocument, KdlEntry, KdlNode, KdlValue};
+
+use crate::error::{ConfigError, SourceFile};
+use crate::formats::Format;
+use crate::model::{KeyCode, KeyCombo, MacroAction};
+use crate::parser::{config_version, CONFIG_VERSION};
+use crate::writer::replace_file;
+
+/// Format a configuration file in place with [`format_config`]
+///
+/// Returns whether the file changed. With `check`, the file is left untouched
+/// and the result tells whether formatting would change it.
+pub fn format_file(path: &Path, check: bool) -> Result<bool, ConfigError> {
+    let content = read_kdl(path, "formatted")?;
+    let formatted = format_config(&content).map_err(|e| e.in_file(Some(&path.display().to_string())))?;
+    update_file(path, &content, &formatted, check)
+}
+
+/// Migrate a configuration file in place with [`migrate_config`]
+///
+/// With `check`, the file is left untouched; the file would change if the
+/// returned migration has changes.
+pub fn migrate_file(path: &Path, check: bool) -> Result<Migration, ConfigError> {
+    let content = read_kdl(path, "migrated")?;
+    let migration = migrate_config(&content).map_err(|e| e.in_file(Some(&path.display().to_string())))?;
+    update_file(path, &content, &migration.content, check)?;
+    Ok(migration)
+}
+
+/// Read a configuration file, which must be KDL to be rewritten
+fn read_kdl(path: &Path, action: &str) -> Result<String, ConfigError> {
+    if Format::of(Some(path)) != Format::Kdl {
+        return Err(ConfigError::Invalid {
+            message: format!(
+                "Cannot rewrite '{}': only KDL configuration files can be {}",
+                path.display(),
+                action
+            ),
+            span: None,
+        });
+    }
+    Ok(std::fs::read_to_string(path)?)
+}
+
+/// Replace a file's content unless checking, returning whether it changed
+fn update_file(path: &Path, content: &str, updated: &str, check: bool) -> Result<bool, ConfigError> {
+    if content == updated {
+        return Ok(false);
+    }
+    if !check {
+        replace_file(path, updated)?;
+        tracing::info!("Rewrote {}", path.display());
+    }
+    Ok(true)
+}
+
+/// Parse configuration source as a KDL document
+fn parse_document(content: &str) -> Result<KdlDocument, ConfigError> {
+    content.parse().map_err(|e: kdl::KdlError| ConfigError::ParseError {
+        src: Box::new(SourceFile::new(content)),
+        span: miette::SourceSpan::from((e.span.offset(), e.span.len())),
+        source: e,
+    })
+}
+
+/// Canonicalize configuration source, returning the formatted source.
+///
+/// Keys and combos are written the way they are displayed: `Esc` becomes
+/// `Escape` and `shift+ctrl+a` becomes `Ctrl+Shift+A`. This applies to the
+/// entries of `remap`, `combo`, `macro` and `profile-switch` blocks, to the
+/// actions of library macros without `{param}` placeholders and to
+/// `macro-abort-key` and `macro-record-key`. Keys that do not parse are left
+/// as written.
+///
+/// The entries of those blocks are then sorted by key, taking the comments
+/// above an entry along with it. Blocks that are already sorted keep their
+/// layout; the entries of a reordered block are re-indented one per line.
+pub fn format_config(content: &str) -> Result<String, ConfigError> {
+    let mut doc = parse_document(content)?;
+
+    for node in doc.nodes_mut() {
+        match node.name().value() {
+            "global" => {
+                for child in children_mut(node) {
+                    match child.name().value() {
+                        "macro-abort-key" | "macro-record-key" => {
+                            if let Some(entry) = child.entries_mut().first_mut() {
+                                canonicalize_entry::<KeyCode>(entry);
+                            }
+                        }
+                        _ => format_rule_block(child),
+                    }
+                }
+            }
+            "device" => {
+                for child in children_mut(node) {
+                    match child.name().value() {
+                        "profile" => format_profile(child),
+                        "profile-switch" => {
+                            for entry in children_mut(child) {
+                                canonicalize_name::<KeyCombo>(entry);
+                            }
+                            sort_entries(child);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            "profile-template" => format_profile(node),
+            "macros" => {
+                for library_macro in children_mut(node) {
+                    format_library_macro(library_macro);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(doc.to_string())
+}
+
+/// The child nodes of a node, if it has any
+fn children_mut(node: &mut KdlNode) -> impl Iterator<Item = &mut KdlNode> {
+    node.children_mut().iter_mut().flat_map(|children| children.nodes_mut())
+}
+
+/// Format the rule blocks of a profile or profile template
+fn format_profile(profile: &mut KdlNode) {
+    for block in children_mut(profile) {
+        format_rule_block(block);
+    }
+}
+
+/// Format a `remap`, `combo` or `macro` block; other nodes are left alone
+fn format_rule_block(block: &mut KdlNode) {
+    let name = block.name().value().to_string();
+    if !matches!(name.as_str(), "remap" | "combo" | "macro") {
+        return;
+    }
+    for entry in children_mut(block) {
+        match name.as_str() {
+            "remap" => {
+                canonicalize_name::<KeyCode>(entry);
+                if let Some(to) = entry.entries_mut().first_mut() {
+                    canonicalize_entry::<KeyCode>(to);
+                }
+            }
+            "combo" => {
+                canonicalize_name::<KeyCombo>(entry);
+                if let Some(to) = entry.entries_mut().first_mut() {
+                    canonicalize_entry::<KeyCombo>(to);
+                }
+            }
+            _ => {
+                canonicalize_name::<KeyCode>(entry);
+                for action in entry.entries_mut() {
+                    canonicalize_entry::<MacroAction>(action);
+                }
+            }
+        }
+    }
+    sort_entries(block);
+}
+
+/// Format the actions of a library macro, which are arguments or `key`
+/// child nodes
+fn format_library_macro(library_macro: &mut KdlNode) {
+    let has_placeholder = |entry: &KdlEntry| entry.value().as_string().is_some_and(|s| s.contains('{'));
+    for action in library_macro.entries_mut() {
+        if !has_placeholder(action) {
+            canonicalize_entry::<MacroAction>(action);
+        }
+    }
+    for action in children_mut(library_macro) {
+        if action.name().value() != "key" {
+            continue;
+        }
+        if let Some(key) = action.entries_mut().first_mut().filter(|key| !has_placeholder(key)) {
+            canonicalize_entry::<KeyCombo>(key);
+        }
+    }
+}
+
+/// How a key, combo or macro action is displayed, if it parses
+fn canonical<K: std::str::FromStr + std::fmt::Display>(written: &str) -> Option<String> {
+    written.parse::<K>().ok().map(|parsed| parsed.to_string())
+}
+
+/// Write a node's name the way it is displayed
+fn canonicalize_name<K: std::str::FromStr + std::fmt::Display>(node: &mut KdlNode) {
+    if let Some(name) = canonical::<K>(node.name().value()) {
+        if name != node.name().value() {
+            node.set_name(name);
+        }
+    }
+}
+
+/// Write a string argument the way it is displayed; properties and other
+/// values are left alone
+fn canonicalize_entry<K: std::str::FromStr + std::fmt::Display>(entry: &mut KdlEntry) {
+    if entry.name().is_some() {
+        return;
+    }
+    let Some(value) = entry.value().as_string().and_then(canonical::<K>) else {
+        return;
+    };
+    if entry.value().as_string() != Some(value.as_str()) {
+        // Drop the written representation, which would still be printed
+        entry.set_value_repr(KdlValue::String(value.clone()).to_string());
+        entry.set_value(value);
+    }
+}
+
+/// Sort the entries of a block by name, keeping the comments above an entry
+/// with it
+///
+/// Entries with the same name, such as conditional entries, keep their order.
+fn sort_entries(block: &mut KdlNode) {
+    let Some(children) = block.children_mut() else {
+        return;
+    };
+    let nodes = children.nodes_mut();
+    if nodes.windows(2).all(|pair| pair[0].name().value() <= pair[1].name().value()) {
+        return;
+    }
+
+    let trivia = |node: &KdlNode| {
+        format!("{}{}", node.leading().unwrap_or_default(), node.trailing().unwrap_or_default())
+    };
+    if !nodes.iter().any(|node| trivia(node).contains('\n')) {
+        // A block on one line stays on one line
+        nodes.sort_by(|a, b| a.name().value().cmp(b.name().value()));
+        for node in nodes.iter_mut() {
+            node.set_leading(" ");
+            node.set_trailing(";");
+        }
+        return;
+    }
+
+    // The indentation of the entries, from the first that starts a line
+    let indent = nodes
+        .iter()
+        .filter_map(|node| node.leading()?.rsplit('\n').next())
+        .find(|indent| !indent.is_empty() && indent.trim().is_empty())
+        .unwrap_or_default()
+        .to_string();
+    // Whatever follows the `{` on its line stays there
+    let head = match nodes[0].leading().unwrap_or_default().split_once('\n') {
+        Some((head, rest)) => {
+            let head = format!("{}\n", head);
+            let rest = rest.to_string();
+            nodes[0].set_leading(rest);
+            head
+        }
+        None => "\n".to_string(),
+    };
+
+    nodes.sort_by(|a, b| a.name().value().cmp(b.name().value()));
+    for (index, node) in nodes.iter_mut().enumerate() {
+        let comments: String = node
+            .leading()
+            .unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| format!("{}{}\n", indent, line))
+            .collect();
+        let head = if index == 0 { head.as_str() } else { "" };
+        node.set_leading(format!("{}{}{}", head, comments, indent));
+        let trailing = node.trailing().unwrap_or_default().trim_end();
+        let trailing = trailing.strip_suffix(';').unwrap_or(trailing).trim_end();
+        node.set_trailing(format!("{}\n", trailing));
+    }
+}
+
+/// The result of [`migrate_config`]
+#[derive(Debug, Clone, Default)]
+pub struct Migration {
+    /// The migrated configuration source
+    pub content: String,
+    /// What was changed, one line per change
+    pub changes: Vec<String>,
+    /// Options that were kept as written, because migrating them would change
+    /// what the configuration does
+    pub kept: Vec<String>,
+}
+
+/// Rewrite configuration source written for an older configuration version
+/// to the current one, [`CONFIG_VERSION`].
+///
+/// A file without a `version` node is version 1. Migrations are:
+///
+/// 2. `app-id-hint`, `workspace-hint` and `output-hint` become `match` rules.
+///    Hints are checked after every `match` rule, in that order, so the new
+///    rules get priorities below the lowest `priority` in the file. Profiles
+///    with `exclude` rules keep their hints, since the exclusions would also
+///    apply to the new rules. A profile's own hint replaces its template's,
+///    while `match` rules add to the template's, so a hint that a profile
+///    using a template overrides is kept on both. Templates defined in another
+///    file are assumed to have every hint. `layout-hint` is left as it is.
+///
+/// Finally, `version` is set to the current version. Files that are already
+/// current are returned unchanged.
+pub fn migrate_config(content: &str) -> Result<Migration, ConfigError> {
+    let mut doc = parse_document(content)?;
+    let version = match doc.nodes().iter().find(|node| node.name().value() == "version") {
+        Some(node) => config_version(node, content)?,
+        None => 1,
+    };
+
+    let mut migration = Migration::default();
+    if version < 2 {
+        hints_to_match_rules(&mut doc, &mut migration);
+    }
+    if version < CONFIG_VERSION {
+        set_version(&mut doc);
+        migration.changes.push(format!("version {} -> {}", version, CONFIG_VERSION));
+    }
+
+    migration.content = doc.to_string();
+    Ok(migration)
+}
+
+/// Hints with a `match` property that selects the same, in the order they
+/// are checked
+const MIGRATED_HINTS: [(&str, &str); 3] = [
+    ("app-id-hint", "app-id"),
+    ("workspace-hint", "workspace"),
+    ("output-hint", "output"),
+];
+
+/// Replace the `app-id-hint`, `workspace-hint` and `output-hint` of profiles
+/// and profile templates with `match` rules
+fn hints_to_match_rules(doc: &mut KdlDocument, migration: &mut Migration) {
+    let lowest = lowest_priority(doc.nodes()).min(0);
+    let overridden = overridden_template_hints(doc.nodes());
+
+    for node in doc.nodes_mut() {
+        match node.name().value() {
+            "device" => {
+                let device = string_arg(node).unwrap_or_default();
+                for profile in children_mut(node).filter(|child| child.name().value() == "profile") {
+                    let name = profile_name(profile);
+                    let what = format!("Profile '{}' of device '{}'", name, device);
+                    let template = template_name(profile);
+                    let keep = |hint: &str| {
+                        let template = template.as_deref()?;
+                        overridden
+                            .contains(&(template.to_string(), hint.to_string()))
+                            .then(|| format!("it overrides the {} of template '{}'", hint, template))
+                    };
+                    migrate_hints(profile, &what, lowest, keep, migration);
+                }
+            }
+            "profile-template" => {
+                let name = profile_name(node);
+                let what = format!("Profile template '{}'", name);
+                let keep = |hint: &str| {
+                    overridden
+                        .contains(&(name.clone(), hint.to_string()))
+                        .then(|| "profiles using the template override it".to_string())
+                };
+                migrate_hints(node, &what, lowest, keep, migration);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The hints that device profiles set over their template's, as
+/// `(template, hint)` pairs
+///
+/// A template not defined in `nodes` may have any hint, so every hint of a
+/// profile using it counts as overridden.
+fn overridden_template_hints(nodes: &[KdlNode]) -> HashSet<(String, String)> {
+    let template_hints: HashMap<String, Vec<String>> = nodes
+        .iter()
+        .filter(|node| node.name().value() == "profile-template")
+        .map(|template| (profile_name(template), migrated_hints(template)))
+        .collect();
+
+    nodes
+        .iter()
+        .filter(|node| node.name().value() == "device")
+        .flat_map(|device| device.children().into_iter().flat_map(KdlDocument::nodes))
+        .filter(|child| child.name().value() == "profile")
+        .filter_map(|profile| Some((template_name(profile)?, migrated_hints(profile))))
+        .flat_map(|(template, hints)| hints.into_iter().map(move |hint| (template.clone(), hint)))
+        .filter(|(template, hint)| template_hints.get(template).is_none_or(|hints| hints.contains(hint)))
+        .collect()
+}
+
+/// The hints of a profile or template that migration replaces
+fn migrated_hints(profile: &KdlNode) -> Vec<String> {
+    profile
+        .children()
+        .into_iter()
+        .flat_map(KdlDocument::nodes)
+        .map(|node| node.name().value())
+        .filter(|name| MIGRATED_HINTS.iter().any(|(hint, _)| hint == name))
+        .map(str::to_string)
+        .collect()
+}
+
+/// The lowest `priority` of the `match` rules in `nodes` and their children
+fn lowest_priority(nodes: &[KdlNode]) -> i64 {
+    nodes
+        .iter()
+        .map(|node| {
+            let own = match node.name().value() {
+                "match" => node.get("priority").and_then(|entry| entry.value().as_i64()).unwrap_or(0),
+                _ => 0,
+            };
+            let children = node.children().map_or(0, |children| lowest_priority(children.nodes()));
+            own.min(children)
+        })
+        .min()
+        .unwrap_or(0)
+}
+
+/// Replace the hints of one profile with `match` rules
+///
+/// `keep` returns why a hint must stay as written, if it must.
+fn migrate_hints(
+    profile: &mut KdlNode,
+    what: &str,
+    lowest: i64,
+    keep: impl Fn(&str) -> Option<String>,
+    migration: &mut Migration,
+) {
+    let Some(children) = profile.children_mut() else {
+        return;
+    };
+    let has_exclude = children.nodes().iter().any(|node| node.name().value() == "exclude");
+
+    for node in children.nodes_mut() {
+        let hint = node.name().value().to_string();
+        let Some(rank) = MIGRATED_HINTS.iter().position(|(name, _)| *name == hint) else {
+            continue;
+        };
+        let Some(value) = string_arg(node) else {
+            continue;
+        };
+        let reason = if has_exclude {
+            Some("its exclude rules would also apply to a match rule".to_string())
+        } else {
+            keep(&hint)
+        };
+        if let Some(reason) = reason {
+            migration.kept.push(format!("{}: kept {} \"{}\", as {}", what, hint, value, reason));
+            continue;
+        }
+
+        let property = MIGRATED_HINTS[rank].1;
+        let pattern = match property {
+            // Hints compare the app ID exactly, match rules search a regex
+            "app-id" => format!("^{}$", regex::escape(&value)),
+            _ => value.clone(),
+        };
+        let mut rule = KdlNode::new("match");
+        rule.push(KdlEntry::new_prop(property, pattern));
+        rule.push(KdlEntry::new_prop("priority", lowest - 1 - rank as i64));
+        migration.changes.push(format!("{}: {} \"{}\" -> {}", what, hint, value, rule.to_string().trim()));
+
+        if let Some(leading) = node.leading() {
+            rule.set_leading(leading);
+        }
+        if let Some(trailing) = node.trailing() {
+            rule.set_trailing(trailing);
+        }
+        *node = rule;
+    }
+}
+
+/// The first argument of a node, if it is a string
+fn string_arg(node: &KdlNode) -> Option<String> {
+    let entry = node.entries().iter().find(|entry| entry.name().is_none())?;
+    entry.value().as_string().map(str::to_string)
+}
+
+/// The name of a profile, which defaults to its template's
+fn profile_name(profile: &KdlNode) -> String {
+    string_arg(profile).or_else(|| template_name(profile)).unwrap_or_default()
+}
+
+/// The template a device profile instantiates (`template="..."`)
+fn template_name(profile: &KdlNode) -> Option<String> {
+    profile.get("template")?.value().as_string().map(str::to_string)
+}
+
+/// Set the file's `version` node to the current version, adding it at the
+/// top of the file after any header comment
+fn set_version(doc: &mut KdlDocument) {
+    let nodes = doc.nodes_mut();
+    if let Some(node) = nodes.iter_mut().find(|node| node.name().value() == "version") {
+        node.clear_entries();
+        node.push(KdlEntry::new(CONFIG_VERSION as i64));
+        return;
+    }
+
+    let mut node = KdlNode::new("version");
+    node.push(KdlEntry::new(CONFIG_VERSION as i64));
+    node.set_trailing("\n");
+    if let Some(first) = nodes.first_mut() {
+        // Comments up to the first blank line describe the file, the rest
+        // the first node
+        let leading = first.leading().unwrap_or_default().to_string();
+        let (header, rest) = match leading.find("\n\n") {
+            Some(end) => leading.split_at(end + 2),
+            None => ("", leading.as_str()),
+        };
+        node.set_leading(header);
+        first.set_leading(format!("\n{}", rest));
+    }
+    nodes.insert(0, node);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::FocusContext;
+    use crate::parser::parse_config_str;
+
+    #[test]
+    fn test_format_canonicalizes_keys_and_sorts_blocks() {
+        let content = r#"// My keyboard
+device "K" {
+    profile "default" {
+        remap {
+            // swap
+            esc "caps" // trailing
+            CapsLock "Esc"
+            A "B" { when app-id="^kitty$"; }
+        }
+        combo { shift+ctrl+a "alt+F4"; Super "LEFTMETA"; }
+        macro {
+            F9 "ctrl+c" "delay(50)" "text(hi)" policy="restart"
+        }
+    }
+    profile-switch {
+        super+2 "default"
+        ctrl+1 "default"
+    }
+}
+global {
+    macro-abort-key "pause"
+}
+macros {
+    switch-tab "alt+{n}" "ctrl+tab" n="1" {
+        key "shift+ctrl+a"
+    }
+}
+"#;
+        let formatted = format_config(content).unwrap();
+        assert_eq!(
+            formatted,
+            r#"// My keyboard
+device "K" {
+    profile "default" {
+        remap {
+            A "B" { when app-id="^kitty$"; }
+            CapsLock "Escape"
+            // swap
+            Escape "CapsLock" // trailing
+        }
+        combo { Ctrl+Shift+A "Alt+F4"; LeftMeta "LeftMeta"; }
+        macro {
+            F9 "Ctrl+C" "delay(50)" "text(hi)" policy="restart"
+        }
+    }
+    profile-switch {
+        Ctrl+1 "default"
+        Super+2 "default"
+    }
+}
+global {
+    macro-abort-key "Pause"
+}
+macros {
+    switch-tab "alt+{n}" "Ctrl+Tab" n="1" {
+        key "Ctrl+Shift+A"
+    }
+}
+"#
+        );
+
+        // Formatting is stable and does not change the configuration, except
+        // for library macro actions, which are stored as written
+        assert_eq!(format_config(&formatted).unwrap(), formatted);
+        let dump = |content: &str| {
+            let mut dump = serde_json::to_value(parse_config_str(content).unwrap()).unwrap();
+            dump["macros"].take();
+            dump
+        };
+        assert_eq!(dump(content), dump(&formatted));
+    }
+
+    #[test]
+    fn test_migrate_hints_to_match_rules() {
+        let content = r#"// Shared config
+
+device "K" {
+    profile "default" { }
+    profile "browser" {
+        app-id-hint "org.mozilla.firefox" // exact
+        workspace-hint "web"
+    }
+    profile "art" {
+        output-hint "HDMI-A-1"
+        layout-hint "de"
+    }
+    profile "video" {
+        match title="YouTube" priority=-2
+        app-id-hint "mpv"
+        exclude is-floating=true
+    }
+}
+profile-template "coding" {
+    app-id-hint "code"
+}
+"#;
+        let migration = migrate_config(content).unwrap();
+        assert_eq!(
+            migration.content,
+            r#"// Shared config
+
+version 2
+
+device "K" {
+    profile "default" { }
+    profile "browser" {
+        match app-id="^org\\.mozilla\\.firefox$" priority=-3 // exact
+        match workspace="web" priority=-4
+    }
+    profile "art" {
+        match output="HDMI-A-1" priority=-5
+        layout-hint "de"
+    }
+    profile "video" {
+        match title="YouTube" priority=-2
+        app-id-hint "mpv"
+        exclude is-floating=true
+    }
+}
+profile-template "coding" {
+    match app-id="^code$" priority=-3
+}
+"#
+        );
+        assert_eq!(migration.changes.len(), 5);
+        assert_eq!(migration.kept.len(), 1);
+        assert!(migration.kept[0].contains("Profile 'video' of device 'K'"), "{:?}", migration.kept);
+        parse_config_str(&migration.content).unwrap();
+
+        // A migrated file is current
+        let again = migrate_config(&migration.content).unwrap();
+        assert_eq!(again.content, migration.content);
+        assert!(again.changes.is_empty());
+    }
+
+    #[test]
+    fn test_migrate_keeps_hints_overridden_by_template_users() {
+        let content = r#"version 1
+profile-template "terminal" {
+    app-id-hint "Alacritty"
+    workspace-hint "shell"
+}
+device "K" {
+    profile "default" { }
+    profile "term" template="terminal" {
+        app-id-hint "kitty"
+    }
+    profile "term2" template="terminal"
+    profile "remote" template="elsewhere" {
+        app-id-hint "remmina"
+        output-hint "DP-1"
+    }
+}
+"#;
+        let migration = migrate_config(content).unwrap();
+        assert_eq!(
+            migration.content,
+            r#"version 2
+profile-template "terminal" {
+    app-id-hint "Alacritty"
+    match workspace="shell" priority=-2
+}
+device "K" {
+    profile "default" { }
+    profile "term" template="terminal" {
+        app-id-hint "kitty"
+    }
+    profile "term2" template="terminal"
+    profile "remote" template="elsewhere" {
+        app-id-hint "remmina"
+        output-hint "DP-1"
+    }
+}
+"#
+        );
+        assert_eq!(migration.kept.len(), 4, "{:?}", migration.kept);
+        assert!(migration.kept[0].contains("Profile template 'terminal'"), "{:?}", migration.kept);
+        assert!(migration.kept[1].contains("template 'terminal'"), "{:?}", migration.kept);
+        assert!(migration.kept[2].contains("template 'elsewhere'"), "{:?}", migration.kept);
+
+        // The profiles match the same windows before and after
+        let before = parse_config_str(&content.replace("template=\"elsewhere\"", "")).unwrap();
+        let after = parse_config_str(&migration.content.replace("template=\"elsewhere\"", "")).unwrap();
+        let window = |app_id: &str| FocusContext {
+            app_id: Some(app_id.to_string()),
+            ..Default::default()
+        };
+        for app_id in ["Alacritty", "kitty", "foot"] {
+            for config in [&before, &after] {
+                let device = &config.devices[0];
+                assert_eq!(
+                    device.profiles["term"].matches_app_id(app_id),
+                    app_id == "kitty",
+                    "{}",
+                    app_id
+                );
+                assert_eq!(device.profiles["term"].match_priority(&window(app_id)), None, "{}", app_id);
+            }
+        }
+    }
+
+    #[test]
+    fn test_migrate_rejects_newer_versions() {
+        let err = migrate_config("version 99\n").unwrap_err();
+        assert!(err.to_string().contains("version 99"), "{}", err);
+    }
+}
@@ -0,0 +1,512 @@
+//! Write changes back into a niri-mapper configuration file
+//!
+//! Edits are made on the parsed KDL document, so comments and formatting of
+//! the rest of the file are preserved. The result is validated by parsing it
+//! as a configuration before the original file is replaced.
+//!
+//! Parsed profiles can also be formatted back to KDL with [`format_profile`].
+
+use std::path::Path;
+
+use kdl::{KdlDocument, KdlEntry, KdlNode};
+
+use crate::error::ConfigError;
+use crate::formats::Format;
+use crate::model::{ConditionalEntry, HostContext, MacroAction, MacroPolicy, Profile, WindowMatch};
+use crate::parser::{device_file, parse_config_replacing};
+
+/// Add a macro to a device profile's `macro` block.
+///
+/// The `profile` and `macro` nodes are created if they do not exist yet. An
+/// existing macro with the same trigger is replaced. The file that defines
+/// the device is edited, which may be included by the configuration file or
+/// be in its `config.d` directory.
+///
+/// The file is written atomically: if the edited configuration does not
+/// parse, or writing fails, the original file is left untouched. Only KDL
+/// files can be edited; JSON and TOML files are usually generated.
+///
+/// # Arguments
+/// * `path` - Path to the configuration file
+/// * `device` - Name of the device block (as configured)
+/// * `profile` - Name of the profile to add the macro to
+/// * `trigger` - Trigger key of the macro (e.g., "F5")
+/// * `actions` - The macro's action sequence
+pub fn write_macro(
+    path: &Path,
+    device: &str,
+    profile: &str,
+    trigger: &str,
+    actions: &[MacroAction],
+) -> Result<(), ConfigError> {
+    let host = HostContext::current();
+    let config_path = path;
+    let path = &device_file(config_path, device, &host)?.unwrap_or_else(|| config_path.to_path_buf());
+    if Format::of(Some(path)) != Format::Kdl {
+        return Err(ConfigError::Invalid {
+            message: format!(
+                "Cannot write macro '{}' to '{}': only KDL configuration files can be edited",
+                trigger,
+                path.display()
+            ),
+            span: None,
+        });
+    }
+    let content = std::fs::read_to_string(path)?;
+    let updated = insert_macro(&content, device, profile, trigger, actions)?;
+
+    // Validate the edited configuration before replacing the original
+    parse_config_replacing(config_path, path, &updated, &host)?;
+
+    replace_file(path, &updated)?;
+
+    tracing::info!(
+        "Wrote macro '{}' to profile '{}' of device '{}' in {}",
+        trigger,
+        profile,
+        device,
+        path.display()
+    );
+
+    Ok(())
+}
+
+/// Replace a file's content through a temporary file, so that it is left
+/// untouched if writing fails
+pub(crate) fn replace_file(path: &Path, content: &str) -> Result<(), ConfigError> {
+    let temp_path = path.with_extension("kdl.tmp");
+    if let Err(e) = std::fs::write(&temp_path, content) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e.into());
+    }
+    if let Err(e) = std::fs::rename(&temp_path, path) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Insert a macro into configuration source, returning the edited source.
+fn insert_macro(
+    content: &str,
+    device: &str,
+    profile: &str,
+    trigger: &str,
+    actions: &[MacroAction],
+) -> Result<String, ConfigError> {
+    let mut doc: KdlDocument = content.parse().map_err(|e: kdl::KdlError| ConfigError::Invalid {
+        message: format!("Failed to parse configuration: {}", e),
+        span: None,
+    })?;
+
+    let device_node = find_named_node(doc.nodes_mut(), "device", device).ok_or_else(|| {
+        ConfigError::Invalid {
+            message: format!("Device '{}' not found in configuration", device),
+            span: None,
+        }
+    })?;
+
+    let profiles = device_node.ensure_children();
+    let profile_node = match find_named_node(profiles.nodes_mut(), "profile", profile) {
+        Some(node) => node,
+        None => {
+            let mut node = KdlNode::new("profile");
+            node.push(KdlEntry::new(profile));
+            profiles.nodes_mut().push(node);
+            profiles.nodes_mut().last_mut().unwrap()
+        }
+    };
+
+    let blocks = profile_node.ensure_children();
+    let macro_block = match blocks.nodes().iter().position(|n| n.name().value() == "macro") {
+        Some(index) => &mut blocks.nodes_mut()[index],
+        None => {
+            blocks.nodes_mut().push(KdlNode::new("macro"));
+            blocks.nodes_mut().last_mut().unwrap()
+        }
+    };
+
+    let mut macro_node = KdlNode::new(trigger);
+    for action in actions {
+        macro_node.push(KdlEntry::new(action.to_string()));
+    }
+
+    // Conditional entries (with a `when` block) are separate macros
+    let macros = macro_block.ensure_children();
+    match macros
+        .nodes()
+        .iter()
+        .position(|n| n.name().value() == trigger && n.children().is_none())
+    {
+        Some(index) => {
+            // Keep the surrounding whitespace and comments of the replaced macro
+            let old = &macros.nodes()[index];
+            if let Some(leading) = old.leading() {
+                macro_node.set_leading(leading);
+            }
+            if let Some(trailing) = old.trailing() {
+                macro_node.set_trailing(trailing);
+            }
+            macros.nodes_mut()[index] = macro_node;
+        }
+        None => macros.nodes_mut().push(macro_node),
+    }
+
+    Ok(doc.to_string())
+}
+
+/// Format a profile as a KDL `profile` node.
+///
+/// Used to show the effective profile once `extends` and `template` are
+/// resolved, so the output contains inherited rules too. Entries are sorted
+/// by key to keep the output stable.
+pub fn format_profile(name: &str, profile: &Profile) -> String {
+    let mut node = KdlNode::new("profile");
+    node.push(KdlEntry::new(name));
+    if let Some(parent) = &profile.extends {
+        node.push(KdlEntry::new_prop("extends", parent.as_str()));
+    }
+    if let Some(template) = &profile.template {
+        node.push(KdlEntry::new_prop("template", template.as_str()));
+    }
+
+    let mut children = KdlDocument::new();
+    let nodes = children.nodes_mut();
+    let hints = [
+        ("app-id-hint", &profile.app_id_hint),
+        ("workspace-hint", &profile.workspace_hint),
+        ("output-hint", &profile.output_hint),
+        ("layout-hint", &profile.layout_hint),
+    ];
+    for (hint, value) in hints {
+        if let Some(value) = value {
+            let mut hint_node = KdlNode::new(hint);
+            hint_node.push(KdlEntry::new(value.as_str()));
+            nodes.push(hint_node);
+        }
+    }
+    nodes.extend(profile.match_rules.iter().map(|rule| window_match_node("match", rule)));
+    nodes.extend(profile.exclude_rules.iter().map(|rule| window_match_node("exclude", rule)));
+
+    let key_value_block = |block: &str, mut entries: Vec<(String, String)>| {
+        entries.sort();
+        entries
+            .into_iter()
+            .map(|(from, to)| key_value_node(&from, &to, &[]))
+            .chain(profile.conditional_rules.iter().filter_map(|rule| match &rule.entry {
+                ConditionalEntry::Remap { from, to } if block == "remap" => {
+                    Some(key_value_node(&from.to_string(), &to.to_string(), &rule.when))
+                }
+                ConditionalEntry::Combo { from, to } if block == "combo" => {
+                    Some(key_value_node(&from.to_string(), &to.to_string(), &rule.when))
+                }
+                _ => None,
+            }))
+            .collect::<Vec<_>>()
+    };
+    push_block(nodes, "remap", key_value_block("remap", written_entries(&profile.remap)));
+    push_block(nodes, "combo", key_value_block("combo", written_entries(&profile.combo)));
+
+    let mut macros: Vec<_> = profile.macros.iter().collect();
+    macros.sort_by_key(|(trigger, _)| trigger.to_string());
+    let macro_nodes = macros
+        .into_iter()
+        .map(|(trigger, actions)| {
+            let policy = profile.macro_policies.get(trigger).copied();
+            macro_node(&trigger.to_string(), actions, policy, &[])
+        })
+        .chain(profile.conditional_rules.iter().filter_map(|rule| match &rule.entry {
+            ConditionalEntry::Macro { trigger, actions, policy } => {
+                Some(macro_node(&trigger.to_string(), actions, Some(*policy), &rule.when))
+            }
+            _ => None,
+        }))
+        .collect();
+    push_block(nodes, "macro", macro_nodes);
+
+    let passthrough_nodes = profile
+        .niri_passthrough
+        .iter()
+        .map(|keybind| {
+            let mut keybind_node = KdlNode::new(keybind.key.as_str());
+            if let Ok(mut action) = keybind.action.parse::<KdlDocument>() {
+                // Drop the formatting of the stored action so it is re-indented
+                action.clear_fmt_recursive();
+                keybind_node.set_children(action);
+            }
+            keybind_node
+        })
+        .collect();
+    push_block(nodes, "niri-passthrough", passthrough_nodes);
+
+    if !children.nodes().is_empty() {
+        node.set_children(children);
+    }
+
+    let mut doc = KdlDocument::new();
+    doc.nodes_mut().push(node);
+    doc.fmt();
+    doc.to_string()
+}
+
+/// Append a block node such as `remap { ... }`, unless it would be empty
+fn push_block(nodes: &mut Vec<KdlNode>, name: &str, entries: Vec<KdlNode>) {
+    if entries.is_empty() {
+        return;
+    }
+    let mut block = KdlNode::new(name);
+    let mut children = KdlDocument::new();
+    children.nodes_mut().extend(entries);
+    block.set_children(children);
+    nodes.push(block);
+}
+
+/// The entries of a `remap` or `combo` block, with keys as they are written
+fn written_entries<K: std::fmt::Display>(
+    entries: &std::collections::HashMap<K, K>,
+) -> Vec<(String, String)> {
+    entries
+        .iter()
+        .map(|(from, to)| (from.to_string(), to.to_string()))
+        .collect()
+}
+
+/// A `remap` or `combo` entry, with its `when` conditions if any
+fn key_value_node(from: &str, to: &str, when: &[WindowMatch]) -> KdlNode {
+    let mut node = KdlNode::new(from);
+    node.push(KdlEntry::new(to));
+    set_conditions(&mut node, when);
+    node
+}
+
+/// A `macro` entry, with its `when` conditions if any
+fn macro_node(
+    trigger: &str,
+    actions: &[MacroAction],
+    policy: Option<MacroPolicy>,
+    when: &[WindowMatch],
+) -> KdlNode {
+    let mut node = KdlNode::new(trigger);
+    for action in actions {
+        node.push(KdlEntry::new(action.to_string()));
+    }
+    if let Some(policy) = policy {
+        node.push(KdlEntry::new_prop("policy", policy.as_str()));
+    }
+    set_conditions(&mut node, when);
+    node
+}
+
+/// Add a child block of `when` conditions to an entry
+fn set_conditions(node: &mut KdlNode, when: &[WindowMatch]) {
+    if when.is_empty() {
+        return;
+    }
+    let mut children = KdlDocument::new();
+    children
+        .nodes_mut()
+        .extend(when.iter().map(|rule| window_match_node("when", rule)));
+    node.set_children(children);
+}
+
+/// A `match`, `exclude` or `when` rule node with its properties
+fn window_match_node(kind: &str, rule: &WindowMatch) -> KdlNode {
+    let mut node = KdlNode::new(kind);
+    if let Some(app_id) = &rule.app_id {
+        node.push(KdlEntry::new_prop("app-id", app_id.as_str()));
+    }
+    if let Some(title) = &rule.title {
+        node.push(KdlEntry::new_prop("title", title.as_str()));
+    }
+    if let Some(is_floating) = rule.is_floating {
+        node.push(KdlEntry::new_prop("is-floating", is_floating));
+    }
+    if let Some(workspace) = &rule.workspace {
+        node.push(KdlEntry::new_prop("workspace", workspace.as_str()));
+    }
+    if let Some(output) = &rule.output {
+        node.push(KdlEntry::new_prop("output", output.as_str()));
+    }
+    if let Some(layout) = &rule.layout {
+        node.push(KdlEntry::new_prop("layout", layout.as_str()));
+    }
+    if rule.priority != 0 {
+        node.push(KdlEntry::new_prop("priority", i64::from(rule.priority)));
+    }
+    node
+}
+
+/// Find a node such as `device "name"` by node name and first argument.
+fn find_named_node<'a>(nodes: &'a mut [KdlNode], kind: &str, name: &str) -> Option<&'a mut KdlNode> {
+    nodes.iter_mut().find(|node| {
+        node.name().value() == kind
+            && node
+                .entries()
+                .first()
+                .and_then(|entry| entry.value().as_string())
+                == Some(name)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_config_str;
+
+    fn actions() -> Vec<MacroAction> {
+        vec![
+            MacroAction::Key("Ctrl+C".parse().unwrap()),
+            MacroAction::Delay(50),
+            MacroAction::Key("Ctrl+V".parse().unwrap()),
+        ]
+    }
+
+    #[test]
+    fn test_insert_macro_into_existing_block() {
+        let content = r#"// My keyboard
+device "Test Keyboard" {
+    profile "default" {
+        macro {
+            F1 "A"
+        }
+    }
+}
+"#;
+        let updated = insert_macro(content, "Test Keyboard", "default", "F5", &actions()).unwrap();
+
+        assert!(updated.starts_with("// My keyboard\n"));
+        assert!(updated.contains(r#"F1 "A""#));
+        assert!(updated.contains(r#"F5 "Ctrl+C" "delay(50)" "Ctrl+V""#));
+
+        let config = parse_config_str(&updated).unwrap();
+        let profile = &config.devices[0].profiles["default"];
+        assert_eq!(profile.macros.len(), 2);
+        assert_eq!(profile.macros[&"F5".parse().unwrap()], actions());
+    }
+
+    #[test]
+    fn test_insert_macro_creates_missing_blocks() {
+        let content = r#"device "Test Keyboard" {
+    profile "default" {
+        remap {
+            CapsLock "Escape"
+        }
+    }
+}
+"#;
+        let updated = insert_macro(content, "Test Keyboard", "gaming", "F5", &actions()).unwrap();
+
+        let config = parse_config_str(&updated).unwrap();
+        let profile = &config.devices[0].profiles["gaming"];
+        assert_eq!(profile.macros[&"F5".parse().unwrap()], actions());
+    }
+
+    #[test]
+    fn test_insert_macro_replaces_existing_trigger() {
+        let content = r#"device "Test Keyboard" {
+    profile "default" {
+        macro {
+            F5 "A"
+        }
+    }
+}
+"#;
+        let updated = insert_macro(content, "Test Keyboard", "default", "F5", &actions()).unwrap();
+
+        let config = parse_config_str(&updated).unwrap();
+        let profile = &config.devices[0].profiles["default"];
+        assert_eq!(profile.macros.len(), 1);
+        assert_eq!(profile.macros[&"F5".parse().unwrap()], actions());
+    }
+
+    #[test]
+    fn test_insert_macro_unknown_device_fails() {
+        let content = r#"device "Test Keyboard" {
+    profile "default" {}
+}
+"#;
+        let result = insert_macro(content, "Other Keyboard", "default", "F5", &actions());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_write_macro_to_drop_in_file() {
+        let dir = std::env::temp_dir().join("niri-mapper-test-write-macro");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("config.d")).unwrap();
+        let main = "global {\n    log-level \"debug\"\n}\n";
+        std::fs::write(dir.join("config.kdl"), main).unwrap();
+        std::fs::write(
+            dir.join("config.d/10-keyboard.kdl"),
+            "// Keyboard\ndevice \"Test Keyboard\" {\n    profile \"default\" {}\n}\n",
+        )
+        .unwrap();
+
+        write_macro(&dir.join("config.kdl"), "Test Keyboard", "default", "F5", &actions()).unwrap();
+
+        // The drop-in file defining the device is edited, not the main file
+        assert_eq!(std::fs::read_to_string(dir.join("config.kdl")).unwrap(), main);
+        let drop_in = std::fs::read_to_string(dir.join("config.d/10-keyboard.kdl")).unwrap();
+        assert!(drop_in.starts_with("// Keyboard\n"));
+        assert!(drop_in.contains(r#"F5 "Ctrl+C" "delay(50)" "Ctrl+V""#), "{}", drop_in);
+        let config = crate::parser::parse_config(&dir.join("config.kdl")).unwrap();
+        assert_eq!(config.devices[0].profiles["default"].macros[&"F5".parse().unwrap()], actions());
+
+        // An edit that breaks the configuration as a whole is not written
+        std::fs::write(dir.join("config.d/20-mouse.kdl"), "device \"Mouse\" {}\n").unwrap();
+        std::fs::write(dir.join("config.d/30-mouse.kdl"), "device \"Mouse\" {}\n").unwrap();
+        assert!(write_macro(&dir.join("config.kdl"), "Test Keyboard", "default", "F7", &actions()).is_err());
+        assert!(!std::fs::read_to_string(dir.join("config.d/10-keyboard.kdl")).unwrap().contains("F7"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_format_profile_round_trips() {
+        let content = r#"
+device "Test Keyboard" {
+    profile "default" {
+        remap {
+            CapsLock "Escape"
+            CapsLock "LeftCtrl" {
+                when app-id="^Alacritty$" is-floating=false
+            }
+        }
+        macro {
+            F5 "Ctrl+c" "delay(50)" policy="restart"
+        }
+        niri-passthrough {
+            Super+Return { spawn "alacritty"; }
+        }
+    }
+    profile "firefox" extends="default" {
+        match title="YouTube" priority=5
+        remap {
+            A "B"
+        }
+    }
+}
+"#;
+        let config = parse_config_str(content).unwrap();
+        let firefox = &config.devices[0].profiles["firefox"];
+        let formatted = format_profile("firefox", firefox);
+        assert!(formatted.starts_with("profile \"firefox\" extends=\"default\" {"));
+        assert!(formatted.contains("match title=\"YouTube\" priority=5"));
+        // Keys are written by their canonical names
+        assert!(formatted.contains("F5 \"Ctrl+C\" \"delay(50)\" policy=\"restart\""));
+
+        // The formatted profile parses back to the same rules
+        let reparsed = format!(
+            "device \"Test Keyboard\" {{\n{}}}",
+            formatted.replace(" extends=\"default\"", "").replace("\"firefox\"", "\"default\"")
+        );
+        let config = parse_config_str(&reparsed).unwrap();
+        let profile = &config.devices[0].profiles["default"];
+        assert_eq!(profile.remap, firefox.remap);
+        assert_eq!(profile.macros, firefox.macros);
+        assert_eq!(profile.macro_policies, firefox.macro_policies);
+        assert_eq!(profile.conditional_rules.len(), 1);
+        assert_eq!(profile.conditional_rules[0].entry, firefox.conditional_rules[0].entry);
+        assert_eq!(profile.niri_passthrough[0].action, firefox.niri_passthrough[0].action);
+    }
+}
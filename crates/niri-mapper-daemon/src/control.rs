@@ -22,6 +22,15 @@
 //! - `{"status": {}}`
 //!   Query daemon status including active profiles per device.
 //!
+//! - `{"abort_macros": {}}`
+//!   Abort all running macros.
+//!
+//! - `{"record_macro": {"device": "...", "name": "..."}}`
+//!   Record a macro from a device until its stop key is pressed.
+//!
+//! - `{"run_macro": {"name": "..."}}`
+//!   Play a recorded macro.
+//!
 //! ## How Manual Switching Works
 //!
 //! 1. CLI sends a `switch_profile` command via this socket
@@ -30,16 +39,12 @@
 //! 4. The remapper's rules are replaced with the new profile's rules
 //! 5. Subsequent key events use the new profile's mappings
 //!
-//! ## Future: Automatic Switching (Backlog)
+//! ## Automatic Switching
 //!
-//! In a future release, the daemon will:
-//! 1. Subscribe to niri's focus change events via IPC
-//! 2. On focus change, check if any profile's `app_id_hint` matches the focused
-//!    window's `app_id`
-//! 3. Automatically invoke `switch_profile` for matching profiles
-//!
-//! The control socket will continue to work for manual overrides even when
-//! automatic switching is implemented.
+//! The daemon also switches profiles on niri focus events when a profile's
+//! `app-id-hint`, `workspace-hint`, `output-hint` or `layout-hint` matches.
+//! A profile selected manually that has no hints stays active until switched
+//! away from.
 //!
 //! # Socket Location
 //!
@@ -57,12 +62,13 @@
 //! niri-mapper switch-profile "Keychron K3 Pro" gaming
 //! ```
 
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
 // Re-export the socket infrastructure from ipc module
-pub use crate::ipc::{DeviceStatus, IpcServer};
+pub use crate::ipc::{DeviceStatus, IpcServer, RunningMacroStatus};
 
 // ============================================================================
 // Control Command Types (v0.4.0 JSON format)
@@ -74,6 +80,9 @@ pub use crate::ipc::{DeviceStatus, IpcServer};
 /// - `{"switch_profile": {"device": "...", "profile": "..."}}`
 /// - `{"list_profiles": {}}`
 /// - `{"status": {}}`
+/// - `{"abort_macros": {}}`
+/// - `{"record_macro": {"device": "...", "name": "..."}}`
+/// - `{"run_macro": {"name": "..."}}`
 ///
 /// This is an alternative to the `IpcRequest` format which uses `{"type": "..."}`.
 /// Both formats can be supported by the daemon for flexibility.
@@ -94,6 +103,21 @@ pub enum ControlCommand {
     ///
     /// JSON format: `{"status": {}}`
     Status(StatusArgs),
+
+    /// Abort all running macros
+    ///
+    /// JSON format: `{"abort_macros": {}}`
+    AbortMacros(AbortMacrosArgs),
+
+    /// Record a macro from a device
+    ///
+    /// JSON format: `{"record_macro": {"device": "Keychron K3 Pro", "name": "greeting"}}`
+    RecordMacro(RecordMacroArgs),
+
+    /// Play a recorded macro
+    ///
+    /// JSON format: `{"run_macro": {"name": "greeting"}}`
+    RunMacro(RunMacroArgs),
 }
 
 /// Arguments for the `switch_profile` command
@@ -117,6 +141,41 @@ pub struct ListProfilesArgs {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
 pub struct StatusArgs {}
 
+/// Arguments for the `abort_macros` command (currently empty, but extensible)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct AbortMacrosArgs {}
+
+/// Arguments for the `record_macro` command
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RecordMacroArgs {
+    /// Name of the device to record
+    pub device: String,
+    /// Name to store the recording under
+    pub name: String,
+    /// Key that stops the recording
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_key: Option<String>,
+    /// Round delays to this many milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantize_ms: Option<u64>,
+    /// Trigger key to bind the recording to in the config file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind: Option<String>,
+    /// Profile to write the binding to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+}
+
+/// Arguments for the `run_macro` command
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RunMacroArgs {
+    /// Name of the library macro or recording to play
+    pub name: String,
+    /// Values for the macro's parameters
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub args: HashMap<String, String>,
+}
+
 // ============================================================================
 // Control Response Types
 // ============================================================================
@@ -142,6 +201,9 @@ pub enum ControlResponse {
     Status {
         /// Status of each grabbed device
         devices: Vec<DeviceStatus>,
+        /// Macros that are currently playing
+        #[serde(default)]
+        running_macros: Vec<RunningMacroStatus>,
     },
 
     /// Error occurred while processing command
@@ -182,6 +244,27 @@ impl From<crate::ipc::IpcRequest> for ControlCommand {
             crate::ipc::IpcRequest::Status => {
                 ControlCommand::Status(StatusArgs {})
             }
+            crate::ipc::IpcRequest::AbortMacros => {
+                ControlCommand::AbortMacros(AbortMacrosArgs {})
+            }
+            crate::ipc::IpcRequest::RecordMacro {
+                device,
+                name,
+                stop_key,
+                quantize_ms,
+                bind,
+                profile,
+            } => ControlCommand::RecordMacro(RecordMacroArgs {
+                device,
+                name,
+                stop_key,
+                quantize_ms,
+                bind,
+                profile,
+            }),
+            crate::ipc::IpcRequest::RunMacro { name, args } => {
+                ControlCommand::RunMacro(RunMacroArgs { name, args })
+            }
         }
     }
 }
@@ -200,6 +283,19 @@ impl From<ControlCommand> for crate::ipc::IpcRequest {
                 }
             }
             ControlCommand::Status(_) => crate::ipc::IpcRequest::Status,
+            ControlCommand::AbortMacros(_) => crate::ipc::IpcRequest::AbortMacros,
+            ControlCommand::RecordMacro(args) => crate::ipc::IpcRequest::RecordMacro {
+                device: args.device,
+                name: args.name,
+                stop_key: args.stop_key,
+                quantize_ms: args.quantize_ms,
+                bind: args.bind,
+                profile: args.profile,
+            },
+            ControlCommand::RunMacro(args) => crate::ipc::IpcRequest::RunMacro {
+                name: args.name,
+                args: args.args,
+            },
         }
     }
 }
@@ -222,8 +318,8 @@ impl From<crate::ipc::IpcResponse> for ControlResponse {
                     }],
                 }
             }
-            crate::ipc::IpcResponse::Status { devices } => {
-                ControlResponse::Status { devices }
+            crate::ipc::IpcResponse::Status { devices, running_macros } => {
+                ControlResponse::Status { devices, running_macros }
             }
             crate::ipc::IpcResponse::Error { message } => {
                 ControlResponse::Error { message }
@@ -471,6 +567,31 @@ mod tests {
         assert_eq!(parsed, cmd);
     }
 
+    #[test]
+    fn test_abort_macros_serialization() {
+        let cmd = ControlCommand::AbortMacros(AbortMacrosArgs {});
+        let json = serde_json::to_string(&cmd).unwrap();
+        assert_eq!(json, r#"{"abort_macros":{}}"#);
+
+        // Round-trip
+        let parsed: ControlCommand = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, cmd);
+    }
+
+    #[test]
+    fn test_run_macro_serialization() {
+        let cmd = ControlCommand::RunMacro(RunMacroArgs {
+            name: "greeting".to_string(),
+            args: HashMap::new(),
+        });
+        let json = serde_json::to_string(&cmd).unwrap();
+        assert_eq!(json, r#"{"run_macro":{"name":"greeting"}}"#);
+
+        // Round-trip
+        let parsed: ControlCommand = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, cmd);
+    }
+
     #[test]
     fn test_response_success_serialization() {
         let response = ControlResponse::Success {
@@ -524,6 +645,7 @@ mod tests {
                 active_profile: "default".to_string(),
                 available_profiles: vec!["default".to_string(), "gaming".to_string()],
             }],
+            running_macros: Vec::new(),
         };
         let json = serde_json::to_string(&response).unwrap();
         assert!(json.contains(r#""status""#));
@@ -601,6 +723,10 @@ mod tests {
         let control = ControlCommand::Status(StatusArgs {});
         let ipc: IpcRequest = control.into();
         assert!(matches!(ipc, IpcRequest::Status));
+
+        let control = ControlCommand::AbortMacros(AbortMacrosArgs {});
+        let ipc: IpcRequest = control.into();
+        assert!(matches!(ipc, IpcRequest::AbortMacros));
     }
 
     // ========================================================================
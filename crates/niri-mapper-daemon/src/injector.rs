@@ -3,13 +3,41 @@
 //! This module provides a virtual keyboard device for injecting remapped key events.
 //! The [`SharedVirtualDevice`] type alias provides a thread-safe, shareable wrapper
 //! around [`VirtualDevice`] for use across multiple input device handlers.
+//!
+//! # Macro Isolation
+//!
+//! Physical input and macro playback share the same virtual device. While a
+//! macro is playing, the [`MacroIsolation`] mode decides what happens to
+//! physical input emitted through [`VirtualDevice::emit_physical`]: it is
+//! passed through, queued until playback ends, or dropped. With isolation
+//! enabled, modifiers held at the start of playback are released first and
+//! pressed again once playback ends.
 
+use std::collections::HashSet;
 use std::sync::Arc;
 
 use anyhow::Result;
-use evdev::{uinput::VirtualDeviceBuilder, AttributeSet, Key, InputEvent};
+use evdev::{uinput::VirtualDeviceBuilder, AttributeSet, EventType, InputEvent, InputEventKind, Key};
+use niri_mapper_config::MacroIsolation;
 use tokio::sync::Mutex;
 
+/// Maximum number of physical events held back during macro playback.
+///
+/// Events beyond this limit are dropped to bound memory use.
+const MAX_QUEUED_EVENTS: usize = 1024;
+
+/// Modifier keys that are released before macro playback.
+const MODIFIER_KEYS: [Key; 8] = [
+    Key::KEY_LEFTCTRL,
+    Key::KEY_RIGHTCTRL,
+    Key::KEY_LEFTSHIFT,
+    Key::KEY_RIGHTSHIFT,
+    Key::KEY_LEFTALT,
+    Key::KEY_RIGHTALT,
+    Key::KEY_LEFTMETA,
+    Key::KEY_RIGHTMETA,
+];
+
 /// A shared virtual device that can be used across multiple async tasks.
 ///
 /// This is the primary interface for injecting remapped events from multiple
@@ -59,9 +87,154 @@ pub fn create_shared_virtual_device(name: &str) -> Result<SharedVirtualDevice> {
     Ok(Arc::new(Mutex::new(device)))
 }
 
+/// Separates physical input from macro playback.
+///
+/// This holds no device handle; it only decides which events to emit, which
+/// keeps it testable without uinput.
+#[derive(Debug, Default)]
+struct PlaybackGate {
+    mode: MacroIsolation,
+    /// Number of macros currently playing
+    depth: usize,
+    /// Modifiers released at the start of playback, pressed again at the end
+    suspended: Vec<Key>,
+    /// Other keys held down when playback started, whose repeats and release
+    /// always pass so they do not stay stuck down on the virtual device
+    held: HashSet<Key>,
+    /// Physical events held back during playback (`queue` mode)
+    queued: Vec<InputEvent>,
+    /// Keys pressed during playback whose later events are dropped (`drop` mode)
+    suppressed: HashSet<Key>,
+}
+
+impl PlaybackGate {
+    /// Start a macro playback, returning the events that release held modifiers.
+    fn begin(&mut self, held: &HashSet<Key>) -> Vec<InputEvent> {
+        self.depth += 1;
+        if self.depth > 1 || self.mode == MacroIsolation::Off {
+            return Vec::new();
+        }
+
+        self.suspended = MODIFIER_KEYS
+            .iter()
+            .copied()
+            .filter(|key| held.contains(key))
+            .collect();
+        self.held = held
+            .iter()
+            .copied()
+            .filter(|key| !MODIFIER_KEYS.contains(key))
+            .collect();
+        self.suspended
+            .iter()
+            .flat_map(|key| key_events(*key, 0))
+            .collect()
+    }
+
+    /// Finish a macro playback, returning the events that restore the modifiers
+    /// and replay queued input once the last macro has finished.
+    fn end(&mut self) -> Vec<InputEvent> {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth > 0 {
+            return Vec::new();
+        }
+
+        self.held.clear();
+        let mut events: Vec<InputEvent> = self
+            .suspended
+            .drain(..)
+            .flat_map(|key| key_events(key, 1))
+            .collect();
+        events.append(&mut self.queued);
+        events
+    }
+
+    /// Filter physical events, returning the events to emit now.
+    fn filter(&mut self, events: &[InputEvent]) -> Vec<InputEvent> {
+        let mut passed = Vec::with_capacity(events.len());
+        let mut removed = false;
+
+        for event in events {
+            let key = match event.kind() {
+                InputEventKind::Key(key) => Some(key),
+                _ => None,
+            };
+
+            // Later events of keys that were pressed and dropped during playback
+            if let Some(key) = key.filter(|key| self.suppressed.contains(key)) {
+                if event.value() == 0 {
+                    self.suppressed.remove(&key);
+                }
+                removed = true;
+                continue;
+            }
+
+            if self.depth == 0 {
+                passed.push(*event);
+                continue;
+            }
+
+            // Repeats and the release of a key held since before playback
+            if let Some(key) = key.filter(|key| self.held.contains(key) && event.value() != 1) {
+                if event.value() == 0 {
+                    self.held.remove(&key);
+                }
+                passed.push(*event);
+                continue;
+            }
+
+            match self.mode {
+                MacroIsolation::Off => passed.push(*event),
+                MacroIsolation::Queue => {
+                    // Releases are kept even when the queue is full, along
+                    // with their SYN report, so no key is left pressed
+                    let release = key.is_some() && event.value() == 0;
+                    let report = event.event_type() == EventType::SYNCHRONIZATION
+                        && self.queued.last().is_some_and(|last| {
+                            last.event_type() == EventType::KEY && last.value() == 0
+                        });
+                    if self.queued.len() < MAX_QUEUED_EVENTS || release || report {
+                        self.queued.push(*event);
+                    } else {
+                        tracing::warn!("Macro isolation queue is full, dropping input event");
+                    }
+                }
+                MacroIsolation::Drop => {
+                    let Some(key) = key else { continue };
+                    match event.value() {
+                        // Released modifiers are not restored after playback
+                        0 => self.suspended.retain(|suspended| *suspended != key),
+                        1 => {
+                            self.suppressed.insert(key);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        // A batch that only had its key events removed would emit a bare SYN
+        if removed && passed.iter().all(|event| event.event_type() == EventType::SYNCHRONIZATION) {
+            passed.clear();
+        }
+        passed
+    }
+}
+
+/// Build a key event followed by a SYN report.
+fn key_events(key: Key, value: i32) -> [InputEvent; 2] {
+    [
+        InputEvent::new(EventType::KEY, key.code(), value),
+        InputEvent::new(EventType::SYNCHRONIZATION, 0, 0),
+    ]
+}
+
 /// A virtual input device for injecting events
 pub struct VirtualDevice {
     device: evdev::uinput::VirtualDevice,
+    /// Keys currently held down on the virtual device
+    held: HashSet<Key>,
+    gate: PlaybackGate,
 }
 
 impl VirtualDevice {
@@ -69,8 +242,10 @@ impl VirtualDevice {
     pub fn new_keyboard(name: &str) -> Result<Self> {
         let mut keys = AttributeSet::<Key>::new();
 
-        // Add all standard keys
-        for code in 0..256u16 {
+        // Add all standard keys, plus the extended keys (media, brightness,
+        // KEY_MACRO* etc.) above the BTN_* range. Buttons are left out so
+        // libinput keeps treating the device as a keyboard.
+        for code in (0..256u16).chain(0x160..0x2c0) {
             keys.insert(Key::new(code));
         }
 
@@ -79,15 +254,73 @@ impl VirtualDevice {
             .with_keys(&keys)?
             .build()?;
 
-        Ok(Self { device })
+        Ok(Self {
+            device,
+            held: HashSet::new(),
+            gate: PlaybackGate::default(),
+        })
     }
 
     /// Emit an input event
     pub fn emit(&mut self, events: &[InputEvent]) -> Result<()> {
+        for event in events {
+            if let InputEventKind::Key(key) = event.kind() {
+                match event.value() {
+                    0 => {
+                        self.held.remove(&key);
+                    }
+                    1 => {
+                        self.held.insert(key);
+                    }
+                    _ => {}
+                }
+            }
+        }
         self.device.emit(events)?;
         Ok(())
     }
 
+    /// Emit events that originate from physical input.
+    ///
+    /// Unlike [`emit()`](Self::emit), this applies the macro isolation mode
+    /// while a macro is playing.
+    pub fn emit_physical(&mut self, events: &[InputEvent]) -> Result<()> {
+        let events = self.gate.filter(events);
+        if !events.is_empty() {
+            self.emit(&events)?;
+        }
+        Ok(())
+    }
+
+    /// Set how physical input is handled during macro playback.
+    pub fn set_macro_isolation(&mut self, mode: MacroIsolation) {
+        self.gate.mode = mode;
+    }
+
+    /// Mark the start of a macro playback.
+    ///
+    /// Releases the held modifiers if isolation is enabled. Every call must be
+    /// paired with [`end_macro_playback()`](Self::end_macro_playback).
+    pub fn begin_macro_playback(&mut self) -> Result<()> {
+        let events = self.gate.begin(&self.held);
+        if !events.is_empty() {
+            self.emit(&events)?;
+        }
+        Ok(())
+    }
+
+    /// Mark the end of a macro playback.
+    ///
+    /// When the last playing macro finishes, the released modifiers are
+    /// pressed again and queued physical input is replayed.
+    pub fn end_macro_playback(&mut self) -> Result<()> {
+        let events = self.gate.end();
+        if !events.is_empty() {
+            self.emit(&events)?;
+        }
+        Ok(())
+    }
+
     /// Send a key press event
     pub fn press_key(&mut self, key: Key) -> Result<()> {
         let press = InputEvent::new(evdev::EventType::KEY, key.code(), 1);
@@ -111,3 +344,159 @@ impl VirtualDevice {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: Key, value: i32) -> Vec<InputEvent> {
+        key_events(key, value).to_vec()
+    }
+
+    fn gate(mode: MacroIsolation) -> PlaybackGate {
+        PlaybackGate {
+            mode,
+            ..Default::default()
+        }
+    }
+
+    fn keys_of(events: &[InputEvent]) -> Vec<(Key, i32)> {
+        events
+            .iter()
+            .filter_map(|event| match event.kind() {
+                InputEventKind::Key(key) => Some((key, event.value())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_gate_off_passes_everything() {
+        let mut gate = gate(MacroIsolation::Off);
+        let held = HashSet::from([Key::KEY_LEFTSHIFT]);
+
+        assert!(gate.begin(&held).is_empty());
+        assert_eq!(keys_of(&gate.filter(&key(Key::KEY_A, 1))), vec![(Key::KEY_A, 1)]);
+        assert!(gate.end().is_empty());
+    }
+
+    #[test]
+    fn test_gate_releases_and_restores_modifiers() {
+        let mut gate = gate(MacroIsolation::Queue);
+        let held = HashSet::from([Key::KEY_LEFTSHIFT, Key::KEY_A]);
+
+        // Only modifiers are released, not other held keys
+        assert_eq!(keys_of(&gate.begin(&held)), vec![(Key::KEY_LEFTSHIFT, 0)]);
+        assert_eq!(keys_of(&gate.end()), vec![(Key::KEY_LEFTSHIFT, 1)]);
+    }
+
+    #[test]
+    fn test_gate_queue_replays_after_playback() {
+        let mut gate = gate(MacroIsolation::Queue);
+        gate.begin(&HashSet::new());
+
+        assert!(gate.filter(&key(Key::KEY_A, 1)).is_empty());
+        assert!(gate.filter(&key(Key::KEY_A, 0)).is_empty());
+
+        assert_eq!(
+            keys_of(&gate.end()),
+            vec![(Key::KEY_A, 1), (Key::KEY_A, 0)]
+        );
+        assert_eq!(keys_of(&gate.filter(&key(Key::KEY_B, 1))), vec![(Key::KEY_B, 1)]);
+    }
+
+    #[test]
+    fn test_gate_queue_restores_before_replaying_release() {
+        let mut gate = gate(MacroIsolation::Queue);
+        let held = HashSet::from([Key::KEY_LEFTCTRL]);
+        gate.begin(&held);
+
+        // Releasing the modifier during playback is replayed after the restore
+        assert!(gate.filter(&key(Key::KEY_LEFTCTRL, 0)).is_empty());
+        assert_eq!(
+            keys_of(&gate.end()),
+            vec![(Key::KEY_LEFTCTRL, 1), (Key::KEY_LEFTCTRL, 0)]
+        );
+    }
+
+    #[test]
+    fn test_gate_drop_discards_input() {
+        let mut gate = gate(MacroIsolation::Drop);
+        let held = HashSet::from([Key::KEY_LEFTSHIFT, Key::KEY_LEFTCTRL]);
+        gate.begin(&held);
+
+        assert!(gate.filter(&key(Key::KEY_A, 1)).is_empty());
+        assert!(gate.filter(&key(Key::KEY_LEFTSHIFT, 0)).is_empty());
+
+        // Shift was released during playback, so only Ctrl is restored
+        assert_eq!(keys_of(&gate.end()), vec![(Key::KEY_LEFTCTRL, 1)]);
+
+        // The key pressed during playback stays suppressed until released
+        assert!(gate.filter(&key(Key::KEY_A, 2)).is_empty());
+        assert!(gate.filter(&key(Key::KEY_A, 0)).is_empty());
+        assert_eq!(keys_of(&gate.filter(&key(Key::KEY_A, 1))), vec![(Key::KEY_A, 1)]);
+    }
+
+    #[test]
+    fn test_gate_drop_passes_release_of_held_key() {
+        let mut gate = gate(MacroIsolation::Drop);
+        let held = HashSet::from([Key::KEY_W, Key::KEY_LEFTSHIFT]);
+        gate.begin(&held);
+
+        // W was held before the macro started: its repeats and release pass
+        assert_eq!(keys_of(&gate.filter(&key(Key::KEY_W, 2))), vec![(Key::KEY_W, 2)]);
+        assert_eq!(keys_of(&gate.filter(&key(Key::KEY_W, 0))), vec![(Key::KEY_W, 0)]);
+
+        // Pressed again during playback, it is dropped like any other key
+        assert!(gate.filter(&key(Key::KEY_W, 1)).is_empty());
+        assert!(gate.filter(&key(Key::KEY_W, 0)).is_empty());
+        assert_eq!(keys_of(&gate.end()), vec![(Key::KEY_LEFTSHIFT, 1)]);
+    }
+
+    #[test]
+    fn test_gate_queue_passes_release_of_held_key() {
+        let mut gate = gate(MacroIsolation::Queue);
+        gate.begin(&HashSet::from([Key::KEY_W]));
+
+        assert_eq!(keys_of(&gate.filter(&key(Key::KEY_W, 0))), vec![(Key::KEY_W, 0)]);
+        assert!(gate.filter(&key(Key::KEY_A, 1)).is_empty());
+        assert_eq!(keys_of(&gate.end()), vec![(Key::KEY_A, 1)]);
+    }
+
+    #[test]
+    fn test_gate_full_queue_keeps_releases() {
+        let mut gate = gate(MacroIsolation::Queue);
+        gate.begin(&HashSet::new());
+
+        assert!(gate.filter(&key(Key::KEY_A, 1)).is_empty());
+        for _ in 0..MAX_QUEUED_EVENTS {
+            gate.filter(&key(Key::KEY_B, 2));
+        }
+        // Presses and repeats are dropped once the queue is full, releases are not
+        assert!(gate.filter(&key(Key::KEY_C, 1)).is_empty());
+        assert!(gate.filter(&key(Key::KEY_A, 0)).is_empty());
+
+        let replayed = gate.end();
+        assert_eq!(replayed.len(), MAX_QUEUED_EVENTS + 2);
+        assert_eq!(keys_of(&replayed[replayed.len() - 2..]), vec![(Key::KEY_A, 0)]);
+        assert_eq!(replayed.last().unwrap().event_type(), EventType::SYNCHRONIZATION);
+        assert!(!keys_of(&replayed).contains(&(Key::KEY_C, 1)));
+    }
+
+    #[test]
+    fn test_gate_nested_playback() {
+        let mut gate = gate(MacroIsolation::Queue);
+        let held = HashSet::from([Key::KEY_LEFTALT]);
+
+        assert_eq!(gate.begin(&held).len(), 2);
+        assert!(gate.begin(&HashSet::new()).is_empty());
+        assert!(gate.filter(&key(Key::KEY_A, 1)).is_empty());
+
+        // Nothing is restored until the last macro finishes
+        assert!(gate.end().is_empty());
+        assert_eq!(
+            keys_of(&gate.end()),
+            vec![(Key::KEY_LEFTALT, 1), (Key::KEY_A, 1)]
+        );
+    }
+}
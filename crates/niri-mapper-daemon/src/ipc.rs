@@ -3,6 +3,7 @@
 //! Provides a Unix domain socket for CLI and external tools to communicate
 //! with the running daemon. Used for profile switching, status queries, etc.
 
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 use anyhow::{Context, Result};
@@ -21,6 +22,9 @@ use tokio::net::{UnixListener, UnixStream};
 /// - `{"type": "profile_switch", "device": "...", "profile": "..."}`
 /// - `{"type": "profile_list", "device": "..."}`
 /// - `{"type": "status"}`
+/// - `{"type": "abort_macros"}`
+/// - `{"type": "record_macro", "device": "...", "name": "..."}`
+/// - `{"type": "run_macro", "name": "..."}`
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum IpcRequest {
@@ -38,6 +42,35 @@ pub enum IpcRequest {
     },
     /// Query overall daemon status
     Status,
+    /// Abort all running macros and drop queued runs
+    AbortMacros,
+    /// Start recording a macro from a device's processed key stream
+    RecordMacro {
+        /// Name of the device to record
+        device: String,
+        /// Name to store the recording under
+        name: String,
+        /// Key that stops the recording (defaults to the configured record key, or Escape)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stop_key: Option<String>,
+        /// Round delays to this many milliseconds (defaults to `macro-record-quantize`)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        quantize_ms: Option<u64>,
+        /// Trigger key to bind the recording to in the config file
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        bind: Option<String>,
+        /// Profile to write the binding to (defaults to the device's active profile)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+    },
+    /// Play a named macro from the config's `macros` library, or a recording
+    RunMacro {
+        /// Name of the library macro or recording
+        name: String,
+        /// Values for the macro's parameters
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        args: HashMap<String, String>,
+    },
 }
 
 /// Response messages sent from the daemon back to CLI/external tools
@@ -61,6 +94,9 @@ pub enum IpcResponse {
     Status {
         /// Status of each grabbed device
         devices: Vec<DeviceStatus>,
+        /// Macros that are currently playing
+        #[serde(default)]
+        running_macros: Vec<RunningMacroStatus>,
     },
     /// Error occurred while processing request
     Error {
@@ -82,6 +118,21 @@ pub struct DeviceStatus {
     pub available_profiles: Vec<String>,
 }
 
+/// Status information for a macro that is currently playing
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RunningMacroStatus {
+    /// Name of the device the macro was triggered on
+    pub device: String,
+    /// Trigger key of the macro (e.g., "F5")
+    pub trigger: String,
+    /// Concurrency policy (e.g., "restart")
+    pub policy: String,
+    /// Milliseconds since the current run started
+    pub running_ms: u64,
+    /// Number of runs queued behind the current one
+    pub queued: usize,
+}
+
 // ============================================================================
 // IPC Server
 // ============================================================================
@@ -346,6 +397,76 @@ mod tests {
         assert_eq!(parsed, request);
     }
 
+    #[test]
+    fn test_request_abort_macros_serialization() {
+        let request = IpcRequest::AbortMacros;
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(json, r#"{"type":"abort_macros"}"#);
+
+        // Round-trip
+        let parsed: IpcRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, request);
+    }
+
+    #[test]
+    fn test_request_record_macro_serialization() {
+        let request = IpcRequest::RecordMacro {
+            device: "Keychron K3 Pro".to_string(),
+            name: "greeting".to_string(),
+            stop_key: None,
+            quantize_ms: Some(10),
+            bind: None,
+            profile: None,
+        };
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"record_macro","device":"Keychron K3 Pro","name":"greeting","quantize_ms":10}"#
+        );
+
+        // Round-trip
+        let parsed: IpcRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, request);
+    }
+
+    #[test]
+    fn test_request_run_macro_serialization() {
+        let request = IpcRequest::RunMacro {
+            name: "greeting".to_string(),
+            args: HashMap::new(),
+        };
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(json, r#"{"type":"run_macro","name":"greeting"}"#);
+
+        // Round-trip
+        let parsed: IpcRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, request);
+
+        let request = IpcRequest::RunMacro {
+            name: "switch-tab".to_string(),
+            args: HashMap::from([("n".to_string(), "3".to_string())]),
+        };
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(json, r#"{"type":"run_macro","name":"switch-tab","args":{"n":"3"}}"#);
+
+        let parsed: IpcRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, request);
+    }
+
+    #[test]
+    fn test_response_status_without_running_macros() {
+        // Responses from older daemons have no running_macros field
+        let json = r#"{"type":"status","devices":[]}"#;
+        let parsed: IpcResponse = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            parsed,
+            IpcResponse::Status {
+                devices: Vec::new(),
+                running_macros: Vec::new(),
+            }
+        );
+    }
+
     #[test]
     fn test_response_success_serialization() {
         // Without message
@@ -391,6 +512,13 @@ mod tests {
                 active_profile: "default".to_string(),
                 available_profiles: vec!["default".to_string(), "gaming".to_string()],
             }],
+            running_macros: vec![RunningMacroStatus {
+                device: "Keychron K3 Pro".to_string(),
+                trigger: "F5".to_string(),
+                policy: "queue".to_string(),
+                running_ms: 120,
+                queued: 2,
+            }],
         };
         let json = serde_json::to_string(&response).unwrap();
         // Verify it contains expected structure
@@ -398,6 +526,7 @@ mod tests {
         assert!(json.contains(r#""name":"Keychron K3 Pro""#));
         assert!(json.contains(r#""path":"/dev/input/event5""#));
         assert!(json.contains(r#""active_profile":"default""#));
+        assert!(json.contains(r#""trigger":"F5""#));
 
         // Round-trip
         let parsed: IpcResponse = serde_json::from_str(&json).unwrap();
@@ -841,6 +970,7 @@ mod tests {
                                     ],
                                 },
                             ],
+                            running_macros: Vec::new(),
                         }
                     }
                     _ => IpcResponse::Error {
@@ -872,7 +1002,7 @@ mod tests {
         // Parse and verify the status response
         let response: IpcResponse = serde_json::from_str(response_line.trim()).unwrap();
         match response {
-            IpcResponse::Status { devices } => {
+            IpcResponse::Status { devices, .. } => {
                 assert_eq!(devices.len(), 2);
 
                 let keychron = devices.iter().find(|d| d.name == "Keychron K3 Pro").unwrap();
@@ -0,0 +1,164 @@
+//! Keysym mode: rule keys name the character a key types
+//!
+//! By default, rule keys are evdev key codes, named after the US layout: a
+//! `Ctrl+Q` combo fires on the key in the US `Q` position, whatever the user's
+//! layout puts there. In keysym mode (`key-mode "keysym"`), rule keys are
+//! interpreted through the xkb keymap instead, so on AZERTY `Ctrl+Q` fires on
+//! the key labeled `Q`, which is the US `A` key.
+//!
+//! # Translation
+//!
+//! A [`KeyTranslation`] is built from the active layout of the keymap. It maps
+//! each *logical* key (the US key that types a character unshifted) to the
+//! *physical* key that types the same character unshifted on the active
+//! layout. Incoming keys are translated to logical keys before rules are
+//! matched, and keys emitted by rules are translated back to physical keys.
+//!
+//! Keys that do not type a character (function keys, modifiers, navigation)
+//! are the same in both modes. Physical keys that type a character without a
+//! US equivalent (e.g., `ö` on a German layout) match no rules.
+//!
+//! The daemon rebuilds the translation when niri reports a keyboard layout
+//! switch, so rules follow the layout the user is typing with.
+
+use std::collections::{HashMap, HashSet};
+
+use evdev::Key;
+use niri_mapper_config::{GlobalConfig, KeyMode};
+
+use crate::text_input::{xkb_settings, Keymap};
+
+/// Translation between logical (rule) keys and physical keys for one layout.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyTranslation {
+    /// Logical key -> physical key typing the same character
+    to_physical: HashMap<Key, Key>,
+    /// Physical key -> logical key typing the same character
+    to_logical: HashMap<Key, Key>,
+    /// Physical keys that type a character on the layout
+    character_keys: HashSet<Key>,
+}
+
+impl KeyTranslation {
+    /// Build the translation for a layout's keymap.
+    pub fn from_keymap(keymap: &Keymap) -> Self {
+        let mut translation = Self::default();
+
+        for (_, stroke) in keymap.iter() {
+            if stroke.modifiers.is_empty() {
+                translation.character_keys.insert(stroke.key);
+            }
+        }
+
+        for (c, us_stroke) in Keymap::us().iter() {
+            if !us_stroke.modifiers.is_empty() || c.is_whitespace() {
+                continue;
+            }
+            if let Some(stroke) = keymap.stroke(c).filter(|s| s.modifiers.is_empty()) {
+                translation.to_physical.insert(us_stroke.key, stroke.key);
+                translation.to_logical.insert(stroke.key, us_stroke.key);
+            }
+        }
+
+        translation
+    }
+
+    /// Load the translation for a layout of the configured keymap.
+    ///
+    /// Returns `None` in keycode mode, or if the keymap cannot be loaded (in
+    /// which case rules fall back to key codes).
+    pub fn from_config(global: &GlobalConfig, layout: u32) -> Option<Self> {
+        if global.key_mode != KeyMode::Keysym {
+            return None;
+        }
+
+        match Keymap::from_xkb(&xkb_settings(global), layout) {
+            Ok(keymap) => {
+                tracing::debug!("Loaded keysym translation for layout {}", layout);
+                Some(Self::from_keymap(&keymap))
+            }
+            Err(e) => {
+                tracing::warn!("Keysym mode unavailable, matching rules by key code: {:#}", e);
+                None
+            }
+        }
+    }
+
+    /// The logical key for a physical key.
+    ///
+    /// Returns `None` for keys that type a character no logical key types,
+    /// since no rule can refer to them.
+    pub fn logical(&self, physical: Key) -> Option<Key> {
+        match self.to_logical.get(&physical) {
+            Some(&logical) => Some(logical),
+            None if self.character_keys.contains(&physical) => None,
+            None => Some(physical),
+        }
+    }
+
+    /// The physical key for a logical key.
+    ///
+    /// Logical keys whose character the layout cannot type unshifted are
+    /// emitted unchanged.
+    pub fn physical(&self, logical: Key) -> Key {
+        self.to_physical.get(&logical).copied().unwrap_or(logical)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use niri_mapper_config::XkbSettings;
+
+    fn translation(layout: &str) -> Option<KeyTranslation> {
+        let settings = XkbSettings {
+            layout: layout.to_string(),
+            ..Default::default()
+        };
+        // libxkbcommon or the xkb data may not be installed (e.g., in the Nix sandbox)
+        Keymap::from_xkb(&settings, 0)
+            .ok()
+            .map(|keymap| KeyTranslation::from_keymap(&keymap))
+    }
+
+    #[test]
+    fn test_us_translation_is_identity() {
+        let translation = KeyTranslation::from_keymap(&Keymap::us());
+        for key in [Key::KEY_Q, Key::KEY_SEMICOLON, Key::KEY_1, Key::KEY_F5, Key::KEY_LEFTCTRL] {
+            assert_eq!(translation.logical(key), Some(key));
+            assert_eq!(translation.physical(key), key);
+        }
+    }
+
+    #[test]
+    fn test_azerty_translation() {
+        let Some(translation) = translation("fr") else {
+            return;
+        };
+
+        // The key labeled Q on AZERTY is in the US A position
+        assert_eq!(translation.physical(Key::KEY_Q), Key::KEY_A);
+        assert_eq!(translation.logical(Key::KEY_A), Some(Key::KEY_Q));
+        assert_eq!(translation.physical(Key::KEY_M), Key::KEY_SEMICOLON);
+
+        // Keys without a character are unchanged
+        assert_eq!(translation.logical(Key::KEY_F5), Some(Key::KEY_F5));
+        assert_eq!(translation.logical(Key::KEY_LEFTCTRL), Some(Key::KEY_LEFTCTRL));
+    }
+
+    #[test]
+    fn test_non_us_characters_match_nothing() {
+        let Some(translation) = translation("de") else {
+            return;
+        };
+
+        assert_eq!(translation.logical(Key::KEY_Y), Some(Key::KEY_Z));
+        // ö has no US equivalent
+        assert_eq!(translation.logical(Key::KEY_SEMICOLON), None);
+    }
+
+    #[test]
+    fn test_keycode_mode_has_no_translation() {
+        assert_eq!(KeyTranslation::from_config(&GlobalConfig::default(), 0), None);
+    }
+}
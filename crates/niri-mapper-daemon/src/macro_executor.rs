@@ -2,27 +2,288 @@
 //!
 //! This module provides the [`MacroExecutor`] struct for executing macro action
 //! sequences (key presses, key combos, and delays) through a virtual device.
+//!
+//! # Concurrency
+//!
+//! Each triggered macro runs in its own tokio task. The executor keeps track of
+//! which macros are running (per device and trigger key) and applies the
+//! macro's [`MacroPolicy`] when it is triggered again before it has finished:
+//!
+//! | Policy              | Trigger while running           | Trigger key released |
+//! |---------------------|---------------------------------|----------------------|
+//! | `ignore` (default)  | dropped                         | -                    |
+//! | `restart`           | running copy aborted, restarted | -                    |
+//! | `queue`             | runs again after current run    | -                    |
+//! | `cancel-on-release` | dropped                         | running copy aborted |
+//!
+//! Aborting a macro is safe at any point: the virtual device lock is only held
+//! across synchronous emits, so a task is never cancelled between a key press
+//! and its release.
 
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use anyhow::{bail, Result};
+use anyhow::Result;
 use evdev::Key;
-use niri_mapper_config::MacroAction;
+use niri_mapper_config::{KeyCode, MacroAction, MacroPolicy};
 use tokio::sync::Mutex;
+use tokio::task::AbortHandle;
 
 use crate::injector::VirtualDevice;
-use crate::remapper::parse_key;
+use crate::keysym::KeyTranslation;
+use crate::niri_ipc::NiriClient;
+use crate::remapper::to_key;
+use crate::text_input::TextTyper;
+
+/// Maximum number of pending runs a `queue` macro can accumulate.
+///
+/// Further triggers are dropped until the queue drains.
+const MAX_QUEUED_RUNS: usize = 16;
+
+/// Identifies a macro binding: the device it was triggered on and its trigger key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MacroId {
+    /// Name of the device the macro was triggered on
+    pub device: String,
+    /// Display name of the trigger key (e.g., "F5")
+    pub trigger: String,
+}
+
+impl MacroId {
+    /// Create a macro ID from a device name and trigger key.
+    pub fn new(device: impl Into<String>, trigger: Key) -> Self {
+        Self {
+            device: device.into(),
+            trigger: KeyCode(trigger.code()).to_string(),
+        }
+    }
+}
+
+impl MacroId {
+    /// Create a macro ID for a macro started by name (e.g., over IPC) rather
+    /// than by a trigger key.
+    pub fn named(name: impl Into<String>) -> Self {
+        Self {
+            device: "ipc".to_string(),
+            trigger: name.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for MacroId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.device, self.trigger)
+    }
+}
+
+/// Snapshot of a running macro, used for status reporting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunningMacroInfo {
+    /// The running macro
+    pub id: MacroId,
+    /// Its concurrency policy
+    pub policy: MacroPolicy,
+    /// Time since the current run started
+    pub elapsed: Duration,
+    /// Number of runs waiting behind the current one (`queue` policy only)
+    pub queued: usize,
+}
+
+/// Outcome of asking the registry to start a macro.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Admission {
+    /// Start a new run; the value identifies the run in later registry calls
+    Start(u64),
+    /// The macro is already running and the trigger was dropped
+    Ignored,
+    /// The macro is already running and another run was queued behind it
+    Queued,
+}
+
+/// Registry entry for a running macro.
+#[derive(Debug)]
+struct RunningMacro {
+    /// Distinguishes this run from earlier (aborted) runs of the same macro
+    generation: u64,
+    policy: MacroPolicy,
+    started: Instant,
+    queued: usize,
+    /// Handle to abort the task; attached right after the task is spawned
+    abort: Option<AbortHandle>,
+}
+
+/// Bookkeeping of running macros, shared between the executor and its tasks.
+///
+/// The registry contains no I/O, which keeps the policy logic testable without
+/// a virtual device.
+#[derive(Debug, Default)]
+struct MacroRegistry {
+    running: HashMap<MacroId, RunningMacro>,
+    next_generation: u64,
+}
+
+impl MacroRegistry {
+    /// Decide whether a triggered macro should start, applying its policy.
+    fn admit(&mut self, id: &MacroId, policy: MacroPolicy) -> Admission {
+        if let Some(running) = self.running.get_mut(id) {
+            match policy {
+                MacroPolicy::Ignore | MacroPolicy::CancelOnRelease => return Admission::Ignored,
+                MacroPolicy::Queue => {
+                    if running.queued >= MAX_QUEUED_RUNS {
+                        tracing::warn!("Macro '{}' queue is full, dropping trigger", id);
+                        return Admission::Ignored;
+                    }
+                    running.queued += 1;
+                    return Admission::Queued;
+                }
+                MacroPolicy::Restart => {
+                    if let Some(handle) = running.abort.take() {
+                        handle.abort();
+                    }
+                    self.running.remove(id);
+                }
+            }
+        }
+
+        self.next_generation += 1;
+        let generation = self.next_generation;
+        self.running.insert(
+            id.clone(),
+            RunningMacro {
+                generation,
+                policy,
+                started: Instant::now(),
+                queued: 0,
+                abort: None,
+            },
+        );
+        Admission::Start(generation)
+    }
+
+    /// Attach the abort handle of the task that was spawned for a run.
+    fn attach(&mut self, id: &MacroId, generation: u64, handle: AbortHandle) {
+        match self.running.get_mut(id) {
+            Some(running) if running.generation == generation => running.abort = Some(handle),
+            // The run already finished or was replaced
+            _ => {}
+        }
+    }
+
+    /// Record that a run has finished.
+    ///
+    /// Returns `true` if a queued run should start immediately.
+    fn finish_run(&mut self, id: &MacroId, generation: u64) -> bool {
+        match self.running.get_mut(id) {
+            Some(running) if running.generation == generation => {
+                if running.queued > 0 {
+                    running.queued -= 1;
+                    running.started = Instant::now();
+                    true
+                } else {
+                    self.running.remove(id);
+                    false
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Handle the release of a macro's trigger key.
+    ///
+    /// Returns `true` if a `cancel-on-release` macro was aborted.
+    fn release(&mut self, id: &MacroId) -> bool {
+        let cancel = self
+            .running
+            .get(id)
+            .is_some_and(|running| running.policy == MacroPolicy::CancelOnRelease);
+        if !cancel {
+            return false;
+        }
+
+        if let Some(running) = self.running.remove(id) {
+            if let Some(handle) = running.abort {
+                handle.abort();
+            }
+        }
+        true
+    }
+
+    /// Abort every running macro and drop all queued runs.
+    ///
+    /// Returns the number of macros that were aborted.
+    fn abort_all(&mut self) -> usize {
+        let count = self.running.len();
+        for (_, running) in self.running.drain() {
+            if let Some(handle) = running.abort {
+                handle.abort();
+            }
+        }
+        count
+    }
+
+    /// List the running macros, sorted by device and trigger.
+    fn snapshot(&self) -> Vec<RunningMacroInfo> {
+        let mut infos: Vec<_> = self
+            .running
+            .iter()
+            .map(|(id, running)| RunningMacroInfo {
+                id: id.clone(),
+                policy: running.policy,
+                elapsed: running.started.elapsed(),
+                queued: running.queued,
+            })
+            .collect();
+        infos.sort_by(|a, b| (&a.id.device, &a.id.trigger).cmp(&(&b.id.device, &b.id.trigger)));
+        infos
+    }
+}
+
+/// Ends a macro playback on the virtual device, even if the macro is aborted.
+struct PlaybackGuard {
+    virtual_device: Option<Arc<Mutex<VirtualDevice>>>,
+}
+
+impl PlaybackGuard {
+    /// End the playback after the macro has run to completion.
+    async fn finish(&mut self) -> Result<()> {
+        let Some(virtual_device) = self.virtual_device.clone() else {
+            return Ok(());
+        };
+
+        // Only disarm once the lock is held, so an abort while waiting for
+        // the lock still ends the playback from drop()
+        let mut vd = virtual_device.lock().await;
+        self.virtual_device = None;
+        vd.end_macro_playback()
+    }
+}
+
+impl Drop for PlaybackGuard {
+    fn drop(&mut self) {
+        // The macro was aborted or failed; the device may be locked elsewhere,
+        // so end the playback from a separate task
+        if let Some(virtual_device) = self.virtual_device.take() {
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                handle.spawn(async move {
+                    if let Err(e) = virtual_device.lock().await.end_macro_playback() {
+                        tracing::error!("Failed to end macro playback: {}", e);
+                    }
+                });
+            }
+        }
+    }
+}
 
 /// Executes macro action sequences through a virtual device.
 ///
-/// The `MacroExecutor` is a stateless executor that takes a shared reference
-/// to a [`VirtualDevice`] and can execute sequences of key actions with delays.
-/// For v0.3.0, it does not track in-progress macros; concurrent macro execution
-/// is allowed.
+/// The `MacroExecutor` takes a shared reference to a [`VirtualDevice`] and runs
+/// sequences of key actions with delays. Macros started with
+/// [`trigger()`](Self::trigger) run in background tasks and are tracked so that
+/// their [`MacroPolicy`] can be applied and they can be aborted.
 ///
-/// `MacroExecutor` is `Clone` and cheap to clone since it only holds an `Arc`.
-/// This allows it to be easily passed into spawned async tasks.
+/// `MacroExecutor` is `Clone` and cheap to clone since it only holds `Arc`s.
+/// All clones share the same set of running macros.
 ///
 /// # Example
 ///
@@ -33,11 +294,14 @@ use crate::remapper::parse_key;
 /// let virtual_device = Arc::new(Mutex::new(VirtualDevice::new_keyboard("test")?));
 /// let executor = MacroExecutor::new(virtual_device);
 ///
-/// // executor.execute_macro(&actions).await?;  // Implemented in task 030-1.1.2
+/// executor.trigger(MacroId::new("My Keyboard", Key::KEY_F5), actions, MacroPolicy::Restart);
 /// ```
 #[derive(Clone)]
 pub struct MacroExecutor {
     virtual_device: Arc<Mutex<VirtualDevice>>,
+    registry: Arc<std::sync::Mutex<MacroRegistry>>,
+    text_typer: Arc<std::sync::RwLock<TextTyper>>,
+    key_translation: Arc<std::sync::RwLock<Option<KeyTranslation>>>,
 }
 
 impl MacroExecutor {
@@ -49,9 +313,94 @@ impl MacroExecutor {
     ///
     /// # Returns
     ///
-    /// A new `MacroExecutor` instance.
+    /// A new `MacroExecutor` instance. Text actions use the built-in US layout
+    /// until [`set_text_typer`](Self::set_text_typer) is called.
     pub fn new(virtual_device: Arc<Mutex<VirtualDevice>>) -> Self {
-        Self { virtual_device }
+        Self {
+            virtual_device,
+            registry: Arc::new(std::sync::Mutex::new(MacroRegistry::default())),
+            text_typer: Arc::new(std::sync::RwLock::new(TextTyper::default())),
+            key_translation: Arc::new(std::sync::RwLock::new(None)),
+        }
+    }
+
+    /// Replace the keymap and Unicode input method used for text actions.
+    pub fn set_text_typer(&self, text_typer: TextTyper) {
+        *self.text_typer.write().unwrap() = text_typer;
+    }
+
+    /// Set the keysym translation applied to key actions (`None` in keycode mode).
+    pub fn set_key_translation(&self, key_translation: Option<KeyTranslation>) {
+        *self.key_translation.write().unwrap() = key_translation;
+    }
+
+    /// Start a macro in a background task, applying its concurrency policy.
+    ///
+    /// If the same macro (same device and trigger key) is still running, the
+    /// policy decides whether the trigger is dropped, queued, or restarts it.
+    pub fn trigger(&self, id: MacroId, actions: Vec<MacroAction>, policy: MacroPolicy) {
+        let mut registry = self.registry.lock().unwrap();
+
+        let generation = match registry.admit(&id, policy) {
+            Admission::Start(generation) => generation,
+            Admission::Ignored => {
+                tracing::debug!("Macro '{}' is already running, ignoring trigger ({})", id, policy);
+                return;
+            }
+            Admission::Queued => {
+                tracing::debug!("Macro '{}' is already running, queued another run", id);
+                return;
+            }
+        };
+
+        tracing::debug!(
+            "Starting macro '{}' with {} actions (policy: {})",
+            id,
+            actions.len(),
+            policy
+        );
+
+        let executor = self.clone();
+        let task_id = id.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                if let Err(e) = executor.execute_macro(&actions).await {
+                    tracing::error!("Macro execution failed for '{}': {}", task_id, e);
+                }
+                if !executor.registry.lock().unwrap().finish_run(&task_id, generation) {
+                    break;
+                }
+            }
+        });
+
+        // The registry is still locked, so the task cannot finish before its
+        // handle is attached
+        registry.attach(&id, generation, handle.abort_handle());
+    }
+
+    /// Handle the release of a macro's trigger key.
+    ///
+    /// Aborts the macro if it is running with the `cancel-on-release` policy.
+    pub fn release(&self, id: &MacroId) {
+        if self.registry.lock().unwrap().release(id) {
+            tracing::debug!("Cancelled macro '{}' on trigger release", id);
+        }
+    }
+
+    /// Abort every running macro and drop all queued runs.
+    ///
+    /// Returns the number of macros that were aborted.
+    pub fn abort_all(&self) -> usize {
+        let count = self.registry.lock().unwrap().abort_all();
+        if count > 0 {
+            tracing::info!("Aborted {} running macro(s)", count);
+        }
+        count
+    }
+
+    /// List the currently running macros.
+    pub fn running(&self) -> Vec<RunningMacroInfo> {
+        self.registry.lock().unwrap().snapshot()
     }
 
     /// Execute a macro action sequence.
@@ -59,7 +408,13 @@ impl MacroExecutor {
     /// This method iterates through the provided actions and executes them
     /// sequentially. For key actions, it parses the key string (which may
     /// include modifiers like "Ctrl+C") and emits the appropriate key events.
-    /// For delay actions, it sleeps for the specified duration.
+    /// For delay actions, it sleeps for the specified duration. Text actions
+    /// are typed using the current keyboard layout.
+    ///
+    /// Playback is bracketed by [`VirtualDevice::begin_macro_playback`] and
+    /// [`VirtualDevice::end_macro_playback`], which apply the configured macro
+    /// isolation to physical input. The end is also signalled if the macro
+    /// fails or its task is aborted.
     ///
     /// # Arguments
     ///
@@ -82,40 +437,74 @@ impl MacroExecutor {
     /// use niri_mapper_config::MacroAction;
     ///
     /// let actions = vec![
-    ///     MacroAction::Key("Ctrl+C".to_string()),
+    ///     MacroAction::Key("Ctrl+C".parse().unwrap()),
     ///     MacroAction::Delay(100),
-    ///     MacroAction::Key("Ctrl+V".to_string()),
+    ///     MacroAction::Key("Ctrl+V".parse().unwrap()),
     /// ];
     ///
     /// executor.execute_macro(&actions).await?;
     /// ```
     pub async fn execute_macro(&self, actions: &[MacroAction]) -> Result<()> {
+        self.virtual_device.lock().await.begin_macro_playback()?;
+        let mut playback = PlaybackGuard {
+            virtual_device: Some(self.virtual_device.clone()),
+        };
+
+        let result = self.execute_actions(actions).await;
+        playback.finish().await?;
+        result
+    }
+
+    /// Execute the actions of a macro without playback bookkeeping.
+    async fn execute_actions(&self, actions: &[MacroAction]) -> Result<()> {
         for action in actions {
             match action {
-                MacroAction::Key(key_string) => {
-                    self.execute_key(key_string).await?;
+                MacroAction::Key(combo) => {
+                    self.execute_key(combo).await?;
                 }
                 MacroAction::Delay(ms) => {
                     tokio::time::sleep(Duration::from_millis(*ms)).await;
                 }
+                MacroAction::Text(text) => {
+                    self.execute_text(text).await?;
+                }
+                MacroAction::SwitchLayout(layout) => {
+                    NiriClient::connect().await?.switch_layout(*layout).await?;
+                }
             }
         }
         Ok(())
     }
 
+    /// Type a string of text, one key stroke at a time.
+    async fn execute_text(&self, text: &str) -> Result<()> {
+        let strokes = self.text_typer.read().unwrap().strokes(text);
+        for stroke in strokes {
+            self.emit_stroke(&stroke.modifiers, stroke.key).await?;
+        }
+        Ok(())
+    }
+
     /// Execute a single key action (may include modifiers).
     ///
-    /// Parses the key string and emits appropriate press/release events.
     /// For simple keys, emits press + release.
     /// For combos (e.g., "Ctrl+C"), emits all modifier presses, key press,
     /// key release, then all modifier releases.
-    async fn execute_key(&self, key_string: &str) -> Result<()> {
-        let (modifiers, key) = self.parse_key_combo(key_string)?;
+    async fn execute_key(&self, combo: &niri_mapper_config::KeyCombo) -> Result<()> {
+        let (modifiers, key) = combo_keys(combo);
+        let key = match self.key_translation.read().unwrap().as_ref() {
+            Some(translation) => translation.physical(key),
+            None => key,
+        };
+        self.emit_stroke(&modifiers, key).await
+    }
 
+    /// Tap a key while holding modifiers.
+    async fn emit_stroke(&self, modifiers: &[Key], key: Key) -> Result<()> {
         let mut vd = self.virtual_device.lock().await;
 
         // Press all modifiers
-        for modifier in &modifiers {
+        for modifier in modifiers {
             vd.press_key(*modifier)?;
         }
 
@@ -129,158 +518,81 @@ impl MacroExecutor {
 
         Ok(())
     }
+}
 
-    /// Parse a key string that may include modifiers.
-    ///
-    /// Splits the key string on `+` to extract modifiers and the trigger key.
-    /// Returns a tuple of (modifier_keys, main_key).
-    ///
-    /// # Supported Modifiers
-    ///
-    /// - `Ctrl` / `Control` -> `KEY_LEFTCTRL`
-    /// - `Shift` -> `KEY_LEFTSHIFT`
-    /// - `Alt` -> `KEY_LEFTALT`
-    /// - `Super` / `Meta` / `Win` -> `KEY_LEFTMETA`
-    ///
-    /// # Examples
-    ///
-    /// - `"A"` -> `([], Key::KEY_A)`
-    /// - `"Ctrl+C"` -> `([KEY_LEFTCTRL], Key::KEY_C)`
-    /// - `"Ctrl+Shift+V"` -> `([KEY_LEFTCTRL, KEY_LEFTSHIFT], Key::KEY_V)`
-    /// - `"Super+1"` -> `([KEY_LEFTMETA], Key::KEY_1)`
-    ///
-    /// When executed, modifiers are pressed in order, the main key is tapped
-    /// (press + release), then modifiers are released in reverse order.
-    fn parse_key_combo(&self, key_string: &str) -> Result<(Vec<Key>, Key)> {
-        let parts: Vec<&str> = key_string.split('+').collect();
-
-        if parts.is_empty() {
-            bail!("Empty key string");
-        }
-
-        let mut modifiers = Vec::new();
-        let mut main_key: Option<Key> = None;
-
-        for (i, part) in parts.iter().enumerate() {
-            let part = part.trim();
-            let is_last = i == parts.len() - 1;
-
-            // Check if this is a modifier
-            let upper = part.to_uppercase();
-            let modifier_key = match upper.as_str() {
-                "CTRL" | "CONTROL" => Some(Key::KEY_LEFTCTRL),
-                "SHIFT" => Some(Key::KEY_LEFTSHIFT),
-                "ALT" => Some(Key::KEY_LEFTALT),
-                "SUPER" | "META" | "WIN" => Some(Key::KEY_LEFTMETA),
-                _ => None,
-            };
-
-            if let Some(mod_key) = modifier_key {
-                if is_last {
-                    // Modifier is the main key (e.g., just "Ctrl")
-                    main_key = Some(mod_key);
-                } else {
-                    modifiers.push(mod_key);
-                }
-            } else {
-                // Not a modifier, must be the main key
-                match parse_key(part) {
-                    Some(key) => main_key = Some(key),
-                    None => bail!("Unknown key: '{}'", part),
-                }
-            }
-        }
-
-        match main_key {
-            Some(key) => Ok((modifiers, key)),
-            None => bail!("No main key found in key string: '{}'", key_string),
-        }
-    }
+/// The modifier keys and the main key of a key combo.
+///
+/// Modifiers map to their left keys (`Ctrl` -> `KEY_LEFTCTRL`) and are pressed
+/// in the order Ctrl, Shift, Alt, Super, then released in reverse order.
+fn combo_keys(combo: &niri_mapper_config::KeyCombo) -> (Vec<Key>, Key) {
+    let modifiers = combo
+        .modifiers
+        .iter()
+        .map(|modifier| to_key(modifier.key()))
+        .collect();
+    (modifiers, to_key(combo.key))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    /// Helper to create a MacroExecutor for testing parse_key_combo.
-    /// Returns None if /dev/uinput is not available (e.g., in Nix sandbox).
-    fn create_test_executor() -> Option<MacroExecutor> {
-        // We need a virtual device for the executor, but we won't use it for parsing tests
-        // Create a minimal mock by using an Arc<Mutex<VirtualDevice>>
-        // Note: This test only tests parse_key_combo, not actual key injection
-        match VirtualDevice::new_keyboard("test-macro-executor") {
-            Ok(vd) => Some(MacroExecutor::new(Arc::new(Mutex::new(vd)))),
-            Err(_) => None, // /dev/uinput not available (sandbox, no permissions, etc.)
-        }
-    }
-
-    /// Helper macro to skip tests when virtual device creation fails
-    macro_rules! require_executor {
-        () => {
-            match create_test_executor() {
-                Some(e) => e,
-                None => {
-                    eprintln!("Skipping test: /dev/uinput not available");
-                    return;
-                }
-            }
-        };
+    /// Parse a macro key action into its modifier keys and main key
+    fn parse_key_combo(key_string: &str) -> Result<(Vec<Key>, Key)> {
+        let combo = key_string.parse().map_err(anyhow::Error::msg)?;
+        Ok(combo_keys(&combo))
     }
 
     #[test]
     fn test_simple_key_no_modifiers() {
-        let executor = require_executor!();
-
         // Simple letter key
-        let (modifiers, key) = executor.parse_key_combo("A").unwrap();
+        let (modifiers, key) = parse_key_combo("A").unwrap();
         assert!(modifiers.is_empty());
         assert_eq!(key, Key::KEY_A);
 
         // Another letter
-        let (modifiers, key) = executor.parse_key_combo("Z").unwrap();
+        let (modifiers, key) = parse_key_combo("Z").unwrap();
         assert!(modifiers.is_empty());
         assert_eq!(key, Key::KEY_Z);
 
         // Number key
-        let (modifiers, key) = executor.parse_key_combo("1").unwrap();
+        let (modifiers, key) = parse_key_combo("1").unwrap();
         assert!(modifiers.is_empty());
         assert_eq!(key, Key::KEY_1);
 
         // Function key
-        let (modifiers, key) = executor.parse_key_combo("F1").unwrap();
+        let (modifiers, key) = parse_key_combo("F1").unwrap();
         assert!(modifiers.is_empty());
         assert_eq!(key, Key::KEY_F1);
 
         // Escape
-        let (modifiers, key) = executor.parse_key_combo("Escape").unwrap();
+        let (modifiers, key) = parse_key_combo("Escape").unwrap();
         assert!(modifiers.is_empty());
         assert_eq!(key, Key::KEY_ESC);
     }
 
     #[test]
     fn test_single_modifier_combo() {
-        let executor = require_executor!();
-
         // Ctrl+C
-        let (modifiers, key) = executor.parse_key_combo("Ctrl+C").unwrap();
+        let (modifiers, key) = parse_key_combo("Ctrl+C").unwrap();
         assert_eq!(modifiers.len(), 1);
         assert_eq!(modifiers[0], Key::KEY_LEFTCTRL);
         assert_eq!(key, Key::KEY_C);
 
         // Shift+A
-        let (modifiers, key) = executor.parse_key_combo("Shift+A").unwrap();
+        let (modifiers, key) = parse_key_combo("Shift+A").unwrap();
         assert_eq!(modifiers.len(), 1);
         assert_eq!(modifiers[0], Key::KEY_LEFTSHIFT);
         assert_eq!(key, Key::KEY_A);
 
         // Alt+Tab
-        let (modifiers, key) = executor.parse_key_combo("Alt+Tab").unwrap();
+        let (modifiers, key) = parse_key_combo("Alt+Tab").unwrap();
         assert_eq!(modifiers.len(), 1);
         assert_eq!(modifiers[0], Key::KEY_LEFTALT);
         assert_eq!(key, Key::KEY_TAB);
 
         // Super+1
-        let (modifiers, key) = executor.parse_key_combo("Super+1").unwrap();
+        let (modifiers, key) = parse_key_combo("Super+1").unwrap();
         assert_eq!(modifiers.len(), 1);
         assert_eq!(modifiers[0], Key::KEY_LEFTMETA);
         assert_eq!(key, Key::KEY_1);
@@ -288,118 +600,104 @@ mod tests {
 
     #[test]
     fn test_multiple_modifiers_combo() {
-        let executor = require_executor!();
-
         // Ctrl+Shift+V (the example from the task requirements)
-        let (modifiers, key) = executor.parse_key_combo("Ctrl+Shift+V").unwrap();
+        let (modifiers, key) = parse_key_combo("Ctrl+Shift+V").unwrap();
         assert_eq!(modifiers.len(), 2);
         assert_eq!(modifiers[0], Key::KEY_LEFTCTRL);
         assert_eq!(modifiers[1], Key::KEY_LEFTSHIFT);
         assert_eq!(key, Key::KEY_V);
 
         // Ctrl+Alt+Delete
-        let (modifiers, key) = executor.parse_key_combo("Ctrl+Alt+Delete").unwrap();
+        let (modifiers, key) = parse_key_combo("Ctrl+Alt+Delete").unwrap();
         assert_eq!(modifiers.len(), 2);
         assert_eq!(modifiers[0], Key::KEY_LEFTCTRL);
         assert_eq!(modifiers[1], Key::KEY_LEFTALT);
         assert_eq!(key, Key::KEY_DELETE);
 
-        // Super+Shift+S (screenshot shortcut)
-        let (modifiers, key) = executor.parse_key_combo("Super+Shift+S").unwrap();
+        // Super+Shift+S (screenshot shortcut): modifiers are pressed in a fixed order
+        let (modifiers, key) = parse_key_combo("Super+Shift+S").unwrap();
         assert_eq!(modifiers.len(), 2);
-        assert_eq!(modifiers[0], Key::KEY_LEFTMETA);
-        assert_eq!(modifiers[1], Key::KEY_LEFTSHIFT);
+        assert_eq!(modifiers[0], Key::KEY_LEFTSHIFT);
+        assert_eq!(modifiers[1], Key::KEY_LEFTMETA);
         assert_eq!(key, Key::KEY_S);
     }
 
     #[test]
     fn test_modifier_aliases() {
-        let executor = require_executor!();
-
         // Control is an alias for Ctrl
-        let (modifiers, key) = executor.parse_key_combo("Control+C").unwrap();
+        let (modifiers, key) = parse_key_combo("Control+C").unwrap();
         assert_eq!(modifiers[0], Key::KEY_LEFTCTRL);
         assert_eq!(key, Key::KEY_C);
 
         // Meta is an alias for Super
-        let (modifiers, key) = executor.parse_key_combo("Meta+Space").unwrap();
+        let (modifiers, key) = parse_key_combo("Meta+Space").unwrap();
         assert_eq!(modifiers[0], Key::KEY_LEFTMETA);
         assert_eq!(key, Key::KEY_SPACE);
 
         // Win is an alias for Super
-        let (modifiers, key) = executor.parse_key_combo("Win+E").unwrap();
+        let (modifiers, key) = parse_key_combo("Win+E").unwrap();
         assert_eq!(modifiers[0], Key::KEY_LEFTMETA);
         assert_eq!(key, Key::KEY_E);
     }
 
     #[test]
     fn test_case_insensitivity() {
-        let executor = require_executor!();
-
         // Modifiers should be case-insensitive
-        let (modifiers, _) = executor.parse_key_combo("ctrl+a").unwrap();
+        let (modifiers, _) = parse_key_combo("ctrl+a").unwrap();
         assert_eq!(modifiers[0], Key::KEY_LEFTCTRL);
 
-        let (modifiers, _) = executor.parse_key_combo("CTRL+A").unwrap();
+        let (modifiers, _) = parse_key_combo("CTRL+A").unwrap();
         assert_eq!(modifiers[0], Key::KEY_LEFTCTRL);
 
-        let (modifiers, _) = executor.parse_key_combo("Ctrl+A").unwrap();
+        let (modifiers, _) = parse_key_combo("Ctrl+A").unwrap();
         assert_eq!(modifiers[0], Key::KEY_LEFTCTRL);
     }
 
     #[test]
     fn test_whitespace_handling() {
-        let executor = require_executor!();
-
         // Should handle spaces around +
-        let (modifiers, key) = executor.parse_key_combo("Ctrl + C").unwrap();
+        let (modifiers, key) = parse_key_combo("Ctrl + C").unwrap();
         assert_eq!(modifiers[0], Key::KEY_LEFTCTRL);
         assert_eq!(key, Key::KEY_C);
     }
 
     #[test]
     fn test_modifier_as_main_key() {
-        let executor = require_executor!();
-
         // A modifier alone should work as the main key
-        let (modifiers, key) = executor.parse_key_combo("Ctrl").unwrap();
+        let (modifiers, key) = parse_key_combo("Ctrl").unwrap();
         assert!(modifiers.is_empty());
         assert_eq!(key, Key::KEY_LEFTCTRL);
 
-        let (modifiers, key) = executor.parse_key_combo("Shift").unwrap();
+        let (modifiers, key) = parse_key_combo("Shift").unwrap();
         assert!(modifiers.is_empty());
         assert_eq!(key, Key::KEY_LEFTSHIFT);
     }
 
     #[test]
     fn test_unknown_key_error() {
-        let executor = require_executor!();
-
         // Unknown key should return an error
-        let result = executor.parse_key_combo("UnknownKey");
+        let result = parse_key_combo("UnknownKey");
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("Unknown key"));
 
         // Unknown key in combo
-        let result = executor.parse_key_combo("Ctrl+UnknownKey");
+        let result = parse_key_combo("Ctrl+UnknownKey");
         assert!(result.is_err());
     }
 
     #[test]
     fn test_modifiers_map_to_left_variants() {
-        let executor = require_executor!();
-
         // Verify all modifiers map to their Left variants as specified in requirements
-        let (modifiers, _) = executor.parse_key_combo("Ctrl+A").unwrap();
+        let (modifiers, _) = parse_key_combo("Ctrl+A").unwrap();
         assert_eq!(modifiers[0], Key::KEY_LEFTCTRL);
 
-        let (modifiers, _) = executor.parse_key_combo("Shift+A").unwrap();
+        let (modifiers, _) = parse_key_combo("Shift+A").unwrap();
         assert_eq!(modifiers[0], Key::KEY_LEFTSHIFT);
 
-        let (modifiers, _) = executor.parse_key_combo("Alt+A").unwrap();
+        let (modifiers, _) = parse_key_combo("Alt+A").unwrap();
         assert_eq!(modifiers[0], Key::KEY_LEFTALT);
 
-        let (modifiers, _) = executor.parse_key_combo("Super+A").unwrap();
+        let (modifiers, _) = parse_key_combo("Super+A").unwrap();
         assert_eq!(modifiers[0], Key::KEY_LEFTMETA);
     }
 
@@ -424,15 +722,13 @@ mod tests {
     /// A press -> A release -> B press -> B release -> C press -> C release
     #[test]
     fn test_simple_key_sequence_parsing() {
-        let executor = require_executor!();
-
         // Test that each key in the sequence parses correctly
         // Macro: ["A", "B", "C"]
         let keys = ["A", "B", "C"];
         let expected_keys = [Key::KEY_A, Key::KEY_B, Key::KEY_C];
 
         for (key_str, expected_key) in keys.iter().zip(expected_keys.iter()) {
-            let (modifiers, key) = executor.parse_key_combo(key_str).unwrap();
+            let (modifiers, key) = parse_key_combo(key_str).unwrap();
             assert!(modifiers.is_empty(), "Simple key '{}' should have no modifiers", key_str);
             assert_eq!(key, *expected_key, "Key '{}' should parse to {:?}", key_str, expected_key);
         }
@@ -459,9 +755,9 @@ mod tests {
 
         // Create macro actions for ["A", "B", "C"]
         let actions = vec![
-            MacroAction::Key("A".to_string()),
-            MacroAction::Key("B".to_string()),
-            MacroAction::Key("C".to_string()),
+            MacroAction::Key("A".parse().unwrap()),
+            MacroAction::Key("B".parse().unwrap()),
+            MacroAction::Key("C".parse().unwrap()),
         ];
 
         // Execute the macro - should complete without error
@@ -498,11 +794,9 @@ mod tests {
         // - tap_key(KEY_C) -> press C, release C
 
         // Verify parsing produces the expected keys
-        let executor = require_executor!();
-
-        let (mods_a, key_a) = executor.parse_key_combo("A").unwrap();
-        let (mods_b, key_b) = executor.parse_key_combo("B").unwrap();
-        let (mods_c, key_c) = executor.parse_key_combo("C").unwrap();
+        let (mods_a, key_a) = parse_key_combo("A").unwrap();
+        let (mods_b, key_b) = parse_key_combo("B").unwrap();
+        let (mods_c, key_c) = parse_key_combo("C").unwrap();
 
         // All should be simple keys (no modifiers)
         assert!(mods_a.is_empty());
@@ -550,16 +844,14 @@ mod tests {
     /// to the correct modifiers and main key.
     #[test]
     fn test_combo_key_sequence_parsing() {
-        let executor = require_executor!();
-
         // Test Ctrl+C parsing
-        let (modifiers, key) = executor.parse_key_combo("Ctrl+C").unwrap();
+        let (modifiers, key) = parse_key_combo("Ctrl+C").unwrap();
         assert_eq!(modifiers.len(), 1, "Ctrl+C should have 1 modifier");
         assert_eq!(modifiers[0], Key::KEY_LEFTCTRL, "Modifier should be LEFTCTRL");
         assert_eq!(key, Key::KEY_C, "Main key should be C");
 
         // Test Ctrl+V parsing
-        let (modifiers, key) = executor.parse_key_combo("Ctrl+V").unwrap();
+        let (modifiers, key) = parse_key_combo("Ctrl+V").unwrap();
         assert_eq!(modifiers.len(), 1, "Ctrl+V should have 1 modifier");
         assert_eq!(modifiers[0], Key::KEY_LEFTCTRL, "Modifier should be LEFTCTRL");
         assert_eq!(key, Key::KEY_V, "Main key should be V");
@@ -586,9 +878,9 @@ mod tests {
 
         // Create macro actions for ["Ctrl+C", "delay(50)", "Ctrl+V"]
         let actions = vec![
-            MacroAction::Key("Ctrl+C".to_string()),
+            MacroAction::Key("Ctrl+C".parse().unwrap()),
             MacroAction::Delay(50),
-            MacroAction::Key("Ctrl+V".to_string()),
+            MacroAction::Key("Ctrl+V".parse().unwrap()),
         ];
 
         // Execute the macro - should complete without error
@@ -618,16 +910,14 @@ mod tests {
         // 3. Tap main key: tap_key(KEY_C) -> [CPress, SYN, CRelease, SYN]
         // 4. Release all modifiers in reverse: release_key(KEY_LEFTCTRL) -> [CtrlRelease, SYN]
 
-        let executor = require_executor!();
-
         // Parse Ctrl+C
-        let (mods_c, key_c) = executor.parse_key_combo("Ctrl+C").unwrap();
+        let (mods_c, key_c) = parse_key_combo("Ctrl+C").unwrap();
         assert_eq!(mods_c.len(), 1);
         assert_eq!(mods_c[0], Key::KEY_LEFTCTRL);
         assert_eq!(key_c, Key::KEY_C);
 
         // Parse Ctrl+V
-        let (mods_v, key_v) = executor.parse_key_combo("Ctrl+V").unwrap();
+        let (mods_v, key_v) = parse_key_combo("Ctrl+V").unwrap();
         assert_eq!(mods_v.len(), 1);
         assert_eq!(mods_v[0], Key::KEY_LEFTCTRL);
         assert_eq!(key_v, Key::KEY_V);
@@ -671,10 +961,8 @@ mod tests {
     /// correctly press modifiers in order and release them in reverse order.
     #[test]
     fn test_multi_modifier_combo_event_sequence() {
-        let executor = require_executor!();
-
         // Parse Ctrl+Shift+V
-        let (modifiers, key) = executor.parse_key_combo("Ctrl+Shift+V").unwrap();
+        let (modifiers, key) = parse_key_combo("Ctrl+Shift+V").unwrap();
         assert_eq!(modifiers.len(), 2, "Ctrl+Shift+V should have 2 modifiers");
         assert_eq!(modifiers[0], Key::KEY_LEFTCTRL, "First modifier should be LEFTCTRL");
         assert_eq!(modifiers[1], Key::KEY_LEFTSHIFT, "Second modifier should be LEFTSHIFT");
@@ -711,4 +999,140 @@ mod tests {
             "LEFTCTRL (pressed first) should be released last"
         );
     }
+
+    // ========================================================================
+    // Macro Registry Tests
+    // ========================================================================
+
+    fn test_id(trigger: Key) -> MacroId {
+        MacroId::new("Test Keyboard", trigger)
+    }
+
+    #[test]
+    fn test_registry_starts_idle_macro() {
+        let mut registry = MacroRegistry::default();
+        let id = test_id(Key::KEY_F5);
+
+        assert!(matches!(registry.admit(&id, MacroPolicy::Ignore), Admission::Start(_)));
+        assert_eq!(registry.snapshot().len(), 1);
+        assert_eq!(registry.snapshot()[0].id.trigger, "F5");
+    }
+
+    #[test]
+    fn test_registry_ignore_policy_drops_retrigger() {
+        let mut registry = MacroRegistry::default();
+        let id = test_id(Key::KEY_F5);
+
+        let Admission::Start(generation) = registry.admit(&id, MacroPolicy::Ignore) else {
+            panic!("First trigger should start");
+        };
+        assert_eq!(registry.admit(&id, MacroPolicy::Ignore), Admission::Ignored);
+
+        // Finishing the run frees the macro for the next trigger
+        assert!(!registry.finish_run(&id, generation));
+        assert!(registry.snapshot().is_empty());
+        assert!(matches!(registry.admit(&id, MacroPolicy::Ignore), Admission::Start(_)));
+    }
+
+    #[test]
+    fn test_registry_restart_policy_replaces_run() {
+        let mut registry = MacroRegistry::default();
+        let id = test_id(Key::KEY_F5);
+
+        let Admission::Start(first) = registry.admit(&id, MacroPolicy::Restart) else {
+            panic!("First trigger should start");
+        };
+        let Admission::Start(second) = registry.admit(&id, MacroPolicy::Restart) else {
+            panic!("Restart should start a new run");
+        };
+        assert_ne!(first, second);
+
+        // The aborted run must not remove the new run when it winds down
+        assert!(!registry.finish_run(&id, first));
+        assert_eq!(registry.snapshot().len(), 1);
+        assert!(!registry.finish_run(&id, second));
+        assert!(registry.snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_registry_queue_policy_runs_again() {
+        let mut registry = MacroRegistry::default();
+        let id = test_id(Key::KEY_F5);
+
+        let Admission::Start(generation) = registry.admit(&id, MacroPolicy::Queue) else {
+            panic!("First trigger should start");
+        };
+        assert_eq!(registry.admit(&id, MacroPolicy::Queue), Admission::Queued);
+        assert_eq!(registry.admit(&id, MacroPolicy::Queue), Admission::Queued);
+        assert_eq!(registry.snapshot()[0].queued, 2);
+
+        assert!(registry.finish_run(&id, generation));
+        assert!(registry.finish_run(&id, generation));
+        assert!(!registry.finish_run(&id, generation));
+        assert!(registry.snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_registry_queue_is_bounded() {
+        let mut registry = MacroRegistry::default();
+        let id = test_id(Key::KEY_F5);
+
+        registry.admit(&id, MacroPolicy::Queue);
+        for _ in 0..MAX_QUEUED_RUNS {
+            assert_eq!(registry.admit(&id, MacroPolicy::Queue), Admission::Queued);
+        }
+        assert_eq!(registry.admit(&id, MacroPolicy::Queue), Admission::Ignored);
+        assert_eq!(registry.snapshot()[0].queued, MAX_QUEUED_RUNS);
+    }
+
+    #[test]
+    fn test_registry_cancel_on_release() {
+        let mut registry = MacroRegistry::default();
+        let cancel = test_id(Key::KEY_F5);
+        let ignore = test_id(Key::KEY_F6);
+
+        registry.admit(&cancel, MacroPolicy::CancelOnRelease);
+        registry.admit(&ignore, MacroPolicy::Ignore);
+
+        // Only the cancel-on-release macro stops when its trigger is released
+        assert!(!registry.release(&ignore));
+        assert!(registry.release(&cancel));
+        assert_eq!(registry.snapshot().len(), 1);
+        assert_eq!(registry.snapshot()[0].id, ignore);
+    }
+
+    #[test]
+    fn test_registry_abort_all() {
+        let mut registry = MacroRegistry::default();
+        let first = test_id(Key::KEY_F5);
+        let second = MacroId::new("Other Keyboard", Key::KEY_F5);
+
+        let Admission::Start(generation) = registry.admit(&first, MacroPolicy::Queue) else {
+            panic!("First trigger should start");
+        };
+        registry.admit(&first, MacroPolicy::Queue);
+        registry.admit(&second, MacroPolicy::Ignore);
+
+        assert_eq!(registry.abort_all(), 2);
+        assert!(registry.snapshot().is_empty());
+
+        // Queued runs are dropped as well
+        assert!(!registry.finish_run(&first, generation));
+        assert_eq!(registry.abort_all(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_registry_abort_handle_stops_task() {
+        let mut registry = MacroRegistry::default();
+        let id = test_id(Key::KEY_F5);
+
+        let Admission::Start(generation) = registry.admit(&id, MacroPolicy::Restart) else {
+            panic!("First trigger should start");
+        };
+        let task = tokio::spawn(std::future::pending::<()>());
+        registry.attach(&id, generation, task.abort_handle());
+
+        registry.admit(&id, MacroPolicy::Restart);
+        assert!(task.await.unwrap_err().is_cancelled());
+    }
 }
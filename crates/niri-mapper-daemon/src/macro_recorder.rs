@@ -0,0 +1,260 @@
+//! Macro recording from the processed key stream
+//!
+//! This module provides the [`MacroRecorder`] struct, which turns the key events
+//! a device produces after remapping into a [`MacroAction`] sequence that can be
+//! played back by the [`MacroExecutor`](crate::macro_executor::MacroExecutor).
+//!
+//! # Recording Model
+//!
+//! Macro actions are key taps with optional modifiers, so the recorder emits:
+//!
+//! - One `Key` action per non-modifier key press, prefixed with the modifiers
+//!   held at that moment (e.g., `Ctrl+C`)
+//! - One `Key` action for a modifier that is pressed and released on its own
+//! - A `Delay` action for the time between two actions, rounded to the
+//!   configured quantization step
+//!
+//! Key repeats are ignored, since holding a key cannot be expressed as a macro
+//! action.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use evdev::{InputEvent, InputEventKind, Key};
+use niri_mapper_config::{KeyCode, KeyCombo, MacroAction, MAX_MACRO_DELAY_MS};
+
+use crate::remapper::{modifier_of, Modifier};
+
+/// Records processed key events as a macro action sequence.
+#[derive(Debug)]
+pub struct MacroRecorder {
+    /// Delays are rounded to a multiple of this many milliseconds (0 = exact)
+    quantize_ms: u64,
+    actions: Vec<MacroAction>,
+    /// Modifiers currently held down
+    held_modifiers: BTreeSet<Modifier>,
+    /// Modifier pressed on its own, recorded as a tap if released before any other key
+    lone_modifier: Option<(Key, Instant)>,
+    /// When the last action was recorded
+    last_action_at: Option<Instant>,
+}
+
+impl MacroRecorder {
+    /// Create a recorder that rounds delays to `quantize_ms` milliseconds.
+    pub fn new(quantize_ms: u64) -> Self {
+        Self {
+            quantize_ms,
+            actions: Vec::new(),
+            held_modifiers: BTreeSet::new(),
+            lone_modifier: None,
+            last_action_at: None,
+        }
+    }
+
+    /// Record a batch of processed events that were emitted at `at`.
+    pub fn record(&mut self, events: &[InputEvent], at: Instant) {
+        for event in events {
+            let InputEventKind::Key(key) = event.kind() else {
+                continue;
+            };
+
+            match (modifier_of(key), event.value()) {
+                (Some(modifier), 1) => {
+                    self.held_modifiers.insert(modifier);
+                    self.lone_modifier = Some((key, at));
+                }
+                (Some(modifier), 0) => {
+                    if let Some((lone, pressed_at)) = self.lone_modifier.take() {
+                        if lone == key {
+                            self.held_modifiers.remove(&modifier);
+                            self.push_key(KeyCombo::new(KeyCode(key.code())), pressed_at);
+                            continue;
+                        }
+                    }
+                    self.held_modifiers.remove(&modifier);
+                }
+                (None, 1) => {
+                    self.lone_modifier = None;
+                    let combo = KeyCombo {
+                        modifiers: self.held_modifiers.clone(),
+                        key: KeyCode(key.code()),
+                    };
+                    self.push_key(combo, at);
+                }
+                // Releases of regular keys and repeats carry no action
+                _ => {}
+            }
+        }
+    }
+
+    /// Finish the recording and return the recorded actions.
+    pub fn finish(self) -> Vec<MacroAction> {
+        self.actions
+    }
+
+    /// Append a key action, preceded by the delay since the previous action.
+    fn push_key(&mut self, combo: KeyCombo, at: Instant) {
+        if let Some(last) = self.last_action_at {
+            let mut delay = self.quantize(at.saturating_duration_since(last).as_millis() as u64);
+            while delay > 0 {
+                let step = delay.min(MAX_MACRO_DELAY_MS);
+                self.actions.push(MacroAction::Delay(step));
+                delay -= step;
+            }
+        }
+        self.actions.push(MacroAction::Key(combo));
+        self.last_action_at = Some(at);
+    }
+
+    /// Round a delay to the nearest multiple of the quantization step.
+    fn quantize(&self, ms: u64) -> u64 {
+        if self.quantize_ms == 0 {
+            return ms;
+        }
+        (ms + self.quantize_ms / 2) / self.quantize_ms * self.quantize_ms
+    }
+}
+
+/// A recording in progress on one device.
+#[derive(Debug)]
+pub struct RecordingSession {
+    /// Name the recording is stored under
+    pub name: String,
+    /// Path of the device being recorded
+    pub device_path: PathBuf,
+    /// Key that stops the recording
+    pub stop_key: Key,
+    /// Where to write the macro in the config file, if requested
+    pub save: Option<SaveTarget>,
+    /// The recorder collecting the device's processed events
+    pub recorder: MacroRecorder,
+}
+
+/// Location in the config file that a finished recording is written to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveTarget {
+    /// Profile whose `macro` block receives the recording
+    pub profile: String,
+    /// Trigger key the recording is bound to
+    pub trigger: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn key_event(key: Key, value: i32) -> InputEvent {
+        InputEvent::new(evdev::EventType::KEY, key.code(), value)
+    }
+
+    fn keys(actions: &[MacroAction]) -> Vec<String> {
+        actions.iter().map(|action| action.to_string()).collect()
+    }
+
+    #[test]
+    fn test_record_simple_taps_with_delays() {
+        let start = Instant::now();
+        let mut recorder = MacroRecorder::new(0);
+
+        recorder.record(&[key_event(Key::KEY_A, 1)], start);
+        recorder.record(&[key_event(Key::KEY_A, 0)], start + Duration::from_millis(30));
+        recorder.record(&[key_event(Key::KEY_B, 1)], start + Duration::from_millis(120));
+        recorder.record(&[key_event(Key::KEY_B, 0)], start + Duration::from_millis(150));
+
+        assert_eq!(keys(&recorder.finish()), vec!["A", "delay(120)", "B"]);
+    }
+
+    #[test]
+    fn test_record_combo_with_held_modifiers() {
+        let start = Instant::now();
+        let mut recorder = MacroRecorder::new(0);
+
+        recorder.record(
+            &[
+                key_event(Key::KEY_LEFTCTRL, 1),
+                key_event(Key::KEY_RIGHTSHIFT, 1),
+                key_event(Key::KEY_V, 1),
+                key_event(Key::KEY_V, 0),
+                key_event(Key::KEY_RIGHTSHIFT, 0),
+                key_event(Key::KEY_LEFTCTRL, 0),
+            ],
+            start,
+        );
+
+        assert_eq!(keys(&recorder.finish()), vec!["Ctrl+Shift+V"]);
+    }
+
+    #[test]
+    fn test_record_lone_modifier_tap() {
+        let start = Instant::now();
+        let mut recorder = MacroRecorder::new(0);
+
+        recorder.record(&[key_event(Key::KEY_LEFTMETA, 1)], start);
+        recorder.record(&[key_event(Key::KEY_LEFTMETA, 0)], start + Duration::from_millis(40));
+
+        assert_eq!(keys(&recorder.finish()), vec!["LeftMeta"]);
+    }
+
+    #[test]
+    fn test_record_ignores_repeats() {
+        let start = Instant::now();
+        let mut recorder = MacroRecorder::new(0);
+
+        recorder.record(
+            &[
+                key_event(Key::KEY_A, 1),
+                key_event(Key::KEY_A, 2),
+                key_event(Key::KEY_A, 2),
+                key_event(Key::KEY_A, 0),
+            ],
+            start,
+        );
+
+        assert_eq!(keys(&recorder.finish()), vec!["A"]);
+    }
+
+    #[test]
+    fn test_record_quantizes_delays() {
+        let start = Instant::now();
+        let mut recorder = MacroRecorder::new(50);
+
+        recorder.record(&[key_event(Key::KEY_A, 1)], start);
+        recorder.record(&[key_event(Key::KEY_B, 1)], start + Duration::from_millis(74));
+        recorder.record(&[key_event(Key::KEY_C, 1)], start + Duration::from_millis(90));
+
+        // 74ms rounds to 50ms; 16ms rounds to 0 and is dropped
+        assert_eq!(keys(&recorder.finish()), vec!["A", "delay(50)", "B", "C"]);
+    }
+
+    #[test]
+    fn test_record_splits_long_delays() {
+        let start = Instant::now();
+        let mut recorder = MacroRecorder::new(0);
+
+        recorder.record(&[key_event(Key::KEY_A, 1)], start);
+        recorder.record(&[key_event(Key::KEY_B, 1)], start + Duration::from_millis(25_000));
+
+        assert_eq!(
+            keys(&recorder.finish()),
+            vec!["A", "delay(10000)", "delay(10000)", "delay(5000)", "B"]
+        );
+    }
+
+    #[test]
+    fn test_recorded_keys_round_trip() {
+        let start = Instant::now();
+        let mut recorder = MacroRecorder::new(0);
+
+        for key in [Key::KEY_F5, Key::KEY_SYSRQ, Key::KEY_DOT] {
+            recorder.record(&[key_event(key, 1), key_event(key, 0)], start);
+        }
+
+        let actions = recorder.finish();
+        for action in &actions {
+            assert_eq!(&action.to_string().parse::<MacroAction>().unwrap(), action);
+        }
+        assert_eq!(keys(&actions)[0], "F5");
+    }
+}
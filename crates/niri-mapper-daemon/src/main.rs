@@ -8,31 +8,38 @@ mod device_manager;
 mod hotplug;
 mod injector;
 mod ipc;
+mod keysym;
 mod macro_executor;
+mod macro_recorder;
 mod niri_ipc;
 mod remapper;
+mod text_input;
 
 pub use device_manager::DeviceManager;
 
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::{Context, Result};
 use clap::Parser;
 use evdev::Device;
 use futures::stream::{SelectAll, StreamExt};
-use niri_mapper_config::Config;
+use niri_mapper_config::{Config, DeviceConfig, FocusContext, KeyCode, MacroAction, MacroPolicy};
 use tokio::signal::unix::{signal, SignalKind};
 use tracing_subscriber::EnvFilter;
 
 use device::DeviceInfo;
 use hotplug::{HotplugEvent, HotplugMonitor};
 use injector::{create_shared_virtual_device, SharedVirtualDevice};
-use ipc::{handle_ipc_connection, DeviceStatus, IpcRequest, IpcResponse, IpcServer};
-use macro_executor::MacroExecutor;
+use ipc::{handle_ipc_connection, DeviceStatus, IpcRequest, IpcResponse, IpcServer, RunningMacroStatus};
+use keysym::KeyTranslation;
+use macro_executor::{MacroExecutor, MacroId};
+use macro_recorder::{MacroRecorder, RecordingSession, SaveTarget};
 use niri_ipc::{NiriEventDispatcher, NiriEventReceiver, DEFAULT_CHANNEL_BUFFER};
 use remapper::{RemapResult, Remapper};
+use text_input::TextTyper;
 
 /// Load and parse configuration from the given path
 ///
@@ -158,6 +165,100 @@ fn grab_configured_devices(config: &Config) -> Result<Vec<GrabbedDevice>> {
     Ok(grabbed_devices)
 }
 
+/// Finish a macro recording.
+///
+/// The recording is stored for playback over IPC and, if requested, written to
+/// the config file as a `macro` block entry.
+fn finish_recording(
+    session: RecordingSession,
+    device_name: &str,
+    recorded_macros: &mut HashMap<String, Vec<MacroAction>>,
+    config_path: &Path,
+) {
+    let actions = session.recorder.finish();
+    if actions.is_empty() {
+        tracing::warn!("Macro recording '{}' captured no keys, discarding", session.name);
+        return;
+    }
+
+    tracing::info!(
+        "Recorded macro '{}' with {} action(s) on device '{}'",
+        session.name,
+        actions.len(),
+        device_name
+    );
+
+    if let Some(target) = &session.save {
+        match niri_mapper_config::write_macro(
+            config_path,
+            device_name,
+            &target.profile,
+            &target.trigger,
+            &actions,
+        ) {
+            Ok(()) => tracing::info!(
+                "Saved macro '{}' as {} in profile '{}'; reload the configuration to use it",
+                session.name,
+                target.trigger,
+                target.profile
+            ),
+            Err(e) => tracing::error!("Failed to save macro '{}': {}", session.name, e),
+        }
+    }
+
+    recorded_macros.insert(session.name, actions);
+}
+
+/// Pick a name for a recording started with the record key.
+fn next_recording_name(recorded_macros: &HashMap<String, Vec<MacroAction>>) -> String {
+    (1..)
+        .map(|n| format!("recording-{}", n))
+        .find(|name| !recorded_macros.contains_key(name))
+        .unwrap()
+}
+
+/// Switch devices to the profiles whose hints match what the user is focused on.
+///
+/// Also passes the focus to every remapper for rules with `when` conditions.
+fn apply_focus_profiles(
+    focus: &FocusContext,
+    device_configs: &[DeviceConfig],
+    remappers: &mut HashMap<PathBuf, Remapper>,
+    device_infos: &HashMap<PathBuf, DeviceInfo>,
+    active_profiles: &mut HashMap<PathBuf, String>,
+) {
+    for remapper in remappers.values_mut() {
+        remapper.set_focus(focus);
+    }
+
+    for (path, info) in device_infos {
+        let Some(device_config) = device_configs
+            .iter()
+            .find(|dc| dc.name.as_ref() == Some(&info.name))
+        else {
+            continue;
+        };
+        let active = active_profiles.get(path).map(String::as_str).unwrap_or("default");
+        let Some(target) = device_config.select_profile(active, focus) else {
+            continue;
+        };
+        if let Some(remapper) = remappers.get_mut(path) {
+            remapper.switch_profile(&device_config.profiles[target]);
+            tracing::info!(
+                app_id = ?focus.app_id,
+                workspace_name = ?focus.workspace_name,
+                workspace_idx = ?focus.workspace_idx,
+                output = ?focus.output,
+                layout = ?focus.layout,
+                "Switched device '{}' to profile '{}'",
+                info.name,
+                target
+            );
+            active_profiles.insert(path.clone(), target.to_string());
+        }
+    }
+}
+
 /// Run the main event loop, processing events from all grabbed devices
 ///
 /// This function:
@@ -220,18 +321,50 @@ async fn run_event_loop(
     // Initialized to "default" for each grabbed device
     let mut active_profiles: HashMap<PathBuf, String> = HashMap::new();
 
-    // Track current focused app for future per-app profile switching (Task 040-4.8)
-    // Updated on focus change events from niri IPC. Currently just tracks state;
-    // automatic profile switching based on focused app is a future backlog item.
-    let mut current_focused_app_id: Option<String> = None;
+    // What the user is focused on (app, workspace, output, keyboard layout),
+    // updated from niri IPC events and used to activate profiles by their hints
+    let mut focus_context = FocusContext::default();
+
+    // Global key that aborts all running macros
+    let mut macro_abort_key = config
+        .global
+        .macro_abort_key
+        .map(remapper::to_key);
+
+    // Global key that starts and stops macro recording on the device it is pressed on
+    let mut macro_record_key = config
+        .global
+        .macro_record_key
+        .map(remapper::to_key);
+    let mut macro_record_quantize = config.global.macro_record_quantize;
+
+    // The recording in progress (at most one at a time) and finished recordings,
+    // which can be played by name over IPC
+    let mut recording: Option<RecordingSession> = None;
+    let mut recorded_macros: HashMap<String, Vec<MacroAction>> = HashMap::new();
+
+    // Named macros from the config's `macros` library, playable over IPC
+    let mut macro_library = config.macros.clone();
+
+    // Active keyboard layout (reported by niri) and the keysym translation
+    // for it, which is `None` unless the config sets `key-mode "keysym"`
+    let mut global_config = config.global.clone();
+    let mut keyboard_layout: u32 = 0;
+    let mut keyboard_layout_names: Vec<String> = Vec::new();
+
+    // Device configs of the loaded config, for switching profiles by layout hint
+    let mut device_configs = config.devices.clone();
+    let mut key_translation = KeyTranslation::from_config(&global_config, keyboard_layout);
+    macro_executor.set_key_translation(key_translation.clone());
 
     // Stream type: yields (device_path, event_result) for path-based device lookup
     let mut streams: SelectAll<futures::stream::BoxStream<'static, (PathBuf, std::io::Result<evdev::InputEvent>)>> = SelectAll::new();
 
     // Initialize streams from initially grabbed devices
     for grabbed_device in grabbed_devices {
-        let GrabbedDevice { device, remapper, info, active_profile } = grabbed_device;
+        let GrabbedDevice { device, mut remapper, info, active_profile } = grabbed_device;
         let path = info.path.clone();
+        remapper.set_key_translation(key_translation.clone());
         let device_name = info.name.clone();
 
         remappers.insert(path.clone(), remapper);
@@ -281,13 +414,81 @@ async fn run_event_loop(
                             }
                         };
 
+                        // The macro abort key is consumed on every device so that it
+                        // never reaches the remapper or the focused application
+                        if let (Some(abort_key), evdev::InputEventKind::Key(key)) =
+                            (macro_abort_key, event.kind())
+                        {
+                            if key == abort_key {
+                                if event.value() == 1 {
+                                    macro_executor.abort_all();
+                                }
+                                continue;
+                            }
+                        }
+
+                        // The recording stop key and the record key are consumed as well;
+                        // a press of either finishes the recording in progress
+                        if let evdev::InputEventKind::Key(key) = event.kind() {
+                            let stops_recording = recording.as_ref().is_some_and(|session| {
+                                (session.device_path == device_path && key == session.stop_key)
+                                    || Some(key) == macro_record_key
+                            });
+
+                            if stops_recording {
+                                if event.value() == 1 {
+                                    if let Some(session) = recording.take() {
+                                        let recorded_device = device_infos
+                                            .get(&session.device_path)
+                                            .map(|info| info.name.clone())
+                                            .unwrap_or_else(|| device_name.clone());
+                                        finish_recording(
+                                            session,
+                                            &recorded_device,
+                                            &mut recorded_macros,
+                                            config_path,
+                                        );
+                                    }
+                                }
+                                continue;
+                            }
+
+                            if recording.is_none() && Some(key) == macro_record_key {
+                                if event.value() == 1 {
+                                    let name = next_recording_name(&recorded_macros);
+                                    tracing::info!(
+                                        "Recording macro '{}' on device '{}'",
+                                        name,
+                                        device_name
+                                    );
+                                    recording = Some(RecordingSession {
+                                        name,
+                                        device_path: device_path.clone(),
+                                        stop_key: key,
+                                        save: None,
+                                        recorder: MacroRecorder::new(macro_record_quantize),
+                                    });
+                                }
+                                continue;
+                            }
+                        }
+
                         // Process the event through the remapper
                         match remapper.process(event) {
                             RemapResult::Events(remapped_events) => {
+                                // Recordings capture the processed key stream
+                                if let Some(session) = recording
+                                    .as_mut()
+                                    .filter(|session| session.device_path == device_path)
+                                {
+                                    session.recorder.record(&remapped_events, Instant::now());
+                                }
+
                                 // Inject remapped events via the virtual device
+                                // (held back or dropped while a macro is playing, if configured)
                                 if !remapped_events.is_empty() {
                                     let mut vd = virtual_device.lock().await;
-                                    if let Err(e) = vd.emit(&remapped_events) {
+                                    if let Err(e) = vd.emit_physical(&remapped_events) {
                                         tracing::error!(
                                             "Failed to inject events for device '{}': {}",
                                             device_name,
@@ -296,29 +497,25 @@ async fn run_event_loop(
                                     }
                                 }
                             }
-                            RemapResult::Macro(actions) => {
-                                // Spawn macro execution as an async task
-                                // This allows the event loop to continue without blocking during delays
-                                // Concurrent macro execution is allowed (no queuing for v0.3.0)
-                                let executor = macro_executor.clone();
-                                let device_name_owned = device_name.clone();
-                                let action_count = actions.len();
-
+                            RemapResult::Macro(trigger) => {
+                                // Macros run in background tasks so the event loop keeps
+                                // processing input during delays; the executor applies
+                                // the macro's concurrency policy
                                 tracing::debug!(
-                                    "Macro triggered on device '{}' with {} actions, spawning execution",
+                                    "Macro triggered on device '{}' by {:?} with {} actions",
                                     device_name,
-                                    action_count
+                                    trigger.key,
+                                    trigger.actions.len()
                                 );
 
-                                tokio::spawn(async move {
-                                    if let Err(e) = executor.execute_macro(&actions).await {
-                                        tracing::error!(
-                                            "Macro execution failed on device '{}': {}",
-                                            device_name_owned,
-                                            e
-                                        );
-                                    }
-                                });
+                                macro_executor.trigger(
+                                    MacroId::new(device_name.as_str(), trigger.key),
+                                    trigger.actions,
+                                    trigger.policy,
+                                );
+                            }
+                            RemapResult::MacroRelease(key) => {
+                                macro_executor.release(&MacroId::new(device_name.as_str(), key));
                             }
                             RemapResult::ProfileSwitch(profile_name) => {
                                 // Profile switching is handled by DeviceRemapper, not Remapper
@@ -362,6 +559,17 @@ async fn run_event_loop(
                         device_infos.remove(&device_path);
                         active_profiles.remove(&device_path);
 
+                        if recording
+                            .as_ref()
+                            .is_some_and(|session| session.device_path == device_path)
+                        {
+                            tracing::warn!(
+                                "Discarding macro recording on disconnected device '{}'",
+                                device_name
+                            );
+                            recording = None;
+                        }
+
                         tracing::info!(
                             "Removed device '{}' from event loop ({} device(s) remaining)",
                             device_name,
@@ -392,6 +600,7 @@ async fn run_event_loop(
             // - Remap rules (1:1 key remappings)
             // - Combo rules (multi-key sequences)
             // - niri-passthrough keybinds
+            // - Global macro-abort-key, macro-isolation and macro-record settings
             // - Profile settings within existing devices (rules are rebuilt from config)
             //
             // ### Requires restart:
@@ -434,9 +643,11 @@ async fn run_event_loop(
                                     match device_config.profiles.get("default") {
                                         Some(default_profile) => {
                                             // Create a new remapper and replace the old one
-                                            let new_remapper = Remapper::from_profile(default_profile);
+                                            let mut new_remapper = Remapper::from_profile(default_profile);
+                                            new_remapper.set_key_translation(key_translation.clone());
                                             if let Some(remapper) = remappers.get_mut(path) {
                                                 *remapper = new_remapper;
+                                                active_profiles.insert(path.clone(), "default".to_string());
                                                 tracing::info!(
                                                     "Updated remapper for device '{}' with new configuration",
                                                     device_info.name
@@ -468,6 +679,37 @@ async fn run_event_loop(
                             not_found_count
                         );
 
+                        macro_abort_key = new_config
+                            .global
+                            .macro_abort_key
+                            .map(remapper::to_key);
+                        macro_record_key = new_config
+                            .global
+                            .macro_record_key
+                            .map(remapper::to_key);
+                        macro_record_quantize = new_config.global.macro_record_quantize;
+                        macro_library = new_config.macros.clone();
+                        virtual_device
+                            .lock()
+                            .await
+                            .set_macro_isolation(new_config.global.macro_isolation);
+                        global_config = new_config.global.clone();
+                        key_translation = KeyTranslation::from_config(&global_config, keyboard_layout);
+                        for remapper in remappers.values_mut() {
+                            remapper.set_key_translation(key_translation.clone());
+                        }
+                        macro_executor.set_key_translation(key_translation.clone());
+                        macro_executor.set_text_typer(TextTyper::from_config(&global_config, keyboard_layout));
+
+                        device_configs = new_config.devices.clone();
+                        apply_focus_profiles(
+                            &focus_context,
+                            &device_configs,
+                            &mut remappers,
+                            &device_infos,
+                            &mut active_profiles,
+                        );
+
                         // Regenerate niri keybinds after successful config reload
                         match niri_mapper_config::write_niri_keybinds(&new_config, &config_path) {
                             Ok(()) => {
@@ -539,8 +781,9 @@ async fn run_event_loop(
                                         // We need to extract the device from DeviceManager
                                         match device_manager.get_event_streams() {
                                             Ok(mut streams_data) => {
-                                                for (path, remapper, info, active_profile, event_stream) in streams_data.drain(..) {
+                                                for (path, mut remapper, info, active_profile, event_stream) in streams_data.drain(..) {
                                                     let device_name = info.name.clone();
+                                                    remapper.set_key_translation(key_translation.clone());
 
                                                     tracing::info!(
                                                         "Device connected: '{}' at {} - grabbing (profile: {})",
@@ -553,6 +796,13 @@ async fn run_event_loop(
                                                     remappers.insert(path.clone(), remapper);
                                                     device_infos.insert(path.clone(), info);
                                                     active_profiles.insert(path.clone(), active_profile);
+                                                    apply_focus_profiles(
+                                                        &focus_context,
+                                                        &device_configs,
+                                                        &mut remappers,
+                                                        &device_infos,
+                                                        &mut active_profiles,
+                                                    );
 
                                                     // Wrap stream with path for identification
                                                     let path_for_stream = path.clone();
@@ -635,7 +885,8 @@ async fn run_event_loop(
             // Handle IPC connections (if server is available)
             //
             // Accepts incoming connections from CLI/external tools and processes
-            // IPC requests (profile_switch, profile_list, status).
+            // IPC requests (profile_switch, profile_list, status, abort_macros,
+            // record_macro, run_macro).
             result = async {
                 match &ipc_server {
                     Some(server) => server.accept().await,
@@ -664,11 +915,27 @@ async fn run_event_loop(
                             })
                             .collect();
 
+                        let running_macros: Vec<RunningMacroStatus> = macro_executor
+                            .running()
+                            .into_iter()
+                            .map(|info| RunningMacroStatus {
+                                device: info.id.device,
+                                trigger: info.id.trigger,
+                                policy: info.policy.to_string(),
+                                running_ms: info.elapsed.as_millis() as u64,
+                                queued: info.queued,
+                            })
+                            .collect();
+
                         // Clone data needed for the handler closure
                         let device_names: Vec<String> = device_infos
                             .values()
                             .map(|info| info.name.clone())
                             .collect();
+                        let device_paths: HashMap<String, PathBuf> = device_infos
+                            .iter()
+                            .map(|(path, info)| (info.name.clone(), path.clone()))
+                            .collect();
 
                         // Handle the IPC connection with a request handler
                         let handler = |request: IpcRequest| -> IpcResponse {
@@ -734,6 +1001,139 @@ async fn run_event_loop(
                                 IpcRequest::Status => {
                                     IpcResponse::Status {
                                         devices: device_statuses.clone(),
+                                        running_macros: running_macros.clone(),
+                                    }
+                                }
+
+                                IpcRequest::RecordMacro {
+                                    device,
+                                    name,
+                                    stop_key,
+                                    quantize_ms,
+                                    bind,
+                                    profile,
+                                } => {
+                                    let Some(path) = device_paths.get(&device) else {
+                                        return IpcResponse::Error {
+                                            message: format!(
+                                                "Device '{}' not found. Available devices: {}",
+                                                device,
+                                                device_names.join(", ")
+                                            ),
+                                        };
+                                    };
+
+                                    if let Some(session) = &recording {
+                                        return IpcResponse::Error {
+                                            message: format!(
+                                                "Macro '{}' is already being recorded",
+                                                session.name
+                                            ),
+                                        };
+                                    }
+
+                                    let stop_key = match stop_key.as_deref() {
+                                        Some(name) => match remapper::parse_key(name) {
+                                            Some(key) => key,
+                                            None => {
+                                                return IpcResponse::Error {
+                                                    message: format!("Unknown stop key '{}'", name),
+                                                };
+                                            }
+                                        },
+                                        None => macro_record_key.unwrap_or(evdev::Key::KEY_ESC),
+                                    };
+
+                                    let save = match bind {
+                                        Some(trigger) => {
+                                            if remapper::parse_key(&trigger).is_none() {
+                                                return IpcResponse::Error {
+                                                    message: format!("Unknown trigger key '{}'", trigger),
+                                                };
+                                            }
+                                            let profile = profile.unwrap_or_else(|| {
+                                                active_profiles
+                                                    .get(path)
+                                                    .cloned()
+                                                    .unwrap_or_else(|| "default".to_string())
+                                            });
+                                            Some(SaveTarget { profile, trigger })
+                                        }
+                                        None => None,
+                                    };
+
+                                    let stop_name = KeyCode(stop_key.code()).to_string();
+                                    tracing::info!(
+                                        "IPC: Recording macro '{}' on device '{}' (stop key: {})",
+                                        name,
+                                        device,
+                                        stop_name
+                                    );
+
+                                    recording = Some(RecordingSession {
+                                        name: name.clone(),
+                                        device_path: path.clone(),
+                                        stop_key,
+                                        save,
+                                        recorder: MacroRecorder::new(
+                                            quantize_ms.unwrap_or(macro_record_quantize),
+                                        ),
+                                    });
+
+                                    IpcResponse::Success {
+                                        message: Some(format!(
+                                            "Recording macro '{}' on device '{}'. Press {} to stop.",
+                                            name, device, stop_name
+                                        )),
+                                    }
+                                }
+
+                                IpcRequest::RunMacro { name, args } => {
+                                    let actions = match (macro_library.get(&name), recorded_macros.get(&name)) {
+                                        (Some(named), _) => named.instantiate(&args).map_err(|e| {
+                                            format!("Macro '{}': {}", name, e)
+                                        }),
+                                        (None, Some(_)) if !args.is_empty() => Err(format!(
+                                            "Macro '{}' is a recording and takes no parameters",
+                                            name
+                                        )),
+                                        (None, Some(actions)) => Ok(actions.clone()),
+                                        (None, None) => {
+                                            let mut names: Vec<&String> =
+                                                macro_library.keys().chain(recorded_macros.keys()).collect();
+                                            names.sort();
+                                            Err(format!(
+                                                "Macro '{}' not found. Available macros: {}",
+                                                name,
+                                                names
+                                                    .iter()
+                                                    .map(|n| n.as_str())
+                                                    .collect::<Vec<_>>()
+                                                    .join(", ")
+                                            ))
+                                        }
+                                    };
+
+                                    match actions {
+                                        Ok(actions) => {
+                                            macro_executor.trigger(
+                                                MacroId::named(name.as_str()),
+                                                actions,
+                                                MacroPolicy::default(),
+                                            );
+                                            IpcResponse::Success {
+                                                message: Some(format!("Started macro '{}'", name)),
+                                            }
+                                        }
+                                        Err(message) => IpcResponse::Error { message },
+                                    }
+                                }
+
+                                IpcRequest::AbortMacros => {
+                                    let count = macro_executor.abort_all();
+                                    tracing::info!("IPC: Aborted {} running macro(s)", count);
+                                    IpcResponse::Success {
+                                        message: Some(format!("Aborted {} running macro(s)", count)),
                                     }
                                 }
                             }
@@ -749,11 +1149,11 @@ async fn run_event_loop(
                     }
                 }
             }
-            // Handle niri IPC events (focus changes, workspace changes)
+            // Handle niri IPC events (focus, workspace and keyboard layout changes)
             //
             // Events are received from the NiriEventDispatcher which runs in a
-            // background task. These events are used to track compositor state
-            // for future per-application profile switching.
+            // background task. They update the focus context, which activates
+            // profiles by their app-id, workspace, output and layout hints.
             result = async {
                 match &mut niri_event_receiver {
                     Some(rx) => rx.recv().await,
@@ -763,41 +1163,91 @@ async fn run_event_loop(
                 match result {
                     Some(event) => {
                         // Log focus change events at debug level (task 040-3.7)
-                        // and update focused app state (task 040-4.8)
+                        // and update the focus context (task 040-4.8)
                         match &event {
                             niri_ipc::NiriEvent::FocusChanged(focus_event) => {
                                 let new_app_id = focus_event.window.as_ref().map(|w| w.app_id.clone());
+                                focus_context.title = focus_event.window.as_ref().map(|w| w.title.clone());
+                                focus_context.is_floating = focus_event.window.as_ref().map(|w| w.is_floating);
 
                                 // Log and update state only if the app_id actually changed
-                                if new_app_id != current_focused_app_id {
+                                if new_app_id != focus_context.app_id {
                                     match &focus_event.window {
                                         Some(window) => {
                                             tracing::debug!(
                                                 app_id = %window.app_id,
                                                 title = %window.title,
-                                                prev_app_id = ?current_focused_app_id,
+                                                prev_app_id = ?focus_context.app_id,
                                                 "Focused app changed"
                                             );
                                         }
                                         None => {
                                             tracing::debug!(
-                                                prev_app_id = ?current_focused_app_id,
+                                                prev_app_id = ?focus_context.app_id,
                                                 "Focused app changed: no window focused"
                                             );
                                         }
                                     }
-                                    current_focused_app_id = new_app_id;
+                                    focus_context.app_id = new_app_id;
                                 }
                             }
                             niri_ipc::NiriEvent::WorkspaceActivated(ws_event) => {
                                 tracing::debug!(
                                     workspace_id = %ws_event.workspace_id,
+                                    name = ?ws_event.name,
+                                    idx = ?ws_event.idx,
+                                    output = %ws_event.output,
                                     is_focused = %ws_event.is_focused,
                                     "Workspace activated"
                                 );
+                                if ws_event.is_focused {
+                                    focus_context.workspace_name = ws_event.name.clone();
+                                    focus_context.workspace_idx = ws_event.idx;
+                                    focus_context.output = Some(ws_event.output.clone())
+                                        .filter(|output| !output.is_empty());
+                                }
+                            }
+                            niri_ipc::NiriEvent::KeyboardLayoutsChanged(layouts) => {
+                                tracing::debug!(
+                                    names = ?layouts.names,
+                                    current_idx = %layouts.current_idx,
+                                    "Keyboard layouts changed"
+                                );
+                                keyboard_layout_names = layouts.names.clone();
+                            }
+                            niri_ipc::NiriEvent::KeyboardLayoutSwitched { idx } => {
+                                tracing::debug!(idx = %idx, "Keyboard layout switched");
                             }
                         }
-                        // TODO(future): Implement automatic per-app profile switching
+
+                        // Follow the active keyboard layout for keysym rules and text actions
+                        let new_layout = match &event {
+                            niri_ipc::NiriEvent::KeyboardLayoutsChanged(layouts) => {
+                                Some(u32::from(layouts.current_idx))
+                            }
+                            niri_ipc::NiriEvent::KeyboardLayoutSwitched { idx } => Some(u32::from(*idx)),
+                            _ => None,
+                        };
+                        if let Some(layout) = new_layout.filter(|&layout| layout != keyboard_layout) {
+                            keyboard_layout = layout;
+                            key_translation = KeyTranslation::from_config(&global_config, keyboard_layout);
+                            for remapper in remappers.values_mut() {
+                                remapper.set_key_translation(key_translation.clone());
+                            }
+                            macro_executor.set_key_translation(key_translation.clone());
+                            macro_executor.set_text_typer(TextTyper::from_config(&global_config, keyboard_layout));
+                        }
+                        focus_context.layout = keyboard_layout_names.get(keyboard_layout as usize).cloned();
+
+                        // Activate the profiles hinted at for the new focus and
+                        // evaluate `when` rules against it
+                        apply_focus_profiles(
+                            &focus_context,
+                            &device_configs,
+                            &mut remappers,
+                            &device_infos,
+                            &mut active_profiles,
+                        );
                     }
                     None => {
                         // Channel closed - event reader task has ended
@@ -872,9 +1322,14 @@ async fn main() -> Result<()> {
     // Create the virtual device for output injection
     let virtual_device = create_shared_virtual_device("niri-mapper")
         .context("Failed to create virtual keyboard device")?;
+    virtual_device
+        .lock()
+        .await
+        .set_macro_isolation(config.global.macro_isolation);
 
     // Create the macro executor with shared access to the virtual device
     let macro_executor = MacroExecutor::new(virtual_device.clone());
+    macro_executor.set_text_typer(TextTyper::from_config(&config.global, 0));
 
     // Initialize IPC server for CLI communication
     // Failure to create IPC server is non-fatal - daemon can still function without it
@@ -915,17 +1370,11 @@ async fn main() -> Result<()> {
         // Create the event dispatcher and spawn the reader task
         let (dispatcher, receiver) = NiriEventDispatcher::new(DEFAULT_CHANNEL_BUFFER);
 
-        // Use an empty window list for now.
-        // The window list is used to enrich focus events with window details.
-        // Task 040-3.8 will enhance this to maintain an up-to-date window list
-        // by tracking WindowOpenedOrChanged and WindowClosed events.
-        //
-        // For v0.4.0, focus events will have limited context (window ID only)
-        // when the window isn't in the initial list. This is acceptable as the
-        // main use case (per-app profile switching) can be implemented later.
+        // The reader tracks windows and workspaces from the event stream itself
+        // (see `CompositorState`), so no extra window list is needed.
         //
         // Note: We use the unit type `()` as a WindowProvider which always
-        // returns an empty window list. This is the simplest approach for now.
+        // returns an empty window list.
         let window_provider: () = ();
 
         match dispatcher.spawn_reader(window_provider).await {
@@ -418,6 +418,35 @@ impl NiriClient {
         }
     }
 
+    /// Ask niri to switch the keyboard layout
+    ///
+    /// This is a convenience method that sends a `SwitchLayout` action.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from `send_request()`, e.g. `NiriError::NiriError`
+    /// if niri rejects the action.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let mut client = NiriClient::connect().await?;
+    /// client.switch_layout(LayoutSwitch::Next).await?;
+    /// ```
+    pub async fn switch_layout(
+        &mut self,
+        layout: niri_mapper_config::LayoutSwitch,
+    ) -> Result<(), NiriError> {
+        let layout = match layout {
+            niri_mapper_config::LayoutSwitch::Next => niri_ipc::LayoutSwitchTarget::Next,
+            niri_mapper_config::LayoutSwitch::Prev => niri_ipc::LayoutSwitchTarget::Prev,
+            niri_mapper_config::LayoutSwitch::Index(idx) => niri_ipc::LayoutSwitchTarget::Index(idx),
+        };
+        let action = niri_ipc::Action::SwitchLayout { layout };
+        self.send_request(niri_ipc::Request::Action(action)).await?;
+        Ok(())
+    }
+
     /// Query the list of all windows from niri
     ///
     /// This is a convenience method that sends a `Request::Windows` and
@@ -1072,6 +1101,7 @@ mod tests {
             id: 1,
             app_id: "test".to_string(),
             title: "Test Window".to_string(),
+            is_floating: false,
         };
 
         // Type assertions via assignment
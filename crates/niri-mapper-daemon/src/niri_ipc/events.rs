@@ -60,6 +60,7 @@ use tracing::{debug, info, warn};
 
 use super::error::NiriError;
 use super::client::get_socket_path;
+use super::state::CompositorState;
 use super::types::NiriEvent;
 
 /// Default number of reconnection retry attempts for event stream
@@ -374,34 +375,33 @@ impl NiriEventStream {
         Ok(event)
     }
 
-    /// Read the next focus-relevant event from the event stream
+    /// Read the next focus-relevant event, tracking compositor state
     ///
     /// This method filters the raw event stream to only return events relevant
     /// to focus-based remapping. Events not related to focus changes (like
     /// layout changes, keyboard layouts, window geometry updates, etc.) are
     /// silently discarded.
     ///
-    /// # Window Context
-    ///
-    /// The `windows` parameter provides the current window list for looking up
-    /// window details when a `WindowFocusChanged` event is received. The caller
-    /// is responsible for keeping this list up-to-date (e.g., by tracking
-    /// `WindowOpenedOrChanged` and `WindowClosed` events separately, or by
-    /// querying the window list via `NiriClient`).
+    /// Every event is applied to `state` so that focus events resolve windows
+    /// from the tracked window list (falling back to `windows`) and workspace
+    /// events carry the workspace's name, index and output. See
+    /// [`CompositorState`].
     ///
     /// # Focus-Relevant Events
     ///
     /// - `WindowFocusChanged` -> `NiriEvent::FocusChanged`
     /// - `WorkspaceActivated` -> `NiriEvent::WorkspaceActivated`
+    /// - `KeyboardLayoutsChanged` -> `NiriEvent::KeyboardLayoutsChanged`
+    /// - `KeyboardLayoutSwitched` -> `NiriEvent::KeyboardLayoutSwitched`
     ///
-    /// # Ignored Events
+    /// # Tracked Events
     ///
     /// - `WindowOpenedOrChanged` (window metadata changes)
     /// - `WindowClosed`
     /// - `WorkspacesChanged`
-    /// - `KeyboardLayoutsChanged`
-    /// - `KeyboardLayoutSwitched`
-    /// - All other compositor state events
+    ///
+    /// These update `state` but are not returned. All other compositor state
+    /// events are ignored.
     ///
     /// # Errors
     ///
@@ -413,10 +413,11 @@ impl NiriEventStream {
     ///
     /// ```ignore
     /// let mut stream = NiriEventStream::connect().await?;
-    /// let mut windows = Vec::new(); // Maintain window list from NiriClient
+    /// let mut state = CompositorState::default();
+    /// let windows = Vec::new(); // Initial window list from NiriClient
     ///
     /// loop {
-    ///     match stream.next_focus_event(&windows).await {
+    ///     match stream.next_tracked_event(&mut state, &windows).await {
     ///         Ok(NiriEvent::FocusChanged(event)) => {
     ///             if let Some(window) = event.window {
     ///                 println!("Focus changed to: {}", window.app_id);
@@ -438,18 +439,17 @@ impl NiriEventStream {
     ///     }
     /// }
     /// ```
-    pub async fn next_focus_event(
+    pub async fn next_tracked_event(
         &mut self,
+        state: &mut CompositorState,
         windows: &[niri_ipc::Window],
     ) -> Result<NiriEvent, NiriError> {
         loop {
             let raw_event = self.next_event().await?;
 
-            // Use the filtering logic from NiriEvent to convert/filter the event
-            if let Some(focus_event) = NiriEvent::from_niri_event(raw_event, windows) {
-                return Ok(focus_event);
+            if let Some(event) = state.update(raw_event, windows) {
+                return Ok(event);
             }
-            // Event not relevant to focus - continue reading
         }
     }
 }
@@ -465,6 +465,8 @@ impl NiriEventStream {
 /// Returns `true` if the event is one of:
 /// - `WindowFocusChanged`
 /// - `WorkspaceActivated`
+/// - `KeyboardLayoutsChanged`
+/// - `KeyboardLayoutSwitched`
 ///
 /// Returns `false` for all other events.
 ///
@@ -482,6 +484,8 @@ pub fn is_focus_relevant(event: &niri_ipc::Event) -> bool {
         event,
         niri_ipc::Event::WindowFocusChanged { .. }
             | niri_ipc::Event::WorkspaceActivated { .. }
+            | niri_ipc::Event::KeyboardLayoutsChanged { .. }
+            | niri_ipc::Event::KeyboardLayoutSwitched { .. }
     )
 }
 
@@ -684,12 +688,16 @@ impl NiriEventDispatcher {
     where
         W: WindowProvider,
     {
+        // Windows and workspaces reported on the stream, reset on reconnection
+        // since niri sends the full state again
+        let mut state = CompositorState::default();
+
         loop {
             // Get current window list from the provider
             let window_list = windows.get_windows();
 
             // Read and filter the next focus-relevant event
-            let event = match stream.next_focus_event(&window_list).await {
+            let event = match stream.next_tracked_event(&mut state, &window_list).await {
                 Ok(event) => event,
                 Err(NiriError::ConnectionClosed) => {
                     // Connection closed (EOF) - attempt reconnection
@@ -702,6 +710,7 @@ impl NiriEventDispatcher {
                         Ok(new_stream) => {
                             info!("Niri event stream reconnected successfully");
                             stream = new_stream;
+                            state = CompositorState::default();
                             continue;
                         }
                         Err(e) => {
@@ -731,6 +740,7 @@ impl NiriEventDispatcher {
                         Ok(new_stream) => {
                             info!("Niri event stream reconnected successfully");
                             stream = new_stream;
+                            state = CompositorState::default();
                             continue;
                         }
                         Err(e) => {
@@ -1023,16 +1033,17 @@ mod tests {
     }
 
     #[test]
-    fn test_next_focus_event_method_exists() {
-        // Compile-time check that next_focus_event method exists with correct signature
-        fn _assert_next_focus_event_exists(
+    fn test_next_tracked_event_method_exists() {
+        // Compile-time check that next_tracked_event method exists with correct signature
+        fn _assert_next_tracked_event_exists(
             stream: &mut super::NiriEventStream,
+            state: &mut super::CompositorState,
             windows: &[niri_ipc::Window],
         ) {
             // This won't actually run, just validates the type signature
             let _: std::pin::Pin<
                 Box<dyn std::future::Future<Output = Result<super::NiriEvent, super::NiriError>> + '_>,
-            > = Box::pin(stream.next_focus_event(windows));
+            > = Box::pin(stream.next_tracked_event(state, windows));
         }
     }
 
@@ -1054,6 +1065,44 @@ mod tests {
         assert!(super::is_focus_relevant(&event));
     }
 
+    #[test]
+    fn test_is_focus_relevant_keyboard_layouts() {
+        let event = niri_ipc::Event::KeyboardLayoutsChanged {
+            keyboard_layouts: niri_ipc::KeyboardLayouts {
+                names: vec!["English (US)".to_string(), "German".to_string()],
+                current_idx: 1,
+            },
+        };
+        assert!(super::is_focus_relevant(&event));
+
+        let event = niri_ipc::Event::KeyboardLayoutSwitched { idx: 0 };
+        assert!(super::is_focus_relevant(&event));
+    }
+
+    #[test]
+    fn test_filter_keyboard_layout_events() {
+        let windows = vec![];
+        let event = niri_ipc::Event::KeyboardLayoutsChanged {
+            keyboard_layouts: niri_ipc::KeyboardLayouts {
+                names: vec!["English (US)".to_string(), "German".to_string()],
+                current_idx: 1,
+            },
+        };
+        match super::filter_focus_event(event, &windows) {
+            Some(super::NiriEvent::KeyboardLayoutsChanged(layouts)) => {
+                assert_eq!(layouts.names.len(), 2);
+                assert_eq!(layouts.current_idx, 1);
+            }
+            other => panic!("Expected KeyboardLayoutsChanged, got {:?}", other),
+        }
+
+        let event = niri_ipc::Event::KeyboardLayoutSwitched { idx: 1 };
+        assert_eq!(
+            super::filter_focus_event(event, &windows),
+            Some(super::NiriEvent::KeyboardLayoutSwitched { idx: 1 })
+        );
+    }
+
     #[test]
     fn test_is_focus_relevant_ignores_window_opened() {
         let window = niri_ipc::Window {
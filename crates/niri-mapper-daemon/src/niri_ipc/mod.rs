@@ -10,6 +10,7 @@
 //!
 //! - `NiriClient`: Main client for sending IPC requests and receiving responses
 //! - `NiriError`: Error types for IPC operations
+//! - `CompositorState`: Windows and workspaces tracked from the event stream
 //!
 //! ## Protocol
 //!
@@ -22,6 +23,7 @@
 mod client;
 mod error;
 mod events;
+mod state;
 mod types;
 
 pub use client::{get_socket_path, NiriClient};
@@ -0,0 +1,274 @@
+//! Compositor state tracked from the niri event stream
+//!
+//! When the event stream starts, niri sends the full compositor state
+//! (`WorkspacesChanged`, `WindowsChanged`, ...) and afterwards only the
+//! changes. [`CompositorState`] follows those events so that focus and
+//! workspace events can be enriched with details the raw events lack:
+//!
+//! - `WindowFocusChanged` only carries a window ID; the tracked window list
+//!   provides the app ID and title.
+//! - `WorkspaceActivated` only carries a workspace ID; the tracked workspace
+//!   list provides the name, index and output.
+//! - Changes to the focused window (e.g., its title) are reported as
+//!   `FocusChanged` events, so title-based rules follow the window.
+//!
+//! The state must be reset when the event stream reconnects, since niri sends
+//! the full state again on the new stream.
+
+use super::types::{FocusChangeEvent, FocusedWindow, NiriEvent, WorkspaceChangeEvent};
+
+/// Windows and workspaces as last reported by niri
+#[derive(Debug, Clone, Default)]
+pub struct CompositorState {
+    windows: Vec<niri_ipc::Window>,
+    workspaces: Vec<niri_ipc::Workspace>,
+}
+
+impl CompositorState {
+    /// Apply a raw niri event and convert it to a [`NiriEvent`]
+    ///
+    /// Besides the conversions of [`NiriEvent::from_niri_event`], this:
+    ///
+    /// - Resolves focused windows against the tracked window list, falling
+    ///   back to `extra_windows` (e.g., from a [`WindowProvider`](super::WindowProvider))
+    /// - Fills in the name, index and output of activated workspaces
+    /// - Reports changes to the focused window as `FocusChanged` events
+    /// - Reports the initially focused workspace and window from the full
+    ///   state sent when the stream starts, as `WorkspaceActivated` and
+    ///   `FocusChanged` events
+    pub fn update(
+        &mut self,
+        event: niri_ipc::Event,
+        extra_windows: &[niri_ipc::Window],
+    ) -> Option<NiriEvent> {
+        match event {
+            niri_ipc::Event::WorkspacesChanged { workspaces } => {
+                let prev_focused = self.focused_workspace_id();
+                self.workspaces = workspaces;
+                let focused = self.focused_workspace_id()?;
+                (prev_focused != Some(focused)).then(|| self.workspace_activated(focused, true))
+            }
+            niri_ipc::Event::WorkspaceActivated { id, focused } => {
+                let output = self
+                    .workspaces
+                    .iter()
+                    .find(|ws| ws.id == id)
+                    .and_then(|ws| ws.output.clone());
+                for ws in &mut self.workspaces {
+                    if ws.output == output {
+                        ws.is_active = ws.id == id;
+                    }
+                    if focused {
+                        ws.is_focused = ws.id == id;
+                    }
+                }
+                Some(self.workspace_activated(id, focused))
+            }
+            niri_ipc::Event::WindowsChanged { windows } => {
+                let prev_focused = self.focused_window_id();
+                self.windows = windows;
+                let focused = self.focused_window_id();
+                (prev_focused != focused).then(|| self.focus_changed(focused, extra_windows))
+            }
+            niri_ipc::Event::WindowOpenedOrChanged { window } => {
+                let focused = window.is_focused.then_some(window.id);
+                if focused.is_some() {
+                    for w in &mut self.windows {
+                        w.is_focused = false;
+                    }
+                }
+                match self.windows.iter_mut().find(|w| w.id == window.id) {
+                    Some(existing) => *existing = window,
+                    None => self.windows.push(window),
+                }
+                // Report the focused window again, since its title or floating
+                // state may have changed
+                focused.map(|id| self.focus_changed(Some(id), extra_windows))
+            }
+            niri_ipc::Event::WindowClosed { id } => {
+                self.windows.retain(|w| w.id != id);
+                None
+            }
+            niri_ipc::Event::WindowFocusChanged { id } => {
+                for w in &mut self.windows {
+                    w.is_focused = Some(w.id) == id;
+                }
+                Some(self.focus_changed(id, extra_windows))
+            }
+            other => NiriEvent::from_niri_event(other, extra_windows),
+        }
+    }
+
+    /// ID of the focused workspace
+    fn focused_workspace_id(&self) -> Option<u64> {
+        self.workspaces.iter().find(|ws| ws.is_focused).map(|ws| ws.id)
+    }
+
+    /// ID of the focused window
+    fn focused_window_id(&self) -> Option<u64> {
+        self.windows.iter().find(|w| w.is_focused).map(|w| w.id)
+    }
+
+    /// Build a `WorkspaceActivated` event with the workspace's details
+    fn workspace_activated(&self, id: u64, is_focused: bool) -> NiriEvent {
+        let workspace = self.workspaces.iter().find(|ws| ws.id == id);
+        NiriEvent::WorkspaceActivated(WorkspaceChangeEvent {
+            workspace_id: id,
+            output: workspace
+                .and_then(|ws| ws.output.clone())
+                .unwrap_or_default(),
+            name: workspace.and_then(|ws| ws.name.clone()),
+            idx: workspace.map(|ws| ws.idx),
+            is_focused,
+        })
+    }
+
+    /// Build a `FocusChanged` event for a window ID
+    fn focus_changed(&self, id: Option<u64>, extra_windows: &[niri_ipc::Window]) -> NiriEvent {
+        let window = id.and_then(|id| {
+            self.windows
+                .iter()
+                .chain(extra_windows)
+                .find(|w| w.id == id)
+                .map(FocusedWindow::from)
+        });
+        NiriEvent::FocusChanged(FocusChangeEvent { window })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_window(id: u64, app_id: &str, is_focused: bool) -> niri_ipc::Window {
+        niri_ipc::Window {
+            id,
+            title: Some(format!("{} window", app_id)),
+            app_id: Some(app_id.to_string()),
+            pid: None,
+            workspace_id: Some(1),
+            is_focused,
+            is_urgent: false,
+            is_floating: false,
+            focus_timestamp: None,
+            layout: niri_ipc::WindowLayout {
+                pos_in_scrolling_layout: None,
+                tile_size: (0.0, 0.0),
+                window_size: (0, 0),
+                tile_pos_in_workspace_view: None,
+                window_offset_in_tile: (0.0, 0.0),
+            },
+        }
+    }
+
+    fn test_workspace(id: u64, idx: u8, name: Option<&str>, output: &str, is_focused: bool) -> niri_ipc::Workspace {
+        niri_ipc::Workspace {
+            id,
+            idx,
+            name: name.map(str::to_string),
+            output: Some(output.to_string()),
+            is_urgent: false,
+            is_active: is_focused,
+            is_focused,
+            active_window_id: None,
+        }
+    }
+
+    #[test]
+    fn test_initial_state_reports_focus() {
+        let mut state = CompositorState::default();
+
+        let event = state.update(
+            niri_ipc::Event::WorkspacesChanged {
+                workspaces: vec![
+                    test_workspace(1, 1, None, "DP-1", false),
+                    test_workspace(2, 2, Some("games"), "DP-1", true),
+                ],
+            },
+            &[],
+        );
+        match event {
+            Some(NiriEvent::WorkspaceActivated(ws)) => {
+                assert_eq!(ws.workspace_id, 2);
+                assert_eq!(ws.name.as_deref(), Some("games"));
+                assert_eq!(ws.idx, Some(2));
+                assert_eq!(ws.output, "DP-1");
+                assert!(ws.is_focused);
+            }
+            other => panic!("Expected WorkspaceActivated, got {:?}", other),
+        }
+
+        let event = state.update(
+            niri_ipc::Event::WindowsChanged {
+                windows: vec![test_window(10, "kitty", false), test_window(11, "firefox", true)],
+            },
+            &[],
+        );
+        match event {
+            Some(NiriEvent::FocusChanged(focus)) => {
+                assert_eq!(focus.window.unwrap().app_id, "firefox");
+            }
+            other => panic!("Expected FocusChanged, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_workspace_activated_is_enriched() {
+        let mut state = CompositorState::default();
+        state.update(
+            niri_ipc::Event::WorkspacesChanged {
+                workspaces: vec![
+                    test_workspace(1, 1, None, "DP-1", true),
+                    test_workspace(5, 1, Some("art"), "HDMI-A-1", false),
+                ],
+            },
+            &[],
+        );
+
+        match state.update(niri_ipc::Event::WorkspaceActivated { id: 5, focused: true }, &[]) {
+            Some(NiriEvent::WorkspaceActivated(ws)) => {
+                assert_eq!(ws.output, "HDMI-A-1");
+                assert_eq!(ws.name.as_deref(), Some("art"));
+                assert_eq!(ws.idx, Some(1));
+            }
+            other => panic!("Expected WorkspaceActivated, got {:?}", other),
+        }
+        assert_eq!(state.focused_workspace_id(), Some(5));
+    }
+
+    #[test]
+    fn test_focused_window_change_is_reported() {
+        let mut state = CompositorState::default();
+        let mut window = test_window(7, "firefox", true);
+        state.update(niri_ipc::Event::WindowsChanged { windows: vec![window.clone()] }, &[]);
+
+        window.title = Some("YouTube - Mozilla Firefox".to_string());
+        match state.update(niri_ipc::Event::WindowOpenedOrChanged { window }, &[]) {
+            Some(NiriEvent::FocusChanged(focus)) => {
+                assert_eq!(focus.window.unwrap().title, "YouTube - Mozilla Firefox");
+            }
+            other => panic!("Expected FocusChanged, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_focus_resolves_tracked_windows() {
+        let mut state = CompositorState::default();
+        assert!(state
+            .update(niri_ipc::Event::WindowOpenedOrChanged { window: test_window(3, "kitty", false) }, &[])
+            .is_none());
+
+        match state.update(niri_ipc::Event::WindowFocusChanged { id: Some(3) }, &[]) {
+            Some(NiriEvent::FocusChanged(focus)) => {
+                assert_eq!(focus.window.unwrap().app_id, "kitty");
+            }
+            other => panic!("Expected FocusChanged, got {:?}", other),
+        }
+
+        state.update(niri_ipc::Event::WindowClosed { id: 3 }, &[]);
+        match state.update(niri_ipc::Event::WindowFocusChanged { id: Some(3) }, &[]) {
+            Some(NiriEvent::FocusChanged(focus)) => assert_eq!(focus.window, None),
+            other => panic!("Expected FocusChanged, got {:?}", other),
+        }
+    }
+}
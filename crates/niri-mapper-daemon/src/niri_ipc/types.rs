@@ -37,6 +37,9 @@ pub struct FocusedWindow {
     ///
     /// This ID is stable for the lifetime of the window.
     pub id: u64,
+
+    /// Whether the window is floating (as opposed to tiled)
+    pub is_floating: bool,
 }
 
 /// Information about a window
@@ -127,6 +130,7 @@ impl From<niri_ipc::Window> for FocusedWindow {
             app_id: window.app_id.unwrap_or_default(),
             title: window.title.unwrap_or_default(),
             id: window.id,
+            is_floating: window.is_floating,
         }
     }
 }
@@ -137,6 +141,7 @@ impl From<&niri_ipc::Window> for FocusedWindow {
             app_id: window.app_id.clone().unwrap_or_default(),
             title: window.title.clone().unwrap_or_default(),
             id: window.id,
+            is_floating: window.is_floating,
         }
     }
 }
@@ -236,6 +241,12 @@ pub struct WorkspaceChangeEvent {
     /// The output (monitor) this workspace is on
     pub output: String,
 
+    /// The workspace's name, if it has one
+    pub name: Option<String>,
+
+    /// Index of the workspace on its output (1-based), if known
+    pub idx: Option<u8>,
+
     /// Whether this workspace also became focused
     ///
     /// If `true`, this is now the single focused workspace across all outputs.
@@ -244,12 +255,34 @@ pub struct WorkspaceChangeEvent {
     pub is_focused: bool,
 }
 
+/// The keyboard layouts configured in niri
+///
+/// Emitted when niri's keyboard layout configuration changes, and once when
+/// the event stream starts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyboardLayouts {
+    /// XKB names of the configured layouts (e.g., "English (US)")
+    pub names: Vec<String>,
+
+    /// Index of the active layout in `names`
+    pub current_idx: u8,
+}
+
+impl From<niri_ipc::KeyboardLayouts> for KeyboardLayouts {
+    fn from(layouts: niri_ipc::KeyboardLayouts) -> Self {
+        Self {
+            names: layouts.names,
+            current_idx: layouts.current_idx,
+        }
+    }
+}
+
 /// Internal representation of niri compositor events
 ///
 /// This enum provides a simplified view of niri IPC events, containing only
 /// the event types relevant to niri-mapper's remapping functionality.
-/// Events not needed for remapping (like layout changes) are filtered out
-/// at the event parsing stage.
+/// Events not needed for remapping (like window layout changes) are filtered
+/// out at the event parsing stage.
 ///
 /// # Conversion
 ///
@@ -262,6 +295,15 @@ pub enum NiriEvent {
 
     /// Workspace was activated
     WorkspaceActivated(WorkspaceChangeEvent),
+
+    /// The configured keyboard layouts changed
+    KeyboardLayoutsChanged(KeyboardLayouts),
+
+    /// The active keyboard layout switched to the layout at `idx`
+    KeyboardLayoutSwitched {
+        /// Index of the newly active layout
+        idx: u8,
+    },
 }
 
 impl NiriEvent {
@@ -274,12 +316,13 @@ impl NiriEvent {
     ///
     /// - `WindowFocusChanged` -> `NiriEvent::FocusChanged`
     /// - `WorkspaceActivated` -> `NiriEvent::WorkspaceActivated`
+    /// - `KeyboardLayoutsChanged` -> `NiriEvent::KeyboardLayoutsChanged`
+    /// - `KeyboardLayoutSwitched` -> `NiriEvent::KeyboardLayoutSwitched`
     ///
     /// # Ignored Events
     ///
     /// - Window opened/closed (not needed for focus-based remapping)
-    /// - Layout changes
-    /// - Keyboard layout changes
+    /// - Window layout changes
     /// - Other compositor state changes
     pub fn from_niri_event(event: niri_ipc::Event, windows: &[niri_ipc::Window]) -> Option<Self> {
         match event {
@@ -299,9 +342,17 @@ impl NiriEvent {
                 Some(NiriEvent::WorkspaceActivated(WorkspaceChangeEvent {
                     workspace_id: id,
                     output: String::new(), // To be enriched by caller with workspace info
+                    name: None,
+                    idx: None,
                     is_focused: focused,
                 }))
             }
+            niri_ipc::Event::KeyboardLayoutsChanged { keyboard_layouts } => {
+                Some(NiriEvent::KeyboardLayoutsChanged(keyboard_layouts.into()))
+            }
+            niri_ipc::Event::KeyboardLayoutSwitched { idx } => {
+                Some(NiriEvent::KeyboardLayoutSwitched { idx })
+            }
             // Ignore other events not relevant to remapping
             _ => None,
         }
@@ -319,6 +370,8 @@ impl NiriEvent {
         NiriEvent::WorkspaceActivated(WorkspaceChangeEvent {
             workspace_id,
             output,
+            name: None,
+            idx: None,
             is_focused,
         })
     }
@@ -427,6 +480,7 @@ mod tests {
             id: 42,
             app_id: "firefox".to_string(),
             title: "Mozilla Firefox".to_string(),
+            is_floating: false,
         };
 
         let event = FocusChangeEvent {
@@ -448,6 +502,8 @@ mod tests {
         let event = WorkspaceChangeEvent {
             workspace_id: 5,
             output: "DP-1".to_string(),
+            name: Some("games".to_string()),
+            idx: Some(2),
             is_focused: true,
         };
 
@@ -462,6 +518,7 @@ mod tests {
             id: 10,
             app_id: "alacritty".to_string(),
             title: "Terminal".to_string(),
+            is_floating: false,
         };
 
         let event = NiriEvent::focus_changed(Some(window.clone()));
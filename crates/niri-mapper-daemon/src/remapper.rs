@@ -33,17 +33,22 @@
 //!
 //! 3. **Control socket**: `{"switch_profile": {"device": "...", "profile": "..."}}`
 //!
-//! ## Future: Automatic Switching (Backlog)
+//! ## Automatic Switching
 //!
-//! Automatic profile switching based on focused application's `app_id` is **not
-//! implemented** in v0.4.0. The intended future behavior:
+//! The daemon also switches profiles automatically from niri focus events,
+//! using the profiles' `app-id-hint`, `workspace-hint`, `output-hint` and
+//! `layout-hint`; see
+//! [`DeviceConfig::select_profile`](niri_mapper_config::DeviceConfig::select_profile)
+//! for the precedence between them.
 //!
-//! 1. Daemon monitors niri focus change events
-//! 2. On focus change, looks up profiles with matching `app_id_hint`
-//! 3. Calls `switch_profile()` automatically for matching profiles
+//! ## Conditional Rules
 //!
-//! The [`Profile::app_id_hint`](niri_mapper_config::Profile::app_id_hint) field
-//! exists for users to annotate their profiles in preparation for this feature.
+//! Within a profile, `remap`, `combo` and `macro` entries can carry `when`
+//! conditions on the focused window, workspace and output. The remapper keeps
+//! the latest focus (see [`Remapper::set_focus()`]) and checks these rules on
+//! every key press before the profile's unconditional rules. Releases and
+//! repeats follow the rule chosen on press, so a focus change while a key is
+//! held cannot leave a key stuck.
 //!
 //! # Combo State Machine Design (Task 020-2.1)
 //!
@@ -102,7 +107,7 @@
 //! ## Key Tracking
 //!
 //! The state machine tracks:
-//! - `held_modifiers: HashSet<Modifier>` - Currently pressed modifier keys (normalized)
+//! - `held_modifiers: BTreeSet<Modifier>` - Currently pressed modifier keys (normalized)
 //! - `active_combo: Option<ActiveCombo>` - The currently active (matched) combo, if any
 //!
 //! ## Modifier Normalization
@@ -126,12 +131,15 @@
 //! - No match: Ctrl held, Q pressed (missing Shift)
 //! - No match: Ctrl held, Shift held, Alt held, Q pressed (extra modifier)
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap};
 use std::fmt;
-use std::str::FromStr;
 
-use evdev::{InputEvent, Key};
-use niri_mapper_config::{MacroAction, Profile};
+use evdev::{InputEvent, InputEventKind, Key};
+use niri_mapper_config::{
+    ConditionalEntry, ConditionalRule, FocusContext, KeyCode, MacroAction, MacroPolicy, Profile,
+};
+
+use crate::keysym::KeyTranslation;
 
 // ============================================================================
 // RemapResult (Task 030-1.2.4, 030-3.3.3)
@@ -153,9 +161,16 @@ pub enum RemapResult {
 
     /// A macro sequence that should be executed asynchronously.
     ///
-    /// The caller should spawn an async task to execute these actions,
-    /// which may include delays between key presses.
-    Macro(Vec<MacroAction>),
+    /// The caller should hand the trigger to the `MacroExecutor`, which applies
+    /// the macro's concurrency policy and runs the actions (which may include
+    /// delays between key presses) in a background task.
+    Macro(MacroTrigger),
+
+    /// The trigger key of a macro was released.
+    ///
+    /// The release event itself is consumed. The caller should forward this to
+    /// the `MacroExecutor` so that `cancel-on-release` macros can be stopped.
+    MacroRelease(Key),
 
     /// A profile switch was triggered by a keybind.
     ///
@@ -164,265 +179,40 @@ pub enum RemapResult {
     ProfileSwitch(String),
 }
 
+/// A macro bound to a trigger key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MacroTrigger {
+    /// The key that triggers the macro
+    pub key: Key,
+    /// The actions to execute
+    pub actions: Vec<MacroAction>,
+    /// What to do if the macro is triggered again while it is still running
+    pub policy: MacroPolicy,
+}
+
 // ============================================================================
 // Combo Types (Task 020-2.1, 020-2.3)
 // ============================================================================
 
-/// Normalized modifier key representation.
-///
-/// Left and right variants are combined into a single modifier type to allow
-/// flexible combo matching (e.g., either left or right Ctrl satisfies "Ctrl").
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
-pub enum Modifier {
-    /// Control key (left or right)
-    Ctrl,
-    /// Shift key (left or right)
-    Shift,
-    /// Alt key (left or right)
-    Alt,
-    /// Super/Meta/Windows key (left or right)
-    Super,
-}
-
-impl Modifier {
-    /// Check if an evdev key is a modifier and return its normalized form.
-    pub fn from_key(key: Key) -> Option<Self> {
-        match key {
-            Key::KEY_LEFTCTRL | Key::KEY_RIGHTCTRL => Some(Modifier::Ctrl),
-            Key::KEY_LEFTSHIFT | Key::KEY_RIGHTSHIFT => Some(Modifier::Shift),
-            Key::KEY_LEFTALT | Key::KEY_RIGHTALT => Some(Modifier::Alt),
-            Key::KEY_LEFTMETA | Key::KEY_RIGHTMETA => Some(Modifier::Super),
-            _ => None,
-        }
-    }
-
-    /// Parse a modifier name string (case-insensitive).
-    ///
-    /// Recognized names:
-    /// - Ctrl: "ctrl", "control"
-    /// - Shift: "shift"
-    /// - Alt: "alt"
-    /// - Super: "super", "meta", "mod", "win", "windows"
-    pub fn from_str_name(name: &str) -> Option<Self> {
-        match name.to_uppercase().as_str() {
-            "CTRL" | "CONTROL" => Some(Modifier::Ctrl),
-            "SHIFT" => Some(Modifier::Shift),
-            "ALT" => Some(Modifier::Alt),
-            "SUPER" | "META" | "MOD" | "WIN" | "WINDOWS" => Some(Modifier::Super),
-            _ => None,
-        }
-    }
-
-    /// Get the default evdev key for this modifier (left variant).
-    pub fn to_key(self) -> Key {
-        match self {
-            Modifier::Ctrl => Key::KEY_LEFTCTRL,
-            Modifier::Shift => Key::KEY_LEFTSHIFT,
-            Modifier::Alt => Key::KEY_LEFTALT,
-            Modifier::Super => Key::KEY_LEFTMETA,
-        }
-    }
-}
-
-impl fmt::Display for Modifier {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Modifier::Ctrl => write!(f, "Ctrl"),
-            Modifier::Shift => write!(f, "Shift"),
-            Modifier::Alt => write!(f, "Alt"),
-            Modifier::Super => write!(f, "Super"),
-        }
-    }
-}
+pub use niri_mapper_config::{KeyCombo, Modifier};
 
-/// A parsed key combination (chord).
-///
-/// Represents a combination like `Ctrl+Shift+Q` as:
-/// - `modifiers`: Set of modifier keys that must be held
-/// - `key`: The trigger key that activates the combo
+/// The modifier an evdev key holds, if it is one.
 ///
-/// # Example
-///
-/// ```ignore
-/// let combo = KeyCombo::parse("Ctrl+Shift+Q")?;
-/// assert_eq!(combo.modifiers, HashSet::from([Modifier::Ctrl, Modifier::Shift]));
-/// assert_eq!(combo.key, Key::KEY_Q);
-/// ```
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct KeyCombo {
-    /// Set of modifiers that must be held for this combo
-    pub modifiers: HashSet<Modifier>,
-    /// The trigger key (non-modifier) that activates the combo
-    pub key: Key,
-}
-
-impl std::hash::Hash for KeyCombo {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        // Hash modifiers in sorted order for consistent hashing
-        let mut mods: Vec<_> = self.modifiers.iter().collect();
-        mods.sort();
-        for m in mods {
-            m.hash(state);
-        }
-        self.key.hash(state);
+/// Left and right variants map to the same modifier, so either Ctrl key
+/// satisfies "Ctrl" in a combo.
+pub fn modifier_of(key: Key) -> Option<Modifier> {
+    match key {
+        Key::KEY_LEFTCTRL | Key::KEY_RIGHTCTRL => Some(Modifier::Ctrl),
+        Key::KEY_LEFTSHIFT | Key::KEY_RIGHTSHIFT => Some(Modifier::Shift),
+        Key::KEY_LEFTALT | Key::KEY_RIGHTALT => Some(Modifier::Alt),
+        Key::KEY_LEFTMETA | Key::KEY_RIGHTMETA => Some(Modifier::Super),
+        _ => None,
     }
 }
 
-impl KeyCombo {
-    /// Create a new KeyCombo with no modifiers.
-    pub fn new(key: Key) -> Self {
-        Self {
-            modifiers: HashSet::new(),
-            key,
-        }
-    }
-
-    /// Create a new KeyCombo with the specified modifiers.
-    pub fn with_modifiers(modifiers: HashSet<Modifier>, key: Key) -> Self {
-        Self { modifiers, key }
-    }
-}
-
-impl fmt::Display for KeyCombo {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        // Sort modifiers for consistent output order: Ctrl, Shift, Alt, Super
-        let mut mods: Vec<_> = self.modifiers.iter().collect();
-        mods.sort();
-
-        for modifier in mods {
-            write!(f, "{}+", modifier)?;
-        }
-
-        // Format the key name (strip KEY_ prefix if present)
-        let key_name = format!("{:?}", self.key);
-        let display_name = key_name.strip_prefix("KEY_").unwrap_or(&key_name);
-        write!(f, "{}", display_name)
-    }
-}
-
-/// Error type for combo parsing failures.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct ComboParseError {
-    /// The original input string that failed to parse
-    pub input: String,
-    /// Description of what went wrong
-    pub reason: String,
-}
-
-impl fmt::Display for ComboParseError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "failed to parse combo '{}': {}", self.input, self.reason)
-    }
-}
-
-impl std::error::Error for ComboParseError {}
-
-/// Parse a combo key string into a structured representation.
-///
-/// Parses strings like `"Ctrl+Shift+Q"` into a [`KeyCombo`] with:
-/// - `modifiers`: `[Ctrl, Shift]`
-/// - `key`: `KEY_Q`
-///
-/// # Format
-///
-/// The expected format is `[Modifier+]...[Modifier+]Key` where:
-/// - Modifiers are `Ctrl`, `Shift`, `Alt`, `Super` (case-insensitive)
-/// - Key is any valid key name (see [`parse_key`])
-/// - Components are separated by `+`
-///
-/// # Modifier Order Independence
-///
-/// Modifier order does not matter: `"Ctrl+Shift+Q"` and `"Shift+Ctrl+Q"` are equivalent.
-///
-/// # Errors
-///
-/// Returns [`ComboParseError`] if:
-/// - The input is empty
-/// - No trigger key is found (only modifiers)
-/// - The trigger key name is unrecognized
-/// - Duplicate modifiers are specified
-///
-/// # Examples
-///
-/// ```ignore
-/// // Simple combo with modifiers
-/// let combo = parse_combo("Ctrl+Shift+Q")?;
-/// assert!(combo.modifiers.contains(&Modifier::Ctrl));
-/// assert!(combo.modifiers.contains(&Modifier::Shift));
-/// assert_eq!(combo.key, Key::KEY_Q);
-///
-/// // Single key (no modifiers) - still valid
-/// let combo = parse_combo("Escape")?;
-/// assert!(combo.modifiers.is_empty());
-/// assert_eq!(combo.key, Key::KEY_ESC);
-///
-/// // Modifier order doesn't matter
-/// let combo1 = parse_combo("Ctrl+Alt+Delete")?;
-/// let combo2 = parse_combo("Alt+Ctrl+Delete")?;
-/// assert_eq!(combo1.modifiers, combo2.modifiers);
-/// ```
-pub fn parse_combo(input: &str) -> Result<KeyCombo, ComboParseError> {
-    let input = input.trim();
-
-    if input.is_empty() {
-        return Err(ComboParseError {
-            input: input.to_string(),
-            reason: "empty input".to_string(),
-        });
-    }
-
-    let parts: Vec<&str> = input.split('+').map(|s| s.trim()).collect();
-
-    if parts.is_empty() || parts.iter().any(|p| p.is_empty()) {
-        return Err(ComboParseError {
-            input: input.to_string(),
-            reason: "invalid format: empty component in combo string".to_string(),
-        });
-    }
-
-    let mut modifiers = HashSet::new();
-    let mut trigger_key: Option<Key> = None;
-
-    for part in &parts {
-        // Try to parse as a modifier first
-        if let Some(modifier) = Modifier::from_str_name(part) {
-            if !modifiers.insert(modifier) {
-                return Err(ComboParseError {
-                    input: input.to_string(),
-                    reason: format!("duplicate modifier: {}", modifier),
-                });
-            }
-        } else {
-            // Not a modifier, must be the trigger key
-            if trigger_key.is_some() {
-                return Err(ComboParseError {
-                    input: input.to_string(),
-                    reason: format!(
-                        "multiple non-modifier keys found: expected exactly one trigger key, got '{}' after already finding one",
-                        part
-                    ),
-                });
-            }
-
-            match parse_key(part) {
-                Some(key) => trigger_key = Some(key),
-                None => {
-                    return Err(ComboParseError {
-                        input: input.to_string(),
-                        reason: format!("unknown key: '{}'", part),
-                    });
-                }
-            }
-        }
-    }
-
-    match trigger_key {
-        Some(key) => Ok(KeyCombo::with_modifiers(modifiers, key)),
-        None => Err(ComboParseError {
-            input: input.to_string(),
-            reason: "no trigger key found (only modifiers specified)".to_string(),
-        }),
-    }
+/// The evdev key of a key from the config
+pub(crate) fn to_key(key: KeyCode) -> Key {
+    Key::new(key.code())
 }
 
 // ============================================================================
@@ -460,7 +250,7 @@ pub struct ActiveCombo {
     /// The trigger key that activated this combo (e.g., KEY_Q)
     pub trigger_key: Key,
     /// The modifiers that were held when the combo was activated
-    pub input_modifiers: HashSet<Modifier>,
+    pub input_modifiers: BTreeSet<Modifier>,
     /// The output combo that was injected (modifiers + key)
     pub output_combo: KeyCombo,
 }
@@ -486,7 +276,7 @@ impl Default for ComboState {
 #[derive(Debug, Clone)]
 pub struct ComboTracker {
     /// Currently held modifiers (normalized)
-    pub held_modifiers: HashSet<Modifier>,
+    pub held_modifiers: BTreeSet<Modifier>,
     /// Current state of the combo state machine
     pub state: ComboState,
     /// Registered combo mappings: input combo -> output combo
@@ -563,7 +353,7 @@ pub mod event_value {
 ///
 /// Note: SYN_REPORT events are NOT included; the caller should add them as needed.
 pub fn generate_combo_press_events(
-    input_modifiers: &HashSet<Modifier>,
+    input_modifiers: &BTreeSet<Modifier>,
     output_combo: &KeyCombo,
 ) -> Vec<InputEvent> {
     let mut events = Vec::new();
@@ -579,7 +369,7 @@ pub fn generate_combo_press_events(
     for modifier in to_release {
         events.push(InputEvent::new(
             evdev::EventType::KEY,
-            modifier.to_key().code(),
+            modifier.key().code(),
             event_value::RELEASE,
         ));
     }
@@ -595,7 +385,7 @@ pub fn generate_combo_press_events(
     for modifier in to_press {
         events.push(InputEvent::new(
             evdev::EventType::KEY,
-            modifier.to_key().code(),
+            modifier.key().code(),
             event_value::PRESS,
         ));
     }
@@ -640,9 +430,9 @@ pub fn generate_combo_press_events(
 /// 3. Press Ctrl (restore)
 /// 4. Press Shift (restore)
 pub fn generate_combo_release_events(
-    input_modifiers: &HashSet<Modifier>,
+    input_modifiers: &BTreeSet<Modifier>,
     output_combo: &KeyCombo,
-    still_held_modifiers: &HashSet<Modifier>,
+    still_held_modifiers: &BTreeSet<Modifier>,
 ) -> Vec<InputEvent> {
     let mut events = Vec::new();
 
@@ -664,7 +454,7 @@ pub fn generate_combo_release_events(
     for modifier in to_release {
         events.push(InputEvent::new(
             evdev::EventType::KEY,
-            modifier.to_key().code(),
+            modifier.key().code(),
             event_value::RELEASE,
         ));
     }
@@ -672,7 +462,7 @@ pub fn generate_combo_release_events(
     // Step 3: Restore input modifiers that are still physically held
     // These are modifiers that were in the input combo, were released for the output,
     // and are still being physically held by the user
-    let released_input_mods: HashSet<_> = input_modifiers
+    let released_input_mods: BTreeSet<_> = input_modifiers
         .difference(&output_combo.modifiers)
         .copied()
         .collect();
@@ -686,7 +476,7 @@ pub fn generate_combo_release_events(
     for modifier in to_restore {
         events.push(InputEvent::new(
             evdev::EventType::KEY,
-            modifier.to_key().code(),
+            modifier.key().code(),
             event_value::PRESS,
         ));
     }
@@ -698,7 +488,7 @@ impl ComboTracker {
     /// Create a new combo tracker with no registered combos.
     pub fn new() -> Self {
         Self {
-            held_modifiers: HashSet::new(),
+            held_modifiers: BTreeSet::new(),
             state: ComboState::Idle,
             combos: HashMap::new(),
             active_combo: None,
@@ -727,7 +517,7 @@ impl ComboTracker {
     /// * `key` - The key that generated the event
     /// * `value` - The event value (0=release, 1=press, 2=repeat)
     pub fn update_held_modifiers(&mut self, key: Key, value: i32) {
-        if let Some(modifier) = Modifier::from_key(key) {
+        if let Some(modifier) = modifier_of(key) {
             match value {
                 0 => {
                     // Release: remove modifier from held set
@@ -793,8 +583,8 @@ impl ComboTracker {
     /// ```ignore
     /// let mut tracker = ComboTracker::new();
     /// tracker.register_combo(
-    ///     parse_combo("Ctrl+Shift+Q").unwrap(),
-    ///     parse_combo("Alt+F4").unwrap(),
+    ///     "Ctrl+Shift+Q".parse::<KeyCombo>().unwrap(),
+    ///     "Alt+F4".parse::<KeyCombo>().unwrap(),
     /// );
     ///
     /// // Simulate Ctrl+Shift held
@@ -807,7 +597,7 @@ impl ComboTracker {
     /// ```
     pub fn check_combo_match(&self, key: Key) -> ComboMatchResult {
         // Skip modifier keys - they cannot be trigger keys
-        if Modifier::from_key(key).is_some() {
+        if modifier_of(key).is_some() {
             return ComboMatchResult::NoMatch;
         }
 
@@ -815,7 +605,7 @@ impl ComboTracker {
         // - Same trigger key
         // - Exactly the same set of modifiers (not a subset, not a superset)
         for (input_combo, output_combo) in &self.combos {
-            if input_combo.key == key && input_combo.modifiers == self.held_modifiers {
+            if to_key(input_combo.key) == key && input_combo.modifiers == self.held_modifiers {
                 return ComboMatchResult::Matched {
                     input: input_combo.clone(),
                     output: output_combo.clone(),
@@ -878,13 +668,13 @@ impl ComboTracker {
     /// tracker.activate_combo(
     ///     Key::KEY_Q,
     ///     [Modifier::Ctrl, Modifier::Shift].into_iter().collect(),
-    ///     parse_combo("Alt+F4").unwrap(),
+    ///     "Alt+F4".parse::<KeyCombo>().unwrap(),
     /// );
     /// ```
     pub fn activate_combo(
         &mut self,
         trigger_key: Key,
-        input_modifiers: HashSet<Modifier>,
+        input_modifiers: BTreeSet<Modifier>,
         output_combo: KeyCombo,
     ) {
         self.active_combo = Some(ActiveCombo {
@@ -977,6 +767,57 @@ impl ComboTracker {
     }
 }
 
+// ============================================================================
+// Conditional Rules
+// ============================================================================
+
+/// Whether pressing `key` with `held_modifiers` triggers a conditional entry
+fn triggers_conditional(entry: &ConditionalEntry, key: Key, held_modifiers: &BTreeSet<Modifier>) -> bool {
+    match entry {
+        ConditionalEntry::Remap { from, .. } => to_key(*from) == key,
+        // Same exact-modifier matching as `ComboTracker::check_combo_match()`
+        ConditionalEntry::Combo { from, .. } => {
+            modifier_of(key).is_none() && to_key(from.key) == key && from.modifiers == *held_modifiers
+        }
+        ConditionalEntry::Macro { trigger, .. } => to_key(*trigger) == key,
+    }
+}
+
+/// The remaps of a profile
+fn parse_remaps(profile: &Profile) -> HashMap<Key, Key> {
+    profile
+        .remap
+        .iter()
+        .map(|(from, to)| (to_key(*from), to_key(*to)))
+        .collect()
+}
+
+/// Register the combos of a profile
+fn register_combos(combo_tracker: &mut ComboTracker, profile: &Profile) {
+    for (input, output) in &profile.combo {
+        tracing::debug!("Registered combo: {} -> {}", input, output);
+        combo_tracker.register_combo(input.clone(), output.clone());
+    }
+}
+
+/// The macros of a profile, keyed by trigger key
+fn parse_macro_triggers(profile: &Profile) -> HashMap<Key, MacroTrigger> {
+    profile
+        .macros
+        .iter()
+        .map(|(trigger, actions)| {
+            tracing::debug!("Registered macro trigger: {} -> {} actions", trigger, actions.len());
+            let key = to_key(*trigger);
+            let trigger = MacroTrigger {
+                key,
+                actions: actions.clone(),
+                policy: profile.macro_policies.get(trigger).copied().unwrap_or_default(),
+            };
+            (key, trigger)
+        })
+        .collect()
+}
+
 // ============================================================================
 // Remapper
 // ============================================================================
@@ -998,7 +839,7 @@ pub struct Remapper {
     /// - Press (value=1): modifier is added to the set
     /// - Release (value=0): modifier is removed from the set
     /// - Repeat (value=2): no change (set already contains the modifier)
-    held_modifiers: HashSet<Modifier>,
+    held_modifiers: BTreeSet<Modifier>,
     /// Combo tracker for handling key combination remappings
     ///
     /// This tracks modifier state and matches input combos against registered
@@ -1011,77 +852,69 @@ pub struct Remapper {
     /// should be executed instead of the normal key event. The sequence can
     /// contain key presses/releases and delays.
     ///
-    /// Populated by `from_profile()` from `profile.macros` configuration.
-    macro_triggers: HashMap<Key, Vec<MacroAction>>,
+    /// Populated by `from_profile()` from `profile.macros` configuration,
+    /// together with the policy from `profile.macro_policies`.
+    macro_triggers: HashMap<Key, MacroTrigger>,
+    /// Keysym mode translation between physical keys and rule keys
+    ///
+    /// `None` in keycode mode, where rules match physical keys directly.
+    key_translation: Option<KeyTranslation>,
+    /// Logical key each held physical key was translated to on press
+    ///
+    /// Releases and repeats use the key of the press, so a layout switch while
+    /// a key is held cannot leave a remapped key stuck.
+    translated_keys: HashMap<Key, Option<Key>>,
+    /// Rules with `when` conditions, in config order
+    conditional_rules: Vec<ConditionalRule>,
+    /// What the user is focused on, for evaluating `conditional_rules`
+    focus: FocusContext,
+    /// Conditional entry each held key triggered on press
+    ///
+    /// Releases and repeats use the entry of the press, even if the focus
+    /// has changed since.
+    held_conditional: HashMap<Key, ConditionalEntry>,
 }
 
 impl Remapper {
     /// Create a new remapper from a profile
     pub fn from_profile(profile: &Profile) -> Self {
-        let mut remap = HashMap::new();
+        let remap = parse_remaps(profile);
 
-        for (from, to) in &profile.remap {
-            if let (Some(from_key), Some(to_key)) = (parse_key(from), parse_key(to)) {
-                remap.insert(from_key, to_key);
-            }
-        }
-
-        // Parse combo mappings from profile
         let mut combo_tracker = ComboTracker::new();
-        for (input_str, output_str) in &profile.combo {
-            match (parse_combo(input_str), parse_combo(output_str)) {
-                (Ok(input_combo), Ok(output_combo)) => {
-                    tracing::debug!(
-                        "Registered combo: {} -> {}",
-                        input_combo,
-                        output_combo
-                    );
-                    combo_tracker.register_combo(input_combo, output_combo);
-                }
-                (Err(e), _) => {
-                    tracing::warn!("Failed to parse input combo '{}': {}", input_str, e);
-                }
-                (_, Err(e)) => {
-                    tracing::warn!("Failed to parse output combo '{}': {}", output_str, e);
-                }
-            }
-        }
+        register_combos(&mut combo_tracker, profile);
 
         // TODO: Parse passthrough keys from niri_passthrough
 
-        // Parse macro triggers from profile.macros (Task 030-1.2.2)
-        // For v0.3.0, only single-key triggers are supported (combo triggers are Out of Scope)
-        let mut macro_triggers = HashMap::new();
-        for (trigger_str, actions) in &profile.macros {
-            match parse_key(trigger_str) {
-                Some(trigger_key) => {
-                    tracing::debug!(
-                        "Registered macro trigger: {} -> {} actions",
-                        trigger_str,
-                        actions.len()
-                    );
-                    macro_triggers.insert(trigger_key, actions.clone());
-                }
-                None => {
-                    // Fail hard on invalid trigger keys as per task requirements
-                    panic!(
-                        "Invalid macro trigger key '{}': unknown key name. \
-                         Valid keys include: A-Z, 0-9, F1-F24, Escape, Tab, etc.",
-                        trigger_str
-                    );
-                }
-            }
-        }
+        let macro_triggers = parse_macro_triggers(profile);
 
         Self {
             remap,
             passthrough: Vec::new(),
-            held_modifiers: HashSet::new(),
+            held_modifiers: BTreeSet::new(),
             combo_tracker,
             macro_triggers,
+            key_translation: None,
+            translated_keys: HashMap::new(),
+            conditional_rules: profile.conditional_rules.clone(),
+            focus: FocusContext::default(),
+            held_conditional: HashMap::new(),
         }
     }
 
+    /// Set what the user is focused on, for rules with `when` conditions.
+    ///
+    /// The focus is kept across profile switches.
+    pub fn set_focus(&mut self, focus: &FocusContext) {
+        self.focus = focus.clone();
+    }
+
+    /// Set the keysym mode translation (`None` matches rules by key code).
+    ///
+    /// The translation is kept across profile switches.
+    pub fn set_key_translation(&mut self, key_translation: Option<KeyTranslation>) {
+        self.key_translation = key_translation;
+    }
+
     /// Switch to a new profile, replacing all remap/combo/macro rules.
     ///
     /// This method reloads the remapper with rules from a new profile while
@@ -1097,14 +930,11 @@ impl Remapper {
     /// - All remap rules are replaced with the new profile's rules
     /// - All combo mappings are replaced with the new profile's combos
     /// - All macro triggers are replaced with the new profile's macros
+    /// - All conditional rules are replaced with the new profile's rules
     /// - Currently held modifiers are preserved (not cleared)
+    /// - Keys held on a conditional rule still release through that rule
     /// - Any active combo is cleared (to avoid stale state)
     ///
-    /// # Panics
-    ///
-    /// Panics if the profile contains invalid macro trigger key names (same as
-    /// `from_profile()`).
-    ///
     /// # Example
     ///
     /// ```ignore
@@ -1116,69 +946,28 @@ impl Remapper {
     /// ```
     pub fn switch_profile(&mut self, profile: &Profile) {
         // Clear existing rules
-        self.remap.clear();
         self.passthrough.clear();
-        self.macro_triggers.clear();
 
         // Clear active combo state to avoid stale output tracking
         // Note: held_modifiers is preserved since physical keys may still be pressed
         self.combo_tracker.clear_active_combo();
         self.combo_tracker.combos.clear();
 
-        // Reload remap rules from new profile
-        for (from, to) in &profile.remap {
-            if let (Some(from_key), Some(to_key)) = (parse_key(from), parse_key(to)) {
-                self.remap.insert(from_key, to_key);
-            }
-        }
+        // Reload remap rules, combo mappings and macro triggers from new profile
+        self.remap = parse_remaps(profile);
+        register_combos(&mut self.combo_tracker, profile);
+        self.macro_triggers = parse_macro_triggers(profile);
 
-        // Reload combo mappings from new profile
-        for (input_str, output_str) in &profile.combo {
-            match (parse_combo(input_str), parse_combo(output_str)) {
-                (Ok(input_combo), Ok(output_combo)) => {
-                    tracing::debug!(
-                        "Registered combo: {} -> {}",
-                        input_combo,
-                        output_combo
-                    );
-                    self.combo_tracker.register_combo(input_combo, output_combo);
-                }
-                (Err(e), _) => {
-                    tracing::warn!("Failed to parse input combo '{}': {}", input_str, e);
-                }
-                (_, Err(e)) => {
-                    tracing::warn!("Failed to parse output combo '{}': {}", output_str, e);
-                }
-            }
-        }
-
-        // Reload macro triggers from new profile
-        for (trigger_str, actions) in &profile.macros {
-            match parse_key(trigger_str) {
-                Some(trigger_key) => {
-                    tracing::debug!(
-                        "Registered macro trigger: {} -> {} actions",
-                        trigger_str,
-                        actions.len()
-                    );
-                    self.macro_triggers.insert(trigger_key, actions.clone());
-                }
-                None => {
-                    // Fail hard on invalid trigger keys as per task requirements
-                    panic!(
-                        "Invalid macro trigger key '{}': unknown key name. \
-                         Valid keys include: A-Z, 0-9, F1-F24, Escape, Tab, etc.",
-                        trigger_str
-                    );
-                }
-            }
-        }
+        // Reload conditional rules; `held_conditional` is preserved so held
+        // keys still release correctly
+        self.conditional_rules = profile.conditional_rules.clone();
 
         tracing::debug!(
-            "Profile switched: {} remaps, {} combos, {} macros",
+            "Profile switched: {} remaps, {} combos, {} macros, {} conditional rules",
             self.remap.len(),
             self.combo_tracker.combos.len(),
-            self.macro_triggers.len()
+            self.macro_triggers.len(),
+            self.conditional_rules.len()
         );
     }
 
@@ -1186,7 +975,7 @@ impl Remapper {
     ///
     /// Returns a reference to the set of modifier keys that are currently pressed.
     /// This is useful for combo detection and debugging.
-    pub fn held_modifiers(&self) -> &HashSet<Modifier> {
+    pub fn held_modifiers(&self) -> &BTreeSet<Modifier> {
         &self.held_modifiers
     }
 
@@ -1206,7 +995,7 @@ impl Remapper {
     /// * `value` - The event value (0=release, 1=press, 2=repeat)
     pub fn update_held_modifiers(&mut self, key: Key, value: i32) {
         // Check if this key is a modifier
-        if let Some(modifier) = Modifier::from_key(key) {
+        if let Some(modifier) = modifier_of(key) {
             match value {
                 0 => {
                     // Release: remove modifier from held set
@@ -1241,6 +1030,8 @@ impl Remapper {
     /// # Processing Order
     ///
     /// On key press (value == 1), events are checked in this order:
+    /// 0. **Conditional rules**: The first remap, combo or macro with a `when`
+    ///    condition matching the current focus (see [`Self::set_focus()`]).
     /// 1. **Macro triggers**: If the key matches a registered macro trigger,
     ///    return `RemapResult::Macro` with the action sequence.
     /// 2. **Combo matching**: If the key + held modifiers match a registered combo,
@@ -1248,8 +1039,75 @@ impl Remapper {
     /// 3. **Simple remaps**: If the key is in the remap table, remap it.
     /// 4. **Passthrough**: Otherwise, pass through unchanged.
     ///
-    /// On key release/repeat, macro triggers are NOT checked (only on press).
+    /// On key release, a macro trigger key is consumed and reported as
+    /// `RemapResult::MacroRelease`. Repeats of a macro trigger key are dropped.
+    ///
+    /// In keysym mode, the key is translated to its logical key before rules
+    /// are matched, and keys emitted by rules are translated back to physical
+    /// keys. Keys that no rule matches pass through untranslated.
     pub fn process(&mut self, event: InputEvent) -> RemapResult {
+        let Some(translation) = &self.key_translation else {
+            return self.process_logical(event);
+        };
+        if event.event_type() != evdev::EventType::KEY {
+            return RemapResult::Events(vec![event]);
+        }
+
+        let physical = Key::new(event.code());
+        let value = event.value();
+        let logical = match value {
+            event_value::PRESS => {
+                let logical = translation.logical(physical);
+                self.translated_keys.insert(physical, logical);
+                logical
+            }
+            event_value::RELEASE => self
+                .translated_keys
+                .remove(&physical)
+                .unwrap_or_else(|| translation.logical(physical)),
+            _ => self
+                .translated_keys
+                .get(&physical)
+                .copied()
+                .unwrap_or_else(|| translation.logical(physical)),
+        };
+
+        // Keys typing characters without a logical key match no rules
+        let Some(logical) = logical else {
+            return RemapResult::Events(vec![event]);
+        };
+
+        match self.process_logical(InputEvent::new(evdev::EventType::KEY, logical.code(), value)) {
+            RemapResult::Events(events) => {
+                let passed_through = events.len() == 1
+                    && events[0].event_type() == evdev::EventType::KEY
+                    && events[0].code() == logical.code()
+                    && events[0].value() == value;
+                if passed_through {
+                    return RemapResult::Events(vec![event]);
+                }
+
+                let translation = self.key_translation.as_ref().expect("checked above");
+                RemapResult::Events(
+                    events
+                        .into_iter()
+                        .map(|e| match e.kind() {
+                            InputEventKind::Key(key) => InputEvent::new(
+                                evdev::EventType::KEY,
+                                translation.physical(key).code(),
+                                e.value(),
+                            ),
+                            _ => e,
+                        })
+                        .collect(),
+                )
+            }
+            other => other,
+        }
+    }
+
+    /// Process an event whose key is a rule (logical) key.
+    fn process_logical(&mut self, event: InputEvent) -> RemapResult {
         // Only process key events
         if event.event_type() != evdev::EventType::KEY {
             return RemapResult::Events(vec![event]);
@@ -1262,12 +1120,39 @@ impl Remapper {
         self.update_held_modifiers(key, value);
         self.combo_tracker.update_held_modifiers(key, value);
 
+        // Releases and repeats of a key pressed on a conditional rule follow that rule
+        if let Some(entry) = self.held_conditional.get(&key) {
+            match value {
+                event_value::RELEASE => {
+                    let entry = self.held_conditional.remove(&key).expect("checked above");
+                    return self.release_conditional(key, entry);
+                }
+                event_value::REPEAT => {
+                    return match entry {
+                        ConditionalEntry::Remap { to, .. } => RemapResult::Events(vec![
+                            InputEvent::new(evdev::EventType::KEY, to.code(), value),
+                        ]),
+                        ConditionalEntry::Combo { to, .. } => RemapResult::Events(vec![
+                            InputEvent::new(evdev::EventType::KEY, to.key.code(), value),
+                        ]),
+                        ConditionalEntry::Macro { .. } => RemapResult::Events(Vec::new()),
+                    };
+                }
+                _ => {}
+            }
+        }
+
         match value {
             event_value::PRESS => {
+                if let Some(entry) = self.matching_conditional(key) {
+                    self.held_conditional.insert(key, entry.clone());
+                    return self.press_conditional(key, entry);
+                }
+
                 // Task 030-1.2.3: Check for macro trigger on key press FIRST
                 // Macros take priority over combos and simple remaps
-                if let Some(actions) = self.macro_triggers.get(&key) {
-                    return RemapResult::Macro(actions.clone());
+                if let Some(trigger) = self.macro_triggers.get(&key) {
+                    return RemapResult::Macro(trigger.clone());
                 }
 
                 // Check for combo match on key press
@@ -1296,6 +1181,11 @@ impl Remapper {
                 }
             }
             event_value::RELEASE => {
+                // The press of a macro trigger was consumed, so its release is too
+                if self.macro_triggers.contains_key(&key) {
+                    return RemapResult::MacroRelease(key);
+                }
+
                 // Check if there's an active combo for this key
                 if self.combo_tracker.has_active_combo_for(key) {
                     // Generate release events for the output combo
@@ -1313,6 +1203,11 @@ impl Remapper {
                 }
             }
             event_value::REPEAT => {
+                // Holding a macro trigger must not re-trigger or leak the key
+                if self.macro_triggers.contains_key(&key) {
+                    return RemapResult::Events(Vec::new());
+                }
+
                 // For repeat events, check if there's an active combo
                 if let Some(active) = self.combo_tracker.get_active_combo() {
                     if active.trigger_key == key {
@@ -1343,6 +1238,51 @@ impl Remapper {
         RemapResult::Events(vec![event])
     }
 
+    /// The entry of the first conditional rule triggered by `key` that
+    /// applies to the current focus
+    fn matching_conditional(&self, key: Key) -> Option<ConditionalEntry> {
+        self.conditional_rules
+            .iter()
+            .find(|rule| triggers_conditional(&rule.entry, key, &self.held_modifiers) && rule.applies(&self.focus))
+            .map(|rule| rule.entry.clone())
+    }
+
+    /// Handle the press of a key that triggered a conditional entry
+    fn press_conditional(&mut self, key: Key, entry: ConditionalEntry) -> RemapResult {
+        match entry {
+            ConditionalEntry::Remap { to, .. } => RemapResult::Events(vec![InputEvent::new(
+                evdev::EventType::KEY,
+                to.code(),
+                event_value::PRESS,
+            )]),
+            ConditionalEntry::Combo { from, to } => {
+                let press_events = generate_combo_press_events(&from.modifiers, &to);
+                self.combo_tracker.activate_combo(key, from.modifiers, to);
+                RemapResult::Events(press_events)
+            }
+            ConditionalEntry::Macro { trigger, actions, policy } => RemapResult::Macro(MacroTrigger {
+                key: to_key(trigger),
+                actions,
+                policy,
+            }),
+        }
+    }
+
+    /// Handle the release of a key whose press triggered a conditional entry
+    fn release_conditional(&mut self, key: Key, entry: ConditionalEntry) -> RemapResult {
+        match entry {
+            ConditionalEntry::Remap { to, .. } => RemapResult::Events(vec![InputEvent::new(
+                evdev::EventType::KEY,
+                to.code(),
+                event_value::RELEASE,
+            )]),
+            ConditionalEntry::Combo { .. } => {
+                RemapResult::Events(self.combo_tracker.handle_trigger_release(key))
+            }
+            ConditionalEntry::Macro { .. } => RemapResult::MacroRelease(key),
+        }
+    }
+
     /// Get the combo tracker for inspection (useful for testing and debugging).
     pub fn combo_tracker(&self) -> &ComboTracker {
         &self.combo_tracker
@@ -1398,14 +1338,11 @@ impl std::error::Error for ProfileError {}
 /// 4. The active profile name and remapper are updated atomically
 /// 5. Subsequent key events use the new profile's remapping rules
 ///
-/// ## Future: Automatic Switching (Backlog)
+/// ## Automatic Switching
 ///
-/// In a future release, the daemon will automatically call `switch_profile()`
-/// when the focused window's `app_id` matches a profile's `app_id_hint`.
-/// This is **not implemented** in v0.4.0 - profiles must be switched manually.
-///
-/// The `app_id_hint` field in [`Profile`](niri_mapper_config::Profile) exists
-/// for users to annotate their profiles in preparation for automatic switching.
+/// The daemon switches profiles when the focused window, workspace, output or
+/// keyboard layout matches a profile's hints; see
+/// [`DeviceConfig::select_profile`](niri_mapper_config::DeviceConfig::select_profile).
 ///
 /// # Example
 ///
@@ -1559,7 +1496,7 @@ impl DeviceRemapper {
             }
 
             // Parse the combo string
-            match parse_combo(&combo_str) {
+            match combo_str.parse::<KeyCombo>() {
                 Ok(combo) => {
                     tracing::debug!(
                         "Registered profile switch: {} -> '{}'",
@@ -1711,9 +1648,10 @@ impl DeviceRemapper {
     ///     RemapResult::Events(events) => {
     ///         virtual_device.emit(&events)?;
     ///     }
-    ///     RemapResult::Macro(actions) => {
-    ///         executor.execute_macro(&actions).await?;
+    ///     RemapResult::Macro(trigger) => {
+    ///         executor.execute_macro(&trigger.actions).await?;
     ///     }
+    ///     RemapResult::MacroRelease(_) => {}
     /// }
     /// ```
     pub fn process(&mut self, event: InputEvent) -> RemapResult {
@@ -1732,7 +1670,7 @@ impl DeviceRemapper {
                 let held_mods = self.remapper.held_modifiers().clone();
 
                 for (combo, profile_name) in &self.profile_switch_combos {
-                    if combo.key == key && combo.modifiers == held_mods {
+                    if to_key(combo.key) == key && combo.modifiers == held_mods {
                         tracing::info!(
                             "Profile switch keybind detected: {} -> '{}'",
                             combo,
@@ -1750,163 +1688,13 @@ impl DeviceRemapper {
     }
 }
 
-/// Parse a key name string to an evdev Key
+/// Parse a key name string to an evdev Key using the shared key registry
 pub(crate) fn parse_key(name: &str) -> Option<Key> {
-    let upper = name.to_uppercase();
-
-    // Common key mappings
-    match upper.as_str() {
-        "CAPSLOCK" | "CAPS_LOCK" | "CAPS" => Some(Key::KEY_CAPSLOCK),
-        "ESCAPE" | "ESC" => Some(Key::KEY_ESC),
-        "ENTER" | "RETURN" => Some(Key::KEY_ENTER),
-        "TAB" => Some(Key::KEY_TAB),
-        "SPACE" => Some(Key::KEY_SPACE),
-        "BACKSPACE" => Some(Key::KEY_BACKSPACE),
-
-        // Letters
-        "A" => Some(Key::KEY_A),
-        "B" => Some(Key::KEY_B),
-        "C" => Some(Key::KEY_C),
-        "D" => Some(Key::KEY_D),
-        "E" => Some(Key::KEY_E),
-        "F" => Some(Key::KEY_F),
-        "G" => Some(Key::KEY_G),
-        "H" => Some(Key::KEY_H),
-        "I" => Some(Key::KEY_I),
-        "J" => Some(Key::KEY_J),
-        "K" => Some(Key::KEY_K),
-        "L" => Some(Key::KEY_L),
-        "M" => Some(Key::KEY_M),
-        "N" => Some(Key::KEY_N),
-        "O" => Some(Key::KEY_O),
-        "P" => Some(Key::KEY_P),
-        "Q" => Some(Key::KEY_Q),
-        "R" => Some(Key::KEY_R),
-        "S" => Some(Key::KEY_S),
-        "T" => Some(Key::KEY_T),
-        "U" => Some(Key::KEY_U),
-        "V" => Some(Key::KEY_V),
-        "W" => Some(Key::KEY_W),
-        "X" => Some(Key::KEY_X),
-        "Y" => Some(Key::KEY_Y),
-        "Z" => Some(Key::KEY_Z),
-
-        // Modifiers
-        "LEFTCTRL" | "LCTRL" | "CTRL" => Some(Key::KEY_LEFTCTRL),
-        "RIGHTCTRL" | "RCTRL" => Some(Key::KEY_RIGHTCTRL),
-        "LEFTSHIFT" | "LSHIFT" | "SHIFT" => Some(Key::KEY_LEFTSHIFT),
-        "RIGHTSHIFT" | "RSHIFT" => Some(Key::KEY_RIGHTSHIFT),
-        "LEFTALT" | "LALT" | "ALT" => Some(Key::KEY_LEFTALT),
-        "RIGHTALT" | "RALT" => Some(Key::KEY_RIGHTALT),
-        "LEFTMETA" | "LMETA" | "SUPER" | "META" => Some(Key::KEY_LEFTMETA),
-        "RIGHTMETA" | "RMETA" => Some(Key::KEY_RIGHTMETA),
-
-        // Number keys
-        "0" => Some(Key::KEY_0),
-        "1" => Some(Key::KEY_1),
-        "2" => Some(Key::KEY_2),
-        "3" => Some(Key::KEY_3),
-        "4" => Some(Key::KEY_4),
-        "5" => Some(Key::KEY_5),
-        "6" => Some(Key::KEY_6),
-        "7" => Some(Key::KEY_7),
-        "8" => Some(Key::KEY_8),
-        "9" => Some(Key::KEY_9),
-
-        // Symbol keys
-        "MINUS" | "-" => Some(Key::KEY_MINUS),
-        "EQUALS" | "EQUAL" | "=" => Some(Key::KEY_EQUAL),
-        "LEFTBRACE" | "LBRACE" | "[" => Some(Key::KEY_LEFTBRACE),
-        "RIGHTBRACE" | "RBRACE" | "]" => Some(Key::KEY_RIGHTBRACE),
-        "SEMICOLON" | ";" => Some(Key::KEY_SEMICOLON),
-        "APOSTROPHE" | "'" => Some(Key::KEY_APOSTROPHE),
-        "GRAVE" | "`" => Some(Key::KEY_GRAVE),
-        "BACKSLASH" | "\\" => Some(Key::KEY_BACKSLASH),
-        "COMMA" | "," => Some(Key::KEY_COMMA),
-        "DOT" | "PERIOD" | "." => Some(Key::KEY_DOT),
-        "SLASH" | "/" => Some(Key::KEY_SLASH),
-
-        // Arrow keys
-        "UP" | "UPARROW" => Some(Key::KEY_UP),
-        "DOWN" | "DOWNARROW" => Some(Key::KEY_DOWN),
-        "LEFT" | "LEFTARROW" => Some(Key::KEY_LEFT),
-        "RIGHT" | "RIGHTARROW" => Some(Key::KEY_RIGHT),
-
-        // Navigation keys
-        "HOME" => Some(Key::KEY_HOME),
-        "END" => Some(Key::KEY_END),
-        "PAGEUP" | "PGUP" => Some(Key::KEY_PAGEUP),
-        "PAGEDOWN" | "PGDN" | "PGDOWN" => Some(Key::KEY_PAGEDOWN),
-        "INSERT" | "INS" => Some(Key::KEY_INSERT),
-        "DELETE" | "DEL" => Some(Key::KEY_DELETE),
-
-        // Function keys F1-F12
-        "F1" => Some(Key::KEY_F1),
-        "F2" => Some(Key::KEY_F2),
-        "F3" => Some(Key::KEY_F3),
-        "F4" => Some(Key::KEY_F4),
-        "F5" => Some(Key::KEY_F5),
-        "F6" => Some(Key::KEY_F6),
-        "F7" => Some(Key::KEY_F7),
-        "F8" => Some(Key::KEY_F8),
-        "F9" => Some(Key::KEY_F9),
-        "F10" => Some(Key::KEY_F10),
-        "F11" => Some(Key::KEY_F11),
-        "F12" => Some(Key::KEY_F12),
-
-        // Function keys F13-F24
-        "F13" => Some(Key::KEY_F13),
-        "F14" => Some(Key::KEY_F14),
-        "F15" => Some(Key::KEY_F15),
-        "F16" => Some(Key::KEY_F16),
-        "F17" => Some(Key::KEY_F17),
-        "F18" => Some(Key::KEY_F18),
-        "F19" => Some(Key::KEY_F19),
-        "F20" => Some(Key::KEY_F20),
-        "F21" => Some(Key::KEY_F21),
-        "F22" => Some(Key::KEY_F22),
-        "F23" => Some(Key::KEY_F23),
-        "F24" => Some(Key::KEY_F24),
-
-        // Numpad keys
-        "KP0" | "NUMPAD0" => Some(Key::KEY_KP0),
-        "KP1" | "NUMPAD1" => Some(Key::KEY_KP1),
-        "KP2" | "NUMPAD2" => Some(Key::KEY_KP2),
-        "KP3" | "NUMPAD3" => Some(Key::KEY_KP3),
-        "KP4" | "NUMPAD4" => Some(Key::KEY_KP4),
-        "KP5" | "NUMPAD5" => Some(Key::KEY_KP5),
-        "KP6" | "NUMPAD6" => Some(Key::KEY_KP6),
-        "KP7" | "NUMPAD7" => Some(Key::KEY_KP7),
-        "KP8" | "NUMPAD8" => Some(Key::KEY_KP8),
-        "KP9" | "NUMPAD9" => Some(Key::KEY_KP9),
-        "KPDOT" | "KPDECIMAL" | "NUMPAD_DOT" => Some(Key::KEY_KPDOT),
-        "KPENTER" | "NUMPAD_ENTER" => Some(Key::KEY_KPENTER),
-        "KPPLUS" | "KPADD" | "NUMPAD_PLUS" => Some(Key::KEY_KPPLUS),
-        "KPMINUS" | "KPSUBTRACT" | "NUMPAD_MINUS" => Some(Key::KEY_KPMINUS),
-        "KPASTERISK" | "KPMULTIPLY" | "NUMPAD_MULTIPLY" => Some(Key::KEY_KPASTERISK),
-        "KPSLASH" | "KPDIVIDE" | "NUMPAD_DIVIDE" => Some(Key::KEY_KPSLASH),
-        "NUMLOCK" | "NUM_LOCK" => Some(Key::KEY_NUMLOCK),
-
-        // Media keys
-        "XF86BACK" => Some(Key::KEY_BACK),
-        "XF86FORWARD" => Some(Key::KEY_FORWARD),
-
-        _ => {
-            // Fallback: Try to parse KEY_* format strings directly using evdev's FromStr
-            // This allows users to use raw kernel key names as an escape hatch
-            if upper.starts_with("KEY_") {
-                match Key::from_str(&upper) {
-                    Ok(key) => return Some(key),
-                    Err(_) => {
-                        tracing::warn!("Unknown evdev key: {}", name);
-                        return None;
-                    }
-                }
-            }
-            tracing::warn!("Unknown key: {}", name);
-            None
-        }
+    let key = niri_mapper_config::key_code(name).map(Key::new);
+    if key.is_none() {
+        tracing::warn!("Unknown key: {}", name);
     }
+    key
 }
 
 #[cfg(test)]
@@ -1917,7 +1705,8 @@ mod tests {
     fn expect_events(result: RemapResult) -> Vec<InputEvent> {
         match result {
             RemapResult::Events(e) => e,
-            RemapResult::Macro(actions) => panic!("Expected RemapResult::Events, got Macro({} actions)", actions.len()),
+            RemapResult::Macro(trigger) => panic!("Expected RemapResult::Events, got Macro({} actions)", trigger.actions.len()),
+            RemapResult::MacroRelease(key) => panic!("Expected RemapResult::Events, got MacroRelease({:?})", key),
             RemapResult::ProfileSwitch(name) => panic!("Expected RemapResult::Events, got ProfileSwitch({})", name),
         }
     }
@@ -2016,9 +1805,31 @@ mod tests {
         assert_eq!(parse_key("InvalidKey123"), None);
     }
 
+    #[test]
+    fn test_key_registry_matches_evdev() {
+        // Every kernel name in the registry that evdev knows has evdev's code
+        // for it. Names evdev does not know are keys newer than the evdev
+        // crate, or aliases such as `KEY_HANGUEL`.
+        let kernel_names = niri_mapper_config::key_names().filter(|name| name.starts_with("KEY_") || name.starts_with("BTN_"));
+        for name in kernel_names {
+            let code = niri_mapper_config::key_code(name).expect("listed names resolve");
+            if let Ok(key) = name.parse::<Key>() {
+                assert_eq!(key.code(), code, "{} has evdev code {}", name, key.code());
+            }
+        }
+
+        // Every key evdev knows is in the registry
+        for code in 1..=0x2ff {
+            let name = format!("{:?}", Key::new(code));
+            if !name.starts_with("unknown") {
+                assert_eq!(niri_mapper_config::key_code(&name), Some(code), "{} is missing from the registry", name);
+            }
+        }
+    }
+
     #[test]
     fn test_parse_key_raw_evdev_format() {
-        // Test that KEY_* format strings are parsed correctly via evdev's FromStr
+        // Test that KEY_* format strings are parsed correctly from the kernel key list
         assert_eq!(parse_key("KEY_LEFTMETA"), Some(Key::KEY_LEFTMETA));
         assert_eq!(parse_key("KEY_A"), Some(Key::KEY_A));
         assert_eq!(parse_key("KEY_CAPSLOCK"), Some(Key::KEY_CAPSLOCK));
@@ -2028,6 +1839,14 @@ mod tests {
         assert_eq!(parse_key("Key_A"), Some(Key::KEY_A));
     }
 
+    #[test]
+    fn test_parse_key_media_macro_and_buttons() {
+        assert_eq!(parse_key("XF86AudioMute"), Some(Key::KEY_MUTE));
+        assert_eq!(parse_key("BrightnessUp"), Some(Key::KEY_BRIGHTNESSUP));
+        assert_eq!(parse_key("KEY_MACRO1"), Some(Key::new(0x290)));
+        assert_eq!(parse_key("MouseSide"), Some(Key::BTN_SIDE));
+    }
+
     #[test]
     fn test_parse_key_unknown_raw_evdev() {
         // Unknown KEY_* names should still return None
@@ -2045,9 +1864,14 @@ mod tests {
         let mut remapper = Remapper {
             remap,
             passthrough: Vec::new(),
-            held_modifiers: HashSet::new(),
+            held_modifiers: BTreeSet::new(),
             combo_tracker: ComboTracker::new(),
             macro_triggers: HashMap::new(),
+            key_translation: None,
+            translated_keys: HashMap::new(),
+            conditional_rules: Vec::new(),
+            focus: FocusContext::default(),
+            held_conditional: HashMap::new(),
         };
 
         const KEY_PRESS: i32 = 1;
@@ -2074,9 +1898,14 @@ mod tests {
         let mut remapper = Remapper {
             remap,
             passthrough: Vec::new(),
-            held_modifiers: HashSet::new(),
+            held_modifiers: BTreeSet::new(),
             combo_tracker: ComboTracker::new(),
             macro_triggers: HashMap::new(),
+            key_translation: None,
+            translated_keys: HashMap::new(),
+            conditional_rules: Vec::new(),
+            focus: FocusContext::default(),
+            held_conditional: HashMap::new(),
         };
 
         const KEY_RELEASE: i32 = 0;
@@ -2103,9 +1932,14 @@ mod tests {
         let mut remapper = Remapper {
             remap,
             passthrough: Vec::new(),
-            held_modifiers: HashSet::new(),
+            held_modifiers: BTreeSet::new(),
             combo_tracker: ComboTracker::new(),
             macro_triggers: HashMap::new(),
+            key_translation: None,
+            translated_keys: HashMap::new(),
+            conditional_rules: Vec::new(),
+            focus: FocusContext::default(),
+            held_conditional: HashMap::new(),
         };
 
         const KEY_PRESS: i32 = 1;
@@ -2144,9 +1978,14 @@ mod tests {
         let mut remapper = Remapper {
             remap,
             passthrough: Vec::new(),
-            held_modifiers: HashSet::new(),
+            held_modifiers: BTreeSet::new(),
             combo_tracker: ComboTracker::new(),
             macro_triggers: HashMap::new(),
+            key_translation: None,
+            translated_keys: HashMap::new(),
+            conditional_rules: Vec::new(),
+            focus: FocusContext::default(),
+            held_conditional: HashMap::new(),
         };
 
         // Event value constants
@@ -2192,47 +2031,47 @@ mod tests {
     fn test_parse_combo_basic() {
         // Task 020-2.3: Parse combo key strings into structured representation
         // Test basic combo parsing with two modifiers and a trigger key
-        let combo = parse_combo("Ctrl+Shift+Q").expect("should parse valid combo");
+        let combo = "Ctrl+Shift+Q".parse::<KeyCombo>().expect("should parse valid combo");
         assert!(combo.modifiers.contains(&Modifier::Ctrl), "should contain Ctrl modifier");
         assert!(combo.modifiers.contains(&Modifier::Shift), "should contain Shift modifier");
         assert_eq!(combo.modifiers.len(), 2, "should have exactly 2 modifiers");
-        assert_eq!(combo.key, Key::KEY_Q, "trigger key should be Q");
+        assert_eq!(to_key(combo.key), Key::KEY_Q, "trigger key should be Q");
     }
 
     #[test]
     fn test_parse_combo_single_modifier() {
         // Test combo with a single modifier
-        let combo = parse_combo("Ctrl+A").expect("should parse valid combo");
+        let combo = "Ctrl+A".parse::<KeyCombo>().expect("should parse valid combo");
         assert!(combo.modifiers.contains(&Modifier::Ctrl));
         assert_eq!(combo.modifiers.len(), 1);
-        assert_eq!(combo.key, Key::KEY_A);
+        assert_eq!(to_key(combo.key), Key::KEY_A);
     }
 
     #[test]
     fn test_parse_combo_all_modifiers() {
         // Test combo with all four modifiers
-        let combo = parse_combo("Ctrl+Shift+Alt+Super+Q").expect("should parse valid combo");
+        let combo = "Ctrl+Shift+Alt+Super+Q".parse::<KeyCombo>().expect("should parse valid combo");
         assert!(combo.modifiers.contains(&Modifier::Ctrl));
         assert!(combo.modifiers.contains(&Modifier::Shift));
         assert!(combo.modifiers.contains(&Modifier::Alt));
         assert!(combo.modifiers.contains(&Modifier::Super));
         assert_eq!(combo.modifiers.len(), 4);
-        assert_eq!(combo.key, Key::KEY_Q);
+        assert_eq!(to_key(combo.key), Key::KEY_Q);
     }
 
     #[test]
     fn test_parse_combo_no_modifiers() {
         // A single key without modifiers is still a valid "combo"
-        let combo = parse_combo("Escape").expect("should parse single key");
+        let combo = "Escape".parse::<KeyCombo>().expect("should parse single key");
         assert!(combo.modifiers.is_empty(), "should have no modifiers");
-        assert_eq!(combo.key, Key::KEY_ESC);
+        assert_eq!(to_key(combo.key), Key::KEY_ESC);
     }
 
     #[test]
     fn test_parse_combo_modifier_order_independence() {
         // Task 020-2.3: Handle modifier order variations (Shift+Ctrl same as Ctrl+Shift)
-        let combo1 = parse_combo("Ctrl+Shift+Q").expect("should parse");
-        let combo2 = parse_combo("Shift+Ctrl+Q").expect("should parse");
+        let combo1 = "Ctrl+Shift+Q".parse::<KeyCombo>().expect("should parse");
+        let combo2 = "Shift+Ctrl+Q".parse::<KeyCombo>().expect("should parse");
         assert_eq!(combo1.modifiers, combo2.modifiers, "modifier order should not matter");
         assert_eq!(combo1.key, combo2.key);
     }
@@ -2240,19 +2079,19 @@ mod tests {
     #[test]
     fn test_parse_combo_alt_ctrl_delete() {
         // Classic combo test
-        let combo = parse_combo("Ctrl+Alt+Delete").expect("should parse");
+        let combo = "Ctrl+Alt+Delete".parse::<KeyCombo>().expect("should parse");
         assert!(combo.modifiers.contains(&Modifier::Ctrl));
         assert!(combo.modifiers.contains(&Modifier::Alt));
         assert_eq!(combo.modifiers.len(), 2);
-        assert_eq!(combo.key, Key::KEY_DELETE);
+        assert_eq!(to_key(combo.key), Key::KEY_DELETE);
     }
 
     #[test]
     fn test_parse_combo_case_insensitive() {
         // Modifiers and keys should be case-insensitive
-        let combo1 = parse_combo("CTRL+SHIFT+Q").expect("should parse uppercase");
-        let combo2 = parse_combo("ctrl+shift+q").expect("should parse lowercase");
-        let combo3 = parse_combo("Ctrl+Shift+Q").expect("should parse mixed case");
+        let combo1 = "CTRL+SHIFT+Q".parse::<KeyCombo>().expect("should parse uppercase");
+        let combo2 = "ctrl+shift+q".parse::<KeyCombo>().expect("should parse lowercase");
+        let combo3 = "Ctrl+Shift+Q".parse::<KeyCombo>().expect("should parse mixed case");
 
         assert_eq!(combo1.modifiers, combo2.modifiers);
         assert_eq!(combo2.modifiers, combo3.modifiers);
@@ -2263,19 +2102,19 @@ mod tests {
     #[test]
     fn test_parse_combo_with_spaces() {
         // Spaces around + should be handled
-        let combo = parse_combo("Ctrl + Shift + Q").expect("should parse with spaces");
+        let combo = "Ctrl + Shift + Q".parse::<KeyCombo>().expect("should parse with spaces");
         assert!(combo.modifiers.contains(&Modifier::Ctrl));
         assert!(combo.modifiers.contains(&Modifier::Shift));
-        assert_eq!(combo.key, Key::KEY_Q);
+        assert_eq!(to_key(combo.key), Key::KEY_Q);
     }
 
     #[test]
     fn test_parse_combo_super_modifier_aliases() {
         // Test various aliases for Super modifier
-        let combo_super = parse_combo("Super+Q").expect("Super should work");
-        let combo_meta = parse_combo("Meta+Q").expect("Meta should work");
-        let combo_mod = parse_combo("Mod+Q").expect("Mod should work");
-        let combo_win = parse_combo("Win+Q").expect("Win should work");
+        let combo_super = "Super+Q".parse::<KeyCombo>().expect("Super should work");
+        let combo_meta = "Meta+Q".parse::<KeyCombo>().expect("Meta should work");
+        let combo_mod = "Mod+Q".parse::<KeyCombo>().expect("Mod should work");
+        let combo_win = "Win+Q".parse::<KeyCombo>().expect("Win should work");
 
         assert_eq!(combo_super.modifiers, combo_meta.modifiers);
         assert_eq!(combo_meta.modifiers, combo_mod.modifiers);
@@ -2286,25 +2125,25 @@ mod tests {
     #[test]
     fn test_parse_combo_ctrl_aliases() {
         // Test Control alias for Ctrl
-        let combo_ctrl = parse_combo("Ctrl+Q").expect("Ctrl should work");
-        let combo_control = parse_combo("Control+Q").expect("Control should work");
+        let combo_ctrl = "Ctrl+Q".parse::<KeyCombo>().expect("Ctrl should work");
+        let combo_control = "Control+Q".parse::<KeyCombo>().expect("Control should work");
         assert_eq!(combo_ctrl.modifiers, combo_control.modifiers);
     }
 
     #[test]
     fn test_parse_combo_function_keys() {
         // Test combos with function keys
-        let combo = parse_combo("Alt+F4").expect("should parse Alt+F4");
+        let combo = "Alt+F4".parse::<KeyCombo>().expect("should parse Alt+F4");
         assert!(combo.modifiers.contains(&Modifier::Alt));
-        assert_eq!(combo.key, Key::KEY_F4);
+        assert_eq!(to_key(combo.key), Key::KEY_F4);
     }
 
     #[test]
     fn test_parse_combo_number_keys() {
         // Test combos with number keys
-        let combo = parse_combo("Super+1").expect("should parse Super+1");
+        let combo = "Super+1".parse::<KeyCombo>().expect("should parse Super+1");
         assert!(combo.modifiers.contains(&Modifier::Super));
-        assert_eq!(combo.key, Key::KEY_1);
+        assert_eq!(to_key(combo.key), Key::KEY_1);
     }
 
     // ========================================================================
@@ -2314,61 +2153,61 @@ mod tests {
     #[test]
     fn test_parse_combo_fail_empty() {
         // Task 020-2.3: Fail hard on unparseable combo strings
-        let result = parse_combo("");
+        let result = "".parse::<KeyCombo>();
         assert!(result.is_err(), "empty string should fail");
-        assert!(result.unwrap_err().reason.contains("empty"));
+        assert!(result.unwrap_err().contains("empty"));
     }
 
     #[test]
     fn test_parse_combo_fail_only_modifiers() {
         // Only modifiers without a trigger key should fail
-        let result = parse_combo("Ctrl+Shift");
+        let result = "Ctrl+Shift".parse::<KeyCombo>();
         assert!(result.is_err(), "only modifiers should fail");
-        assert!(result.unwrap_err().reason.contains("no trigger key"));
+        assert!(result.unwrap_err().contains("no trigger key"));
     }
 
     #[test]
     fn test_parse_combo_fail_unknown_key() {
         // Unknown key names should fail
-        let result = parse_combo("Ctrl+UnknownKey");
+        let result = "Ctrl+UnknownKey".parse::<KeyCombo>();
         assert!(result.is_err(), "unknown key should fail");
-        assert!(result.unwrap_err().reason.contains("unknown key"));
+        assert!(result.unwrap_err().contains("Unknown key"));
     }
 
     #[test]
     fn test_parse_combo_fail_duplicate_modifier() {
         // Duplicate modifiers should fail
-        let result = parse_combo("Ctrl+Ctrl+Q");
+        let result = "Ctrl+Ctrl+Q".parse::<KeyCombo>();
         assert!(result.is_err(), "duplicate modifier should fail");
-        assert!(result.unwrap_err().reason.contains("duplicate"));
+        assert!(result.unwrap_err().contains("duplicate"));
     }
 
     #[test]
     fn test_parse_combo_fail_multiple_trigger_keys() {
         // Multiple non-modifier keys should fail
-        let result = parse_combo("Ctrl+A+B");
+        let result = "Ctrl+A+B".parse::<KeyCombo>();
         assert!(result.is_err(), "multiple trigger keys should fail");
-        assert!(result.unwrap_err().reason.contains("multiple"));
+        assert!(result.unwrap_err().contains("multiple"));
     }
 
     #[test]
     fn test_parse_combo_fail_empty_component() {
         // Empty components (double +) should fail
-        let result = parse_combo("Ctrl++Q");
+        let result = "Ctrl++Q".parse::<KeyCombo>();
         assert!(result.is_err(), "empty component should fail");
     }
 
     #[test]
     fn test_parse_combo_fail_trailing_plus() {
         // Trailing + should fail
-        let result = parse_combo("Ctrl+Q+");
+        let result = "Ctrl+Q+".parse::<KeyCombo>();
         assert!(result.is_err(), "trailing + should fail");
     }
 
     #[test]
     fn test_parse_combo_fail_leading_plus() {
         // Leading + should fail
-        let result = parse_combo("+Ctrl+Q");
+        let result = "+Ctrl+Q".parse::<KeyCombo>();
         assert!(result.is_err(), "leading + should fail");
     }
 
@@ -2379,34 +2218,34 @@ mod tests {
     #[test]
     fn test_modifier_from_key() {
         // Test that evdev keys are correctly identified as modifiers
-        assert_eq!(Modifier::from_key(Key::KEY_LEFTCTRL), Some(Modifier::Ctrl));
-        assert_eq!(Modifier::from_key(Key::KEY_RIGHTCTRL), Some(Modifier::Ctrl));
-        assert_eq!(Modifier::from_key(Key::KEY_LEFTSHIFT), Some(Modifier::Shift));
-        assert_eq!(Modifier::from_key(Key::KEY_RIGHTSHIFT), Some(Modifier::Shift));
-        assert_eq!(Modifier::from_key(Key::KEY_LEFTALT), Some(Modifier::Alt));
-        assert_eq!(Modifier::from_key(Key::KEY_RIGHTALT), Some(Modifier::Alt));
-        assert_eq!(Modifier::from_key(Key::KEY_LEFTMETA), Some(Modifier::Super));
-        assert_eq!(Modifier::from_key(Key::KEY_RIGHTMETA), Some(Modifier::Super));
+        assert_eq!(modifier_of(Key::KEY_LEFTCTRL), Some(Modifier::Ctrl));
+        assert_eq!(modifier_of(Key::KEY_RIGHTCTRL), Some(Modifier::Ctrl));
+        assert_eq!(modifier_of(Key::KEY_LEFTSHIFT), Some(Modifier::Shift));
+        assert_eq!(modifier_of(Key::KEY_RIGHTSHIFT), Some(Modifier::Shift));
+        assert_eq!(modifier_of(Key::KEY_LEFTALT), Some(Modifier::Alt));
+        assert_eq!(modifier_of(Key::KEY_RIGHTALT), Some(Modifier::Alt));
+        assert_eq!(modifier_of(Key::KEY_LEFTMETA), Some(Modifier::Super));
+        assert_eq!(modifier_of(Key::KEY_RIGHTMETA), Some(Modifier::Super));
 
         // Non-modifier keys should return None
-        assert_eq!(Modifier::from_key(Key::KEY_A), None);
-        assert_eq!(Modifier::from_key(Key::KEY_SPACE), None);
-        assert_eq!(Modifier::from_key(Key::KEY_ENTER), None);
+        assert_eq!(modifier_of(Key::KEY_A), None);
+        assert_eq!(modifier_of(Key::KEY_SPACE), None);
+        assert_eq!(modifier_of(Key::KEY_ENTER), None);
     }
 
     #[test]
     fn test_modifier_to_key() {
         // Modifiers should convert back to left variants
-        assert_eq!(Modifier::Ctrl.to_key(), Key::KEY_LEFTCTRL);
-        assert_eq!(Modifier::Shift.to_key(), Key::KEY_LEFTSHIFT);
-        assert_eq!(Modifier::Alt.to_key(), Key::KEY_LEFTALT);
-        assert_eq!(Modifier::Super.to_key(), Key::KEY_LEFTMETA);
+        assert_eq!(to_key(Modifier::Ctrl.key()), Key::KEY_LEFTCTRL);
+        assert_eq!(to_key(Modifier::Shift.key()), Key::KEY_LEFTSHIFT);
+        assert_eq!(to_key(Modifier::Alt.key()), Key::KEY_LEFTALT);
+        assert_eq!(to_key(Modifier::Super.key()), Key::KEY_LEFTMETA);
     }
 
     #[test]
     fn test_key_combo_display() {
         // Test Display implementation for KeyCombo
-        let combo = parse_combo("Ctrl+Shift+Q").expect("should parse");
+        let combo = "Ctrl+Shift+Q".parse::<KeyCombo>().expect("should parse");
         let display = format!("{}", combo);
         // Should contain all components (order may vary for modifiers, but key is last)
         assert!(display.contains("Ctrl"));
@@ -2417,8 +2256,8 @@ mod tests {
     #[test]
     fn test_key_combo_hash_equality() {
         // Two KeyCombos with same content should hash the same
-        let combo1 = parse_combo("Ctrl+Shift+Q").expect("should parse");
-        let combo2 = parse_combo("Shift+Ctrl+Q").expect("should parse");
+        let combo1 = "Ctrl+Shift+Q".parse::<KeyCombo>().expect("should parse");
+        let combo2 = "Shift+Ctrl+Q".parse::<KeyCombo>().expect("should parse");
 
         // They should be equal
         assert_eq!(combo1, combo2);
@@ -2442,8 +2281,8 @@ mod tests {
     fn test_combo_tracker_register() {
         // Test ComboTracker registration
         let mut tracker = ComboTracker::new();
-        let input = parse_combo("Ctrl+Shift+Q").expect("should parse");
-        let output = parse_combo("Alt+F4").expect("should parse");
+        let input = "Ctrl+Shift+Q".parse::<KeyCombo>().expect("should parse");
+        let output = "Alt+F4".parse::<KeyCombo>().expect("should parse");
 
         tracker.register_combo(input.clone(), output.clone());
 
@@ -2477,9 +2316,14 @@ mod tests {
         let mut remapper = Remapper {
             remap: HashMap::new(),
             passthrough: Vec::new(),
-            held_modifiers: HashSet::new(),
+            held_modifiers: BTreeSet::new(),
             combo_tracker: ComboTracker::new(),
             macro_triggers: HashMap::new(),
+            key_translation: None,
+            translated_keys: HashMap::new(),
+            conditional_rules: Vec::new(),
+            focus: FocusContext::default(),
+            held_conditional: HashMap::new(),
         };
 
         const KEY_PRESS: i32 = 1;
@@ -2507,9 +2351,14 @@ mod tests {
         let mut remapper = Remapper {
             remap: HashMap::new(),
             passthrough: Vec::new(),
-            held_modifiers: HashSet::new(),
+            held_modifiers: BTreeSet::new(),
             combo_tracker: ComboTracker::new(),
             macro_triggers: HashMap::new(),
+            key_translation: None,
+            translated_keys: HashMap::new(),
+            conditional_rules: Vec::new(),
+            focus: FocusContext::default(),
+            held_conditional: HashMap::new(),
         };
 
         const KEY_PRESS: i32 = 1;
@@ -2537,9 +2386,14 @@ mod tests {
         let mut remapper = Remapper {
             remap: HashMap::new(),
             passthrough: Vec::new(),
-            held_modifiers: HashSet::new(),
+            held_modifiers: BTreeSet::new(),
             combo_tracker: ComboTracker::new(),
             macro_triggers: HashMap::new(),
+            key_translation: None,
+            translated_keys: HashMap::new(),
+            conditional_rules: Vec::new(),
+            focus: FocusContext::default(),
+            held_conditional: HashMap::new(),
         };
 
         const KEY_PRESS: i32 = 1;
@@ -2563,9 +2417,14 @@ mod tests {
         let mut remapper = Remapper {
             remap: HashMap::new(),
             passthrough: Vec::new(),
-            held_modifiers: HashSet::new(),
+            held_modifiers: BTreeSet::new(),
             combo_tracker: ComboTracker::new(),
             macro_triggers: HashMap::new(),
+            key_translation: None,
+            translated_keys: HashMap::new(),
+            conditional_rules: Vec::new(),
+            focus: FocusContext::default(),
+            held_conditional: HashMap::new(),
         };
 
         const KEY_PRESS: i32 = 1;
@@ -2589,9 +2448,14 @@ mod tests {
         let mut remapper = Remapper {
             remap: HashMap::new(),
             passthrough: Vec::new(),
-            held_modifiers: HashSet::new(),
+            held_modifiers: BTreeSet::new(),
             combo_tracker: ComboTracker::new(),
             macro_triggers: HashMap::new(),
+            key_translation: None,
+            translated_keys: HashMap::new(),
+            conditional_rules: Vec::new(),
+            focus: FocusContext::default(),
+            held_conditional: HashMap::new(),
         };
 
         const KEY_PRESS: i32 = 1;
@@ -2620,9 +2484,14 @@ mod tests {
         let mut remapper = Remapper {
             remap: HashMap::new(),
             passthrough: Vec::new(),
-            held_modifiers: HashSet::new(),
+            held_modifiers: BTreeSet::new(),
             combo_tracker: ComboTracker::new(),
             macro_triggers: HashMap::new(),
+            key_translation: None,
+            translated_keys: HashMap::new(),
+            conditional_rules: Vec::new(),
+            focus: FocusContext::default(),
+            held_conditional: HashMap::new(),
         };
 
         const KEY_PRESS: i32 = 1;
@@ -2651,9 +2520,14 @@ mod tests {
         let mut remapper = Remapper {
             remap,
             passthrough: Vec::new(),
-            held_modifiers: HashSet::new(),
+            held_modifiers: BTreeSet::new(),
             combo_tracker: ComboTracker::new(),
             macro_triggers: HashMap::new(),
+            key_translation: None,
+            translated_keys: HashMap::new(),
+            conditional_rules: Vec::new(),
+            focus: FocusContext::default(),
+            held_conditional: HashMap::new(),
         };
 
         const KEY_PRESS: i32 = 1;
@@ -2683,8 +2557,8 @@ mod tests {
         // Task 020-2.4: Ctrl+Shift+Q matches when exactly Ctrl, Shift held and Q pressed
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Ctrl+Shift+Q").expect("should parse"),
-            parse_combo("Alt+F4").expect("should parse"),
+            "Ctrl+Shift+Q".parse::<KeyCombo>().expect("should parse"),
+            "Alt+F4".parse::<KeyCombo>().expect("should parse"),
         );
 
         // Press Ctrl, then Shift
@@ -2695,12 +2569,12 @@ mod tests {
         let result = tracker.check_combo_match(Key::KEY_Q);
         match result {
             ComboMatchResult::Matched { input, output } => {
-                assert_eq!(input.key, Key::KEY_Q);
+                assert_eq!(to_key(input.key), Key::KEY_Q);
                 assert!(input.modifiers.contains(&Modifier::Ctrl));
                 assert!(input.modifiers.contains(&Modifier::Shift));
                 assert_eq!(input.modifiers.len(), 2);
 
-                assert_eq!(output.key, Key::KEY_F4);
+                assert_eq!(to_key(output.key), Key::KEY_F4);
                 assert!(output.modifiers.contains(&Modifier::Alt));
                 assert_eq!(output.modifiers.len(), 1);
             }
@@ -2716,8 +2590,8 @@ mod tests {
         // Ctrl+Shift+Alt+Q should NOT match Ctrl+Shift+Q
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Ctrl+Shift+Q").expect("should parse"),
-            parse_combo("Alt+F4").expect("should parse"),
+            "Ctrl+Shift+Q".parse::<KeyCombo>().expect("should parse"),
+            "Alt+F4".parse::<KeyCombo>().expect("should parse"),
         );
 
         // Press Ctrl, Shift, AND Alt (extra modifier)
@@ -2735,8 +2609,8 @@ mod tests {
         // Ctrl+Q should NOT match Ctrl+Shift+Q (missing Shift)
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Ctrl+Shift+Q").expect("should parse"),
-            parse_combo("Alt+F4").expect("should parse"),
+            "Ctrl+Shift+Q".parse::<KeyCombo>().expect("should parse"),
+            "Alt+F4".parse::<KeyCombo>().expect("should parse"),
         );
 
         // Press only Ctrl (missing Shift)
@@ -2752,8 +2626,8 @@ mod tests {
         // Ctrl+Shift+W should NOT match Ctrl+Shift+Q combo
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Ctrl+Shift+Q").expect("should parse"),
-            parse_combo("Alt+F4").expect("should parse"),
+            "Ctrl+Shift+Q".parse::<KeyCombo>().expect("should parse"),
+            "Alt+F4".parse::<KeyCombo>().expect("should parse"),
         );
 
         // Press Ctrl and Shift
@@ -2770,8 +2644,8 @@ mod tests {
         // Pressing a modifier key should never trigger a combo match
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Ctrl+Q").expect("should parse"),
-            parse_combo("Alt+F4").expect("should parse"),
+            "Ctrl+Q".parse::<KeyCombo>().expect("should parse"),
+            "Alt+F4".parse::<KeyCombo>().expect("should parse"),
         );
 
         // Press Ctrl
@@ -2787,8 +2661,8 @@ mod tests {
         // A combo with no modifiers (just a key) should match when no modifiers are held
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Escape").expect("should parse"),
-            parse_combo("CapsLock").expect("should parse"),
+            "Escape".parse::<KeyCombo>().expect("should parse"),
+            "CapsLock".parse::<KeyCombo>().expect("should parse"),
         );
 
         // No modifiers held
@@ -2798,9 +2672,9 @@ mod tests {
         let result = tracker.check_combo_match(Key::KEY_ESC);
         match result {
             ComboMatchResult::Matched { input, output } => {
-                assert_eq!(input.key, Key::KEY_ESC);
+                assert_eq!(to_key(input.key), Key::KEY_ESC);
                 assert!(input.modifiers.is_empty());
-                assert_eq!(output.key, Key::KEY_CAPSLOCK);
+                assert_eq!(to_key(output.key), Key::KEY_CAPSLOCK);
             }
             ComboMatchResult::NoMatch => {
                 panic!("Expected combo match for Escape");
@@ -2813,8 +2687,8 @@ mod tests {
         // A combo with no modifiers should NOT match when modifiers are held
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Escape").expect("should parse"),
-            parse_combo("CapsLock").expect("should parse"),
+            "Escape".parse::<KeyCombo>().expect("should parse"),
+            "CapsLock".parse::<KeyCombo>().expect("should parse"),
         );
 
         // Press Ctrl
@@ -2830,12 +2704,12 @@ mod tests {
         // Test with multiple registered combos
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Ctrl+Q").expect("should parse"),
-            parse_combo("Alt+F4").expect("should parse"),
+            "Ctrl+Q".parse::<KeyCombo>().expect("should parse"),
+            "Alt+F4".parse::<KeyCombo>().expect("should parse"),
         );
         tracker.register_combo(
-            parse_combo("Ctrl+Shift+Q").expect("should parse"),
-            parse_combo("Super+Q").expect("should parse"),
+            "Ctrl+Shift+Q".parse::<KeyCombo>().expect("should parse"),
+            "Super+Q".parse::<KeyCombo>().expect("should parse"),
         );
 
         // Test Ctrl+Q match
@@ -2843,7 +2717,7 @@ mod tests {
         let result = tracker.check_combo_match(Key::KEY_Q);
         match result {
             ComboMatchResult::Matched { output, .. } => {
-                assert_eq!(output.key, Key::KEY_F4);
+                assert_eq!(to_key(output.key), Key::KEY_F4);
                 assert!(output.modifiers.contains(&Modifier::Alt));
             }
             ComboMatchResult::NoMatch => {
@@ -2856,7 +2730,7 @@ mod tests {
         let result = tracker.check_combo_match(Key::KEY_Q);
         match result {
             ComboMatchResult::Matched { output, .. } => {
-                assert_eq!(output.key, Key::KEY_Q);
+                assert_eq!(to_key(output.key), Key::KEY_Q);
                 assert!(output.modifiers.contains(&Modifier::Super));
             }
             ComboMatchResult::NoMatch => {
@@ -2895,8 +2769,8 @@ mod tests {
         // Test the convenience method handle_key_press
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Ctrl+Q").expect("should parse"),
-            parse_combo("Alt+F4").expect("should parse"),
+            "Ctrl+Q".parse::<KeyCombo>().expect("should parse"),
+            "Alt+F4".parse::<KeyCombo>().expect("should parse"),
         );
 
         // Press Ctrl using handle_key_press
@@ -2929,8 +2803,8 @@ mod tests {
         // Test that right-hand modifiers also work for matching
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Ctrl+Shift+Q").expect("should parse"),
-            parse_combo("Alt+F4").expect("should parse"),
+            "Ctrl+Shift+Q".parse::<KeyCombo>().expect("should parse"),
+            "Alt+F4".parse::<KeyCombo>().expect("should parse"),
         );
 
         // Press RIGHT Ctrl and RIGHT Shift
@@ -2947,8 +2821,8 @@ mod tests {
         // Test combo with all four modifiers
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Ctrl+Shift+Alt+Super+Q").expect("should parse"),
-            parse_combo("F1").expect("should parse"),
+            "Ctrl+Shift+Alt+Super+Q".parse::<KeyCombo>().expect("should parse"),
+            "F1".parse::<KeyCombo>().expect("should parse"),
         );
 
         // Press all four modifiers
@@ -2962,7 +2836,7 @@ mod tests {
         match result {
             ComboMatchResult::Matched { input, output } => {
                 assert_eq!(input.modifiers.len(), 4);
-                assert_eq!(output.key, Key::KEY_F1);
+                assert_eq!(to_key(output.key), Key::KEY_F1);
             }
             ComboMatchResult::NoMatch => {
                 panic!("Expected combo match for Ctrl+Shift+Alt+Super+Q");
@@ -3001,8 +2875,8 @@ mod tests {
         // 4. Press F4 (output key)
         use super::{generate_combo_press_events, event_value};
 
-        let input_modifiers: HashSet<Modifier> = [Modifier::Ctrl, Modifier::Shift].into_iter().collect();
-        let output_combo = parse_combo("Alt+F4").expect("should parse");
+        let input_modifiers: BTreeSet<Modifier> = [Modifier::Ctrl, Modifier::Shift].into_iter().collect();
+        let output_combo = "Alt+F4".parse::<KeyCombo>().expect("should parse");
 
         let events = generate_combo_press_events(&input_modifiers, &output_combo);
 
@@ -3037,9 +2911,9 @@ mod tests {
         // 4. Restore Shift (still held)
         use super::{generate_combo_release_events, event_value};
 
-        let input_modifiers: HashSet<Modifier> = [Modifier::Ctrl, Modifier::Shift].into_iter().collect();
-        let output_combo = parse_combo("Alt+F4").expect("should parse");
-        let still_held: HashSet<Modifier> = [Modifier::Ctrl, Modifier::Shift].into_iter().collect();
+        let input_modifiers: BTreeSet<Modifier> = [Modifier::Ctrl, Modifier::Shift].into_iter().collect();
+        let output_combo = "Alt+F4".parse::<KeyCombo>().expect("should parse");
+        let still_held: BTreeSet<Modifier> = [Modifier::Ctrl, Modifier::Shift].into_iter().collect();
 
         let events = generate_combo_release_events(&input_modifiers, &output_combo, &still_held);
 
@@ -3070,8 +2944,8 @@ mod tests {
         // Ctrl is in both, so it should NOT be released or pressed
         use super::{generate_combo_press_events, event_value};
 
-        let input_modifiers: HashSet<Modifier> = [Modifier::Ctrl, Modifier::Shift].into_iter().collect();
-        let output_combo = parse_combo("Ctrl+F4").expect("should parse");
+        let input_modifiers: BTreeSet<Modifier> = [Modifier::Ctrl, Modifier::Shift].into_iter().collect();
+        let output_combo = "Ctrl+F4".parse::<KeyCombo>().expect("should parse");
 
         let events = generate_combo_press_events(&input_modifiers, &output_combo);
 
@@ -3093,8 +2967,8 @@ mod tests {
         // Simple key remap: Escape -> CapsLock (no modifiers)
         use super::{generate_combo_press_events, event_value};
 
-        let input_modifiers: HashSet<Modifier> = HashSet::new();
-        let output_combo = parse_combo("CapsLock").expect("should parse");
+        let input_modifiers: BTreeSet<Modifier> = BTreeSet::new();
+        let output_combo = "CapsLock".parse::<KeyCombo>().expect("should parse");
 
         let events = generate_combo_press_events(&input_modifiers, &output_combo);
 
@@ -3110,9 +2984,9 @@ mod tests {
         // Ctrl+Shift+Q -> Alt+F4, but user released Ctrl+Shift before Q
         use super::{generate_combo_release_events, event_value};
 
-        let input_modifiers: HashSet<Modifier> = [Modifier::Ctrl, Modifier::Shift].into_iter().collect();
-        let output_combo = parse_combo("Alt+F4").expect("should parse");
-        let still_held: HashSet<Modifier> = HashSet::new(); // User released everything
+        let input_modifiers: BTreeSet<Modifier> = [Modifier::Ctrl, Modifier::Shift].into_iter().collect();
+        let output_combo = "Alt+F4".parse::<KeyCombo>().expect("should parse");
+        let still_held: BTreeSet<Modifier> = BTreeSet::new(); // User released everything
 
         let events = generate_combo_release_events(&input_modifiers, &output_combo, &still_held);
 
@@ -3132,8 +3006,8 @@ mod tests {
         // Ctrl+Q -> Ctrl+W (same modifiers, different key)
         use super::{generate_combo_press_events, event_value};
 
-        let input_modifiers: HashSet<Modifier> = [Modifier::Ctrl].into_iter().collect();
-        let output_combo = parse_combo("Ctrl+W").expect("should parse");
+        let input_modifiers: BTreeSet<Modifier> = [Modifier::Ctrl].into_iter().collect();
+        let output_combo = "Ctrl+W".parse::<KeyCombo>().expect("should parse");
 
         let events = generate_combo_press_events(&input_modifiers, &output_combo);
 
@@ -3150,8 +3024,8 @@ mod tests {
         use super::{generate_combo_press_events, generate_combo_release_events, event_value};
 
         // Setup: Ctrl+Shift+Q -> Alt+F4
-        let input = parse_combo("Ctrl+Shift+Q").expect("should parse input");
-        let output = parse_combo("Alt+F4").expect("should parse output");
+        let input = "Ctrl+Shift+Q".parse::<KeyCombo>().expect("should parse input");
+        let output = "Alt+F4".parse::<KeyCombo>().expect("should parse output");
         let input_modifiers = input.modifiers.clone();
 
         // Simulate: User holds Ctrl+Shift, presses Q
@@ -3226,8 +3100,8 @@ mod tests {
 
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Ctrl+Shift+Q").expect("should parse"),
-            parse_combo("Alt+F4").expect("should parse"),
+            "Ctrl+Shift+Q".parse::<KeyCombo>().expect("should parse"),
+            "Alt+F4".parse::<KeyCombo>().expect("should parse"),
         );
 
         // Step 1: Press Ctrl+Shift
@@ -3245,7 +3119,7 @@ mod tests {
                 assert!(tracker.has_active_combo_for(Key::KEY_Q));
                 let active = tracker.get_active_combo().expect("should have active combo");
                 assert_eq!(active.trigger_key, Key::KEY_Q);
-                assert_eq!(active.output_combo.key, Key::KEY_F4);
+                assert_eq!(to_key(active.output_combo.key), Key::KEY_F4);
             }
             _ => panic!("Expected combo match"),
         }
@@ -3292,8 +3166,8 @@ mod tests {
 
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Ctrl+Shift+Q").expect("should parse"),
-            parse_combo("Alt+F4").expect("should parse"),
+            "Ctrl+Shift+Q".parse::<KeyCombo>().expect("should parse"),
+            "Alt+F4".parse::<KeyCombo>().expect("should parse"),
         );
 
         // Press Ctrl+Shift+Q and activate combo
@@ -3341,8 +3215,8 @@ mod tests {
         // Test that releasing a different key doesn't affect active combo
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Ctrl+Q").expect("should parse"),
-            parse_combo("Alt+F4").expect("should parse"),
+            "Ctrl+Q".parse::<KeyCombo>().expect("should parse"),
+            "Alt+F4".parse::<KeyCombo>().expect("should parse"),
         );
 
         // Activate combo with Q
@@ -3366,8 +3240,8 @@ mod tests {
     fn test_combo_activate_and_clear() {
         // Test activate_combo and clear_active_combo methods
         let mut tracker = ComboTracker::new();
-        let output = parse_combo("Alt+F4").expect("should parse");
-        let input_modifiers: HashSet<Modifier> = [Modifier::Ctrl].into_iter().collect();
+        let output = "Alt+F4".parse::<KeyCombo>().expect("should parse");
+        let input_modifiers: BTreeSet<Modifier> = [Modifier::Ctrl].into_iter().collect();
 
         // Initially no active combo
         assert!(tracker.get_active_combo().is_none());
@@ -3395,8 +3269,8 @@ mod tests {
 
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Ctrl+Q").expect("should parse"),
-            parse_combo("Ctrl+W").expect("should parse"),
+            "Ctrl+Q".parse::<KeyCombo>().expect("should parse"),
+            "Ctrl+W".parse::<KeyCombo>().expect("should parse"),
         );
 
         // Press Ctrl+Q and activate combo
@@ -3419,8 +3293,8 @@ mod tests {
         // Test that state transitions to Idle when all modifiers are released
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Ctrl+Q").expect("should parse"),
-            parse_combo("Alt+F4").expect("should parse"),
+            "Ctrl+Q".parse::<KeyCombo>().expect("should parse"),
+            "Alt+F4".parse::<KeyCombo>().expect("should parse"),
         );
 
         // Activate combo
@@ -3442,8 +3316,8 @@ mod tests {
         // Test that state transitions to ModifiersHeld when some modifiers still held
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Ctrl+Shift+Q").expect("should parse"),
-            parse_combo("Alt+F4").expect("should parse"),
+            "Ctrl+Shift+Q".parse::<KeyCombo>().expect("should parse"),
+            "Alt+F4".parse::<KeyCombo>().expect("should parse"),
         );
 
         // Activate combo with Ctrl+Shift
@@ -3468,8 +3342,8 @@ mod tests {
 
         let mut tracker = ComboTracker::new();
         tracker.register_combo(
-            parse_combo("Ctrl+Shift+Q").expect("should parse"),
-            parse_combo("Alt+F4").expect("should parse"),
+            "Ctrl+Shift+Q".parse::<KeyCombo>().expect("should parse"),
+            "Alt+F4".parse::<KeyCombo>().expect("should parse"),
         );
 
         // === User presses Ctrl ===
@@ -3533,8 +3407,8 @@ mod tests {
         use niri_mapper_config::Profile;
 
         let mut profile = Profile::default();
-        profile.combo.insert("Ctrl+Q".to_string(), "Alt+F4".to_string());
-        profile.combo.insert("Ctrl+Shift+A".to_string(), "Super+A".to_string());
+        profile.combo.insert("Ctrl+Q".parse().unwrap(), "Alt+F4".parse().unwrap());
+        profile.combo.insert("Ctrl+Shift+A".parse().unwrap(), "Super+A".parse().unwrap());
 
         let remapper = Remapper::from_profile(&profile);
 
@@ -3543,18 +3417,18 @@ mod tests {
     }
 
     #[test]
-    fn test_remapper_from_profile_invalid_combo_ignored() {
-        // Task 020-2.7: Invalid combos should be skipped with warning
+    fn test_remapper_from_profile_invalid_combo_rejected() {
+        // Task 020-2.7: Invalid combos never reach the remapper
         use niri_mapper_config::Profile;
 
         let mut profile = Profile::default();
-        profile.combo.insert("Ctrl+Q".to_string(), "Alt+F4".to_string());
-        profile.combo.insert("InvalidKey+Q".to_string(), "Alt+F4".to_string()); // Invalid
-        profile.combo.insert("Ctrl+A".to_string(), "UnknownOutput".to_string()); // Invalid
+        profile.combo.insert("Ctrl+Q".parse().unwrap(), "Alt+F4".parse().unwrap());
 
-        let remapper = Remapper::from_profile(&profile);
+        // Invalid combos are rejected by the config parser, so they never reach the remapper
+        assert!("InvalidKey+Q".parse::<niri_mapper_config::KeyCombo>().is_err());
+        assert!("UnknownOutput".parse::<niri_mapper_config::KeyCombo>().is_err());
 
-        // Only valid combo should be loaded
+        let remapper = Remapper::from_profile(&profile);
         assert_eq!(remapper.combo_tracker.combos.len(), 1);
     }
 
@@ -3564,7 +3438,7 @@ mod tests {
         use niri_mapper_config::Profile;
 
         let mut profile = Profile::default();
-        profile.combo.insert("Ctrl+Q".to_string(), "Alt+F4".to_string());
+        profile.combo.insert("Ctrl+Q".parse().unwrap(), "Alt+F4".parse().unwrap());
 
         let mut remapper = Remapper::from_profile(&profile);
 
@@ -3596,7 +3470,7 @@ mod tests {
         use niri_mapper_config::Profile;
 
         let mut profile = Profile::default();
-        profile.combo.insert("Ctrl+Q".to_string(), "Alt+F4".to_string());
+        profile.combo.insert("Ctrl+Q".parse().unwrap(), "Alt+F4".parse().unwrap());
 
         let mut remapper = Remapper::from_profile(&profile);
 
@@ -3629,7 +3503,7 @@ mod tests {
         use niri_mapper_config::Profile;
 
         let mut profile = Profile::default();
-        profile.combo.insert("Ctrl+Q".to_string(), "Alt+F4".to_string());
+        profile.combo.insert("Ctrl+Q".parse().unwrap(), "Alt+F4".parse().unwrap());
 
         let mut remapper = Remapper::from_profile(&profile);
 
@@ -3651,8 +3525,8 @@ mod tests {
         use niri_mapper_config::Profile;
 
         let mut profile = Profile::default();
-        profile.remap.insert("CapsLock".to_string(), "Escape".to_string());
-        profile.combo.insert("Ctrl+Q".to_string(), "Alt+F4".to_string());
+        profile.remap.insert("CapsLock".parse().unwrap(), "Escape".parse().unwrap());
+        profile.combo.insert("Ctrl+Q".parse().unwrap(), "Alt+F4".parse().unwrap());
 
         let mut remapper = Remapper::from_profile(&profile);
 
@@ -3669,8 +3543,8 @@ mod tests {
         use niri_mapper_config::Profile;
 
         let mut profile = Profile::default();
-        profile.remap.insert("Q".to_string(), "W".to_string()); // Q -> W remap
-        profile.combo.insert("Ctrl+Q".to_string(), "Alt+F4".to_string()); // Ctrl+Q combo
+        profile.remap.insert("Q".parse().unwrap(), "W".parse().unwrap()); // Q -> W remap
+        profile.combo.insert("Ctrl+Q".parse().unwrap(), "Alt+F4".parse().unwrap()); // Ctrl+Q combo
 
         let mut remapper = Remapper::from_profile(&profile);
 
@@ -3692,8 +3566,8 @@ mod tests {
         use niri_mapper_config::Profile;
 
         let mut profile = Profile::default();
-        profile.remap.insert("Q".to_string(), "W".to_string()); // Q -> W remap
-        profile.combo.insert("Ctrl+Shift+Q".to_string(), "Alt+F4".to_string()); // Different combo
+        profile.remap.insert("Q".parse().unwrap(), "W".parse().unwrap()); // Q -> W remap
+        profile.combo.insert("Ctrl+Shift+Q".parse().unwrap(), "Alt+F4".parse().unwrap()); // Different combo
 
         let mut remapper = Remapper::from_profile(&profile);
 
@@ -3721,7 +3595,7 @@ mod tests {
         use niri_mapper_config::Profile;
 
         let mut profile = Profile::default();
-        profile.combo.insert("Ctrl+Shift+Q".to_string(), "Alt+F4".to_string());
+        profile.combo.insert("Ctrl+Shift+Q".parse().unwrap(), "Alt+F4".parse().unwrap());
 
         let mut remapper = Remapper::from_profile(&profile);
 
@@ -3935,7 +3809,7 @@ mod tests {
         // Test that we can access the inner remapper
         let mut profiles = HashMap::new();
         let mut profile = Profile::default();
-        profile.remap.insert("A".to_string(), "B".to_string());
+        profile.remap.insert("A".parse().unwrap(), "B".parse().unwrap());
         profiles.insert("default".to_string(), profile);
 
         let mut device_remapper = DeviceRemapper::new(
@@ -3969,12 +3843,12 @@ mod tests {
 
         // Default profile: A -> B
         let mut default_profile = Profile::default();
-        default_profile.remap.insert("A".to_string(), "B".to_string());
+        default_profile.remap.insert("A".parse().unwrap(), "B".parse().unwrap());
         profiles.insert("default".to_string(), default_profile);
 
         // Gaming profile: A -> C
         let mut gaming_profile = Profile::default();
-        gaming_profile.remap.insert("A".to_string(), "C".to_string());
+        gaming_profile.remap.insert("A".parse().unwrap(), "C".parse().unwrap());
         profiles.insert("gaming".to_string(), gaming_profile);
 
         let mut device_remapper = DeviceRemapper::new(
@@ -4085,11 +3959,11 @@ mod tests {
         let mut profiles = HashMap::new();
 
         let mut default_profile = Profile::default();
-        default_profile.remap.insert("A".to_string(), "B".to_string());
+        default_profile.remap.insert("A".parse().unwrap(), "B".parse().unwrap());
         profiles.insert("default".to_string(), default_profile);
 
         let mut gaming_profile = Profile::default();
-        gaming_profile.remap.insert("A".to_string(), "C".to_string());
+        gaming_profile.remap.insert("A".parse().unwrap(), "C".parse().unwrap());
         profiles.insert("gaming".to_string(), gaming_profile);
 
         let mut profile_switch = HashMap::new();
@@ -4156,12 +4030,12 @@ mod tests {
 
         // Default profile: A -> B
         let mut default_profile = Profile::default();
-        default_profile.remap.insert("A".to_string(), "B".to_string());
+        default_profile.remap.insert("A".parse().unwrap(), "B".parse().unwrap());
         profiles.insert("default".to_string(), default_profile);
 
         // Gaming profile: A -> C
         let mut gaming_profile = Profile::default();
-        gaming_profile.remap.insert("A".to_string(), "C".to_string());
+        gaming_profile.remap.insert("A".parse().unwrap(), "C".parse().unwrap());
         profiles.insert("gaming".to_string(), gaming_profile);
 
         // Profile switch keybinds
@@ -4314,7 +4188,7 @@ mod tests {
                     events.iter().map(|e| Key::new(e.code())).collect::<Vec<_>>()
                 );
             }
-            RemapResult::Macro(_) => {
+            RemapResult::Macro(_) | RemapResult::MacroRelease(_) => {
                 panic!("Profile switch keybind should NOT produce Macro");
             }
         }
@@ -4351,7 +4225,7 @@ mod tests {
             RemapResult::ProfileSwitch(name) => {
                 panic!("Should NOT trigger ProfileSwitch without modifiers, got '{}'", name);
             }
-            RemapResult::Macro(_) => {
+            RemapResult::Macro(_) | RemapResult::MacroRelease(_) => {
                 panic!("Should NOT trigger Macro");
             }
         }
@@ -4394,7 +4268,7 @@ mod tests {
             RemapResult::ProfileSwitch(name) => {
                 panic!("Should NOT trigger ProfileSwitch with partial modifiers (Ctrl only), got '{}'", name);
             }
-            RemapResult::Macro(_) => {
+            RemapResult::Macro(_) | RemapResult::MacroRelease(_) => {
                 panic!("Should NOT trigger Macro");
             }
         }
@@ -4442,7 +4316,7 @@ mod tests {
             RemapResult::ProfileSwitch(name) => {
                 panic!("Should NOT trigger ProfileSwitch with extra modifiers (Ctrl+Shift vs Ctrl), got '{}'", name);
             }
-            RemapResult::Macro(_) => {
+            RemapResult::Macro(_) | RemapResult::MacroRelease(_) => {
                 panic!("Should NOT trigger Macro");
             }
         }
@@ -4529,4 +4403,266 @@ mod tests {
             other => panic!("Expected ProfileSwitch with RightCtrl, got {:?}", other),
         }
     }
+
+    // ========================================================================
+    // Macro Trigger Tests
+    // ========================================================================
+
+    #[test]
+    fn test_macro_trigger_carries_policy() {
+        let mut profile = Profile::default();
+        profile.macros.insert("F5".parse().unwrap(), vec![MacroAction::Key("A".parse().unwrap())]);
+        profile.macro_policies.insert("F5".parse().unwrap(), MacroPolicy::Restart);
+        profile.macros.insert("F6".parse().unwrap(), vec![MacroAction::Key("B".parse().unwrap())]);
+
+        let mut remapper = Remapper::from_profile(&profile);
+
+        match remapper.process(InputEvent::new(evdev::EventType::KEY, Key::KEY_F5.code(), event_value::PRESS)) {
+            RemapResult::Macro(trigger) => {
+                assert_eq!(trigger.key, Key::KEY_F5);
+                assert_eq!(trigger.policy, MacroPolicy::Restart);
+                assert_eq!(trigger.actions.len(), 1);
+            }
+            other => panic!("Expected Macro, got {:?}", other),
+        }
+
+        match remapper.process(InputEvent::new(evdev::EventType::KEY, Key::KEY_F6.code(), event_value::PRESS)) {
+            RemapResult::Macro(trigger) => {
+                assert_eq!(trigger.policy, MacroPolicy::Ignore, "unset policy should default to ignore");
+            }
+            other => panic!("Expected Macro, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_macro_trigger_release_and_repeat_are_consumed() {
+        let mut profile = Profile::default();
+        profile.macros.insert("F5".parse().unwrap(), vec![MacroAction::Key("A".parse().unwrap())]);
+
+        let mut remapper = Remapper::from_profile(&profile);
+
+        let _ = remapper.process(InputEvent::new(evdev::EventType::KEY, Key::KEY_F5.code(), event_value::PRESS));
+
+        let events = expect_events(remapper.process(InputEvent::new(
+            evdev::EventType::KEY,
+            Key::KEY_F5.code(),
+            event_value::REPEAT,
+        )));
+        assert!(events.is_empty(), "repeat of a macro trigger should be dropped");
+
+        match remapper.process(InputEvent::new(evdev::EventType::KEY, Key::KEY_F5.code(), event_value::RELEASE)) {
+            RemapResult::MacroRelease(key) => assert_eq!(key, Key::KEY_F5),
+            other => panic!("Expected MacroRelease, got {:?}", other),
+        }
+    }
+
+    fn azerty_translation() -> Option<KeyTranslation> {
+        let settings = niri_mapper_config::XkbSettings {
+            layout: "fr".to_string(),
+            ..Default::default()
+        };
+        // libxkbcommon or the xkb data may not be installed (e.g., in the Nix sandbox)
+        crate::text_input::Keymap::from_xkb(&settings, 0)
+            .ok()
+            .map(|keymap| KeyTranslation::from_keymap(&keymap))
+    }
+
+    #[test]
+    fn test_keysym_mode_translates_rule_keys() {
+        let Some(translation) = azerty_translation() else {
+            return;
+        };
+        let mut profile = Profile::default();
+        profile.remap.insert("Q".parse().unwrap(), "M".parse().unwrap());
+        let mut remapper = Remapper::from_profile(&profile);
+        remapper.set_key_translation(Some(translation));
+
+        // The key labeled Q on AZERTY (US A position) emits the key labeled M
+        let events = expect_events(remapper.process(InputEvent::new(
+            evdev::EventType::KEY,
+            Key::KEY_A.code(),
+            event_value::PRESS,
+        )));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].code(), Key::KEY_SEMICOLON.code());
+
+        // The key in the US Q position types A on AZERTY and passes through
+        let events = expect_events(remapper.process(InputEvent::new(
+            evdev::EventType::KEY,
+            Key::KEY_Q.code(),
+            event_value::PRESS,
+        )));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].code(), Key::KEY_Q.code());
+    }
+
+    #[test]
+    fn test_keysym_mode_release_after_layout_switch() {
+        let Some(translation) = azerty_translation() else {
+            return;
+        };
+        let mut profile = Profile::default();
+        profile.remap.insert("Q".parse().unwrap(), "Escape".parse().unwrap());
+        let mut remapper = Remapper::from_profile(&profile);
+        remapper.set_key_translation(Some(translation));
+
+        let events = expect_events(remapper.process(InputEvent::new(
+            evdev::EventType::KEY,
+            Key::KEY_A.code(),
+            event_value::PRESS,
+        )));
+        assert_eq!(events[0].code(), Key::KEY_ESC.code());
+
+        // Switching to US while the key is held must still release Escape
+        remapper.set_key_translation(Some(KeyTranslation::from_keymap(&crate::text_input::Keymap::us())));
+        let events = expect_events(remapper.process(InputEvent::new(
+            evdev::EventType::KEY,
+            Key::KEY_A.code(),
+            event_value::RELEASE,
+        )));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].code(), Key::KEY_ESC.code());
+        assert_eq!(events[0].value(), event_value::RELEASE);
+    }
+
+    // ========================================================================
+    // Conditional Rule Tests
+    // ========================================================================
+
+    fn when_app_id(pattern: &str) -> Vec<niri_mapper_config::WindowMatch> {
+        vec![niri_mapper_config::WindowMatch {
+            app_id: Some(regex::Regex::new(pattern).unwrap()),
+            ..Default::default()
+        }]
+    }
+
+    fn focus_on(app_id: &str) -> FocusContext {
+        FocusContext {
+            app_id: Some(app_id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn key_event(key: Key, value: i32) -> InputEvent {
+        InputEvent::new(evdev::EventType::KEY, key.code(), value)
+    }
+
+    fn capslock_profile() -> Profile {
+        let mut profile = Profile::default();
+        profile.remap.insert("CapsLock".parse().unwrap(), "Escape".parse().unwrap());
+        profile.conditional_rules.push(niri_mapper_config::ConditionalRule {
+            when: when_app_id("^Alacritty$"),
+            entry: ConditionalEntry::Remap {
+                from: "CapsLock".parse().unwrap(),
+                to: "LeftCtrl".parse().unwrap(),
+            },
+        });
+        profile
+    }
+
+    #[test]
+    fn test_conditional_remap_follows_focus() {
+        let mut remapper = Remapper::from_profile(&capslock_profile());
+
+        let events = expect_events(remapper.process(key_event(Key::KEY_CAPSLOCK, event_value::PRESS)));
+        assert_eq!(events[0].code(), Key::KEY_ESC.code());
+        let _ = remapper.process(key_event(Key::KEY_CAPSLOCK, event_value::RELEASE));
+
+        remapper.set_focus(&focus_on("Alacritty"));
+        let events = expect_events(remapper.process(key_event(Key::KEY_CAPSLOCK, event_value::PRESS)));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].code(), Key::KEY_LEFTCTRL.code());
+        let _ = remapper.process(key_event(Key::KEY_CAPSLOCK, event_value::RELEASE));
+
+        remapper.set_focus(&focus_on("firefox"));
+        let events = expect_events(remapper.process(key_event(Key::KEY_CAPSLOCK, event_value::PRESS)));
+        assert_eq!(events[0].code(), Key::KEY_ESC.code());
+    }
+
+    #[test]
+    fn test_conditional_remap_release_after_focus_change() {
+        let mut remapper = Remapper::from_profile(&capslock_profile());
+        remapper.set_focus(&focus_on("Alacritty"));
+
+        let _ = remapper.process(key_event(Key::KEY_CAPSLOCK, event_value::PRESS));
+        remapper.set_focus(&focus_on("firefox"));
+
+        // Repeats and the release still go to the key chosen on press
+        let events = expect_events(remapper.process(key_event(Key::KEY_CAPSLOCK, event_value::REPEAT)));
+        assert_eq!(events[0].code(), Key::KEY_LEFTCTRL.code());
+        assert_eq!(events[0].value(), event_value::REPEAT);
+        let events = expect_events(remapper.process(key_event(Key::KEY_CAPSLOCK, event_value::RELEASE)));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].code(), Key::KEY_LEFTCTRL.code());
+        assert_eq!(events[0].value(), event_value::RELEASE);
+
+        // The next press uses the new focus
+        let events = expect_events(remapper.process(key_event(Key::KEY_CAPSLOCK, event_value::PRESS)));
+        assert_eq!(events[0].code(), Key::KEY_ESC.code());
+    }
+
+    #[test]
+    fn test_conditional_combo_and_macro() {
+        let mut profile = Profile::default();
+        profile.conditional_rules.push(niri_mapper_config::ConditionalRule {
+            when: when_app_id("kitty"),
+            entry: ConditionalEntry::Combo {
+                from: "Ctrl+W".parse().unwrap(),
+                to: "Ctrl+Shift+W".parse().unwrap(),
+            },
+        });
+        profile.conditional_rules.push(niri_mapper_config::ConditionalRule {
+            when: when_app_id("kitty"),
+            entry: ConditionalEntry::Macro {
+                trigger: "F5".parse().unwrap(),
+                actions: vec![MacroAction::Key("Ctrl+R".parse().unwrap())],
+                policy: MacroPolicy::Restart,
+            },
+        });
+        let mut remapper = Remapper::from_profile(&profile);
+
+        // Without a matching focus, both pass through
+        let _ = remapper.process(key_event(Key::KEY_LEFTCTRL, event_value::PRESS));
+        let events = expect_events(remapper.process(key_event(Key::KEY_W, event_value::PRESS)));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].code(), Key::KEY_W.code());
+        let _ = remapper.process(key_event(Key::KEY_W, event_value::RELEASE));
+        let events = expect_events(remapper.process(key_event(Key::KEY_F5, event_value::PRESS)));
+        assert_eq!(events[0].code(), Key::KEY_F5.code());
+        let _ = remapper.process(key_event(Key::KEY_F5, event_value::RELEASE));
+
+        remapper.set_focus(&focus_on("kitty"));
+        let events = expect_events(remapper.process(key_event(Key::KEY_W, event_value::PRESS)));
+        assert!(events.iter().any(|e| e.code() == Key::KEY_LEFTSHIFT.code() && e.value() == event_value::PRESS));
+        assert!(remapper.combo_tracker().has_active_combo_for(Key::KEY_W));
+        let events = expect_events(remapper.process(key_event(Key::KEY_W, event_value::RELEASE)));
+        assert!(events.iter().any(|e| e.code() == Key::KEY_W.code() && e.value() == event_value::RELEASE));
+        let _ = remapper.process(key_event(Key::KEY_LEFTCTRL, event_value::RELEASE));
+
+        match remapper.process(key_event(Key::KEY_F5, event_value::PRESS)) {
+            RemapResult::Macro(trigger) => {
+                assert_eq!(trigger.key, Key::KEY_F5);
+                assert_eq!(trigger.policy, MacroPolicy::Restart);
+            }
+            other => panic!("Expected Macro, got {:?}", other),
+        }
+        match remapper.process(key_event(Key::KEY_F5, event_value::RELEASE)) {
+            RemapResult::MacroRelease(key) => assert_eq!(key, Key::KEY_F5),
+            other => panic!("Expected MacroRelease, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_switch_profile_reloads_conditional_rules() {
+        let mut remapper = Remapper::from_profile(&Profile::default());
+        remapper.set_focus(&focus_on("Alacritty"));
+
+        let events = expect_events(remapper.process(key_event(Key::KEY_CAPSLOCK, event_value::PRESS)));
+        assert_eq!(events[0].code(), Key::KEY_CAPSLOCK.code());
+        let _ = remapper.process(key_event(Key::KEY_CAPSLOCK, event_value::RELEASE));
+
+        remapper.switch_profile(&capslock_profile());
+        let events = expect_events(remapper.process(key_event(Key::KEY_CAPSLOCK, event_value::PRESS)));
+        assert_eq!(events[0].code(), Key::KEY_LEFTCTRL.code());
+    }
 }
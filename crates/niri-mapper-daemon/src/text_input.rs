@@ -0,0 +1,435 @@
+//! Typing Unicode text through the virtual keyboard
+//!
+//! Macro `text()` actions are turned into key strokes by looking up each
+//! character in the user's xkb keymap (by default the one configured in niri),
+//! so text comes out right on any keyboard layout. Characters the layout
+//! cannot produce are entered with the configured [`UnicodeInput`] method.
+//!
+//! # Keymap Resolution
+//!
+//! For every key, the keymap is evaluated with no modifiers, Shift, AltGr
+//! (level 3) and Shift+AltGr. Each character is typed with the first of these
+//! that produces it. Dead keys and compose sequences are not used.
+//!
+//! libxkbcommon is loaded at runtime. If it cannot be loaded or the keymap
+//! fails to compile, a built-in US layout table is used instead.
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::path::PathBuf;
+use std::ptr;
+
+use anyhow::{bail, Context, Result};
+use evdev::Key;
+use niri_mapper_config::{GlobalConfig, UnicodeInput, XkbSettings};
+use xkbcommon_dl::{
+    xkb_context_flags, xkb_keymap, xkb_keymap_compile_flags, xkb_keymap_format, xkb_rule_names,
+    XkbCommon, XKB_MOD_INVALID,
+};
+
+/// Offset between evdev key codes and xkb key codes.
+const XKB_KEYCODE_OFFSET: u32 = 8;
+
+/// The `ISO_Level3_Shift` keysym (AltGr).
+const KEYSYM_ISO_LEVEL3_SHIFT: u32 = 0xfe03;
+
+/// A key tapped while holding modifiers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyStroke {
+    /// Modifiers pressed before and released after the key
+    pub modifiers: Vec<Key>,
+    /// The key to tap
+    pub key: Key,
+}
+
+impl KeyStroke {
+    fn new(modifiers: Vec<Key>, key: Key) -> Self {
+        Self { modifiers, key }
+    }
+}
+
+/// Characters a keyboard layout can type, with the key strokes that type them.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    strokes: HashMap<char, KeyStroke>,
+}
+
+impl Keymap {
+    /// Compile an xkb keymap and resolve the characters of one of its layouts.
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - xkb rule names, or a keymap file
+    /// * `layout` - Index of the layout (group) to use, for multi-layout keymaps
+    pub fn from_xkb(settings: &XkbSettings, layout: u32) -> Result<Self> {
+        let xkb = xkbcommon_dl::xkbcommon_option().context("libxkbcommon could not be loaded")?;
+
+        // Read the keymap file before creating any xkb objects
+        let keymap_source = match &settings.file {
+            Some(path) => Some(CString::new(
+                std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read xkb keymap {}", path.display()))?,
+            )?),
+            None => None,
+        };
+
+        // Empty names select the xkb defaults
+        let name = |value: &str| -> Result<Option<CString>> {
+            Ok(if value.is_empty() { None } else { Some(CString::new(value)?) })
+        };
+        let rules = name(&settings.rules)?;
+        let model = name(&settings.model)?;
+        let layouts = name(&settings.layout)?;
+        let variant = name(&settings.variant)?;
+        let options = settings.options.as_deref().map(CString::new).transpose()?;
+        let as_ptr = |s: &Option<CString>| s.as_ref().map_or(ptr::null(), |s| s.as_ptr());
+
+        // SAFETY: all pointers passed to libxkbcommon are either null or point to
+        // NUL-terminated strings that outlive the calls, and every object created
+        // here is released before returning.
+        unsafe {
+            let context = (xkb.xkb_context_new)(xkb_context_flags::XKB_CONTEXT_NO_FLAGS);
+            if context.is_null() {
+                bail!("Failed to create xkb context");
+            }
+
+            let keymap = match &keymap_source {
+                Some(source) => (xkb.xkb_keymap_new_from_string)(
+                    context,
+                    source.as_ptr(),
+                    xkb_keymap_format::XKB_KEYMAP_FORMAT_TEXT_V1,
+                    xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+                ),
+                None => {
+                    let names = xkb_rule_names {
+                        rules: as_ptr(&rules),
+                        model: as_ptr(&model),
+                        layout: as_ptr(&layouts),
+                        variant: as_ptr(&variant),
+                        options: as_ptr(&options),
+                    };
+                    (xkb.xkb_keymap_new_from_names)(
+                        context,
+                        &names,
+                        xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+                    )
+                }
+            };
+
+            let result = if keymap.is_null() {
+                Err(anyhow::anyhow!("Failed to compile xkb keymap {:?}", settings))
+            } else {
+                let strokes = resolve_strokes(xkb, keymap, layout);
+                (xkb.xkb_keymap_unref)(keymap);
+                strokes
+            };
+
+            (xkb.xkb_context_unref)(context);
+            result.map(|strokes| Self { strokes })
+        }
+    }
+
+    /// The built-in US layout, used when no xkb keymap is available.
+    pub fn us() -> Self {
+        const UNSHIFTED: &str = "`1234567890-=qwertyuiop[]\\asdfghjkl;'zxcvbnm,./";
+        const SHIFTED: &str = "~!@#$%^&*()_+QWERTYUIOP{}|ASDFGHJKL:\"ZXCVBNM<>?";
+        const KEYS: [Key; 47] = [
+            Key::KEY_GRAVE, Key::KEY_1, Key::KEY_2, Key::KEY_3, Key::KEY_4, Key::KEY_5,
+            Key::KEY_6, Key::KEY_7, Key::KEY_8, Key::KEY_9, Key::KEY_0, Key::KEY_MINUS,
+            Key::KEY_EQUAL, Key::KEY_Q, Key::KEY_W, Key::KEY_E, Key::KEY_R, Key::KEY_T,
+            Key::KEY_Y, Key::KEY_U, Key::KEY_I, Key::KEY_O, Key::KEY_P, Key::KEY_LEFTBRACE,
+            Key::KEY_RIGHTBRACE, Key::KEY_BACKSLASH, Key::KEY_A, Key::KEY_S, Key::KEY_D,
+            Key::KEY_F, Key::KEY_G, Key::KEY_H, Key::KEY_J, Key::KEY_K, Key::KEY_L,
+            Key::KEY_SEMICOLON, Key::KEY_APOSTROPHE, Key::KEY_Z, Key::KEY_X, Key::KEY_C,
+            Key::KEY_V, Key::KEY_B, Key::KEY_N, Key::KEY_M, Key::KEY_COMMA, Key::KEY_DOT,
+            Key::KEY_SLASH,
+        ];
+
+        let mut strokes = HashMap::new();
+        for ((plain, shifted), key) in UNSHIFTED.chars().zip(SHIFTED.chars()).zip(KEYS) {
+            strokes.insert(plain, KeyStroke::new(vec![], key));
+            strokes.insert(shifted, KeyStroke::new(vec![Key::KEY_LEFTSHIFT], key));
+        }
+        strokes.insert(' ', KeyStroke::new(vec![], Key::KEY_SPACE));
+        strokes.insert('\t', KeyStroke::new(vec![], Key::KEY_TAB));
+        strokes.insert('\n', KeyStroke::new(vec![], Key::KEY_ENTER));
+
+        Self { strokes }
+    }
+
+    /// The key stroke that types `c`, if the layout has one.
+    pub fn stroke(&self, c: char) -> Option<&KeyStroke> {
+        self.strokes.get(&c)
+    }
+
+    /// Iterate over all characters of the layout with their key strokes.
+    pub fn iter(&self) -> impl Iterator<Item = (char, &KeyStroke)> {
+        self.strokes.iter().map(|(&c, stroke)| (c, stroke))
+    }
+}
+
+/// Find the key stroke for every character the keymap's `layout` can type.
+///
+/// # Safety
+///
+/// `keymap` must be a valid keymap created by `xkb`.
+unsafe fn resolve_strokes(
+    xkb: &XkbCommon,
+    keymap: *mut xkb_keymap,
+    layout: u32,
+) -> Result<HashMap<char, KeyStroke>> {
+    let num_layouts = (xkb.xkb_keymap_num_layouts)(keymap);
+    if layout >= num_layouts {
+        bail!("Keymap has {} layout(s), layout {} does not exist", num_layouts, layout);
+    }
+
+    let state = (xkb.xkb_state_new)(keymap);
+    if state.is_null() {
+        bail!("Failed to create xkb state");
+    }
+
+    let min = (xkb.xkb_keymap_min_keycode)(keymap).max(XKB_KEYCODE_OFFSET);
+    let max = (xkb.xkb_keymap_max_keycode)(keymap);
+    let to_key = |keycode: u32| Key::new((keycode - XKB_KEYCODE_OFFSET) as u16);
+
+    // AltGr is whichever key produces ISO_Level3_Shift on its first level.
+    // RightAlt is checked first, since keymaps also bind it to the virtual
+    // LVL3 key, which no keyboard has.
+    let is_level3 = |keycode: u32| {
+        let mut syms = ptr::null();
+        let count = (xkb.xkb_keymap_key_get_syms_by_level)(keymap, keycode, layout, 0, &mut syms);
+        count > 0 && *syms == KEYSYM_ISO_LEVEL3_SHIFT
+    };
+    let right_alt = Key::KEY_RIGHTALT.code() as u32 + XKB_KEYCODE_OFFSET;
+    let level3_key = std::iter::once(right_alt)
+        .chain(min..=max)
+        .find(|&keycode| is_level3(keycode))
+        .map_or(Key::KEY_RIGHTALT, to_key);
+
+    let shift = (xkb.xkb_keymap_mod_get_index)(keymap, c"Shift".as_ptr());
+    let level3 = (xkb.xkb_keymap_mod_get_index)(keymap, c"Mod5".as_ptr());
+
+    // Modifier combinations in order of preference
+    let mut levels = vec![(0, vec![])];
+    if shift != XKB_MOD_INVALID {
+        levels.push((1 << shift, vec![Key::KEY_LEFTSHIFT]));
+    }
+    if level3 != XKB_MOD_INVALID {
+        levels.push((1 << level3, vec![level3_key]));
+        if shift != XKB_MOD_INVALID {
+            levels.push(((1 << shift) | (1 << level3), vec![Key::KEY_LEFTSHIFT, level3_key]));
+        }
+    }
+
+    let mut strokes = HashMap::new();
+    for (mask, modifiers) in levels {
+        (xkb.xkb_state_update_mask)(state, mask, 0, 0, 0, 0, layout);
+        for keycode in min..=max {
+            let sym = (xkb.xkb_state_key_get_one_sym)(state, keycode);
+            let Some(c) = char::from_u32((xkb.xkb_keysym_to_utf32)(sym)) else {
+                continue;
+            };
+            let c = match c {
+                '\r' => '\n',
+                '\t' | '\n' => c,
+                c if c == '\0' || c.is_control() => continue,
+                c => c,
+            };
+            strokes
+                .entry(c)
+                .or_insert_with(|| KeyStroke::new(modifiers.clone(), to_key(keycode)));
+        }
+    }
+
+    (xkb.xkb_state_unref)(state);
+    Ok(strokes)
+}
+
+/// Turns text into the key strokes that type it.
+#[derive(Debug, Clone)]
+pub struct TextTyper {
+    keymap: Keymap,
+    unicode_input: UnicodeInput,
+}
+
+impl Default for TextTyper {
+    fn default() -> Self {
+        Self::new(Keymap::us(), UnicodeInput::default())
+    }
+}
+
+impl TextTyper {
+    /// Create a typer for a keymap and Unicode fallback method.
+    pub fn new(keymap: Keymap, unicode_input: UnicodeInput) -> Self {
+        Self {
+            keymap,
+            unicode_input,
+        }
+    }
+
+    /// Create a typer for a layout of the configured keymap (see [`xkb_settings`]).
+    ///
+    /// Falls back to the built-in US layout if the keymap cannot be loaded.
+    pub fn from_config(global: &GlobalConfig, layout: u32) -> Self {
+        let keymap = match Keymap::from_xkb(&xkb_settings(global), layout) {
+            Ok(keymap) => keymap,
+            Err(e) => {
+                tracing::warn!("Using the US layout to type macro text: {:#}", e);
+                Keymap::us()
+            }
+        };
+
+        Self::new(keymap, global.unicode_input)
+    }
+
+    /// The key strokes that type `text`.
+    ///
+    /// Characters missing from the keymap are entered with the Unicode input
+    /// method, or skipped with a warning if it is disabled or cannot be typed.
+    pub fn strokes(&self, text: &str) -> Vec<KeyStroke> {
+        let mut strokes = Vec::new();
+        for c in text.chars() {
+            if let Some(stroke) = self.keymap.stroke(c) {
+                strokes.push(stroke.clone());
+                continue;
+            }
+
+            match self.unicode_strokes(c) {
+                Some(unicode) => strokes.extend(unicode),
+                None => tracing::warn!("Cannot type character {:?} (U+{:04X})", c, c as u32),
+            }
+        }
+        strokes
+    }
+
+    /// The key strokes that enter `c` by its code point.
+    fn unicode_strokes(&self, c: char) -> Option<Vec<KeyStroke>> {
+        match self.unicode_input {
+            UnicodeInput::None => None,
+            UnicodeInput::CtrlShiftU => {
+                let u = self.keymap.stroke('u')?;
+                let mut strokes = vec![KeyStroke::new(
+                    vec![Key::KEY_LEFTCTRL, Key::KEY_LEFTSHIFT],
+                    u.key,
+                )];
+                for digit in format!("{:x}", c as u32).chars() {
+                    strokes.push(self.keymap.stroke(digit)?.clone());
+                }
+                strokes.push(KeyStroke::new(vec![], Key::KEY_SPACE));
+                Some(strokes)
+            }
+        }
+    }
+}
+
+/// The xkb settings of the keymap the user types with.
+///
+/// Uses the `xkb` settings of the config if present, and otherwise the
+/// keyboard settings of niri's config.
+pub fn xkb_settings(global: &GlobalConfig) -> XkbSettings {
+    match &global.xkb {
+        Some(settings) => settings.clone(),
+        None => niri_xkb_settings().unwrap_or_default(),
+    }
+}
+
+/// Read the xkb settings from niri's config file, if it configures any.
+fn niri_xkb_settings() -> Option<XkbSettings> {
+    let path = niri_config_path();
+    let content = std::fs::read_to_string(&path).ok()?;
+    match niri_mapper_config::parse_niri_xkb(&content) {
+        Ok(settings) => settings,
+        Err(e) => {
+            tracing::warn!("Failed to read xkb settings from {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Location of niri's config file (`$NIRI_CONFIG`, else under the XDG config dir).
+fn niri_config_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("NIRI_CONFIG") {
+        return PathBuf::from(path);
+    }
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(shellexpand::tilde("~/.config").into_owned()));
+    config_dir.join("niri").join("config.kdl")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stroke(modifiers: &[Key], key: Key) -> KeyStroke {
+        KeyStroke::new(modifiers.to_vec(), key)
+    }
+
+    #[test]
+    fn test_us_keymap_strokes() {
+        let typer = TextTyper::default();
+        assert_eq!(
+            typer.strokes("Hi!\n"),
+            vec![
+                stroke(&[Key::KEY_LEFTSHIFT], Key::KEY_H),
+                stroke(&[], Key::KEY_I),
+                stroke(&[Key::KEY_LEFTSHIFT], Key::KEY_1),
+                stroke(&[], Key::KEY_ENTER),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ctrl_shift_u_fallback() {
+        let typer = TextTyper::default();
+        assert_eq!(
+            typer.strokes("é"),
+            vec![
+                stroke(&[Key::KEY_LEFTCTRL, Key::KEY_LEFTSHIFT], Key::KEY_U),
+                stroke(&[], Key::KEY_E),
+                stroke(&[], Key::KEY_9),
+                stroke(&[], Key::KEY_SPACE),
+            ]
+        );
+
+        // Characters outside the BMP use their full code point
+        assert_eq!(typer.strokes("😀").len(), 1 + 5 + 1);
+    }
+
+    #[test]
+    fn test_unicode_input_none_skips_characters() {
+        let typer = TextTyper::new(Keymap::us(), UnicodeInput::None);
+        assert_eq!(typer.strokes("café"), typer.strokes("caf"));
+    }
+
+    #[test]
+    fn test_xkb_german_layout() {
+        let settings = XkbSettings {
+            layout: "de".to_string(),
+            variant: "nodeadkeys".to_string(),
+            ..Default::default()
+        };
+        // libxkbcommon or the xkb data may not be installed (e.g., in the Nix sandbox)
+        let Ok(keymap) = Keymap::from_xkb(&settings, 0) else {
+            return;
+        };
+
+        assert_eq!(keymap.stroke('z'), Some(&stroke(&[], Key::KEY_Y)));
+        assert_eq!(keymap.stroke('Z'), Some(&stroke(&[Key::KEY_LEFTSHIFT], Key::KEY_Y)));
+        assert_eq!(keymap.stroke('ö'), Some(&stroke(&[], Key::KEY_SEMICOLON)));
+        assert_eq!(keymap.stroke('@'), Some(&stroke(&[Key::KEY_RIGHTALT], Key::KEY_Q)));
+        assert_eq!(keymap.stroke('\n'), Some(&stroke(&[], Key::KEY_ENTER)));
+    }
+
+    #[test]
+    fn test_xkb_missing_layout_index_fails() {
+        let settings = XkbSettings {
+            layout: "us".to_string(),
+            ..Default::default()
+        };
+        if let Ok(keymap) = Keymap::from_xkb(&settings, 0) {
+            assert_eq!(keymap.stroke('a'), Some(&stroke(&[], Key::KEY_A)));
+            assert!(Keymap::from_xkb(&settings, 3).is_err());
+        }
+    }
+}